@@ -0,0 +1,22 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(
+            &["proto/dump_cat.proto", "proto/arrow/flight/flight.proto"],
+            &["proto"],
+        )?;
+    tonic_build::configure()
+        .build_server(false)
+        // Clients are made from a channel, the generated `connect` needs
+        // the prelude of edition 2021.
+        .build_transport(false)
+        .compile_protos(
+            &[
+                "proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
+                "proto/opentelemetry/proto/collector/logs/v1/logs_service.proto",
+            ],
+            &["proto"],
+        )?;
+    Ok(())
+}
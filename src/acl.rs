@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use failure::Fallible;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DomainsFile {
+    List(Vec<String>),
+    Map { domains: Vec<String> },
+}
+
+/// The domains a team may see, loaded from `--allowed-domains`.
+///
+/// The file is either a YAML list of domains or a mapping with a `domains`
+/// list:
+///
+/// ```yaml
+/// domains:
+///   - shop-web
+///   - order-service
+/// ```
+#[derive(Debug)]
+pub struct AllowedDomains {
+    domains: HashSet<String>,
+    /// The file the domains were loaded from.
+    path: Option<PathBuf>,
+}
+
+impl AllowedDomains {
+    pub fn new(domains: impl IntoIterator<Item = String>) -> Self {
+        AllowedDomains {
+            domains: domains.into_iter().collect(),
+            path: None,
+        }
+    }
+
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let file: DomainsFile = serde_yaml::from_reader(File::open(path)?)?;
+        let domains = match file {
+            DomainsFile::List(domains) | DomainsFile::Map { domains } => domains,
+        };
+        Ok(AllowedDomains {
+            path: Some(path.to_path_buf()),
+            ..AllowedDomains::new(domains)
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The domains allowed by both.
+    pub fn intersection(&self, other: &AllowedDomains) -> Self {
+        AllowedDomains {
+            domains: self.domains.intersection(&other.domains).cloned().collect(),
+            path: None,
+        }
+    }
+
+    pub fn allows(&self, domain: &str) -> bool {
+        self.domains.contains(domain)
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam::{RecvTimeoutError, Sender};
+use failure::{bail, format_err, Fallible};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::client::Tls;
+use lettre::{Message as Email, SmtpTransport, Transport};
+use log::{info, warn};
+use serde_json::json;
+
+use crate::logging;
+use crate::message_tree::{MessageTree, Text};
+use crate::tls::ClientTls;
+
+pub mod rules;
+
+/// Message ids quoted per notification.
+const SAMPLES: usize = 5;
+
+/// Parses a duration like `300ms`, `30s`, `5m`, `1h` or `7d`. A bare number
+/// is in seconds.
+pub fn parse_duration(s: &str) -> Fallible<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format_err!("invalid duration {}, e.g. 30s or 5m", s))?;
+    Ok(match unit {
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 3600),
+        "d" => Duration::from_secs(value * 86400),
+        _ => bail!("invalid duration unit in {}, expected ms, s, m, h or d", s),
+    })
+}
+
+/// Where notifications are sent.
+#[derive(Debug, Clone, Default)]
+pub struct Channels {
+    pub slack_webhook: Option<String>,
+    pub email: Vec<Mailbox>,
+    pub smtp_server: String,
+    pub from: Option<Mailbox>,
+    pub tls: ClientTls,
+}
+
+impl Channels {
+    pub fn is_empty(&self) -> bool {
+        self.slack_webhook.is_none() && self.email.is_empty()
+    }
+
+    /// Sends a notification, logging failures.
+    fn send(&self, subject: &str, text: &str) {
+        match self.notify(subject, text) {
+            Ok(()) => info!("alert sent: {}", subject),
+            Err(e) => warn!(
+                error_kind = logging::error_kind(&e);
+                "failed to send alert {}: {}", subject, e
+            ),
+        }
+    }
+
+    fn notify(&self, subject: &str, text: &str) -> Fallible<()> {
+        if let Some(url) = &self.slack_webhook {
+            self.tls
+                .http_agent(None)?
+                .post(url)
+                .header("Content-Type", "application/json")
+                .send(json!({ "text": text }).to_string())?;
+        }
+        if !self.email.is_empty() {
+            let from = match &self.from {
+                Some(from) => from.clone(),
+                None => "dump-cat <dump-cat@localhost>".parse()?,
+            };
+            let mut email = Email::builder().from(from).subject(subject);
+            for to in &self.email {
+                email = email.to(to.clone());
+            }
+            // `smtps://` servers are connected to over TLS, others are asked
+            // for STARTTLS when they offer it.
+            let (server, implicit_tls) = match self.smtp_server.strip_prefix("smtps://") {
+                Some(server) => (server, true),
+                None => (self.smtp_server.as_str(), false),
+            };
+            let (host, port) = match server.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse()?),
+                None if implicit_tls => (server, 465),
+                None => (server, 25),
+            };
+            let parameters = self.tls.smtp_parameters(host)?;
+            let tls = if implicit_tls {
+                Tls::Wrapper(parameters)
+            } else {
+                Tls::Opportunistic(parameters)
+            };
+            // An internal relay, like the ones cron mails go through.
+            SmtpTransport::builder_dangerous(host)
+                .port(port)
+                .tls(tls)
+                .build()
+                .send(&email.body(text.to_string())?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Occurrences of one alert key since its last notification.
+struct Pending {
+    notified_at: Instant,
+    count: u64,
+    samples: Vec<Text>,
+}
+
+/// How often counted matches are checked for the end of their cooldown.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Notifies about the trees matching an alert query, grouped by `ty` and
+/// `name` of their root.
+///
+/// The first match of a group is notified right away. Further matches
+/// during `cooldown` are counted and notified together once it's over, so
+/// a burst sends at most one notification per cooldown. Notifications are
+/// sent from a background thread.
+pub struct Alerter {
+    state: Arc<State>,
+    sender: Sender<(String, String)>,
+    handle: JoinHandle<()>,
+}
+
+struct State {
+    query: String,
+    cooldown: Duration,
+    pending: Mutex<HashMap<(Text, Text), Pending>>,
+}
+
+impl Alerter {
+    pub fn start(query: &str, cooldown: Duration, channels: Channels) -> Fallible<Self> {
+        let state = Arc::new(State {
+            query: query.to_string(),
+            cooldown,
+            pending: Mutex::new(HashMap::new()),
+        });
+        let (sender, receiver) = crossbeam::unbounded::<(String, String)>();
+        let thread_state = state.clone();
+        let handle = thread::Builder::new()
+            .name("Alerter".to_string())
+            .spawn(move || {
+                let notify = |(subject, text): (String, String)| channels.send(&subject, &text);
+                loop {
+                    match receiver.recv_timeout(TICK) {
+                        Ok(notification) => notify(notification),
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    thread_state.flush(false).into_iter().for_each(notify);
+                }
+                thread_state.flush(true).into_iter().for_each(notify);
+            })?;
+        Ok(Alerter {
+            state,
+            sender,
+            handle,
+        })
+    }
+
+    /// Records `tree`, which matched the alert query.
+    pub fn observe(&self, tree: &MessageTree) {
+        let key = (tree.message.ty().clone(), tree.message.name().clone());
+        let now = Instant::now();
+        let mut pending = self.state.pending.lock().expect("alerts poisoned");
+        match pending.get_mut(&key) {
+            Some(p) if now.duration_since(p.notified_at) < self.state.cooldown || p.count > 0 => {
+                p.count += 1;
+                if p.samples.len() < SAMPLES {
+                    p.samples.push(tree.message_id.clone());
+                }
+            }
+            _ => {
+                let notification =
+                    self.state
+                        .notification(&key, 1, std::slice::from_ref(&tree.message_id));
+                let _ = self.sender.send(notification);
+                pending.insert(
+                    key,
+                    Pending {
+                        notified_at: now,
+                        count: 0,
+                        samples: vec![],
+                    },
+                );
+            }
+        }
+    }
+
+    /// Sends the matches counted so far and waits for the notifications to
+    /// be sent.
+    pub fn finish(self) {
+        drop(self.sender);
+        self.handle.join().expect("alerter thread");
+    }
+}
+
+impl State {
+    /// Notifications of the matches counted during a cooldown that is over,
+    /// or of all of them with `all`.
+    fn flush(&self, all: bool) -> Vec<(String, String)> {
+        let now = Instant::now();
+        let mut notifications = vec![];
+        for (key, p) in self.pending.lock().expect("alerts poisoned").iter_mut() {
+            if p.count > 0 && (all || now.duration_since(p.notified_at) >= self.cooldown) {
+                notifications.push(self.notification(key, p.count, &p.samples));
+                p.notified_at = now;
+                p.count = 0;
+                p.samples.clear();
+            }
+        }
+        notifications
+    }
+
+    fn notification(
+        &self,
+        (ty, name): &(Text, Text),
+        count: u64,
+        samples: &[Text],
+    ) -> (String, String) {
+        let subject = format!("[dump-cat] {} {}", ty, name);
+        let text = format!(
+            "{} {}: {} trees matching `{}`, e.g. {}",
+            ty,
+            name,
+            count,
+            self.query,
+            samples.join(", ")
+        );
+        (subject, text)
+    }
+}
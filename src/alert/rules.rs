@@ -0,0 +1,265 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crossbeam::Sender;
+use failure::{bail, format_err, Fallible};
+use serde::Deserialize;
+
+use crate::alert::{parse_duration, Channels};
+use crate::fields::Field;
+use crate::filter::Filter;
+use crate::message_tree::MessageTree;
+use crate::query::QueryLang;
+use crate::running::Aggregates;
+use crate::status_class;
+
+/// Event time between two evaluations of the p99 of a group.
+const P99_EVALUATION_INTERVAL_MS: u64 = 1000;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RulesFile {
+    List(Vec<RuleSpec>),
+    Map { rules: Vec<RuleSpec> },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    query: Option<String>,
+    group_by: Option<String>,
+    window: String,
+    #[serde(default)]
+    min_count: usize,
+    error_rate: Option<f64>,
+    p99_ms: Option<u64>,
+}
+
+/// One rule of the rules file.
+struct Rule {
+    name: String,
+    query: Option<String>,
+    group_by: Field,
+    window_ms: u64,
+    min_count: usize,
+    error_rate: Option<f64>,
+    p99_ms: Option<u64>,
+}
+
+#[derive(Default)]
+struct Window {
+    /// Timestamp, error and duration of the trees, oldest first.
+    trees: VecDeque<(u64, bool, Option<u64>)>,
+    errors: usize,
+    firing: bool,
+    p99_evaluated_at: u64,
+    p99_ms: Option<u64>,
+}
+
+impl Window {
+    fn push(&mut self, timestamp: u64, error: bool, duration: Option<u64>, window_ms: u64) {
+        self.trees.push_back((timestamp, error, duration));
+        self.errors += error as usize;
+        while let Some(&(oldest, error, _)) = self.trees.front() {
+            if oldest + window_ms > timestamp {
+                break;
+            }
+            self.trees.pop_front();
+            self.errors -= error as usize;
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        self.errors as f64 / self.trees.len() as f64
+    }
+
+    fn p99_ms(&mut self, now: u64) -> Option<u64> {
+        if self.p99_ms.is_none() || now >= self.p99_evaluated_at + P99_EVALUATION_INTERVAL_MS {
+            let mut durations: Vec<u64> = self.trees.iter().filter_map(|t| t.2).collect();
+            durations.sort_unstable();
+            self.p99_ms = match durations.len() {
+                0 => None,
+                n => Some(durations[(n * 99).div_ceil(100) - 1]),
+            };
+            self.p99_evaluated_at = now;
+        }
+        self.p99_ms
+    }
+}
+
+impl Rule {
+    fn from_spec(spec: RuleSpec, lang: QueryLang) -> Fallible<Self> {
+        if spec.error_rate.is_none() && spec.p99_ms.is_none() {
+            bail!("rule {}: needs error_rate or p99_ms", spec.name);
+        }
+        let window =
+            parse_duration(&spec.window).map_err(|e| format_err!("rule {}: {}", spec.name, e))?;
+        Filter::compile(spec.query.as_deref(), lang)
+            .map_err(|e| format_err!("rule {}: {}", spec.name, e))?;
+        Ok(Rule {
+            group_by: spec.group_by.as_deref().unwrap_or("name").parse()?,
+            name: spec.name,
+            query: spec.query,
+            window_ms: window.as_millis() as u64,
+            min_count: spec.min_count,
+            error_rate: spec.error_rate,
+            p99_ms: spec.p99_ms,
+        })
+    }
+
+    /// Values exceeding the thresholds of the rule, if any.
+    fn violations(&self, window: &mut Window, now: u64) -> Vec<String> {
+        let mut violations = vec![];
+        if window.trees.len() < self.min_count.max(1) {
+            return violations;
+        }
+        if let Some(threshold) = self.error_rate {
+            let rate = window.error_rate();
+            if rate >= threshold {
+                violations.push(format!(
+                    "error rate {:.1}% >= {:.1}%",
+                    rate * 100.0,
+                    threshold * 100.0
+                ));
+            }
+        }
+        if let Some(threshold) = self.p99_ms {
+            match window.p99_ms(now) {
+                Some(p99) if p99 >= threshold => {
+                    violations.push(format!("p99 {}ms >= {}ms", p99, threshold))
+                }
+                _ => {}
+            }
+        }
+        violations
+    }
+}
+
+/// Thresholds evaluated over a sliding window of the trees of each group,
+/// loaded from `--alert-rules`:
+///
+/// ```yaml
+/// rules:
+///   - name: checkout errors
+///     query: 'ty == "URL"'
+///     group_by: name
+///     window: 5m
+///     min_count: 20
+///     error_rate: 0.05
+///   - name: slow SQL
+///     query: 'ty == "SQL"'
+///     window: 1m
+///     p99_ms: 500
+/// ```
+///
+/// `query` selects the trees a rule looks at, all of them by default.
+/// `group_by` is a field, `name` by default. A tree is an error when the
+/// `status_class` of its root isn't `success`; the p99 is over root
+/// transaction durations.
+/// Windows follow the timestamps of the trees, so a file can be replayed.
+///
+/// A notification is sent when a group starts violating a rule and when it
+/// gets back under its thresholds.
+pub struct AlertRules {
+    rules: Vec<Rule>,
+    lang: QueryLang,
+    /// One per rule, shared by the filters of the filter threads.
+    aggregates: Vec<Arc<Aggregates>>,
+    windows: Mutex<HashMap<(usize, String), Window>>,
+    sender: Sender<(String, String)>,
+    handle: JoinHandle<()>,
+}
+
+impl AlertRules {
+    pub fn load(path: &Path, lang: QueryLang, channels: Channels) -> Fallible<Self> {
+        let file: RulesFile = serde_yaml::from_reader(File::open(path)?)?;
+        let specs = match file {
+            RulesFile::List(rules) | RulesFile::Map { rules } => rules,
+        };
+        let rules = specs
+            .into_iter()
+            .map(|spec| Rule::from_spec(spec, lang))
+            .collect::<Fallible<Vec<_>>>()?;
+
+        let (sender, receiver) = crossbeam::unbounded::<(String, String)>();
+        let handle = thread::Builder::new()
+            .name("AlertRules".to_string())
+            .spawn(move || {
+                for (subject, text) in receiver {
+                    channels.send(&subject, &text);
+                }
+            })?;
+        Ok(AlertRules {
+            aggregates: rules.iter().map(|_| Arc::default()).collect(),
+            rules,
+            lang,
+            windows: Mutex::new(HashMap::new()),
+            sender,
+            handle,
+        })
+    }
+
+    /// The filters of the rules, compiled for one filter thread.
+    pub fn filters(&self) -> Fallible<Vec<Filter>> {
+        self.rules
+            .iter()
+            .zip(&self.aggregates)
+            .map(|(rule, aggregates)| {
+                let filter = Filter::compile(rule.query.as_deref(), self.lang)?;
+                Ok(filter.with_aggregates(aggregates.clone()))
+            })
+            .collect()
+    }
+
+    /// Adds `tree` to the windows of the rules whose filter, out of
+    /// `filters`, matches it.
+    pub fn observe(&self, filters: &[Filter], tree: &MessageTree) -> Fallible<()> {
+        let timestamp = tree.message.timestamp_in_ms();
+        let error = !status_class::is_success(tree.message.status());
+        let duration = tree.message.duration_in_ms();
+        for (i, (rule, filter)) in self.rules.iter().zip(filters).enumerate() {
+            if !filter.matches(tree)? {
+                continue;
+            }
+            let group = rule.group_by.value(tree).unwrap_or_default();
+            let mut windows = self.windows.lock().expect("alert rules poisoned");
+            let window = windows.entry((i, group.clone())).or_default();
+            window.push(timestamp, error, duration, rule.window_ms);
+
+            let violations = rule.violations(window, timestamp);
+            if violations.is_empty() == window.firing {
+                window.firing = !violations.is_empty();
+                let state = if window.firing { "FIRING" } else { "RESOLVED" };
+                let subject = format!("[dump-cat] {} {}: {}", state, rule.name, group);
+                let text = if window.firing {
+                    format!(
+                        "{} {}={}: {} over the last {} trees, e.g. {}",
+                        rule.name,
+                        rule.group_by,
+                        group,
+                        violations.join(", "),
+                        window.trees.len(),
+                        tree.message_id
+                    )
+                } else {
+                    format!(
+                        "{} {}={}: back under the thresholds",
+                        rule.name, rule.group_by, group
+                    )
+                };
+                let _ = self.sender.send((subject, text));
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the notifications to be sent.
+    pub fn finish(self) {
+        drop(self.sender);
+        self.handle.join().expect("alert rules thread");
+    }
+}
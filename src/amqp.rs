@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use failure::{bail, Fallible};
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties};
+use log::warn;
+
+use crate::fields::Field;
+use crate::message_tree::{Message, MessageTree};
+use crate::output;
+use crate::tls::ClientTls;
+
+pub const DEFAULT_ROUTING_KEY_TEMPLATE: &str = "{domain}.{ty}";
+
+/// Routing key with `{field}` placeholders replaced by the fields of each
+/// tree, e.g. `{domain}.{ty}`.
+#[derive(Debug, Clone)]
+pub struct RoutingKeyTemplate(Vec<Segment>);
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+impl FromStr for RoutingKeyTemplate {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let mut segments = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => bail!("unclosed {{ in routing key template {}", s),
+            };
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            segments.push(Segment::Field(rest[start + 1..end].parse()?));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(RoutingKeyTemplate(segments))
+    }
+}
+
+impl RoutingKeyTemplate {
+    pub fn render(&self, tree: &MessageTree) -> String {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(s) => s.clone(),
+                Segment::Field(field) => field.value(tree).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Publishes matched trees as persistent JSON messages to an exchange.
+///
+/// Publisher confirms are enabled and waited for by `finish`, which also
+/// reports the trees the broker couldn't route to any queue.
+pub struct AmqpPublisher {
+    runtime: tokio::runtime::Runtime,
+    // Closing the connection when dropped would lose unconfirmed messages.
+    _connection: Connection,
+    channel: Channel,
+    exchange: String,
+    routing_key: RoutingKeyTemplate,
+}
+
+impl AmqpPublisher {
+    /// Connects to `url`, over TLS for `amqps://` URLs.
+    pub fn connect(
+        url: &str,
+        exchange: &str,
+        routing_key: RoutingKeyTemplate,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        let tls = tls.amqp_config()?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        let (connection, channel) = runtime.block_on(async {
+            let connection =
+                Connection::connect_with_config(url, ConnectionProperties::default(), tls).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            Ok::<_, lapin::Error>((connection, channel))
+        })?;
+        Ok(AmqpPublisher {
+            runtime,
+            _connection: connection,
+            channel,
+            exchange: exchange.to_string(),
+            routing_key,
+        })
+    }
+
+    /// Publishes `tree`, with its message replaced by `message`, e.g. after
+    /// decoding payloads.
+    pub fn publish(&self, tree: &MessageTree, message: &Message) -> Fallible<()> {
+        let payload = output::tree_json(tree, message).to_string();
+        let mut headers = FieldTable::default();
+        headers.insert(
+            "domain".into(),
+            AMQPValue::LongString(tree.domain.as_str().into()),
+        );
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_message_id(tree.message_id.as_str().into())
+            .with_app_id("dump-cat".into())
+            .with_delivery_mode(2)
+            .with_headers(headers);
+
+        self.runtime.block_on(self.channel.basic_publish(
+            &self.exchange,
+            &self.routing_key.render(tree),
+            BasicPublishOptions {
+                mandatory: true,
+                ..Default::default()
+            },
+            payload.as_bytes(),
+            properties,
+        ))?;
+        Ok(())
+    }
+
+    /// Waits for the broker to confirm every published tree.
+    pub fn finish(&self) -> Fallible<()> {
+        let returned = self.runtime.block_on(self.channel.wait_for_confirms())?;
+        if !returned.is_empty() {
+            warn!(
+                "amqp: {} trees weren't routed to any queue of exchange {:?}",
+                returned.len(),
+                self.exchange
+            );
+        }
+        Ok(())
+    }
+}
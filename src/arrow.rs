@@ -0,0 +1,566 @@
+use std::io::Write;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use failure::Fallible;
+
+use crate::csv::Column;
+use crate::fields::Field;
+use crate::message_tree::MessageTree;
+use crate::output::Output;
+use crate::sink::Sink;
+
+/// Rows of a record batch when `--arrow-batch-size` isn't given.
+pub const DEFAULT_BATCH_SIZE: &str = "1024";
+
+/// Marks the start of an encapsulated message, and with a zero length the
+/// end of the stream.
+const CONTINUATION: u32 = 0xFFFF_FFFF;
+
+/// MetadataVersion.V5.
+const METADATA_VERSION: i16 = 4;
+
+/// Members of the MessageHeader union.
+const SCHEMA: u8 = 1;
+const RECORD_BATCH: u8 = 3;
+
+/// TimeUnit.MILLISECOND.
+const MILLISECOND: i16 = 1;
+
+/// Arrow type of the values of a column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Utf8,
+    Int64,
+    /// Milliseconds since the epoch, in UTC.
+    Timestamp,
+    Bool,
+}
+
+impl Type {
+    fn of(column: Column) -> Self {
+        match column {
+            Column::Ts => Type::Timestamp,
+            Column::DurationInMs => Type::Int64,
+            Column::Data => Type::Utf8,
+            Column::Field(Field::TimestampInMs)
+            | Column::Field(Field::DurationInMs)
+            | Column::Field(Field::SelfDurationInMs) => Type::Int64,
+            Column::Field(Field::HasConcurrentChildren) => Type::Bool,
+            Column::Field(_) => Type::Utf8,
+        }
+    }
+
+    /// Member of the Type union and its table.
+    fn flatbuffer(self) -> (u8, Object) {
+        match self {
+            Type::Utf8 => (5, Object::Table(vec![])),
+            Type::Int64 => (
+                2,
+                Object::Table(vec![(0, Scalar::I32(64)), (1, Scalar::U8(1))]),
+            ),
+            Type::Timestamp => (
+                10,
+                Object::Table(vec![
+                    (0, Scalar::I16(MILLISECOND)),
+                    (1, Scalar::Offset(Object::String("UTC".to_string()))),
+                ]),
+            ),
+            Type::Bool => (6, Object::Table(vec![])),
+        }
+    }
+}
+
+/// A value of a row, `None` when the tree has none.
+enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+fn value(column: Column, tree: &MessageTree) -> Option<Value> {
+    let message = &tree.message;
+    match (column, Type::of(column)) {
+        (Column::Ts, _) => Some(Value::Int(message.timestamp_in_ms() as i64)),
+        (Column::DurationInMs, _) => message.duration_in_ms().map(|d| Value::Int(d as i64)),
+        (Column::Data, _) => Some(Value::Str(message.data().to_string())),
+        (Column::Field(field), Type::Int64) => field.number(tree).map(|n| Value::Int(n as i64)),
+        (Column::Field(field), Type::Bool) => field.value(tree).map(|v| Value::Bool(v == "true")),
+        (Column::Field(field), _) => field.value(tree).map(Value::Str),
+    }
+}
+
+/// The values of a tree for the columns of an `Encoder`.
+pub struct Row(Vec<Option<Value>>);
+
+/// An encapsulated IPC message: the `Message` flatbuffer, padded for the
+/// body to start 8 bytes aligned, and the body.
+pub struct IpcMessage {
+    pub metadata: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+impl IpcMessage {
+    /// Writes the message in the IPC streaming format, after the
+    /// continuation marker and the size of the metadata.
+    fn write(&self, out: &mut dyn Write) -> Fallible<()> {
+        out.write_u32::<LittleEndian>(CONTINUATION)?;
+        out.write_i32::<LittleEndian>(self.metadata.len() as i32)?;
+        out.write_all(&self.metadata)?;
+        out.write_all(&self.body)?;
+        Ok(())
+    }
+}
+
+/// Encodes trees as the schema and record batches of `columns`, written by
+/// `ArrowStream` or sent over Arrow Flight.
+pub struct Encoder {
+    columns: Vec<Column>,
+}
+
+impl Encoder {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Encoder { columns }
+    }
+
+    pub fn schema(&self) -> IpcMessage {
+        let fields = self
+            .columns
+            .iter()
+            .map(|&column| {
+                let (type_type, ty) = Type::of(column).flatbuffer();
+                Object::Table(vec![
+                    (0, Scalar::Offset(Object::String(column.to_string()))),
+                    (1, Scalar::U8(1)),
+                    (2, Scalar::U8(type_type)),
+                    (3, Scalar::Offset(ty)),
+                    (5, Scalar::Offset(Object::Tables(vec![]))),
+                ])
+            })
+            .collect();
+        let schema = Object::Table(vec![
+            (0, Scalar::I16(0)),
+            (1, Scalar::Offset(Object::Tables(fields))),
+        ]);
+        message(SCHEMA, schema, vec![])
+    }
+
+    pub fn row(&self, tree: &MessageTree) -> Row {
+        Row(self.columns.iter().map(|&c| value(c, tree)).collect())
+    }
+
+    pub fn batch(&self, rows: &[Row]) -> Fallible<IpcMessage> {
+        let mut body = vec![];
+        let mut nodes = vec![];
+        let mut buffers = vec![];
+        for (i, &column) in self.columns.iter().enumerate() {
+            let values: Vec<_> = rows.iter().map(|row| row.0[i].as_ref()).collect();
+            let nulls = values.iter().filter(|v| v.is_none()).count();
+            nodes.push((values.len() as i64, nulls as i64));
+
+            let mut validity = vec![0u8; values.len().div_ceil(8)];
+            for (j, v) in values.iter().enumerate() {
+                if v.is_some() {
+                    validity[j / 8] |= 1 << (j % 8);
+                }
+            }
+            push_buffer(&mut body, &mut buffers, &validity);
+            match Type::of(column) {
+                Type::Utf8 => {
+                    let mut offsets = vec![];
+                    let mut data = vec![];
+                    offsets.write_i32::<LittleEndian>(0)?;
+                    for v in &values {
+                        if let Some(Value::Str(s)) = v {
+                            data.extend_from_slice(s.as_bytes());
+                        }
+                        offsets.write_i32::<LittleEndian>(data.len() as i32)?;
+                    }
+                    push_buffer(&mut body, &mut buffers, &offsets);
+                    push_buffer(&mut body, &mut buffers, &data);
+                }
+                Type::Int64 | Type::Timestamp => {
+                    let mut data = vec![];
+                    for v in &values {
+                        let n = match v {
+                            Some(Value::Int(n)) => *n,
+                            _ => 0,
+                        };
+                        data.write_i64::<LittleEndian>(n)?;
+                    }
+                    push_buffer(&mut body, &mut buffers, &data);
+                }
+                Type::Bool => {
+                    let mut data = vec![0u8; values.len().div_ceil(8)];
+                    for (j, v) in values.iter().enumerate() {
+                        if let Some(Value::Bool(true)) = v {
+                            data[j / 8] |= 1 << (j % 8);
+                        }
+                    }
+                    push_buffer(&mut body, &mut buffers, &data);
+                }
+            }
+        }
+
+        let structs = |pairs: &[(i64, i64)]| {
+            let mut bytes = vec![];
+            for &(a, b) in pairs {
+                bytes.extend_from_slice(&a.to_le_bytes());
+                bytes.extend_from_slice(&b.to_le_bytes());
+            }
+            Object::Structs(bytes, pairs.len() as u32)
+        };
+        let batch = Object::Table(vec![
+            (0, Scalar::I64(rows.len() as i64)),
+            (1, Scalar::Offset(structs(&nodes))),
+            (2, Scalar::Offset(structs(&buffers))),
+        ]);
+        Ok(message(RECORD_BATCH, batch, body))
+    }
+}
+
+/// Writes matched trees as Arrow record batches of `batch_size` rows in the
+/// IPC streaming format, e.g. for `pyarrow.ipc.open_stream`.
+///
+/// The filter threads share the stream: each of them appends its trees to
+/// the batch being filled, and the batch is written once full.
+pub struct ArrowStream {
+    encoder: Encoder,
+    batch_size: usize,
+    rows: Mutex<Vec<Row>>,
+    out: Output,
+}
+
+impl ArrowStream {
+    /// Writes the schema of `columns`.
+    pub fn start(columns: Vec<Column>, batch_size: usize, out: Output) -> Fallible<Arc<Self>> {
+        let encoder = Encoder::new(columns);
+        encoder.schema().write(&mut *out.lock())?;
+        Ok(Arc::new(ArrowStream {
+            encoder,
+            batch_size: batch_size.max(1),
+            rows: Mutex::new(vec![]),
+            out,
+        }))
+    }
+
+    fn append(&self, tree: &MessageTree) -> Fallible<()> {
+        let row = self.encoder.row(tree);
+        let full = {
+            let mut rows = self.rows.lock().expect("arrow rows poisoned");
+            rows.push(row);
+            if rows.len() < self.batch_size {
+                return Ok(());
+            }
+            mem::take(&mut *rows)
+        };
+        self.write_batch(&full)
+    }
+
+    /// Writes the rows left as a last batch.
+    fn write_rest(&self) -> Fallible<()> {
+        let rows = mem::take(&mut *self.rows.lock().expect("arrow rows poisoned"));
+        if rows.is_empty() {
+            return Ok(());
+        }
+        self.write_batch(&rows)
+    }
+
+    fn write_batch(&self, rows: &[Row]) -> Fallible<()> {
+        self.encoder.batch(rows)?.write(&mut *self.out.lock())
+    }
+
+    /// Writes the rows left and the end of the stream. The output is left
+    /// to be finished by the caller.
+    pub fn finish(&self) -> Fallible<()> {
+        self.write_rest()?;
+        let mut out = self.out.lock();
+        out.write_u32::<LittleEndian>(CONTINUATION)?;
+        out.write_u32::<LittleEndian>(0)?;
+        Ok(())
+    }
+}
+
+impl Sink for Arc<ArrowStream> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.append(tree)
+    }
+
+    /// Batches are only written once full, or by `finish`, so that the
+    /// threads don't each write a small one.
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(())
+    }
+}
+
+/// Appends `data` to `body` padded to 8 bytes, and its `(offset, length)`
+/// to `buffers`.
+fn push_buffer(body: &mut Vec<u8>, buffers: &mut Vec<(i64, i64)>, data: &[u8]) {
+    buffers.push((body.len() as i64, data.len() as i64));
+    body.extend_from_slice(data);
+    body.resize(body.len().next_multiple_of(8), 0);
+}
+
+/// The message of `header` and its `body`.
+fn message(header_type: u8, header: Object, body: Vec<u8>) -> IpcMessage {
+    let message = Object::Table(vec![
+        (0, Scalar::I16(METADATA_VERSION)),
+        (1, Scalar::U8(header_type)),
+        (2, Scalar::Offset(header)),
+        (3, Scalar::I64(body.len() as i64)),
+    ]);
+    let mut metadata = flatbuffer(&message);
+    // The body starts 8 bytes aligned.
+    metadata.resize((metadata.len() + 8).next_multiple_of(8) - 8, 0);
+    IpcMessage { metadata, body }
+}
+
+/// A field of a flatbuffer table.
+enum Scalar {
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    /// Another object, laid out after the table.
+    Offset(Object),
+}
+
+impl Scalar {
+    fn size(&self) -> usize {
+        match self {
+            Scalar::U8(_) => 1,
+            Scalar::I16(_) => 2,
+            Scalar::I32(_) | Scalar::Offset(_) => 4,
+            Scalar::I64(_) => 8,
+        }
+    }
+}
+
+/// The flatbuffer objects of the Arrow metadata.
+enum Object {
+    /// Fields by their slot in the schema of the table.
+    Table(Vec<(u16, Scalar)>),
+    String(String),
+    Tables(Vec<Object>),
+    /// Structs of 8 bytes aligned fields, and their number.
+    Structs(Vec<u8>, u32),
+}
+
+/// Serializes `root`. Every object is laid out before the objects it
+/// refers to, as offsets are unsigned, and its vtable right before it.
+fn flatbuffer(root: &Object) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    let position = write_object(&mut buf, root);
+    patch_offset(&mut buf, 0, position);
+    buf
+}
+
+fn align(buf: &mut Vec<u8>, alignment: usize) {
+    buf.resize(buf.len().next_multiple_of(alignment), 0);
+}
+
+/// Points the offset at `at` to `target`.
+fn patch_offset(buf: &mut [u8], at: usize, target: usize) {
+    buf[at..at + 4].copy_from_slice(&((target - at) as u32).to_le_bytes());
+}
+
+fn write_object(buf: &mut Vec<u8>, object: &Object) -> usize {
+    match object {
+        Object::Table(fields) => {
+            let slots = fields
+                .iter()
+                .map(|(slot, _)| *slot as usize + 1)
+                .max()
+                .unwrap_or(0);
+            align(buf, 2);
+            let vtable = buf.len();
+            buf.resize(vtable + 4 + 2 * slots, 0);
+
+            align(buf, 8);
+            let table = buf.len();
+            buf.extend_from_slice(&((table - vtable) as i32).to_le_bytes());
+            let mut by_size: Vec<_> = fields.iter().collect();
+            by_size.sort_by_key(|(_, scalar)| std::cmp::Reverse(scalar.size()));
+            let mut children = vec![];
+            for (slot, scalar) in by_size {
+                align(buf, scalar.size());
+                let at = buf.len();
+                match scalar {
+                    Scalar::U8(v) => buf.push(*v),
+                    Scalar::I16(v) => buf.extend_from_slice(&v.to_le_bytes()),
+                    Scalar::I32(v) => buf.extend_from_slice(&v.to_le_bytes()),
+                    Scalar::I64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+                    Scalar::Offset(child) => {
+                        buf.extend_from_slice(&[0; 4]);
+                        children.push((at, child));
+                    }
+                }
+                let entry = vtable + 4 + 2 * *slot as usize;
+                buf[entry..entry + 2].copy_from_slice(&((at - table) as u16).to_le_bytes());
+            }
+            let vtable_size = (4 + 2 * slots) as u16;
+            let table_size = (buf.len() - table) as u16;
+            buf[vtable..vtable + 2].copy_from_slice(&vtable_size.to_le_bytes());
+            buf[vtable + 2..vtable + 4].copy_from_slice(&table_size.to_le_bytes());
+
+            for (at, child) in children {
+                let position = write_object(buf, child);
+                patch_offset(buf, at, position);
+            }
+            table
+        }
+        Object::String(s) => {
+            align(buf, 4);
+            let position = buf.len();
+            buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            buf.extend_from_slice(s.as_bytes());
+            buf.push(0);
+            position
+        }
+        Object::Tables(tables) => {
+            align(buf, 4);
+            let position = buf.len();
+            buf.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+            let first = buf.len();
+            buf.resize(first + 4 * tables.len(), 0);
+            for (i, table) in tables.iter().enumerate() {
+                let table_position = write_object(buf, table);
+                patch_offset(buf, first + 4 * i, table_position);
+            }
+            position
+        }
+        Object::Structs(bytes, count) => {
+            // The structs, after their count, are 8 bytes aligned.
+            while buf.len() % 8 != 4 {
+                buf.push(0);
+            }
+            let position = buf.len();
+            buf.extend_from_slice(&count.to_le_bytes());
+            buf.extend_from_slice(bytes);
+            position
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int64Type, TimestampMillisecondType};
+    use arrow_array::Array;
+    use arrow_ipc::reader::StreamReader;
+    use arrow_ipc::{root_as_message, MessageHeader, MetadataVersion};
+
+    use super::*;
+    use crate::message_tree::{InnerEvent, InnerTransaction, Message};
+
+    fn trees() -> Vec<MessageTree> {
+        let url = Message::Transaction(Arc::new(InnerTransaction {
+            ty: "URL".to_string(),
+            name: "/api/item/1".to_string(),
+            timestamp_in_ms: 1_715_400_000_000,
+            status: "0".to_string(),
+            duration_in_ms: 140,
+            ..Default::default()
+        }));
+        let event = Message::Event(Arc::new(InnerEvent {
+            ty: "Error".to_string(),
+            name: "java.lang.Exception".to_string(),
+            timestamp_in_ms: 1_715_400_000_100,
+            status: "ERROR".to_string(),
+            ..Default::default()
+        }));
+        vec![url.clone(), event, url]
+            .into_iter()
+            .map(|message| MessageTree {
+                message,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn columns() -> Vec<Column> {
+        ["ts", "name", "duration_in_ms", "has_concurrent_children"]
+            .iter()
+            .map(|c| c.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn messages() {
+        let encoder = Encoder::new(columns());
+        let schema = encoder.schema();
+        // Padded for the body to start 8 bytes aligned after the prefix.
+        assert_eq!(schema.metadata.len() % 8, 0);
+        assert!(schema.body.is_empty());
+        let message = root_as_message(&schema.metadata).unwrap();
+        assert_eq!(message.version(), MetadataVersion::V5);
+        assert_eq!(message.header_type(), MessageHeader::Schema);
+        assert_eq!(message.bodyLength(), 0);
+
+        let rows: Vec<_> = trees().iter().map(|t| encoder.row(t)).collect();
+        let batch = encoder.batch(&rows).unwrap();
+        assert_eq!(batch.metadata.len() % 8, 0);
+        let message = root_as_message(&batch.metadata).unwrap();
+        assert_eq!(message.header_type(), MessageHeader::RecordBatch);
+        let header = message.header_as_record_batch().unwrap();
+        assert_eq!(header.length(), 3);
+        // The validity and values of every column, the offsets of strings.
+        assert_eq!(header.nodes().unwrap().len(), 4);
+        assert_eq!(header.buffers().unwrap().len(), 9);
+        for buffer in header.buffers().unwrap() {
+            assert_eq!(buffer.offset() % 8, 0);
+        }
+    }
+
+    #[test]
+    fn read_by_arrow() {
+        let encoder = Encoder::new(columns());
+        let rows: Vec<_> = trees().iter().map(|t| encoder.row(t)).collect();
+        let mut stream = vec![];
+        encoder.schema().write(&mut stream).unwrap();
+        encoder
+            .batch(&rows[..2])
+            .unwrap()
+            .write(&mut stream)
+            .unwrap();
+        encoder
+            .batch(&rows[2..])
+            .unwrap()
+            .write(&mut stream)
+            .unwrap();
+        stream.write_u32::<LittleEndian>(CONTINUATION).unwrap();
+        stream.write_u32::<LittleEndian>(0).unwrap();
+
+        let reader = StreamReader::try_new(Cursor::new(stream), None).unwrap();
+        let schema = reader.schema();
+        let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(
+            names,
+            ["ts", "name", "duration_in_ms", "has_concurrent_children"]
+        );
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+            [2, 1]
+        );
+
+        let first = &batches[0];
+        let ts = first.column(0).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(ts.values(), &[1_715_400_000_000, 1_715_400_000_100]);
+        let name = first.column(1).as_string::<i32>();
+        assert_eq!(name.value(0), "/api/item/1");
+        assert_eq!(name.value(1), "java.lang.Exception");
+        let duration = first.column(2).as_primitive::<Int64Type>();
+        assert_eq!(duration.value(0), 140);
+        assert!(duration.is_null(1));
+        let concurrent = first.column(3).as_boolean();
+        assert!(!concurrent.value(0));
+        assert_eq!(concurrent.null_count(), 0);
+
+        let second = &batches[1];
+        assert_eq!(second.column(1).as_string::<i32>().value(0), "/api/item/1");
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use failure::{bail, format_err, Fallible};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::acl::AllowedDomains;
+
+type Fingerprint = [u8; 32];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuthFile {
+    clients: Vec<ClientSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientSpec {
+    name: String,
+    token_sha256: Option<String>,
+    certificate_sha256: Option<String>,
+    domains: Option<Vec<String>>,
+}
+
+/// A client of the served API.
+#[derive(Debug)]
+pub struct Client {
+    pub name: String,
+    /// The domains the client may query, all of them when `None`.
+    pub allowed_domains: Option<Arc<AllowedDomains>>,
+}
+
+/// The clients allowed to query `grpc-serve`, loaded from `--auth`:
+///
+/// ```yaml
+/// clients:
+///   - name: checkout-team
+///     token_sha256: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+///     domains: [shop-web, order-service]
+///   - name: sre-dashboard
+///     certificate_sha256: "3A:9C:...:E1"
+/// ```
+///
+/// Clients send their token as `authorization: Bearer <token>` metadata,
+/// the file only holds its SHA-256. With `--tls-client-ca`, a client can
+/// instead be identified by the SHA-256 fingerprint of its certificate, as
+/// printed by `openssl x509 -noout -fingerprint -sha256`. A client without
+/// `domains` may query every domain.
+#[derive(Debug)]
+pub struct Auth {
+    tokens: HashMap<Fingerprint, Arc<Client>>,
+    certificates: HashMap<Fingerprint, Arc<Client>>,
+}
+
+/// Parses a SHA-256 in hex, with or without colons.
+fn parse_fingerprint(s: &str) -> Fallible<Fingerprint> {
+    let hex_digits: String = s.chars().filter(|&c| c != ':').collect();
+    let bytes = hex::decode(&hex_digits).map_err(|_| format_err!("invalid SHA-256 {}", s))?;
+    bytes
+        .try_into()
+        .map_err(|_| format_err!("invalid SHA-256 {}, expected 32 bytes", s))
+}
+
+fn sha256(data: &[u8]) -> Fingerprint {
+    Sha256::digest(data).into()
+}
+
+impl Auth {
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let file: AuthFile = serde_yaml::from_reader(File::open(path)?)?;
+        let mut auth = Auth {
+            tokens: HashMap::new(),
+            certificates: HashMap::new(),
+        };
+        for spec in file.clients {
+            if spec.token_sha256.is_none() && spec.certificate_sha256.is_none() {
+                bail!(
+                    "client {}: needs token_sha256 or certificate_sha256",
+                    spec.name
+                );
+            }
+            let name = &spec.name;
+            let client = Arc::new(Client {
+                name: name.clone(),
+                allowed_domains: spec.domains.map(|d| Arc::new(AllowedDomains::new(d))),
+            });
+            if let Some(token) = &spec.token_sha256 {
+                let fingerprint =
+                    parse_fingerprint(token).map_err(|e| format_err!("client {}: {}", name, e))?;
+                if auth.tokens.insert(fingerprint, client.clone()).is_some() {
+                    bail!("client {}: token already used", name);
+                }
+            }
+            if let Some(certificate) = &spec.certificate_sha256 {
+                let fingerprint = parse_fingerprint(certificate)
+                    .map_err(|e| format_err!("client {}: {}", name, e))?;
+                if auth.certificates.insert(fingerprint, client).is_some() {
+                    bail!("client {}: certificate already used", name);
+                }
+            }
+        }
+        Ok(auth)
+    }
+
+    /// Whether clients may be identified by their certificates.
+    pub fn uses_certificates(&self) -> bool {
+        !self.certificates.is_empty()
+    }
+
+    /// The client sending `token` or, without a token, presenting
+    /// `certificate`, which TLS already verified.
+    pub fn authenticate(
+        &self,
+        token: Option<&str>,
+        certificate: Option<&[u8]>,
+    ) -> Option<Arc<Client>> {
+        match (token, certificate) {
+            (Some(token), _) => self.tokens.get(&sha256(token.as_bytes())).cloned(),
+            (None, Some(certificate)) => self.certificates.get(&sha256(certificate)).cloned(),
+            (None, None) => None,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Fallible;
+use log::info;
+
+use crate::filter::Filter;
+use crate::message_tree_dumper::MessageTreeDumperBuilder;
+use crate::query::QueryLang;
+
+/// Total time spent probing decoding thread counts.
+const PROBE_DURATION: Duration = Duration::from_secs(3);
+
+/// A thread count has to beat the previous one by this ratio to be kept.
+const MIN_SPEEDUP: f64 = 1.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    pub decoding_threads: usize,
+    pub filter_threads: usize,
+    pub block_reader_channel_buffer_size: usize,
+    pub tree_decoder_channel_buffer_size: usize,
+}
+
+/// Decodes the beginning of `path` with an increasing number of decoding
+/// threads and derives the pipeline sizes from the fastest setting and the
+/// cost of evaluating `query`.
+pub fn tune(path: &Path, query: Option<&str>, lang: QueryLang) -> Fallible<Tuning> {
+    let cpus = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut candidates = vec![];
+    let mut threads = 1;
+    while threads < cpus {
+        candidates.push(threads);
+        threads *= 2;
+    }
+    candidates.push(cpus);
+    let per_candidate = PROBE_DURATION / candidates.len() as u32;
+
+    let mut best = (1, 0.0);
+    for threads in candidates {
+        let rate = probe_decoding(path, threads, per_candidate)?;
+        info!(
+            "auto-tune: {} decoding threads, {:.0} trees/s",
+            threads, rate
+        );
+        if rate < best.1 * MIN_SPEEDUP {
+            break;
+        }
+        best = (threads, rate);
+    }
+    let (decoding_threads, rate) = best;
+
+    let filter_cost = probe_filter(path, query, lang)?;
+    let needed = (rate * filter_cost.as_secs_f64()).ceil() as usize;
+    let filter_threads = needed.clamp(1, cpus.saturating_sub(decoding_threads).max(1));
+
+    let tuning = Tuning {
+        decoding_threads,
+        filter_threads,
+        block_reader_channel_buffer_size: decoding_threads * 2,
+        tree_decoder_channel_buffer_size: filter_threads * 64,
+    };
+    info!("auto-tune: {:?}", tuning);
+    Ok(tuning)
+}
+
+/// Trees decoded per second with `threads` decoding threads.
+fn probe_decoding(path: &Path, threads: usize, duration: Duration) -> Fallible<f64> {
+    let dumper = MessageTreeDumperBuilder::default()
+        .path(path.to_path_buf())
+        .threads(threads)
+        .block_reader_channel_buffer_size(threads * 2)
+        .tree_decoder_channel_buffer_size(1024usize)
+        .build()
+        .expect("build dumper");
+    let trees = dumper.read_trees();
+
+    let start = Instant::now();
+    let mut count = 0u64;
+    while start.elapsed() < duration {
+        match trees.recv_timeout(duration) {
+            Ok(_) => count += 1,
+            Err(_) => break,
+        }
+    }
+    // Dropping the receiver stops the decoding threads.
+    Ok(count as f64 / start.elapsed().as_secs_f64())
+}
+
+/// Average time to evaluate `query` on one tree.
+fn probe_filter(path: &Path, query: Option<&str>, lang: QueryLang) -> Fallible<Duration> {
+    const SAMPLE: usize = 1000;
+
+    let filter = Filter::compile(query, lang)?;
+    let dumper = MessageTreeDumperBuilder::default()
+        .path(path.to_path_buf())
+        .build()
+        .expect("build dumper");
+    let trees: Vec<_> = dumper.into_iter().take(SAMPLE).collect();
+    if trees.is_empty() {
+        return Ok(Duration::from_secs(0));
+    }
+
+    let start = Instant::now();
+    for tree in &trees {
+        filter.matches(tree)?;
+    }
+    Ok(start.elapsed() / trees.len() as u32)
+}
@@ -0,0 +1,341 @@
+use std::io::Write;
+
+use failure::Fallible;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use rand::RngCore;
+
+use crate::message_tree::{Message, MessageTree};
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+
+/// A block is written once it holds this many bytes before compression.
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Schema of the records of the files written by `ContainerWriter`.
+///
+/// The messages of a tree are flattened, root first and every message
+/// before its children, each pointing at the position of its parent, as
+/// Hive and Spark reject recursive schemas.
+pub const SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "MessageTree",
+  "namespace": "dump_cat",
+  "fields": [
+    {"name": "domain", "type": "string"},
+    {"name": "hostname", "type": "string"},
+    {"name": "ip_address", "type": "string"},
+    {"name": "message_id", "type": "string"},
+    {"name": "parent_message_id", "type": "string"},
+    {"name": "root_message_id", "type": "string"},
+    {"name": "session_token", "type": "string"},
+    {"name": "thread_group_name", "type": "string"},
+    {"name": "thread_id", "type": "string"},
+    {"name": "thread_name", "type": "string"},
+    {"name": "messages", "type": {"type": "array", "items": {
+      "type": "record",
+      "name": "Message",
+      "fields": [
+        {"name": "parent", "type": ["null", "int"], "default": null},
+        {"name": "kind", "type": {"type": "enum", "name": "Kind",
+          "symbols": ["EVENT", "TRANSACTION", "HEARTBEAT", "METRIC", "TRACE"]}},
+        {"name": "status", "type": "string"},
+        {"name": "ty", "type": "string"},
+        {"name": "name", "type": "string"},
+        {"name": "timestamp_in_ms", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+        {"name": "data", "type": "string"},
+        {"name": "duration_in_ms", "type": ["null", "long"], "default": null}
+      ]
+    }}}
+  ]
+}"#;
+
+/// Writes trees as an Avro object container file, with `SCHEMA` embedded
+/// and blocks compressed with the `deflate` codec, for Hadoop tools.
+pub struct ContainerWriter<W: Write> {
+    out: W,
+    sync: [u8; 16],
+    /// Encoded records of the block being filled.
+    block: Vec<u8>,
+    records: u64,
+}
+
+impl<W: Write> ContainerWriter<W> {
+    pub fn new(mut out: W) -> Fallible<Self> {
+        let mut sync = [0; 16];
+        rand::thread_rng().fill_bytes(&mut sync);
+        let mut header = MAGIC.to_vec();
+        // The metadata, a map of one block of two entries.
+        write_long(&mut header, 2);
+        write_bytes(&mut header, b"avro.schema");
+        write_bytes(&mut header, SCHEMA.as_bytes());
+        write_bytes(&mut header, b"avro.codec");
+        write_bytes(&mut header, b"deflate");
+        write_long(&mut header, 0);
+        header.extend_from_slice(&sync);
+        out.write_all(&header)?;
+        Ok(ContainerWriter {
+            out,
+            sync,
+            block: vec![],
+            records: 0,
+        })
+    }
+
+    pub fn write(&mut self, tree: &MessageTree) -> Fallible<()> {
+        for field in [
+            &tree.domain,
+            &tree.hostname,
+            &tree.ip_address,
+            &tree.message_id,
+            &tree.parent_message_id,
+            &tree.root_message_id,
+            &tree.session_token,
+            &tree.thread_group_name,
+            &tree.thread_id,
+            &tree.thread_name,
+        ] {
+            write_bytes(&mut self.block, field.as_bytes());
+        }
+        let mut messages = vec![];
+        flatten(&tree.message, None, &mut messages);
+        // The array as one block, followed by the empty block ending it.
+        write_long(&mut self.block, messages.len() as i64);
+        for (message, parent) in &messages {
+            self.write_message(message, *parent);
+        }
+        write_long(&mut self.block, 0);
+
+        self.records += 1;
+        if self.block.len() >= BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    fn write_message(&mut self, message: &Message, parent: Option<usize>) {
+        let block = &mut self.block;
+        match parent {
+            None => write_long(block, 0),
+            Some(parent) => {
+                write_long(block, 1);
+                write_long(block, parent as i64);
+            }
+        }
+        let (kind, data) = match message {
+            Message::Event(e) => (0, &e.data),
+            Message::Transaction(t) => (1, &t.data),
+            Message::Heartbeat(h) => (2, &h.data),
+            Message::Metric(m) => (3, &m.data),
+            Message::Trace(t) => (4, &t.data),
+        };
+        write_long(block, kind);
+        write_bytes(block, message.status().as_bytes());
+        write_bytes(block, message.ty().as_bytes());
+        write_bytes(block, message.name().as_bytes());
+        write_long(block, message.timestamp_in_ms() as i64);
+        write_bytes(block, data.as_bytes());
+        match message.duration_in_ms() {
+            None => write_long(block, 0),
+            Some(duration) => {
+                write_long(block, 1);
+                write_long(block, duration as i64);
+            }
+        }
+    }
+
+    fn flush_block(&mut self) -> Fallible<()> {
+        if self.records == 0 {
+            return Ok(());
+        }
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&self.block)?;
+        let compressed = encoder.finish()?;
+        let mut header = vec![];
+        write_long(&mut header, self.records as i64);
+        write_long(&mut header, compressed.len() as i64);
+        self.out.write_all(&header)?;
+        self.out.write_all(&compressed)?;
+        self.out.write_all(&self.sync)?;
+        self.block.clear();
+        self.records = 0;
+        Ok(())
+    }
+
+    /// Writes the last block.
+    pub fn finish(mut self) -> Fallible<W> {
+        self.flush_block()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// `message` and the messages under it, each with the position of its
+/// parent in `messages`.
+fn flatten<'a>(
+    message: &'a Message,
+    parent: Option<usize>,
+    messages: &mut Vec<(&'a Message, Option<usize>)>,
+) {
+    let position = messages.len();
+    messages.push((message, parent));
+    if let Message::Transaction(t) = message {
+        for child in &t.children {
+            flatten(child, Some(position), messages);
+        }
+    }
+}
+
+/// Writes `n` as a zigzag varint, Avro's encoding of ints and longs.
+fn write_long(out: &mut Vec<u8>, n: i64) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(out, bytes.len() as i64);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::Arc;
+
+    use flate2::read::DeflateDecoder;
+
+    use super::*;
+    use crate::message_tree::{InnerEvent, InnerTransaction};
+
+    #[test]
+    fn longs() {
+        // The examples of the Avro specification.
+        for (n, bytes) in [
+            (0, &[0x00][..]),
+            (-1, &[0x01]),
+            (1, &[0x02]),
+            (-2, &[0x03]),
+            (2, &[0x04]),
+            (-64, &[0x7f]),
+            (64, &[0x80, 0x01]),
+        ] {
+            let mut out = vec![];
+            write_long(&mut out, n);
+            assert_eq!(out, bytes, "{}", n);
+        }
+    }
+
+    /// Reads what `write_long` and `write_bytes` wrote.
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn long(&mut self) -> i64 {
+            let (mut n, mut shift) = (0u64, 0);
+            loop {
+                let b = self.0[0];
+                self.0 = &self.0[1..];
+                n |= u64::from(b & 0x7f) << shift;
+                shift += 7;
+                if b < 0x80 {
+                    return (n >> 1) as i64 ^ -((n & 1) as i64);
+                }
+            }
+        }
+
+        fn bytes(&mut self) -> &[u8] {
+            let len = self.long() as usize;
+            let (bytes, rest) = self.0.split_at(len);
+            self.0 = rest;
+            bytes
+        }
+
+        fn string(&mut self) -> String {
+            String::from_utf8(self.bytes().to_vec()).unwrap()
+        }
+    }
+
+    fn tree() -> MessageTree {
+        let event = Message::Event(Arc::new(InnerEvent {
+            ty: "Error".to_string(),
+            name: "java.lang.Exception".to_string(),
+            timestamp_in_ms: 1_715_400_000_100,
+            status: "ERROR".to_string(),
+            data: "stack".to_string(),
+            ..Default::default()
+        }));
+        MessageTree {
+            domain: "shop-web".to_string(),
+            message_id: "shop-web-0a000001-475000-1".to_string(),
+            message: Message::Transaction(Arc::new(InnerTransaction {
+                ty: "URL".to_string(),
+                name: "/api/item/1".to_string(),
+                timestamp_in_ms: 1_715_400_000_000,
+                status: "0".to_string(),
+                data: "a=1".to_string(),
+                duration_in_ms: 140,
+                children: vec![event],
+                summary: None,
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn container_file() {
+        serde_json::from_str::<serde_json::Value>(SCHEMA).unwrap();
+
+        let mut writer = ContainerWriter::new(vec![]).unwrap();
+        writer.write(&tree()).unwrap();
+        writer.write(&tree()).unwrap();
+        let file = writer.finish().unwrap();
+
+        assert_eq!(&file[..4], MAGIC);
+        let mut reader = Reader(&file[4..]);
+        assert_eq!(reader.long(), 2);
+        assert_eq!(reader.string(), "avro.schema");
+        assert_eq!(reader.string(), SCHEMA);
+        assert_eq!(reader.string(), "avro.codec");
+        assert_eq!(reader.string(), "deflate");
+        assert_eq!(reader.long(), 0);
+        let (sync, rest) = reader.0.split_at(16);
+        let mut reader = Reader(rest);
+
+        assert_eq!(reader.long(), 2);
+        let mut block = vec![];
+        DeflateDecoder::new(reader.bytes())
+            .read_to_end(&mut block)
+            .unwrap();
+        assert_eq!(reader.0, sync);
+
+        let mut records = Reader(&block);
+        for _ in 0..2 {
+            let header: Vec<_> = (0..10).map(|_| records.string()).collect();
+            assert_eq!(header[0], "shop-web");
+            assert_eq!(header[3], "shop-web-0a000001-475000-1");
+            assert_eq!(records.long(), 2);
+            // The transaction: no parent, TRANSACTION, then its fields.
+            assert_eq!(records.long(), 0);
+            assert_eq!(records.long(), 1);
+            let fields: Vec<_> = (0..3).map(|_| records.string()).collect();
+            assert_eq!(fields, ["0", "URL", "/api/item/1"]);
+            assert_eq!(records.long(), 1_715_400_000_000);
+            assert_eq!(records.string(), "a=1");
+            assert_eq!((records.long(), records.long()), (1, 140));
+            // The event, child of the transaction at 0.
+            assert_eq!((records.long(), records.long()), (1, 0));
+            assert_eq!(records.long(), 0);
+            let fields: Vec<_> = (0..3).map(|_| records.string()).collect();
+            assert_eq!(fields, ["ERROR", "Error", "java.lang.Exception"]);
+            assert_eq!(records.long(), 1_715_400_000_100);
+            assert_eq!(records.string(), "stack");
+            assert_eq!(records.long(), 0);
+            // The end of the array.
+            assert_eq!(records.long(), 0);
+        }
+        assert!(records.0.is_empty());
+    }
+}
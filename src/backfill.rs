@@ -0,0 +1,172 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::{bail, Fallible};
+use log::{debug, warn};
+
+use crate::logview_writer::LogviewWriter;
+use crate::message_id::ParsedMessageId;
+use crate::message_tree::MessageTree;
+use crate::sink::Sink;
+
+/// Bytes of an index entry: the offset of the block in the data file and
+/// of the tree in the decompressed block.
+const INDEX_ENTRY_LEN: u64 = 6;
+
+/// Blocks are cut before the offset of a tree overflows its index entry.
+const BLOCK_SIZE: usize = u16::MAX as usize;
+
+struct Bucket {
+    data: LogviewWriter<BufWriter<File>>,
+    index: File,
+}
+
+impl Bucket {
+    /// Opens the data and index files of the bucket at `path`, appending to
+    /// them when they exist.
+    fn open(path: &Path) -> Fallible<Self> {
+        fs::create_dir_all(path.parent().expect("bucket in a directory"))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(with_extension(path, "dat"))?;
+        let len = file.metadata()?.len();
+        let out = BufWriter::new(file);
+        let data = match len {
+            0 => LogviewWriter::new(out)?,
+            len => LogviewWriter::append(out, len),
+        }
+        .block_size(BLOCK_SIZE);
+        let index = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(with_extension(path, "idx"))?;
+        Ok(Bucket { data, index })
+    }
+
+    fn write(&mut self, id: &ParsedMessageId, tree: &MessageTree) -> Fallible<()> {
+        let (block, offset) = self.data.write(tree)?;
+        if block > u64::from(u32::MAX) {
+            bail!("backfill: data file of {} over 4GB", tree.message_id);
+        }
+        self.index
+            .seek(SeekFrom::Start(id.index * INDEX_ENTRY_LEN))?;
+        self.index.write_u32::<BigEndian>(block as u32)?;
+        self.index.write_u16::<BigEndian>(offset as u16)?;
+        Ok(())
+    }
+}
+
+/// `path` with `extension` added, as the ip in the name of buckets has dots.
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+/// Re-encodes trees into the buckets the CAT console reads logviews from,
+/// e.g. to put repaired or filtered trees back:
+/// `{dir}/{yyyyMMdd}/{HH}/{domain}-{ip}.dat` and `.idx`, by the domain, ip
+/// and hour of the message id, the hour being in the local time zone like
+/// the CAT server.
+///
+/// The data file is a logview of blocks of at most 64KB. The index has an
+/// entry of 6 bytes for every index of a message id of the hour, at
+/// `index * 6`: the offset of the block of the tree in the data file as a
+/// big endian u32, then the offset of the tree in the decompressed block
+/// as a u16. Existing buckets are appended to, the trees written last
+/// winning in the index.
+pub struct Backfill {
+    dir: PathBuf,
+    buckets: Mutex<HashMap<PathBuf, Bucket>>,
+    written: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl Backfill {
+    pub fn new(dir: impl Into<PathBuf>) -> Arc<Self> {
+        Arc::new(Backfill {
+            dir: dir.into(),
+            buckets: Mutex::new(HashMap::new()),
+            written: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        })
+    }
+
+    /// Path of the bucket of `id`, without extension.
+    fn bucket_path(&self, id: &ParsedMessageId) -> Fallible<PathBuf> {
+        let hour = time::at(time::Timespec::new(
+            (id.hour_timestamp_in_ms() / 1000) as i64,
+            0,
+        ));
+        Ok(self
+            .dir
+            .join(hour.strftime("%Y%m%d")?.to_string())
+            .join(hour.strftime("%H")?.to_string())
+            .join(format!("{}-{}", id.domain, id.ip())))
+    }
+
+    fn write(&self, tree: &MessageTree) -> Fallible<()> {
+        let id = match ParsedMessageId::parse(&tree.message_id) {
+            Some(id) => id,
+            None => {
+                debug!("backfill: skip tree with message id {:?}", tree.message_id);
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        };
+        let path = self.bucket_path(&id)?;
+        let mut buckets = self.buckets.lock().expect("backfill buckets poisoned");
+        let bucket = match buckets.entry(path) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let bucket = Bucket::open(e.key())?;
+                e.insert(bucket)
+            }
+        };
+        bucket.write(&id, tree)?;
+        self.written.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes the last blocks of the buckets and prints how many trees went
+    /// to how many of them.
+    pub fn finish(&self, out: &mut dyn Write) -> Fallible<()> {
+        let buckets = mem::take(&mut *self.buckets.lock().expect("backfill buckets poisoned"));
+        let count = buckets.len();
+        for (_, bucket) in buckets {
+            bucket.data.finish()?;
+            bucket.index.sync_all()?;
+        }
+        let skipped = self.skipped.load(Ordering::Relaxed);
+        if skipped > 0 {
+            warn!(
+                "backfill: skipped {} trees without a valid message id",
+                skipped
+            );
+        }
+        writeln!(
+            out,
+            "backfill: wrote {} trees to {} buckets under {}",
+            self.written.load(Ordering::Relaxed),
+            count,
+            self.dir.display()
+        )?;
+        Ok(())
+    }
+}
+
+impl Sink for Arc<Backfill> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.write(tree)
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam::{Receiver, RecvTimeoutError, Sender};
+use failure::{bail, format_err, Fallible};
+use log::{error, warn};
+
+use crate::logging;
+
+/// Batches waiting to be posted with `Retries::Queued`, the oldest is
+/// dropped beyond this.
+const MAX_QUEUED_BATCHES: usize = 100;
+
+/// Delay before retrying a failed post, doubled after every failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Posts still failing this many times in a row once the input is done are
+/// given up with `Retries::Queued`.
+const MAX_ATTEMPTS_ON_EXIT: u32 = 5;
+
+/// A service the trees are posted to by batches, e.g. a webhook.
+pub trait Post: Send + 'static {
+    /// A tree, as sent to the service.
+    type Item: Send + 'static;
+
+    /// Names the service in logs.
+    const NAME: &'static str;
+
+    /// Whether `batch` is to be posted without waiting for more trees.
+    fn is_full(&self, batch: &[Self::Item]) -> bool;
+
+    /// Posts `batch`, logging what was posted. Trees to post again are
+    /// given back.
+    fn post(&mut self, batch: Vec<Self::Item>) -> Result<(), Failed<Self::Item>>;
+}
+
+/// Trees of a batch to post again after a backoff.
+pub struct Failed<T> {
+    pub items: Vec<T>,
+    /// Why the post failed, or `None` when the service only asked to slow
+    /// down, which doesn't count as a failed attempt.
+    pub error: Option<failure::Error>,
+}
+
+impl<T> Failed<T> {
+    pub fn new(items: Vec<T>, error: impl Into<failure::Error>) -> Self {
+        Failed {
+            items,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// What is done about failed posts.
+#[derive(Debug, Clone, Copy)]
+pub enum Retries {
+    /// Failed batches are retried in order while new trees keep being
+    /// batched, up to `MAX_QUEUED_BATCHES`, and given up once the input is
+    /// done and they failed `MAX_ATTEMPTS_ON_EXIT` more times. Suits
+    /// following a file, where trees keep coming.
+    Queued,
+    /// A failed batch is retried this many times before the export fails,
+    /// as a backfill shouldn't silently lose trees. Until then, filter
+    /// threads wait for it to go through.
+    Blocking(u32),
+}
+
+/// Posts trees by batches from a background thread, retrying failed posts
+/// with exponential backoff.
+///
+/// A batch is posted once the `Post` finds it full or, with an `interval`,
+/// `interval` after its first tree.
+pub struct BatchPoster<T> {
+    sender: Sender<T>,
+    handle: JoinHandle<Fallible<()>>,
+    name: &'static str,
+}
+
+impl<T: Send + 'static> BatchPoster<T> {
+    /// Posts with `post` from a thread named `thread_name`, queuing up to
+    /// `capacity` trees.
+    pub fn start<P: Post<Item = T>>(
+        thread_name: &str,
+        post: P,
+        capacity: usize,
+        interval: Option<Duration>,
+        retries: Retries,
+    ) -> Fallible<Self> {
+        let (sender, receiver) = crossbeam::bounded(capacity.max(1));
+        let worker = Worker {
+            post,
+            interval,
+            retries,
+            queue: VecDeque::new(),
+            backoff: MIN_BACKOFF,
+            retry_at: None,
+        };
+        let handle = thread::Builder::new()
+            .name(thread_name.to_string())
+            // Logged as filter threads only see the channel closed.
+            .spawn(move || worker.run(receiver).inspect_err(|e| error!("{}", e)))?;
+        Ok(BatchPoster {
+            sender,
+            handle,
+            name: P::NAME,
+        })
+    }
+
+    pub fn send(&self, item: T) -> Fallible<()> {
+        self.sender
+            .send(item)
+            .map_err(|_| format_err!("{}: export stopped after a post failed", self.name))
+    }
+
+    /// Posts the remaining trees.
+    pub fn finish(self) -> Fallible<()> {
+        let name = self.name;
+        drop(self.sender);
+        self.handle
+            .join()
+            .unwrap_or_else(|_| panic!("{} thread panicked", name))
+    }
+}
+
+struct Worker<P: Post> {
+    post: P,
+    interval: Option<Duration>,
+    retries: Retries,
+    queue: VecDeque<Vec<P::Item>>,
+    backoff: Duration,
+    /// Set after a failure with `Retries::Queued`, until the next attempt.
+    retry_at: Option<Instant>,
+}
+
+impl<P: Post> Worker<P> {
+    fn run(mut self, receiver: Receiver<P::Item>) -> Fallible<()> {
+        let mut batch = vec![];
+        let mut flush_at: Option<Instant> = None;
+        loop {
+            let deadline = match (flush_at, self.retry_at) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let received = match deadline {
+                Some(d) => receiver.recv_timeout(d.saturating_duration_since(Instant::now())),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(item) => {
+                    if batch.is_empty() {
+                        flush_at = self.interval.map(|i| Instant::now() + i);
+                    }
+                    batch.push(item);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if self.post.is_full(&batch) || flush_at.is_some_and(|t| t <= Instant::now()) {
+                self.enqueue(std::mem::take(&mut batch));
+                flush_at = None;
+            }
+            if self.retry_at.is_none_or(|t| t <= Instant::now()) {
+                self.post_queued()?;
+            }
+        }
+
+        if !batch.is_empty() {
+            self.enqueue(batch);
+        }
+        if let Retries::Blocking(_) = self.retries {
+            return self.post_queued();
+        }
+        for _ in 0..MAX_ATTEMPTS_ON_EXIT {
+            if let Some(retry_at) = self.retry_at {
+                thread::sleep(retry_at.saturating_duration_since(Instant::now()));
+            }
+            self.post_queued()?;
+            if self.queue.is_empty() {
+                return Ok(());
+            }
+        }
+        let dropped: usize = self.queue.iter().map(Vec::len).sum();
+        warn!("{}: gave up posting {} trees", P::NAME, dropped);
+        Ok(())
+    }
+
+    fn enqueue(&mut self, batch: Vec<P::Item>) {
+        if self.queue.len() >= MAX_QUEUED_BATCHES {
+            let dropped = self.queue.pop_front().map_or(0, |b| b.len());
+            warn!("{}: retry queue full, dropped {} trees", P::NAME, dropped);
+        }
+        self.queue.push_back(batch);
+    }
+
+    /// Posts the queued batches in order. With `Retries::Queued`, stops at
+    /// the first failure, to retry later.
+    fn post_queued(&mut self) -> Fallible<()> {
+        let mut failures = 0;
+        while let Some(batch) = self.queue.pop_front() {
+            let Failed { items, error } = match self.post.post(batch) {
+                Ok(()) => {
+                    self.backoff = MIN_BACKOFF;
+                    self.retry_at = None;
+                    failures = 0;
+                    continue;
+                }
+                Err(failed) => failed,
+            };
+            match (self.retries, error) {
+                (Retries::Blocking(retries), Some(e)) if failures >= retries => bail!(
+                    "{}: gave up posting {} trees after {} attempts: {}",
+                    P::NAME,
+                    items.len(),
+                    failures + 1,
+                    e
+                ),
+                (_, Some(e)) => {
+                    warn!(
+                        error_kind = logging::error_kind(&e);
+                        "{}: {}, retrying in {:?}", P::NAME, e, self.backoff
+                    );
+                    failures += 1;
+                }
+                (_, None) => warn!(
+                    "{}: retrying {} trees in {:?}",
+                    P::NAME,
+                    items.len(),
+                    self.backoff
+                ),
+            }
+            self.queue.push_front(items);
+            let backoff = self.backoff;
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            match self.retries {
+                Retries::Blocking(_) => thread::sleep(backoff),
+                Retries::Queued => {
+                    self.retry_at = Some(Instant::now() + backoff);
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure::Fallible;
+
+use crate::human;
+
+/// Time over which the waits on a buffer are added up before resizing it.
+const WINDOW: Duration = Duration::from_millis(100);
+
+/// Less waiting in a window than this is noise.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+/// How many times its initial size a buffer grows at most.
+const MAX_GROWTH: usize = 16;
+
+/// The number of items a buffer between two stages holds, resized every
+/// window by how long both sides waited on it.
+///
+/// It grows when the producers waited for room and the consumers for
+/// items in the same window, as it's too small to even out the bursts of
+/// the stages, and shrinks back toward its initial size when only one side
+/// waited, as the other stage is the bottleneck and buffering more only
+/// holds memory.
+#[derive(Debug)]
+pub struct AdaptiveBuffer {
+    initial: usize,
+    limit: AtomicUsize,
+    peak: AtomicUsize,
+    resizes: AtomicU64,
+    /// Nanoseconds waited in the current window.
+    producer_wait: AtomicU64,
+    consumer_wait: AtomicU64,
+    /// Nanoseconds waited overall.
+    producers_waited: AtomicU64,
+    consumers_waited: AtomicU64,
+    window: Mutex<Instant>,
+}
+
+impl AdaptiveBuffer {
+    pub fn new(initial: usize) -> Self {
+        let initial = initial.max(1);
+        AdaptiveBuffer {
+            initial,
+            limit: AtomicUsize::new(initial),
+            peak: AtomicUsize::new(initial),
+            resizes: AtomicU64::new(0),
+            producer_wait: AtomicU64::new(0),
+            consumer_wait: AtomicU64::new(0),
+            producers_waited: AtomicU64::new(0),
+            consumers_waited: AtomicU64::new(0),
+            window: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// The number of items producers fill the buffer up to.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Records a producer waiting `wait` for room.
+    pub fn producer_waited(&self, wait: Duration) {
+        let nanos = wait.as_nanos() as u64;
+        self.producer_wait.fetch_add(nanos, Ordering::Relaxed);
+        self.producers_waited.fetch_add(nanos, Ordering::Relaxed);
+        self.resize();
+    }
+
+    /// Records a consumer waiting `wait` for an item.
+    pub fn consumer_waited(&self, wait: Duration) {
+        let nanos = wait.as_nanos() as u64;
+        self.consumer_wait.fetch_add(nanos, Ordering::Relaxed);
+        self.consumers_waited.fetch_add(nanos, Ordering::Relaxed);
+        self.resize();
+    }
+
+    /// Resizes the buffer by the waits of the window once it's over.
+    fn resize(&self) {
+        let mut window = match self.window.try_lock() {
+            Ok(window) if window.elapsed() >= WINDOW => window,
+            _ => return,
+        };
+        *window = Instant::now();
+        let min_wait = MIN_WAIT.as_nanos() as u64;
+        let producers = self.producer_wait.swap(0, Ordering::Relaxed) >= min_wait;
+        let consumers = self.consumer_wait.swap(0, Ordering::Relaxed) >= min_wait;
+        let limit = self.limit();
+        let resized = match (producers, consumers) {
+            (true, true) => (limit * 2).min(self.initial * MAX_GROWTH),
+            (true, false) | (false, true) => (limit - limit / 4).max(self.initial),
+            (false, false) => limit,
+        };
+        if resized != limit {
+            self.limit.store(resized, Ordering::Relaxed);
+            self.peak.fetch_max(resized, Ordering::Relaxed);
+            self.resizes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn render(&self, name: &str, out: &mut dyn Write) -> Fallible<()> {
+        let waited = |nanos: &AtomicU64| {
+            human::duration_ms((nanos.load(Ordering::Relaxed) / 1_000_000) as f64)
+        };
+        writeln!(
+            out,
+            "{} buffer: {} now, from {} up to {}, {} resizes; producers waited {}, consumers waited {}",
+            name,
+            self.limit(),
+            self.initial,
+            self.peak.load(Ordering::Relaxed),
+            human::count(self.resizes.load(Ordering::Relaxed)),
+            waited(&self.producers_waited),
+            waited(&self.consumers_waited)
+        )?;
+        Ok(())
+    }
+}
+
+/// The buffers of the decoding pipeline: of the blocks read for the
+/// decoders, and of the trees decoded for the filters.
+#[derive(Debug)]
+pub struct StageBuffers {
+    pub blocks: AdaptiveBuffer,
+    pub trees: AdaptiveBuffer,
+}
+
+impl StageBuffers {
+    pub fn new(blocks: usize, trees: usize) -> Arc<Self> {
+        Arc::new(StageBuffers {
+            blocks: AdaptiveBuffer::new(blocks),
+            trees: AdaptiveBuffer::new(trees),
+        })
+    }
+
+    /// Prints the sizes the buffers were given and the waits on them, for
+    /// `--timing`.
+    pub fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        self.blocks.render("blocks", out)?;
+        self.trees.render("trees", out)
+    }
+}
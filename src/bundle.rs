@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use failure::{bail, format_err, Fallible};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::message_tree_dumper::MessageTreeDumper;
+use crate::sidecar;
+
+const MANIFEST: &str = "manifest.json";
+const LOGVIEWS: &str = "logviews/";
+const INDEXES: &str = "indexes/";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Name of the logview in the bundle.
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub trees: u64,
+    pub first_timestamp_in_ms: Option<u64>,
+    pub last_timestamp_in_ms: Option<u64>,
+    pub domains: BTreeSet<String>,
+    /// Name of the sidecar of the logview in the bundle, if any.
+    pub index: Option<String>,
+}
+
+/// Describes the content of a bundle, stored as its last member.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub first_timestamp_in_ms: Option<u64>,
+    pub last_timestamp_in_ms: Option<u64>,
+    pub domains: BTreeSet<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+/// A logview stored in a bundle, as a byte range of the bundle.
+pub struct Member {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+pub fn sha256(path: &Path) -> Fallible<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Whether `path` is a bundle rather than a logview, which starts with -1.
+pub fn is_bundle(path: &Path) -> bool {
+    let mut header = [0; 512];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut header)) {
+        Ok(()) => &header[257..262] == b"ustar",
+        Err(_) => false,
+    }
+}
+
+/// Writes `files`, their fresh sidecars and a manifest into one tar file at
+/// `out`. `dumper_for` decodes each file to fill the manifest.
+pub fn pack(
+    files: &[PathBuf],
+    out: &Path,
+    dumper_for: impl Fn(PathBuf) -> Fallible<MessageTreeDumper>,
+) -> Fallible<Manifest> {
+    let mut builder = tar::Builder::new(File::create(out)?);
+    let mut manifest = Manifest::default();
+    for path in files {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format_err!("invalid file name {}", path.display()))?
+            .to_string();
+        if manifest.files.iter().any(|f| f.name == name) {
+            bail!("two files named {}", name);
+        }
+
+        let mut file = ManifestFile {
+            name: name.clone(),
+            size: fs::metadata(path)?.len(),
+            sha256: sha256(path)?,
+            trees: 0,
+            first_timestamp_in_ms: None,
+            last_timestamp_in_ms: None,
+            domains: BTreeSet::new(),
+            index: None,
+        };
+        for tree in dumper_for(path.clone())?.into_iter() {
+            let ts = tree.message.timestamp_in_ms();
+            file.trees += 1;
+            file.first_timestamp_in_ms = Some(file.first_timestamp_in_ms.map_or(ts, |t| t.min(ts)));
+            file.last_timestamp_in_ms = Some(file.last_timestamp_in_ms.map_or(ts, |t| t.max(ts)));
+            if !file.domains.contains(&tree.domain) {
+                file.domains.insert(tree.domain);
+            }
+        }
+        builder.append_path_with_name(path, format!("{}{}", LOGVIEWS, name))?;
+
+        if sidecar::Sidecar::is_fresh(path)? {
+            let index = format!("{}{}.cols", INDEXES, name);
+            builder.append_path_with_name(sidecar::sidecar_path(path), &index)?;
+            file.index = Some(index);
+        }
+
+        manifest.first_timestamp_in_ms = min_max(
+            manifest.first_timestamp_in_ms,
+            file.first_timestamp_in_ms,
+            u64::min,
+        );
+        manifest.last_timestamp_in_ms = min_max(
+            manifest.last_timestamp_in_ms,
+            file.last_timestamp_in_ms,
+            u64::max,
+        );
+        manifest.domains.extend(file.domains.iter().cloned());
+        manifest.files.push(file);
+    }
+
+    let json = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST, json.as_slice())?;
+    builder.into_inner()?;
+    Ok(manifest)
+}
+
+fn min_max(a: Option<u64>, b: Option<u64>, f: fn(u64, u64) -> u64) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(f(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The logviews of the bundle at `path`, in the order they were packed.
+pub fn members(path: &Path) -> Fallible<Vec<Member>> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut members = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if let Some(name) = name.strip_prefix(LOGVIEWS) {
+            members.push(Member {
+                name: name.to_string(),
+                offset: entry.raw_file_position(),
+                size: entry.size(),
+            });
+        }
+    }
+    Ok(members)
+}
+
+pub fn read_manifest(path: &Path) -> Fallible<Manifest> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()?.as_os_str() == MANIFEST {
+            return Ok(serde_json::from_reader(entry)?);
+        }
+    }
+    bail!("no manifest in {}", path.display())
+}
+
+/// `name` under `dir`, refusing names that could escape it: bundles are
+/// handed between teams and their member names cannot be trusted.
+fn member_path(dir: &Path, name: &str) -> Fallible<PathBuf> {
+    let relative = Path::new(name);
+    let plain = relative.components().next().is_some()
+        && relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if !plain {
+        bail!("refusing to unpack {:?} outside of {}", name, dir.display());
+    }
+    Ok(dir.join(relative))
+}
+
+/// Extracts the bundle at `path` into `dir`, with the sidecars next to
+/// their logviews, and checks the logviews against the manifest.
+pub fn unpack(path: &Path, dir: &Path) -> Fallible<Manifest> {
+    let manifest = read_manifest(path)?;
+    fs::create_dir_all(dir)?;
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let target = match (name.strip_prefix(LOGVIEWS), name.strip_prefix(INDEXES)) {
+            (Some(name), _) | (_, Some(name)) => member_path(dir, name)?,
+            _ => continue,
+        };
+        entry.unpack(&target)?;
+    }
+
+    for file in &manifest.files {
+        let logview = member_path(dir, &file.name)?;
+        if sha256(&logview)? != file.sha256 {
+            bail!("checksum mismatch for {}", file.name);
+        }
+        // The content is the one the sidecar was built from, only the
+        // modification time changed.
+        if file.index.is_some() {
+            sidecar::Sidecar::restamp(&logview)?;
+        }
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn member_paths_stay_in_dir() {
+        let dir = Path::new("/tmp/unpacked");
+        assert_eq!(
+            member_path(dir, "a/b.dat").unwrap(),
+            dir.join("a").join("b.dat")
+        );
+        for name in &["../x", "a/../../x", "/etc/passwd", "./a", ""] {
+            assert!(member_path(dir, name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::{bail, format_err, Fallible};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::alert;
+use crate::message_tree::{MessageTree, Text};
+use crate::message_tree_dumper::MessageTreeDumper;
+use crate::prune;
+use crate::sidecar::source_version;
+
+/// Name of the catalog in the directory it describes.
+pub const CATALOG_FILE: &str = ".dump-cat-catalog.json";
+
+/// Formats `--since` and `--until` take, in UTC, besides durations ago.
+const TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d",
+];
+
+/// What a logview of the directory holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Relative to the directory.
+    pub path: PathBuf,
+    /// Size and modification time of the file when it was read, to tell
+    /// whether the entry is stale.
+    pub size: u64,
+    pub modified: u128,
+    pub trees: u64,
+    /// Earliest and latest timestamps of the root messages.
+    pub first_in_ms: u64,
+    pub last_in_ms: u64,
+    pub domains: BTreeSet<Text>,
+}
+
+/// The logviews of an archive directory with their time ranges and
+/// domains, so queries only read the files that can hold the trees they
+/// ask for.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Catalog {
+    pub built_at_in_ms: u64,
+    pub files: Vec<Entry>,
+}
+
+impl Catalog {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(CATALOG_FILE)
+    }
+
+    /// Reads every logview under `dir`, with the dumpers of `dumper_for`,
+    /// and writes the catalog of the directory. Files unchanged since the
+    /// previous catalog aren't read again.
+    pub fn build(
+        dir: &Path,
+        dumper_for: impl Fn(PathBuf) -> Fallible<MessageTreeDumper>,
+    ) -> Fallible<PathBuf> {
+        let mut previous: HashMap<_, _> = match Catalog::load(dir) {
+            Ok(catalog) => catalog
+                .files
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect(),
+            Err(_) => HashMap::new(),
+        };
+        let mut catalog = Catalog {
+            built_at_in_ms: now_in_ms(),
+            files: vec![],
+        };
+        for path in prune::logviews(dir)? {
+            let relative = path.strip_prefix(dir)?.to_path_buf();
+            let (size, modified) = source_version(&path)?;
+            if let Some(entry) = previous.remove(&relative) {
+                if (entry.size, entry.modified) == (size, modified) {
+                    catalog.files.push(entry);
+                    continue;
+                }
+            }
+            info!("cataloging {}", path.display());
+            let mut entry = Entry {
+                path: relative,
+                size,
+                modified,
+                trees: 0,
+                first_in_ms: u64::MAX,
+                last_in_ms: 0,
+                domains: BTreeSet::new(),
+            };
+            for tree in dumper_for(path)?.read_trees() {
+                let timestamp = tree.message.timestamp_in_ms();
+                entry.trees += 1;
+                entry.first_in_ms = entry.first_in_ms.min(timestamp);
+                entry.last_in_ms = entry.last_in_ms.max(timestamp);
+                entry.domains.insert(tree.domain);
+            }
+            catalog.files.push(entry);
+        }
+
+        let out = Catalog::path(dir);
+        serde_json::to_writer_pretty(BufWriter::new(File::create(&out)?), &catalog)?;
+        Ok(out)
+    }
+
+    pub fn load(dir: &Path) -> Fallible<Self> {
+        let path = Catalog::path(dir);
+        let file = File::open(&path).map_err(|e| {
+            format_err!(
+                "read {}: {}, build it with dump-cat catalog build {}",
+                path.display(),
+                e,
+                dir.display()
+            )
+        })?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// The files of `dir` which can hold trees of `route`, in catalog
+    /// order. Files changed since the catalog was built are read anyway.
+    pub fn select(&self, dir: &Path, route: &Route) -> Vec<PathBuf> {
+        let mut selected = vec![];
+        for entry in &self.files {
+            let path = dir.join(&entry.path);
+            match source_version(&path) {
+                Ok(version) if version == (entry.size, entry.modified) => {
+                    if !route.overlaps(entry) {
+                        continue;
+                    }
+                }
+                Ok(_) => warn!(
+                    "{} changed since the catalog was built, reading it anyway",
+                    path.display()
+                ),
+                Err(_) => {
+                    warn!("{} is gone, skipping it", path.display());
+                    continue;
+                }
+            }
+            selected.push(path);
+        }
+        info!(
+            "catalog of {}: reading {} of {} files",
+            dir.display(),
+            selected.len(),
+            self.files.len()
+        );
+        selected
+    }
+}
+
+/// The domains and time range of the trees asked for, by `--domain`,
+/// `--since` and `--until`.
+#[derive(Debug, Clone, Default)]
+pub struct Route {
+    /// Any domain when empty.
+    pub domains: Vec<Text>,
+    /// Root timestamps from `since_in_ms` included to `until_in_ms`
+    /// excluded.
+    pub since_in_ms: Option<u64>,
+    pub until_in_ms: Option<u64>,
+}
+
+impl Route {
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty() && self.since_in_ms.is_none() && self.until_in_ms.is_none()
+    }
+
+    pub fn matches(&self, tree: &MessageTree) -> bool {
+        let timestamp = tree.message.timestamp_in_ms();
+        (self.domains.is_empty() || self.domains.contains(&tree.domain))
+            && self.since_in_ms.is_none_or(|since| timestamp >= since)
+            && self.until_in_ms.is_none_or(|until| timestamp < until)
+    }
+
+    fn overlaps(&self, entry: &Entry) -> bool {
+        entry.trees > 0
+            && (self.domains.is_empty() || self.domains.iter().any(|d| entry.domains.contains(d)))
+            && self
+                .since_in_ms
+                .is_none_or(|since| entry.last_in_ms >= since)
+            && self
+                .until_in_ms
+                .is_none_or(|until| entry.first_in_ms < until)
+    }
+}
+
+/// Parses `--since` and `--until`: a time like `2024-05-11T04:00:00Z` in
+/// UTC, or a duration before now like `2h`.
+pub fn parse_time(s: &str) -> Fallible<u64> {
+    if let Ok(ago) = alert::parse_duration(s) {
+        return Ok(now_in_ms().saturating_sub(ago.as_millis() as u64));
+    }
+    let trimmed = s.trim_end_matches('Z');
+    for format in TIME_FORMATS {
+        if let Ok(tm) = time::strptime(trimmed, format) {
+            let spec = tm.to_timespec();
+            if spec.sec < 0 {
+                break;
+            }
+            return Ok(spec.sec as u64 * 1000);
+        }
+    }
+    bail!(
+        "invalid time {}, e.g. 2024-05-11T04:00:00Z or 2h for two hours ago",
+        s
+    )
+}
+
+fn now_in_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,868 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use failure::{bail, format_err, Fallible};
+use regex::Regex;
+
+use crate::query;
+use crate::running;
+
+/// A value of a CEL expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    String(String),
+    List(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Double(d) => write!(f, "{}", d),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::List(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Double(_) => "double",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+        }
+    }
+
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Double(b)) => (*a as f64).partial_cmp(b),
+            (Value::Double(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Double(a), Value::Double(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals(b))
+            }
+            _ => self.compare(other) == Some(Ordering::Equal),
+        }
+    }
+}
+
+/// `s` as a string literal.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Int(i64),
+    Double(f64),
+    String(String),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", "[", "]",
+    ",", ".", "?", ":",
+];
+
+fn tokenize(source: &str) -> Fallible<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, 't')) => value.push('\t'),
+                        Some((_, c)) => value.push(c),
+                        None => bail!("unclosed string at {}", start),
+                    },
+                    Some((_, q)) if q == c => break,
+                    Some((_, c)) => value.push(c),
+                    None => bail!("unclosed string at {}", start),
+                }
+            }
+            tokens.push(Token::String(value));
+        } else if let Some((len, ms)) = query::timestamp_ms(&source[start..]) {
+            while chars.peek().is_some_and(|&(i, _)| i < start + len) {
+                chars.next();
+            }
+            tokens.push(Token::Int(ms));
+        } else if c.is_ascii_digit() {
+            let mut end = start;
+            let mut double = false;
+            while let Some(&(i, c)) = chars.peek() {
+                let fraction = c == '.'
+                    && !double
+                    && source[i + 1..].starts_with(|c: char| c.is_ascii_digit());
+                if c.is_ascii_digit() || fraction {
+                    double |= fraction;
+                    end = i + 1;
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            // A unit makes a duration, in milliseconds.
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_alphabetic() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let text = &source[start..end];
+            if text.ends_with(char::is_alphabetic) {
+                match query::duration_ms(text) {
+                    Some(ms) => tokens.push(Token::Int(ms)),
+                    None => bail!(
+                        "invalid number {}, durations are in ms, s, m, h or d, e.g. 500ms",
+                        text
+                    ),
+                }
+                continue;
+            }
+            tokens.push(if double {
+                Token::Double(text.parse()?)
+            } else {
+                Token::Int(text.parse()?)
+            });
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    end = i + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Identifier(source[start..end].to_string()));
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| source[start..].starts_with(**p))
+                .ok_or_else(|| format_err!("unexpected {:?} at {}", c, start))?;
+            for _ in 0..punct.len() {
+                chars.next();
+            }
+            tokens.push(Token::Punct(punct));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    /// A variable, with the fields selected from it joined by dots, e.g.
+    /// `transaction.duration_in_ms`.
+    Variable(String),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `has(variable)`.
+    Has(String),
+    Call(String, Vec<Expr>),
+    /// `matches` with a literal pattern, compiled once.
+    Matches(Box<Expr>, Regex),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Calls of `running::FUNCTIONS` parsed so far.
+    running_calls: i64,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.peek() == Some(&Token::Punct(PUNCTUATION[index_of(punct)])) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Fallible<()> {
+        if !self.eat(punct) {
+            bail!("expected `{}` instead of {}", punct, describe(self.peek()));
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Fallible<Expr> {
+        let condition = self.or()?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.or()?;
+        self.expect(":")?;
+        let otherwise = self.expr()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn or(&mut self) -> Fallible<Expr> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Fallible<Expr> {
+        let mut left = self.relation()?;
+        while self.eat("&&") {
+            left = Expr::And(Box::new(left), Box::new(self.relation()?));
+        }
+        Ok(left)
+    }
+
+    fn relation(&mut self) -> Fallible<Expr> {
+        let mut left = self.addition()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct(op)) if ["==", "!=", "<", "<=", ">", ">="].contains(op) => *op,
+                Some(Token::Identifier(name)) if name == "in" => "in",
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.addition()?));
+        }
+    }
+
+    fn addition(&mut self) -> Fallible<Expr> {
+        let mut left = self.multiplication()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct(op)) if *op == "+" || *op == "-" => *op,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplication()?));
+        }
+    }
+
+    fn multiplication(&mut self) -> Fallible<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct(op)) if ["*", "/", "%"].contains(op) => *op,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Fallible<Expr> {
+        if self.eat("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else if self.eat("-") {
+            Ok(Expr::Negate(Box::new(self.unary()?)))
+        } else {
+            self.member()
+        }
+    }
+
+    fn member(&mut self) -> Fallible<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(".") {
+                let field = match self.next() {
+                    Some(Token::Identifier(field)) => field,
+                    other => bail!(
+                        "expected a field after . instead of {}",
+                        describe(other.as_ref())
+                    ),
+                };
+                if self.eat("(") {
+                    let mut args = vec![expr];
+                    args.extend(self.arguments()?);
+                    expr = self.call(field, args)?;
+                } else {
+                    expr = match expr {
+                        Expr::Variable(path) => Expr::Variable(format!("{}.{}", path, field)),
+                        _ => bail!("fields can only be selected from variables"),
+                    };
+                }
+            } else if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                expr = Expr::Binary("[]", Box::new(expr), Box::new(index));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// `method(name, args)`, with the calls of `running::FUNCTIONS` given
+    /// their number first: their aggregates are kept per call.
+    fn call(&mut self, name: String, args: Vec<Expr>) -> Fallible<Expr> {
+        let mut expr = method(name, args)?;
+        if let Expr::Call(name, args) = &mut expr {
+            if running::FUNCTIONS.iter().any(|(f, _)| f == name) {
+                args.insert(0, Expr::Literal(Value::Int(self.running_calls)));
+                self.running_calls += 1;
+            }
+        }
+        Ok(expr)
+    }
+
+    /// The arguments of a call, after its `(`.
+    fn arguments(&mut self) -> Fallible<Vec<Expr>> {
+        let mut args = vec![];
+        if self.eat(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(")") {
+                return Ok(args);
+            }
+            if !self.eat(",") {
+                bail!("expected `,` or `)` instead of {}", describe(self.peek()));
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Fallible<Expr> {
+        Ok(match self.next() {
+            Some(Token::Int(i)) => Expr::Literal(Value::Int(i)),
+            Some(Token::Double(d)) => Expr::Literal(Value::Double(d)),
+            Some(Token::String(s)) => Expr::Literal(Value::String(s)),
+            Some(Token::Identifier(name)) => match name.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                _ if self.eat("(") => {
+                    let args = self.arguments()?;
+                    if name == "has" {
+                        match args.as_slice() {
+                            [Expr::Variable(path)] => Expr::Has(path.clone()),
+                            _ => bail!("has() takes a field, e.g. has(transaction.duration_in_ms)"),
+                        }
+                    } else {
+                        self.call(name, args)?
+                    }
+                }
+                _ => Expr::Variable(name),
+            },
+            Some(Token::Punct("(")) => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                expr
+            }
+            Some(Token::Punct("[")) => {
+                let mut items = vec![];
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.expr()?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            bail!("expected `,` or `]` instead of {}", describe(self.peek()));
+                        }
+                    }
+                }
+                Expr::List(items)
+            }
+            other => bail!(
+                "expected an operand instead of {}",
+                describe(other.as_ref())
+            ),
+        })
+    }
+}
+
+fn index_of(punct: &str) -> usize {
+    PUNCTUATION
+        .iter()
+        .position(|p| *p == punct)
+        .expect("known punctuation")
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(Token::Identifier(name)) => format!("`{}`", name),
+        Some(Token::Int(i)) => i.to_string(),
+        Some(Token::Double(d)) => d.to_string(),
+        Some(Token::String(s)) => format!("{:?}", s),
+        Some(Token::Punct(p)) => format!("`{}`", p),
+        None => "the end of the expression".to_string(),
+    }
+}
+
+/// Functions, called as `f(x, ...)` or `x.f(...)`.
+const FUNCTIONS: &[(&str, usize)] = &[
+    ("size", 1),
+    ("startsWith", 2),
+    ("endsWith", 2),
+    ("contains", 2),
+    ("matches", 2),
+    ("int", 1),
+    ("double", 1),
+    ("string", 1),
+];
+
+fn method(name: String, args: Vec<Expr>) -> Fallible<Expr> {
+    let functions = FUNCTIONS
+        .iter()
+        .chain(query::TIMESTAMP_FUNCTIONS)
+        .chain(running::FUNCTIONS);
+    let arity = match functions.clone().find(|(f, _)| *f == name) {
+        Some(&(_, arity)) => arity,
+        None => bail!(
+            "unknown function {}, expected one of {}",
+            name,
+            functions.map(|(f, _)| *f).collect::<Vec<_>>().join(", ")
+        ),
+    };
+    if args.len() != arity {
+        bail!("{} takes {} arguments, got {}", name, arity, args.len());
+    }
+    if name == "matches" {
+        if let [_, Expr::Literal(Value::String(pattern))] = args.as_slice() {
+            let regex = Regex::new(pattern)?;
+            let target = args.into_iter().next().expect("target");
+            return Ok(Expr::Matches(Box::new(target), regex));
+        }
+    }
+    Ok(Expr::Call(name, args))
+}
+
+/// Functions provided by the caller of [`Program::eval`].
+pub type Functions<'a> = dyn Fn(&str, &[Value]) -> Option<Fallible<Value>> + 'a;
+
+/// A compiled CEL expression.
+///
+/// Supports literals, lists, the usual operators, `in`, `?:`, `has()`,
+/// the functions of `FUNCTIONS` and `query::TIMESTAMP_FUNCTIONS`. Variables are looked up by their dotted
+/// name, e.g. `transaction.duration_in_ms`. As in CEL, `&&` and `||` are
+/// false and true when either side is, even if the other fails, so
+/// `has(x) && x > 1` works for trees without `x`.
+#[derive(Debug)]
+pub struct Program {
+    expr: Expr,
+    variables: Vec<String>,
+}
+
+impl Program {
+    pub fn compile(source: &str) -> Fallible<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            running_calls: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.peek().is_some() {
+            bail!(
+                "expected an operator instead of {}",
+                describe(parser.peek())
+            );
+        }
+        let mut variables = vec![];
+        collect_variables(&expr, &mut variables);
+        Ok(Program { expr, variables })
+    }
+
+    /// Variables referenced by the expression, in order of appearance.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Evaluates the expression, calling `functions` for the functions it
+    /// provides, which return `None` for the others.
+    pub fn eval(&self, variables: &HashMap<&str, Value>, functions: &Functions) -> Fallible<Value> {
+        eval(
+            &self.expr,
+            &Env {
+                variables,
+                functions,
+            },
+        )
+    }
+}
+
+/// What an expression is evaluated against.
+struct Env<'a> {
+    variables: &'a HashMap<&'a str, Value>,
+    functions: &'a Functions<'a>,
+}
+
+fn collect_variables(expr: &Expr, variables: &mut Vec<String>) {
+    let mut add = |name: &String| {
+        if !variables.contains(name) {
+            variables.push(name.clone());
+        }
+    };
+    match expr {
+        Expr::Variable(name) | Expr::Has(name) => add(name),
+        Expr::Literal(_) => {}
+        Expr::List(items) | Expr::Call(_, items) => {
+            items.iter().for_each(|e| collect_variables(e, variables))
+        }
+        Expr::Not(e) | Expr::Negate(e) | Expr::Matches(e, _) => collect_variables(e, variables),
+        Expr::Binary(_, a, b) | Expr::And(a, b) | Expr::Or(a, b) => {
+            collect_variables(a, variables);
+            collect_variables(b, variables);
+        }
+        Expr::Conditional(a, b, c) => {
+            collect_variables(a, variables);
+            collect_variables(b, variables);
+            collect_variables(c, variables);
+        }
+    }
+}
+
+fn eval_bool(expr: &Expr, env: &Env) -> Fallible<bool> {
+    match eval(expr, env)? {
+        Value::Bool(b) => Ok(b),
+        other => bail!("expected a bool, got {} {}", other.type_name(), other),
+    }
+}
+
+fn eval(expr: &Expr, env: &Env) -> Fallible<Value> {
+    Ok(match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Variable(name) => env
+            .variables
+            .get(name.as_str())
+            .cloned()
+            .ok_or_else(|| format_err!("no such key: {}", name))?,
+        Expr::Has(name) => Value::Bool(env.variables.contains_key(name.as_str())),
+        Expr::List(items) => Value::List(
+            items
+                .iter()
+                .map(|e| eval(e, env))
+                .collect::<Fallible<_>>()?,
+        ),
+        Expr::Not(e) => Value::Bool(!eval_bool(e, env)?),
+        Expr::Negate(e) => match eval(e, env)? {
+            Value::Int(i) => Value::Int(-i),
+            Value::Double(d) => Value::Double(-d),
+            other => bail!("can't negate {} {}", other.type_name(), other),
+        },
+        // An error on one side is dropped when the other side decides.
+        Expr::And(a, b) => match (eval_bool(a, env), eval_bool(b, env)) {
+            (Ok(false), _) | (_, Ok(false)) => Value::Bool(false),
+            (Ok(true), Ok(true)) => Value::Bool(true),
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        },
+        Expr::Or(a, b) => match (eval_bool(a, env), eval_bool(b, env)) {
+            (Ok(true), _) | (_, Ok(true)) => Value::Bool(true),
+            (Ok(false), Ok(false)) => Value::Bool(false),
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        },
+        Expr::Conditional(condition, then, otherwise) => {
+            if eval_bool(condition, env)? {
+                eval(then, env)?
+            } else {
+                eval(otherwise, env)?
+            }
+        }
+        Expr::Binary(op, a, b) => binary(op, eval(a, env)?, eval(b, env)?)?,
+        Expr::Matches(target, regex) => match eval(target, env)? {
+            Value::String(s) => Value::Bool(regex.is_match(&s)),
+            other => bail!("matches needs a string, got {}", other.type_name()),
+        },
+        Expr::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|e| eval(e, env))
+                .collect::<Fallible<Vec<_>>>()?;
+            match (env.functions)(name, &args) {
+                Some(result) => result?,
+                None => call(name, args)?,
+            }
+        }
+    })
+}
+
+fn binary(op: &str, a: Value, b: Value) -> Fallible<Value> {
+    let mismatch = |a: &Value, b: &Value| {
+        format_err!(
+            "no overload of {} for {} and {}",
+            op,
+            a.type_name(),
+            b.type_name()
+        )
+    };
+    Ok(match op {
+        "==" => Value::Bool(a.equals(&b)),
+        "!=" => Value::Bool(!a.equals(&b)),
+        "<" | "<=" | ">" | ">=" => {
+            let ordering = a.compare(&b).ok_or_else(|| mismatch(&a, &b))?;
+            Value::Bool(match op {
+                "<" => ordering == Ordering::Less,
+                "<=" => ordering != Ordering::Greater,
+                ">" => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            })
+        }
+        "in" => match &b {
+            Value::List(items) => Value::Bool(items.iter().any(|item| item.equals(&a))),
+            _ => return Err(mismatch(&a, &b)),
+        },
+        "[]" => match (&a, &b) {
+            (Value::List(items), Value::Int(i)) => items
+                .get(*i as usize)
+                .cloned()
+                .ok_or_else(|| format_err!("index {} out of range", i))?,
+            _ => return Err(mismatch(&a, &b)),
+        },
+        _ => match (a, b) {
+            (Value::Int(a), Value::Int(b)) => Value::Int(
+                match op {
+                    "+" => a.checked_add(b),
+                    "-" => a.checked_sub(b),
+                    "*" => a.checked_mul(b),
+                    "/" => a.checked_div(b),
+                    _ => a.checked_rem(b),
+                }
+                .ok_or_else(|| format_err!("{} {} {} overflows or divides by zero", a, op, b))?,
+            ),
+            (Value::Double(a), Value::Double(b)) => Value::Double(match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            }),
+            (Value::String(a), Value::String(b)) if op == "+" => Value::String(a + &b),
+            (Value::List(mut a), Value::List(b)) if op == "+" => {
+                a.extend(b);
+                Value::List(a)
+            }
+            (a, b) => return Err(mismatch(&a, &b)),
+        },
+    })
+}
+
+fn call(name: &str, args: Vec<Value>) -> Fallible<Value> {
+    if query::TIMESTAMP_FUNCTIONS.iter().any(|(f, _)| *f == name) {
+        let ints = args
+            .iter()
+            .map(|arg| match arg {
+                Value::Int(i) => Ok(*i),
+                other => Err(format_err!(
+                    "{} takes ints, got {} {}",
+                    name,
+                    other.type_name(),
+                    other
+                )),
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+        return Ok(Value::Int(query::call_timestamp_function(name, &ints)?));
+    }
+    Ok(match (name, args.as_slice()) {
+        ("size", [Value::String(s)]) => Value::Int(s.chars().count() as i64),
+        ("size", [Value::List(items)]) => Value::Int(items.len() as i64),
+        ("startsWith", [Value::String(s), Value::String(p)]) => {
+            Value::Bool(s.starts_with(p.as_str()))
+        }
+        ("endsWith", [Value::String(s), Value::String(p)]) => Value::Bool(s.ends_with(p.as_str())),
+        ("contains", [Value::String(s), Value::String(p)]) => Value::Bool(s.contains(p.as_str())),
+        ("matches", [Value::String(s), Value::String(p)]) => {
+            Value::Bool(Regex::new(p)?.is_match(s))
+        }
+        ("int", [Value::Int(i)]) => Value::Int(*i),
+        ("int", [Value::Double(d)]) => Value::Int(*d as i64),
+        ("int", [Value::String(s)]) => Value::Int(s.parse()?),
+        ("double", [Value::Int(i)]) => Value::Double(*i as f64),
+        ("double", [Value::Double(d)]) => Value::Double(*d),
+        ("double", [Value::String(s)]) => Value::Double(s.parse()?),
+        ("string", [Value::String(s)]) => Value::String(s.clone()),
+        ("string", [value]) => Value::String(value.to_string()),
+        _ => {
+            let types: Vec<_> = args.iter().map(Value::type_name).collect();
+            bail!("no overload of {} for {}", name, types.join(", "))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> Fallible<Value> {
+        let variables: HashMap<&str, Value> = vec![
+            ("name", Value::from("/api/item/1")),
+            ("status", Value::from("0")),
+            ("transaction.duration_in_ms", Value::Int(140)),
+        ]
+        .into_iter()
+        .collect();
+        Program::compile(source)?.eval(&variables, &|_, _| None)
+    }
+
+    fn eval_ok(source: &str) -> Value {
+        eval(source).unwrap_or_else(|e| panic!("{}: {}", source, e))
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize(r#"a.b >= 1.5 && 'x\'y' != "z""#).unwrap(),
+            [
+                Token::Identifier("a".to_string()),
+                Token::Punct("."),
+                Token::Identifier("b".to_string()),
+                Token::Punct(">="),
+                Token::Double(1.5),
+                Token::Punct("&&"),
+                Token::String("x'y".to_string()),
+                Token::Punct("!="),
+                Token::String("z".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("2s 2024-05-11T04:00:00Z 7").unwrap(),
+            [
+                Token::Int(2000),
+                Token::Int(1_715_400_000_000),
+                Token::Int(7)
+            ]
+        );
+        assert!(tokenize("\"open").is_err());
+        assert!(tokenize("2parsecs").is_err());
+        assert!(tokenize("a # b").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval_ok("1 + 2 * 3 - 4 % 3"), Value::Int(6));
+        assert_eq!(eval_ok("(1 + 2) * 3"), Value::Int(9));
+        assert_eq!(eval_ok("-2 * 3 < 0 && !(1 == 2)"), Value::Bool(true));
+        assert_eq!(eval_ok("1 < 2 ? \"a\" : \"b\""), Value::from("a"));
+        assert_eq!(eval_ok("false ? 1 : true ? 2 : 3"), Value::Int(2));
+        assert_eq!(eval_ok("1.5 + 1.0"), Value::Double(2.5));
+    }
+
+    #[test]
+    fn variables_and_functions() {
+        assert_eq!(
+            eval_ok("transaction.duration_in_ms > 100ms && status == \"0\""),
+            Value::Bool(true)
+        );
+        assert_eq!(eval_ok("name.startsWith(\"/api\")"), Value::Bool(true));
+        assert_eq!(eval_ok("name.matches(\"item/[0-9]+$\")"), Value::Bool(true));
+        assert_eq!(eval_ok("size(name)"), Value::Int(11));
+        assert_eq!(eval_ok("int(\"12\") + 1"), Value::Int(13));
+        assert_eq!(eval_ok("string(1) + \"s\""), Value::from("1s"));
+        assert_eq!(eval_ok("status in [\"0\", \"200\"]"), Value::Bool(true));
+        assert_eq!(
+            eval_ok("[1, 2] + [3]"),
+            Value::List(vec![Value::Int(1), Value::Int(2), Value::Int(3)])
+        );
+        assert_eq!(
+            Program::compile("name == \"x\" || missing > 1 && name != \"y\"")
+                .unwrap()
+                .variables(),
+            ["name", "missing"]
+        );
+    }
+
+    #[test]
+    fn missing_variables() {
+        assert_eq!(eval_ok("has(missing)"), Value::Bool(false));
+        assert_eq!(eval_ok("has(missing) && missing > 1"), Value::Bool(false));
+        assert_eq!(eval_ok("status == \"0\" || missing"), Value::Bool(true));
+        assert!(eval("missing > 1").is_err());
+        assert!(eval("status == \"1\" || missing").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        for source in &[
+            "1 +",
+            "(1",
+            "1 2",
+            "unknown(1)",
+            "size(1, 2)",
+            "has(1)",
+            "name.x.y(1)",
+            "1 ? 2",
+        ] {
+            assert!(Program::compile(source).is_err(), "{}", source);
+        }
+        for source in &["1 + \"a\"", "\"a\" < 1", "-\"a\"", "size(1)", "1 && true"] {
+            assert!(eval(source).is_err(), "{}", source);
+        }
+    }
+}
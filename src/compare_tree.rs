@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use failure::{format_err, Fallible};
+use serde::{Deserialize, Serialize};
+
+use crate::message_tree::Message;
+
+/// Expected shape of a message: what it is, how long it takes and the
+/// shapes of its children in order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Shape {
+    pub ty: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_in_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Shape>,
+}
+
+impl Shape {
+    pub fn of(message: &Message) -> Self {
+        let children = match message {
+            Message::Transaction(t) => t.children.iter().map(Shape::of).collect(),
+            _ => vec![],
+        };
+        Shape {
+            ty: message.ty().to_string(),
+            name: message.name().to_string(),
+            duration_in_ms: message.duration_in_ms(),
+            children,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Fallible<()> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Fallible<Self> {
+        serde_json::from_reader(File::open(path)?)
+            .map_err(|e| format_err!("invalid baseline {}: {}", path.display(), e))
+    }
+
+    fn label(&self) -> String {
+        format!("{}:{}", self.ty, self.name)
+    }
+}
+
+/// How far a duration may be from the baseline: the larger of `ratio` of
+/// the expected duration and `ms`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    pub ratio: f64,
+    pub ms: u64,
+}
+
+impl Tolerance {
+    fn of(&self, expected: u64) -> u64 {
+        ((expected as f64 * self.ratio) as u64).max(self.ms)
+    }
+}
+
+/// Prints where `actual` differs from `expected`, one line per difference
+/// prefixed by the path to it, and returns the number of differences.
+///
+/// Children are matched by `ty:name` in order, so a child missing,
+/// unexpected or moved is reported once instead of shifting the comparison
+/// of its siblings.
+pub fn compare(
+    actual: &Shape,
+    expected: &Shape,
+    tolerance: Tolerance,
+    out: &mut dyn Write,
+) -> Fallible<usize> {
+    if actual.label() != expected.label() {
+        writeln!(out, "{}: expected {}", actual.label(), expected.label())?;
+        return Ok(1);
+    }
+    compare_node(actual, expected, &actual.label(), tolerance, out)
+}
+
+fn compare_node(
+    actual: &Shape,
+    expected: &Shape,
+    path: &str,
+    tolerance: Tolerance,
+    out: &mut dyn Write,
+) -> Fallible<usize> {
+    let mut differences = 0;
+    if let (Some(actual), Some(expected)) = (actual.duration_in_ms, expected.duration_in_ms) {
+        let allowed = tolerance.of(expected);
+        if actual.abs_diff(expected) > allowed {
+            writeln!(
+                out,
+                "{}: {}ms, expected {}ms ±{}ms",
+                path, actual, expected, allowed
+            )?;
+            differences += 1;
+        }
+    }
+
+    let actual_labels: Vec<_> = actual.children.iter().map(Shape::label).collect();
+    let expected_labels: Vec<_> = expected.children.iter().map(Shape::label).collect();
+    let pairs = longest_common_subsequence(&actual_labels, &expected_labels);
+    let missing: Vec<_> = (0..expected_labels.len())
+        .filter(|j| !pairs.iter().any(|&(_, e)| e == *j))
+        .collect();
+    let unexpected: Vec<_> = (0..actual_labels.len())
+        .filter(|i| !pairs.iter().any(|&(a, _)| a == *i))
+        .collect();
+    for &j in &missing {
+        let label = &expected_labels[j];
+        match unexpected.iter().find(|&&i| &actual_labels[i] == label) {
+            Some(i) => writeln!(
+                out,
+                "{} > {}: moved from child {} to {}",
+                path,
+                label,
+                j + 1,
+                i + 1
+            )?,
+            None => writeln!(out, "{} > {}: missing", path, label)?,
+        }
+        differences += 1;
+    }
+    for &i in &unexpected {
+        let label = &actual_labels[i];
+        if !missing.iter().any(|&j| &expected_labels[j] == label) {
+            writeln!(out, "{} > {}: unexpected", path, label)?;
+            differences += 1;
+        }
+    }
+
+    for (i, j) in pairs {
+        let child_path = format!("{} > {}", path, actual_labels[i]);
+        differences += compare_node(
+            &actual.children[i],
+            &expected.children[j],
+            &child_path,
+            tolerance,
+            out,
+        )?;
+    }
+    Ok(differences)
+}
+
+/// Indexes of the elements of `a` and `b` in their longest common
+/// subsequence.
+fn longest_common_subsequence(a: &[String], b: &[String]) -> Vec<(usize, usize)> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut pairs = vec![];
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::str::FromStr;
+
+use failure::{bail, Error, Fallible};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::avro;
+use crate::filter::Filter;
+use crate::message_tree::{Message, MessageTree, Text};
+use crate::remote_call::REMOTE_CALL_TYPE;
+
+/// What `convert` writes the trees as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Chrome trace events, for chrome://tracing or Perfetto.
+    Chrome,
+    /// Traces as returned by the Jaeger query API, for the Jaeger UI.
+    Jaeger,
+    /// Tables of a SQLite database, see [`crate::sqlite`].
+    Sqlite,
+    /// An Avro object container file, see [`avro::ContainerWriter`].
+    Avro,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "chrome" => Format::Chrome,
+            "jaeger" => Format::Jaeger,
+            "sqlite" => Format::Sqlite,
+            "avro" => Format::Avro,
+            _ => bail!(
+                "unknown format {}, expected chrome, jaeger, sqlite or avro",
+                s
+            ),
+        })
+    }
+}
+
+/// Writes the trees matching `filter` as `format`, returning how many.
+pub fn convert(
+    trees: impl IntoIterator<Item = MessageTree>,
+    filter: &Filter,
+    format: Format,
+    out: &mut dyn Write,
+) -> Fallible<u64> {
+    let mut converted = 0;
+    match format {
+        Format::Chrome => {
+            let mut trace = ChromeTrace::start(out)?;
+            for tree in trees {
+                if filter.matches(&tree)? {
+                    trace.write_tree(&tree)?;
+                    converted += 1;
+                }
+            }
+            trace.finish()?;
+        }
+        Format::Jaeger => {
+            let mut traces = JaegerTraces::default();
+            for tree in trees {
+                if filter.matches(&tree)? {
+                    traces.add(tree);
+                    converted += 1;
+                }
+            }
+            traces.write(out)?;
+        }
+        Format::Avro => {
+            let mut writer = avro::ContainerWriter::new(out)?;
+            for tree in trees {
+                if filter.matches(&tree)? {
+                    writer.write(&tree)?;
+                    converted += 1;
+                }
+            }
+            writer.finish()?;
+        }
+        Format::Sqlite => bail!("sqlite is written to a database, with sqlite::export"),
+    }
+    Ok(converted)
+}
+
+/// Trees as a JSON object of trace events: every tree on the thread of its
+/// process, `domain hostname`, with its transactions as complete events,
+/// nested by time, and its other messages as instant events.
+struct ChromeTrace<'a> {
+    out: &'a mut dyn Write,
+    /// Ids of the processes by domain and hostname, and of the threads by
+    /// process and thread id, numbered in the order they are seen.
+    processes: HashMap<(Text, Text), u64>,
+    threads: HashMap<(u64, Text), u64>,
+    events: u64,
+}
+
+impl<'a> ChromeTrace<'a> {
+    fn start(out: &'a mut dyn Write) -> Fallible<Self> {
+        write!(out, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+        Ok(ChromeTrace {
+            out,
+            processes: HashMap::new(),
+            threads: HashMap::new(),
+            events: 0,
+        })
+    }
+
+    fn event(&mut self, event: Value) -> Fallible<()> {
+        let separator = if self.events == 0 { "" } else { "," };
+        write!(self.out, "{}\n{}", separator, event)?;
+        self.events += 1;
+        Ok(())
+    }
+
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let next = self.processes.len() as u64 + 1;
+        let pid = *self
+            .processes
+            .entry((tree.domain.clone(), tree.hostname.clone()))
+            .or_insert(next);
+        if pid == next {
+            self.event(json!({
+                "ph": "M", "name": "process_name", "pid": pid, "tid": 0,
+                "args": {"name": format!("{} {}", tree.domain, tree.hostname)},
+            }))?;
+        }
+        let next = self.threads.len() as u64 + 1;
+        let tid = *self
+            .threads
+            .entry((pid, tree.thread_id.clone()))
+            .or_insert(next);
+        if tid == next {
+            self.event(json!({
+                "ph": "M", "name": "thread_name", "pid": pid, "tid": tid,
+                "args": {"name": format!("{} ({})", tree.thread_name, tree.thread_id)},
+            }))?;
+        }
+        self.write_message(pid, tid, &tree.message, Some(&tree.message_id))
+    }
+
+    fn write_message(
+        &mut self,
+        pid: u64,
+        tid: u64,
+        message: &Message,
+        message_id: Option<&Text>,
+    ) -> Fallible<()> {
+        let mut event = json!({
+            "name": message.name(),
+            "cat": message.ty(),
+            "pid": pid,
+            "tid": tid,
+            "ts": message.timestamp_in_ms() * 1000,
+            "args": {"status": message.status(), "data": message.data()},
+        });
+        match message.duration_in_ms() {
+            Some(duration) => {
+                event["ph"] = json!("X");
+                event["dur"] = json!(duration * 1000);
+            }
+            None => {
+                event["ph"] = json!("i");
+                event["s"] = json!("t");
+            }
+        }
+        if let Some(id) = message_id {
+            event["args"]["message_id"] = json!(id);
+        }
+        self.event(event)?;
+        if let Message::Transaction(t) = message {
+            for child in &t.children {
+                self.write_message(pid, tid, child, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Fallible<()> {
+        writeln!(self.out, "\n]}}")?;
+        Ok(())
+    }
+}
+
+/// Trees grouped by trace, the root message id of CAT, as the JSON of the
+/// Jaeger query API, which the Jaeger UI opens with "Upload JSON".
+///
+/// Every transaction is a span, its events and other messages the logs of
+/// the span. The root span of a tree is a child of the span of the
+/// `RemoteCall` event calling it, or of the root span of its parent tree.
+/// The trees are held until all of them are read, as the trees of a trace
+/// can be anywhere in the input.
+#[derive(Default)]
+struct JaegerTraces {
+    traces: BTreeMap<Text, Vec<MessageTree>>,
+}
+
+impl JaegerTraces {
+    fn add(&mut self, tree: MessageTree) {
+        let root = match tree.root_message_id.is_empty() {
+            true => tree.message_id.clone(),
+            false => tree.root_message_id.clone(),
+        };
+        self.traces.entry(root).or_default().push(tree);
+    }
+
+    fn write(&self, out: &mut dyn Write) -> Fallible<()> {
+        let data: Vec<_> = self
+            .traces
+            .iter()
+            .map(|(root, trees)| jaeger_trace(root, trees))
+            .collect();
+        writeln!(out, "{}", json!({ "data": data }))?;
+        Ok(())
+    }
+}
+
+/// The first `bytes` bytes of the digest of `s`, the trace id of a root
+/// message id with 16 bytes and the span id of the `index`th message of a
+/// tree, of `{message_id}/{index}`, with 8, so the ids of exports of the
+/// same trees agree.
+pub fn derived_id(s: &str, bytes: usize) -> Vec<u8> {
+    Sha256::digest(s.as_bytes())[..bytes].to_vec()
+}
+
+/// `derived_id` in hex, as Jaeger ids are.
+fn hex_id(s: &str, bytes: usize) -> String {
+    hex::encode(derived_id(s, bytes))
+}
+
+fn tag(key: &str, value: impl Into<Value>) -> Value {
+    let value = value.into();
+    let ty = match value {
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "int64",
+        _ => "string",
+    };
+    json!({"key": key, "type": ty, "value": value})
+}
+
+fn jaeger_trace(root: &Text, trees: &[MessageTree]) -> Value {
+    let trace_id = hex_id(root, 16);
+    let mut spans = Spans {
+        trace_id: trace_id.clone(),
+        spans: vec![],
+        callers: HashMap::new(),
+    };
+    let mut processes = BTreeMap::new();
+    let mut roots = vec![];
+    for tree in trees {
+        let key = (&tree.domain, &tree.hostname, &tree.ip_address);
+        let next = format!("p{}", processes.len() + 1);
+        let process_id = processes
+            .entry(key)
+            .or_insert_with(|| (next, tree))
+            .0
+            .clone();
+        roots.push((spans.spans.len(), tree));
+        spans.add(&tree.message, &tree.message_id, &process_id, &mut 0, None);
+    }
+    let root_spans: HashMap<_, _> = roots
+        .iter()
+        .map(|&(i, tree)| (&tree.message_id, spans.spans[i]["spanID"].clone()))
+        .collect();
+    for &(i, tree) in &roots {
+        let parent = spans
+            .callers
+            .get(&tree.message_id)
+            .map(|span_id| json!(span_id))
+            .or_else(|| root_spans.get(&tree.parent_message_id).cloned());
+        if let Some(parent) = parent {
+            spans.spans[i]["references"] =
+                json!([{"refType": "CHILD_OF", "traceID": trace_id, "spanID": parent}]);
+        }
+        spans.spans[i]["tags"]
+            .as_array_mut()
+            .expect("tags")
+            .extend([
+                tag("cat.message_id", tree.message_id.as_str()),
+                tag("thread.name", tree.thread_name.as_str()),
+            ]);
+    }
+
+    let processes: serde_json::Map<_, _> = processes
+        .into_values()
+        .map(|(id, tree)| {
+            let process = json!({
+                "serviceName": tree.domain,
+                "tags": [
+                    tag("hostname", tree.hostname.as_str()),
+                    tag("ip", tree.ip_address.as_str()),
+                ],
+            });
+            (id, process)
+        })
+        .collect();
+    json!({
+        "traceID": trace_id,
+        "spans": spans.spans,
+        "processes": processes,
+    })
+}
+
+/// The spans of a trace being built.
+struct Spans {
+    trace_id: String,
+    spans: Vec<Value>,
+    /// Span of the transaction of every `RemoteCall` event, by the message
+    /// id it calls.
+    callers: HashMap<Text, String>,
+}
+
+impl Spans {
+    /// Adds the span of `message`, the `index`th message of the tree of
+    /// `message_id`, and of the transactions under it.
+    fn add(
+        &mut self,
+        message: &Message,
+        message_id: &Text,
+        process_id: &str,
+        index: &mut usize,
+        parent: Option<&str>,
+    ) {
+        let span_id = hex_id(&format!("{}/{}", message_id, index), 8);
+        *index += 1;
+        let mut tags = vec![tag("cat.type", message.ty().as_str())];
+        if message.status() != "0" {
+            tags.extend([
+                tag("error", true),
+                tag("cat.status", message.status().as_str()),
+            ]);
+        }
+        if !message.data().is_empty() {
+            tags.push(tag("cat.data", message.data().as_str()));
+        }
+        let references = match parent {
+            Some(parent) => {
+                json!([{"refType": "CHILD_OF", "traceID": self.trace_id, "spanID": parent}])
+            }
+            None => json!([]),
+        };
+        let at = self.spans.len();
+        self.spans.push(json!({
+            "traceID": self.trace_id,
+            "spanID": span_id,
+            "operationName": format!("{}:{}", message.ty(), message.name()),
+            "references": references,
+            "startTime": message.timestamp_in_ms() * 1000,
+            "duration": message.duration_in_ms().unwrap_or(0) * 1000,
+            "tags": tags,
+            "logs": [],
+            "processID": process_id,
+        }));
+
+        let children = match message {
+            Message::Transaction(t) => &t.children,
+            _ => return,
+        };
+        let mut logs = vec![];
+        for child in children {
+            match child {
+                Message::Transaction(_) => {
+                    self.add(child, message_id, process_id, index, Some(&span_id))
+                }
+                _ => {
+                    if child.ty() == REMOTE_CALL_TYPE && !child.data().is_empty() {
+                        self.callers.insert(child.data().clone(), span_id.clone());
+                    }
+                    let mut fields = vec![
+                        tag("event", child.ty().as_str()),
+                        tag("name", child.name().as_str()),
+                        tag("status", child.status().as_str()),
+                    ];
+                    if !child.data().is_empty() {
+                        fields.push(tag("data", child.data().as_str()));
+                    }
+                    logs.push(json!({
+                        "timestamp": child.timestamp_in_ms() * 1000,
+                        "fields": fields,
+                    }));
+                }
+            }
+        }
+        self.spans[at]["logs"] = json!(logs);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::message_tree::{InnerTransaction, Message, Text};
+
+/// Duration of `t` not covered by its children, floored at 0 as children
+/// running concurrently may add up to more than their parent.
+pub fn self_time_in_ms(t: &InnerTransaction) -> u64 {
+    let children: u64 = t.children.iter().filter_map(Message::duration_in_ms).sum();
+    t.duration_in_ms.saturating_sub(children)
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub ty: Text,
+    pub name: Text,
+    pub duration_in_ms: u64,
+    pub self_time_in_ms: u64,
+}
+
+/// The chain of transactions contributing the most wall time, starting at
+/// `message`.
+///
+/// Each step descends into the longest child, unless the transaction spent
+/// more time on its own than in that child, in which case the chain ends
+/// there. Empty when `message` isn't a transaction.
+pub fn critical_path(message: &Message) -> Vec<Step> {
+    let mut path = vec![];
+    let mut current = message;
+    while let Message::Transaction(t) = current {
+        let self_time_in_ms = self_time_in_ms(t);
+        path.push(Step {
+            ty: t.ty.clone(),
+            name: t.name.clone(),
+            duration_in_ms: t.duration_in_ms,
+            self_time_in_ms,
+        });
+        let longest = t
+            .children
+            .iter()
+            .filter(|c| c.duration_in_ms().is_some())
+            .max_by_key(|c| c.duration_in_ms());
+        match longest {
+            Some(child) if child.duration_in_ms().unwrap_or(0) > self_time_in_ms => current = child,
+            _ => break,
+        }
+    }
+    path
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use failure::{Error, Fallible};
+
+use crate::fields::Field;
+use crate::message_tree::{Message, MessageTree};
+use crate::report::format_timestamp;
+
+/// Columns printed when `--columns` isn't given.
+pub const DEFAULT_COLUMNS: &[Column] = &[
+    Column::Ts,
+    Column::Field(Field::Ty),
+    Column::Field(Field::Name),
+    Column::Field(Field::Status),
+    Column::DurationInMs,
+    Column::Field(Field::Domain),
+    Column::Field(Field::MessageId),
+];
+
+/// A column of `--csv`: a field of the tree, or one of the root message
+/// only printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    /// The timestamp as RFC 3339, `timestamp_in_ms` being the raw one.
+    Ts,
+    /// `transaction.duration_in_ms`, empty for other messages.
+    DurationInMs,
+    Data,
+    Field(Field),
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "ts" => Column::Ts,
+            "duration_in_ms" => Column::DurationInMs,
+            "data" => Column::Data,
+            field => Column::Field(field.parse()?),
+        })
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Column::Ts => f.write_str("ts"),
+            Column::DurationInMs => f.write_str("duration_in_ms"),
+            Column::Data => f.write_str("data"),
+            Column::Field(field) => field.fmt(f),
+        }
+    }
+}
+
+/// Prints trees as CSV rows of `columns`, quoted as in RFC 4180 but ended
+/// by `\n` for line based tools, after a header row printed with the first
+/// of them.
+pub struct Csv {
+    columns: Vec<Column>,
+    header_written: AtomicBool,
+}
+
+impl Csv {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Csv {
+            columns,
+            header_written: AtomicBool::new(false),
+        }
+    }
+
+    /// Writes the row of `tree`, whose root is printed as `message`, e.g.
+    /// with its payload decoded.
+    ///
+    /// `out` must be held for the header to be written once.
+    pub fn write(
+        &self,
+        tree: &MessageTree,
+        message: &Message,
+        out: &mut dyn Write,
+    ) -> Fallible<()> {
+        if !self.header_written.swap(true, Ordering::Relaxed) {
+            let header: Vec<_> = self.columns.iter().map(|c| quote(&c.to_string())).collect();
+            writeln!(out, "{}", header.join(","))?;
+        }
+        let mut row = Vec::with_capacity(self.columns.len());
+        for column in &self.columns {
+            let value = match column {
+                Column::Ts => format_timestamp(message.timestamp_in_ms())?,
+                Column::DurationInMs => message
+                    .duration_in_ms()
+                    .map(|d| d.to_string())
+                    .unwrap_or_default(),
+                Column::Data => message.data().to_string(),
+                Column::Field(field) => field.value(tree).unwrap_or_default(),
+            };
+            row.push(quote(&value));
+        }
+        writeln!(out, "{}", row.join(","))?;
+        Ok(())
+    }
+}
+
+/// `value` as a CSV field, quoted when it has a comma, quote or line break.
+fn quote(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use failure::{bail, Fallible};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::batch_poster::{BatchPoster, Failed, Post, Retries};
+use crate::message_tree::{Message, MessageTree};
+use crate::output;
+use crate::report;
+use crate::tls::ClientTls;
+
+pub const DEFAULT_INDEX: &str = "cat-{date}";
+
+/// A bulk request is sent once it holds this many bytes, whatever its
+/// number of documents.
+const MAX_BULK_BYTES: usize = 5 * 1024 * 1024;
+
+/// Smallest number of documents a bulk request is cut down to when
+/// Elasticsearch pushes back.
+const MIN_BATCH_SIZE: usize = 10;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Indexes matched trees into Elasticsearch through its `_bulk` API, from a
+/// background thread, a document per tree with its message id as `_id`,
+/// so exporting the same trees again updates them.
+///
+/// Documents are the trees as sent by the other message sinks with an
+/// `@timestamp` for Kibana, in the index named by `index`, where `{date}`
+/// is replaced by the `yyyy.MM.dd` day of the tree in UTC.
+///
+/// Batches shrink by half, down to `MIN_BATCH_SIZE`, whenever the cluster
+/// rejects a request or documents with 429, and grow back by a tenth of
+/// `batch_size` after every batch accepted. Rejected documents are sent
+/// again after a backoff, while filter threads wait. Requests failing
+/// otherwise are retried `retries` times, see `Retries::Blocking`, and
+/// documents Elasticsearch refuses, e.g. not matching the mapping of the
+/// index, are logged and skipped.
+pub struct Elasticsearch {
+    /// Action and source lines of a tree.
+    poster: BatchPoster<Vec<u8>>,
+    index: String,
+}
+
+impl Elasticsearch {
+    pub fn start(
+        url: &str,
+        index: &str,
+        batch_size: usize,
+        retries: u32,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        if index.is_empty() {
+            bail!("empty Elasticsearch index");
+        }
+        let bulk = Bulk {
+            url: format!("{}/_bulk", url.trim_end_matches('/')),
+            agent: tls.http_agent(Some(TIMEOUT))?,
+            max_batch_size: batch_size.max(1),
+            batch_size: batch_size.max(1),
+        };
+        let poster = BatchPoster::start(
+            "Elasticsearch",
+            bulk,
+            batch_size.max(1),
+            None,
+            Retries::Blocking(retries),
+        )?;
+        Ok(Elasticsearch {
+            poster,
+            index: index.to_string(),
+        })
+    }
+
+    /// Converts `tree`, with its message replaced by `message`, e.g. after
+    /// decoding payloads, to the document it is indexed as, in the index of
+    /// its day.
+    pub fn send(&self, tree: &MessageTree, message: &Message) -> Fallible<()> {
+        let timestamp_in_ms = message.timestamp_in_ms();
+        let mut doc = output::tree_json(tree, message);
+        doc["@timestamp"] = json!(report::format_timestamp(timestamp_in_ms)?);
+        let index = self.index.replace("{date}", &day(timestamp_in_ms)?);
+        let action = json!({ "index": { "_index": index, "_id": tree.message_id } });
+        let mut lines = serde_json::to_vec(&action)?;
+        lines.push(b'\n');
+        serde_json::to_writer(&mut lines, &doc)?;
+        lines.push(b'\n');
+        self.poster.send(lines)
+    }
+
+    /// Indexes the remaining trees.
+    pub fn finish(self) -> Fallible<()> {
+        self.poster.finish()
+    }
+}
+
+/// `yyyy.MM.dd` of `ms` since the epoch, in UTC.
+fn day(ms: u64) -> Fallible<String> {
+    let tm = time::at_utc(time::Timespec::new((ms / 1000) as i64, 0));
+    Ok(tm.strftime("%Y.%m.%d")?.to_string())
+}
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    errors: bool,
+    items: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+struct Bulk {
+    url: String,
+    agent: ureq::Agent,
+    max_batch_size: usize,
+    /// Documents per request, lowered while Elasticsearch pushes back.
+    batch_size: usize,
+}
+
+impl Post for Bulk {
+    type Item = Vec<u8>;
+
+    const NAME: &'static str = "elasticsearch";
+
+    fn is_full(&self, batch: &[Vec<u8>]) -> bool {
+        batch.len() >= self.batch_size
+            || batch.iter().map(Vec::len).sum::<usize>() >= MAX_BULK_BYTES
+    }
+
+    /// Indexes `docs`, giving back those rejected with 429, all of them
+    /// when the request itself was.
+    fn post(&mut self, docs: Vec<Vec<u8>>) -> Result<(), Failed<Vec<u8>>> {
+        let rejected = match self.request(&docs) {
+            Ok(response) => {
+                let rejected = self.rejected(&docs, response);
+                info!(
+                    "elasticsearch: indexed {} trees",
+                    docs.len() - rejected.len()
+                );
+                rejected
+            }
+            Err(e) if is_throttled(&e) => docs,
+            Err(e) => return Err(Failed::new(docs, e)),
+        };
+        if rejected.is_empty() {
+            self.batch_size =
+                (self.batch_size + self.max_batch_size.div_ceil(10)).min(self.max_batch_size);
+            return Ok(());
+        }
+        self.batch_size = (self.batch_size / 2).max(MIN_BATCH_SIZE);
+        warn!(
+            "elasticsearch: {} trees rejected, batches cut down to {}",
+            rejected.len(),
+            self.batch_size
+        );
+        Err(Failed {
+            items: rejected,
+            error: None,
+        })
+    }
+}
+
+impl Bulk {
+    fn request(&self, docs: &[Vec<u8>]) -> Fallible<BulkResponse> {
+        let body = self
+            .agent
+            .post(&self.url)
+            .header("Content-Type", "application/x-ndjson")
+            .send(docs.concat())?
+            .body_mut()
+            .with_config()
+            .limit(64 * 1024 * 1024)
+            .read_to_string()?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// The documents of `docs` rejected with 429, to be sent again. Those
+    /// refused for other reasons are logged and dropped.
+    fn rejected(&self, docs: &[Vec<u8>], response: BulkResponse) -> Vec<Vec<u8>> {
+        if !response.errors {
+            return vec![];
+        }
+        let mut rejected = vec![];
+        for (doc, item) in docs.iter().zip(response.items) {
+            let result = match item.values().next() {
+                Some(result) => result,
+                None => continue,
+            };
+            match result["status"].as_u64() {
+                Some(429) => rejected.push(doc.clone()),
+                Some(status) if status >= 300 => warn!(
+                    "elasticsearch: tree {} refused with {}: {}",
+                    result["_id"].as_str().unwrap_or_default(),
+                    status,
+                    result["error"]
+                ),
+                _ => {}
+            }
+        }
+        rejected
+    }
+}
+
+fn is_throttled(e: &failure::Error) -> bool {
+    matches!(
+        e.downcast_ref::<ureq::Error>(),
+        Some(ureq::Error::StatusCode(429))
+    )
+}
@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::path::Path;
+
+use failure::Fallible;
+use rand::seq::index;
+
+use crate::filter::Filter;
+use crate::human;
+use crate::message_tree_dumper::{block_offsets, read_block, read_blocks_at};
+
+/// z of the two-sided 95% confidence intervals.
+const Z_95: f64 = 1.96;
+
+/// A total over all blocks, extrapolated from the blocks sampled.
+struct Total {
+    /// Value of every block sampled.
+    samples: Vec<f64>,
+}
+
+impl Total {
+    /// Estimate of the total over `blocks` and its 95% confidence interval,
+    /// from the normal approximation of the mean of the samples, corrected
+    /// for sampling without replacement.
+    fn estimate(&self, blocks: usize) -> (f64, f64, f64) {
+        let n = self.samples.len() as f64;
+        let sum: f64 = self.samples.iter().sum();
+        let mean = sum / n;
+        let variance = match self.samples.len() {
+            0 | 1 => 0.0,
+            _ => self.samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0),
+        };
+        let population = blocks as f64;
+        let correction = (1.0 - n / population).max(0.0);
+        let margin = Z_95 * population * (variance / n * correction).sqrt();
+        let total = mean * population;
+        // The blocks sampled are a lower bound.
+        ((total - margin).max(sum), total, total + margin)
+    }
+}
+
+/// Decodes `sample` random blocks of the logview at `path` and prints the
+/// trees, matches of `filter` and bytes of output a full run would have,
+/// with 95% confidence intervals.
+///
+/// Output is measured as the lines printed for the matches, in JSON with
+/// `json`.
+pub fn estimate(
+    path: &Path,
+    filter: &Filter,
+    json: bool,
+    sample: usize,
+    out: &mut dyn Write,
+) -> Fallible<()> {
+    let offsets = block_offsets(path)?;
+    let mut picked: Vec<_> = index::sample(
+        &mut rand::thread_rng(),
+        offsets.len(),
+        sample.min(offsets.len()),
+    )
+    .into_iter()
+    .map(|i| offsets[i])
+    .collect();
+    picked.sort_unstable();
+
+    let mut trees = Total { samples: vec![] };
+    let mut matches = Total { samples: vec![] };
+    let mut bytes = Total { samples: vec![] };
+    for (_, _, block) in read_blocks_at(path, 0, picked)? {
+        let (mut block_trees, mut block_matches, mut block_bytes) = (0, 0, 0);
+        for tree in read_block(block) {
+            block_trees += 1;
+            if !filter.matches(&tree)? {
+                continue;
+            }
+            block_matches += 1;
+            block_bytes += if json {
+                serde_json::to_string(&tree.message)?.len()
+            } else {
+                tree.message.to_string().len()
+            } + 1;
+        }
+        trees.samples.push(f64::from(block_trees));
+        matches.samples.push(f64::from(block_matches));
+        bytes.samples.push(block_bytes as f64);
+    }
+
+    writeln!(
+        out,
+        "blocks: {} sampled of {}",
+        trees.samples.len(),
+        offsets.len()
+    )?;
+    if offsets.is_empty() {
+        return Ok(());
+    }
+    for (label, total, format) in [
+        ("trees", &trees, count as fn(f64) -> String),
+        ("matches", &matches, count),
+        ("output", &bytes, size),
+    ] {
+        let (low, total, high) = total.estimate(offsets.len());
+        writeln!(
+            out,
+            "{}: ~{} (95% CI {} to {})",
+            label,
+            format(total),
+            format(low),
+            format(high)
+        )?;
+    }
+    Ok(())
+}
+
+fn count(n: f64) -> String {
+    human::number(n, 0)
+}
+
+fn size(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < units.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{} {}", human::number(value, 1), units[unit])
+}
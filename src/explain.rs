@@ -0,0 +1,170 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use failure::Fallible;
+
+use crate::bundle;
+use crate::filter::{self, Filter};
+use crate::message_tree_dumper::{block_offsets, read_block, MessageBlockReader};
+use crate::query::QueryLang;
+use crate::sidecar::{self, Sidecar};
+
+/// Blocks decoded to estimate the cost of a scan.
+const SAMPLE_BLOCKS: usize = 20;
+
+/// What `--explain` needs to know about the command line.
+pub struct Plan<'a> {
+    pub query: Option<&'a str>,
+    pub lang: QueryLang,
+    pub path: &'a Path,
+    /// Whether the output only depends on the columns of the sidecar.
+    pub sidecar_eligible: bool,
+    pub allowed_domains: bool,
+    pub cache: bool,
+    pub decoding_threads: usize,
+    pub filter_threads: usize,
+}
+
+/// Prints how `plan` would be run: the variables of the query, the
+/// optimizations that apply and an estimate of the time to scan the file,
+/// from its size, its number of blocks and the first blocks decoded.
+pub fn explain(plan: &Plan, out: &mut dyn Write) -> Fallible<()> {
+    let variables = plan
+        .query
+        .map(|q| filter::variables(q, plan.lang))
+        .unwrap_or_default();
+    match plan.query {
+        Some(query) => {
+            Filter::compile(Some(query), plan.lang)?;
+            writeln!(out, "query: {}", query)?;
+        }
+        None => writeln!(out, "query: none, every tree matches")?,
+    }
+
+    writeln!(out, "variables:")?;
+    for variable in &variables {
+        let description = filter::VARIABLES
+            .iter()
+            .find(|(name, _)| name == variable)
+            .map_or("unknown, evaluating the query fails", |(_, d)| *d);
+        writeln!(out, "  {}\t{}", variable, description)?;
+    }
+    if variables.is_empty() {
+        writeln!(out, "  none")?;
+    }
+
+    writeln!(out, "optimizations:")?;
+    let lazy: Vec<_> = variables
+        .iter()
+        .filter(|v| filter::LAZY_VARIABLES.contains(&v.as_str()))
+        .collect();
+    if lazy.is_empty() {
+        writeln!(
+            out,
+            "  lazy variables: {} not computed",
+            filter::LAZY_VARIABLES.join(", ")
+        )?;
+    } else {
+        let lazy: Vec<_> = lazy.iter().map(|v| v.as_str()).collect();
+        writeln!(
+            out,
+            "  lazy variables: {} referenced, walks every tree",
+            lazy.join(", ")
+        )?;
+    }
+    let sidecar_path = sidecar::sidecar_path(plan.path);
+    let sidecar = if bundle::is_bundle(plan.path) {
+        "not used for bundles".to_string()
+    } else if !plan.sidecar_eligible {
+        let outside: Vec<_> = variables
+            .iter()
+            .filter(|v| !sidecar::COLUMNS.contains(&v.as_str()))
+            .map(String::as_str)
+            .collect();
+        if outside.is_empty() {
+            "not used, only aggregations on its columns can read it".to_string()
+        } else {
+            format!("not used, {} not in its columns", outside.join(", "))
+        }
+    } else if Sidecar::is_fresh(plan.path)? {
+        format!("reads {} instead of the file", sidecar_path.display())
+    } else if sidecar_path.exists() {
+        format!(
+            "{} is stale, rebuild it with `cache build`",
+            sidecar_path.display()
+        )
+    } else {
+        "would be used, build it with `cache build`".to_string()
+    };
+    writeln!(out, "  sidecar: {}", sidecar)?;
+    writeln!(
+        out,
+        "  domain pushdown: {}",
+        if plan.allowed_domains {
+            "trees of other domains are skipped after their header"
+        } else {
+            "off, needs --allowed-domains"
+        }
+    )?;
+    writeln!(
+        out,
+        "  result cache: {}",
+        if plan.cache {
+            "matched trees are reread from their blocks on a hit"
+        } else {
+            "off, needs --cache"
+        }
+    )?;
+
+    writeln!(out, "scan:")?;
+    let size = fs::metadata(plan.path)?.len();
+    writeln!(out, "  size: {:.1} MB", size as f64 / 1e6)?;
+    if bundle::is_bundle(plan.path) {
+        writeln!(out, "  estimate: not available for bundles")?;
+        return Ok(());
+    }
+    let blocks = block_offsets(plan.path)?.len();
+    writeln!(out, "  blocks: {}", blocks)?;
+    let (sampled, trees, decoding, filtering) = sample(plan.path, plan.query, plan.lang)?;
+    if sampled == 0 {
+        return Ok(());
+    }
+    let per_block = |d: Duration| d.as_secs_f64() / sampled as f64;
+    let trees_estimate = trees as f64 / sampled as f64 * blocks as f64;
+    let decoding_estimate = per_block(decoding) * blocks as f64;
+    let filtering_estimate = per_block(filtering) * blocks as f64;
+    writeln!(
+        out,
+        "  estimate: ~{:.0} trees, {:.1}s decoding, {:.1}s querying, from the first {} blocks",
+        trees_estimate,
+        decoding_estimate / plan.decoding_threads.max(1) as f64,
+        filtering_estimate / plan.filter_threads.max(1) as f64,
+        sampled
+    )?;
+    Ok(())
+}
+
+/// Blocks sampled, their trees, and the time to decode and query them.
+fn sample(
+    path: &Path,
+    query: Option<&str>,
+    lang: QueryLang,
+) -> Fallible<(usize, usize, Duration, Duration)> {
+    let filter = Filter::compile(query, lang)?;
+    let blocks: Vec<_> = MessageBlockReader::open(path)?
+        .into_iter()
+        .take(SAMPLE_BLOCKS)
+        .collect();
+    let sampled = blocks.len();
+    let start = Instant::now();
+    let trees: Vec<_> = blocks.into_iter().flat_map(read_block).collect();
+    let decoding = start.elapsed();
+    let start = Instant::now();
+    for tree in &trees {
+        // Unknown variables are already reported.
+        let _ = filter.matches(tree);
+    }
+    Ok((sampled, trees.len(), decoding, start.elapsed()))
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder};
+use failure::{bail, format_err, Fallible};
+use log::{debug, warn};
+
+use crate::logging;
+use crate::message_tree::{try_read_data, MessageTree};
+use crate::message_tree_dumper::{read_block, MessageBlockReader};
+
+/// Logview API of the CAT server, `{id}` is replaced by the message id.
+pub const DEFAULT_API_PATH: &str = "/cat/r/m/{id}?forceDownload=true";
+
+pub const DEFAULT_MAX_RECONNECTS: &str = "5";
+
+/// Delay before fetching a logview again after a transient failure, doubled
+/// after every failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Largest logview read from the server, far above the size of any tree,
+/// so a misbehaving server can't exhaust memory.
+const MAX_LOGVIEW_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Fetches the logview of `id` from a CAT server and decodes it.
+pub fn fetch(
+    agent: &ureq::Agent,
+    server: &str,
+    api_path: &str,
+    id: &str,
+) -> Fallible<Vec<MessageTree>> {
+    let url = format!(
+        "{}{}",
+        server.trim_end_matches('/'),
+        api_path.replace("{id}", id)
+    );
+    debug!("fetch logview: {}", url);
+    let mut response = agent.get(&url).call()?;
+    let body = response
+        .body_mut()
+        .with_config()
+        .limit(MAX_LOGVIEW_BYTES)
+        .read_to_vec()
+        .map_err(|e| match e {
+            ureq::Error::BodyExceedsLimit(limit) => {
+                format_err!("logview {} larger than {} bytes", id, limit)
+            }
+            e => e.into(),
+        })?;
+    decode_payload(body)
+}
+
+/// Fetches `id`, again with exponential backoff after a transient failure,
+/// up to `max_reconnects` times. A truncated logview is fetched again from
+/// the start.
+fn fetch_with_retries(
+    agent: &ureq::Agent,
+    server: &str,
+    api_path: &str,
+    id: &str,
+    max_reconnects: u32,
+) -> Fallible<Vec<MessageTree>> {
+    let mut backoff = MIN_BACKOFF;
+    let mut reconnects = 0;
+    loop {
+        match fetch(agent, server, api_path, id) {
+            Err(e) if reconnects < max_reconnects && is_transient(&e) => {
+                warn!(
+                    error_kind = logging::error_kind(&e);
+                    "fetch logview {}: {}, retrying in {:?}", id, e, backoff
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                reconnects += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether fetching again may succeed: network errors, server errors or
+/// throttling, and payloads cut short.
+fn is_transient(e: &failure::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<ureq::Error>() {
+        return match e {
+            ureq::Error::StatusCode(status) => *status >= 500 || *status == 429,
+            ureq::Error::Io(_)
+            | ureq::Error::Timeout(_)
+            | ureq::Error::HostNotFound
+            | ureq::Error::ConnectionFailed
+            | ureq::Error::Protocol(_)
+            | ureq::Error::BodyStalled => true,
+            _ => false,
+        };
+    }
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+/// Fetches every id in turn. Failures are logged and skipped so that one
+/// missing logview does not abort a whole batch.
+pub fn fetch_all(
+    agent: &ureq::Agent,
+    server: &str,
+    api_path: &str,
+    ids: &[String],
+    max_reconnects: u32,
+) -> Vec<MessageTree> {
+    let mut trees = vec![];
+    for id in ids {
+        match fetch_with_retries(agent, server, api_path, id, max_reconnects) {
+            Ok(t) => trees.extend(t),
+            Err(e) => warn!(
+                error_kind = logging::error_kind(&e);
+                "fetch logview {} error: {}", id, e
+            ),
+        }
+    }
+    trees
+}
+
+/// Reads message ids, one per line. Blank lines and `#` comments are skipped.
+pub fn read_ids_file(path: impl AsRef<Path>) -> Fallible<Vec<String>> {
+    let path = path.as_ref();
+    let content =
+        fs::read_to_string(path).map_err(|e| format_err!("read {}: {}", path.display(), e))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Decodes a payload in one of the encodings produced by CAT: a single
+/// `NT1` tree, a length prefixed sequence of trees, or a whole logview file.
+pub fn decode_payload(body: Vec<u8>) -> Fallible<Vec<MessageTree>> {
+    if body.starts_with(b"NT1") {
+        return Ok(vec![MessageTree::decode(&mut body.as_slice())?]);
+    }
+    if body.len() >= 4 && BigEndian::read_i32(&body) == -1 {
+        let blocks = MessageBlockReader::new(std::io::Cursor::new(body))?;
+        return Ok(blocks.into_iter().flat_map(read_block).collect());
+    }
+    if body.len() >= 7 && &body[4..7] == b"NT1" {
+        let mut reader = body.as_slice();
+        let mut trees = vec![];
+        while let Some(buf) = try_read_data(&mut reader)? {
+            trees.push(MessageTree::decode(&mut buf.as_slice())?);
+        }
+        return Ok(trees);
+    }
+
+    let preview: String = String::from_utf8_lossy(&body).chars().take(80).collect();
+    bail!("unrecognized logview payload: {:?}", preview)
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+use std::str::FromStr;
+
+use failure::{format_err, Error};
+
+use crate::critical_path;
+use crate::message_tree::{Message, MessageTree};
+use crate::stacktrace;
+use crate::status_class;
+
+/// A value of a tree that can be grouped or aggregated on, named like the
+/// variables of `--query`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Domain,
+    Hostname,
+    IpAddress,
+    MessageId,
+    ParentMessageId,
+    RootMessageId,
+    SessionToken,
+    ThreadGroupName,
+    ThreadId,
+    ThreadName,
+    Status,
+    StatusClass,
+    Ty,
+    Name,
+    TimestampInMs,
+    DurationInMs,
+    SelfDurationInMs,
+    HasConcurrentChildren,
+    ExceptionClass,
+}
+
+const NAMES: &[(&str, Field)] = &[
+    ("domain", Field::Domain),
+    ("hostname", Field::Hostname),
+    ("ip_address", Field::IpAddress),
+    ("message_id", Field::MessageId),
+    ("parent_message_id", Field::ParentMessageId),
+    ("root_message_id", Field::RootMessageId),
+    ("session_token", Field::SessionToken),
+    ("thread_group_name", Field::ThreadGroupName),
+    ("thread_id", Field::ThreadId),
+    ("thread_name", Field::ThreadName),
+    ("status", Field::Status),
+    ("status_class", Field::StatusClass),
+    ("ty", Field::Ty),
+    ("name", Field::Name),
+    ("timestamp_in_ms", Field::TimestampInMs),
+    ("transaction.duration_in_ms", Field::DurationInMs),
+    ("self_duration_in_ms", Field::SelfDurationInMs),
+    ("has_concurrent_children", Field::HasConcurrentChildren),
+    ("exception_class", Field::ExceptionClass),
+];
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, field)| *field)
+            .ok_or_else(|| {
+                let names: Vec<_> = NAMES.iter().map(|(name, _)| *name).collect();
+                format_err!(
+                    "unknown field {:?}, expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = NAMES
+            .iter()
+            .find(|(_, field)| field == self)
+            .map(|(name, _)| *name)
+            .expect("every field is named");
+        f.write_str(name)
+    }
+}
+
+impl Field {
+    /// The value of the field in `tree`, `None` when the tree has none.
+    pub fn value(self, tree: &MessageTree) -> Option<String> {
+        let text = match self {
+            Field::Domain => &tree.domain,
+            Field::Hostname => &tree.hostname,
+            Field::IpAddress => &tree.ip_address,
+            Field::MessageId => &tree.message_id,
+            Field::ParentMessageId => &tree.parent_message_id,
+            Field::RootMessageId => &tree.root_message_id,
+            Field::SessionToken => &tree.session_token,
+            Field::ThreadGroupName => &tree.thread_group_name,
+            Field::ThreadId => &tree.thread_id,
+            Field::ThreadName => &tree.thread_name,
+            Field::Status => tree.message.status(),
+            Field::StatusClass => return Some(status_class::of(tree.message.status()).to_string()),
+            Field::Ty => tree.message.ty(),
+            Field::Name => tree.message.name(),
+            Field::TimestampInMs => return Some(tree.message.timestamp_in_ms().to_string()),
+            Field::DurationInMs => return tree.message.duration_in_ms().map(|d| d.to_string()),
+            Field::SelfDurationInMs => return self.number(tree).map(|d| d.to_string()),
+            Field::HasConcurrentChildren => {
+                return Some(tree.message.has_concurrent_children().to_string())
+            }
+            Field::ExceptionClass => return stacktrace::find(tree).map(|s| s.exception_class),
+        };
+        Some(text.clone())
+    }
+
+    /// The value of the field as a number, `None` when missing or not numeric.
+    pub fn number(self, tree: &MessageTree) -> Option<f64> {
+        match self {
+            Field::TimestampInMs => Some(tree.message.timestamp_in_ms() as f64),
+            Field::DurationInMs => tree.message.duration_in_ms().map(|d| d as f64),
+            Field::SelfDurationInMs => match &tree.message {
+                Message::Transaction(t) => Some(critical_path::self_time_in_ms(t) as f64),
+                _ => None,
+            },
+            _ => self.value(tree)?.parse().ok(),
+        }
+    }
+}
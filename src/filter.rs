@@ -0,0 +1,356 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use evalexpr::*;
+use failure::{bail, Fallible};
+
+use crate::cel;
+use crate::critical_path;
+use crate::message_tree::{Message, MessageTree};
+use crate::query::{self, QueryLang};
+use crate::running::{self, Aggregates};
+use crate::selector;
+use crate::stacktrace;
+use crate::status_class;
+
+/// The variables of queries, with what they hold.
+pub const VARIABLES: &[(&str, &str)] = &[
+    ("message_id", "id of the tree"),
+    ("status", "status of the root message"),
+    (
+        "status_class",
+        "success, client-error, server-error or timeout, from the status of the root message and --status-map",
+    ),
+    ("ty", "type of the root message"),
+    ("name", "name of the root message"),
+    (
+        "timestamp_in_ms",
+        "timestamp of the root message, in ms since the epoch",
+    ),
+    (
+        "transaction.duration_in_ms",
+        "duration of the root transaction, unset for other roots",
+    ),
+    (
+        "has_concurrent_children",
+        "whether children of the root overlap in time",
+    ),
+    (
+        "self_duration_in_ms",
+        "time of the root transaction not spent in its children",
+    ),
+    (
+        "exception_class",
+        "class of the first exception in the stack traces of the tree",
+    ),
+    (
+        "event.status",
+        "status of the root event, unset for other roots",
+    ),
+    ("event.ty", "type of the root event, unset for other roots"),
+    (
+        "event.name",
+        "name of the root event, unset for other roots",
+    ),
+    (
+        "event.data",
+        "data of the root event, unset for other roots",
+    ),
+    (
+        "heartbeat.status",
+        "status of the root heartbeat, unset for other roots",
+    ),
+    (
+        "heartbeat.ty",
+        "type of the root heartbeat, unset for other roots",
+    ),
+    (
+        "heartbeat.name",
+        "name of the root heartbeat, unset for other roots",
+    ),
+    (
+        "heartbeat.data",
+        "data of the root heartbeat, unset for other roots",
+    ),
+    (
+        "metric.status",
+        "status of the root metric, unset for other roots",
+    ),
+    (
+        "metric.ty",
+        "type of the root metric, unset for other roots",
+    ),
+    (
+        "metric.name",
+        "name of the root metric, unset for other roots",
+    ),
+    (
+        "metric.data",
+        "data of the root metric, unset for other roots",
+    ),
+];
+
+/// Variables only computed for queries referencing them, since they walk
+/// the whole tree.
+pub const LAZY_VARIABLES: &[&str] = &["exception_class"];
+
+/// A compiled query, of either language.
+enum Expr {
+    Evalexpr(Node),
+    Cel(cel::Program),
+}
+
+/// A compiled `--query` expression.
+///
+/// Variables that are expensive to compute are only set when the query
+/// references them.
+pub struct Filter {
+    expr: Option<Expr>,
+    variables: HashSet<String>,
+    /// Whether the query calls `running::FUNCTIONS`.
+    running: bool,
+    aggregates: Arc<Aggregates>,
+}
+
+impl Filter {
+    /// Compiles `query`, written in `lang` or as a selector, see
+    /// [`selector::to_cel`].
+    pub fn compile(query: Option<&str>, lang: QueryLang) -> Fallible<Self> {
+        let expr = match (query, lang) {
+            (None, _) => None,
+            (Some(query), _) if selector::is_selector(query) => {
+                Some(Expr::Cel(compile_cel(&selector::to_cel(query)?)?))
+            }
+            (Some(query), QueryLang::Evalexpr) => Some(Expr::Evalexpr(query::compile(query)?)),
+            (Some(query), QueryLang::Cel) => Some(Expr::Cel(compile_cel(query)?)),
+        };
+        let variables = query
+            .map(|q| variables(q, lang).into_iter().collect())
+            .unwrap_or_default();
+        let running = query.is_some_and(|q| {
+            query::tokenize(q).iter().any(|token| {
+                token.kind == query::Kind::Identifier
+                    && running::FUNCTIONS.iter().any(|(f, _)| *f == token.text)
+            })
+        });
+        Ok(Filter {
+            expr,
+            variables,
+            running,
+            aggregates: Arc::default(),
+        })
+    }
+
+    /// Shares the trees matched so far with the other filters of
+    /// `aggregates`, e.g. the ones of other filter threads.
+    pub fn with_aggregates(mut self, aggregates: Arc<Aggregates>) -> Self {
+        self.aggregates = aggregates;
+        self
+    }
+
+    pub fn matches(&self, tree: &MessageTree) -> Fallible<bool> {
+        let expr = match &self.expr {
+            Some(expr) => expr,
+            None => return Ok(true),
+        };
+        let values = self.values(tree);
+        // Trees are taken one at a time when the query depends on the ones
+        // matched before.
+        let _evaluation = if self.running {
+            Some(self.aggregates.lock())
+        } else {
+            None
+        };
+        let passed = Rc::new(RefCell::new(HashSet::new()));
+        let matched = match expr {
+            Expr::Evalexpr(node) => {
+                let mut context = HashMapContext::new();
+                for (name, value) in values {
+                    let value = match value {
+                        cel::Value::Bool(b) => b.into(),
+                        cel::Value::Int(i) => i.into(),
+                        cel::Value::String(s) => s.into(),
+                        _ => unreachable!("variables are bools, ints or strings"),
+                    };
+                    context.set_value(name.into(), value)?;
+                }
+                for &(name, arity) in query::TIMESTAMP_FUNCTIONS {
+                    let function = move |args: &[Value]| {
+                        let args = args
+                            .iter()
+                            .map(Value::as_int)
+                            .collect::<Result<Vec<_>, _>>()?;
+                        query::call_timestamp_function(name, &args)
+                            .map(Value::Int)
+                            .map_err(|e| EvalexprError::CustomMessage(e.to_string()))
+                    };
+                    context.set_function(
+                        name.into(),
+                        Function::new(Some(arity), Box::new(function)),
+                    )?;
+                }
+                for &(name, arity) in running::FUNCTIONS.iter().filter(|_| self.running) {
+                    let aggregates = self.aggregates.clone();
+                    let passed = passed.clone();
+                    // The call site is passed first, see `query::compile`.
+                    let function = move |args: &[Value]| {
+                        let value = match &args[1] {
+                            Value::String(s) => format!("{:?}", s),
+                            other => other.to_string(),
+                        };
+                        let key = (args[0].as_int()?, value);
+                        let mut passed = passed.borrow_mut();
+                        Ok(match aggregates.call(name, key, &mut passed) {
+                            cel::Value::Int(i) => Value::Int(i),
+                            cel::Value::Bool(b) => Value::Boolean(b),
+                            _ => unreachable!("running functions return ints or bools"),
+                        })
+                    };
+                    context.set_function(
+                        name.into(),
+                        Function::new(Some(arity + 1), Box::new(function)),
+                    )?;
+                }
+                node.eval_boolean_with_context(&context)?
+            }
+            Expr::Cel(program) => {
+                let functions = |name: &str, args: &[cel::Value]| {
+                    if !running::FUNCTIONS.iter().any(|(f, _)| *f == name) {
+                        return None;
+                    }
+                    // The call site is passed first, see `cel::Program::compile`.
+                    let site = match args[0] {
+                        cel::Value::Int(site) => site,
+                        _ => unreachable!("call sites are ints"),
+                    };
+                    let key = (site, args[1].to_string());
+                    let mut passed = passed.borrow_mut();
+                    Some(Ok(self.aggregates.call(name, key, &mut passed)))
+                };
+                match program.eval(&values, &functions)? {
+                    cel::Value::Bool(b) => b,
+                    other => bail!("query evaluated to {} instead of a bool", other),
+                }
+            }
+        };
+        if matched && self.running {
+            self.aggregates.record(passed.take());
+        }
+        Ok(matched)
+    }
+
+    /// The values of the variables for `tree`. Unset variables are missing.
+    fn values(&self, tree: &MessageTree) -> HashMap<&'static str, cel::Value> {
+        let mut values = HashMap::new();
+        values.insert("message_id", tree.message_id.as_str().into());
+        values.insert("status", tree.message.status().as_str().into());
+        values.insert(
+            "status_class",
+            status_class::of(tree.message.status()).into(),
+        );
+        values.insert("ty", tree.message.ty().as_str().into());
+        values.insert("name", tree.message.name().as_str().into());
+        values.insert(
+            "timestamp_in_ms",
+            cel::Value::Int(tree.message.timestamp_in_ms() as i64),
+        );
+        if let Some(duration) = tree.message.duration_in_ms() {
+            values.insert(
+                "transaction.duration_in_ms",
+                cel::Value::Int(duration as i64),
+            );
+        }
+        values.insert(
+            "has_concurrent_children",
+            cel::Value::Bool(tree.message.has_concurrent_children()),
+        );
+        if let Message::Transaction(t) = &tree.message {
+            values.insert(
+                "self_duration_in_ms",
+                cel::Value::Int(critical_path::self_time_in_ms(t) as i64),
+            );
+        }
+        // Fields of the root under the name of its kind, so a query can
+        // tell which kind it's looking at.
+        let typed = match &tree.message {
+            Message::Event(e) => Some((
+                ["event.status", "event.ty", "event.name", "event.data"],
+                [&e.status, &e.ty, &e.name, &e.data],
+            )),
+            Message::Heartbeat(h) => Some((
+                [
+                    "heartbeat.status",
+                    "heartbeat.ty",
+                    "heartbeat.name",
+                    "heartbeat.data",
+                ],
+                [&h.status, &h.ty, &h.name, &h.data],
+            )),
+            Message::Metric(m) => Some((
+                ["metric.status", "metric.ty", "metric.name", "metric.data"],
+                [&m.status, &m.ty, &m.name, &m.data],
+            )),
+            Message::Transaction(_) | Message::Trace(_) => None,
+        };
+        if let Some((names, fields)) = typed {
+            for (name, field) in names.iter().zip(fields) {
+                values.insert(*name, field.as_str().into());
+            }
+        }
+        // One of LAZY_VARIABLES.
+        if self.variables.contains("exception_class") {
+            let class = stacktrace::find(tree)
+                .map(|s| s.exception_class)
+                .unwrap_or_default();
+            values.insert("exception_class", cel::Value::String(class));
+        }
+        values
+    }
+}
+
+/// Compiles a CEL `query`, rejecting variables that are never set.
+fn compile_cel(query: &str) -> Fallible<cel::Program> {
+    let program = cel::Program::compile(query)?;
+    let known = VARIABLES.iter().map(|(name, _)| *name);
+    for variable in program.variables() {
+        if known.clone().any(|name| name == variable) {
+            continue;
+        }
+        match query::closest(variable, known) {
+            Some(name) => bail!("unknown variable `{}`, did you mean `{}`?", variable, name),
+            None => bail!(
+                "unknown variable `{}`, for the string, write \"{}\"",
+                variable,
+                variable
+            ),
+        }
+    }
+    Ok(program)
+}
+
+/// Identifiers referenced as variables in a query, in order of appearance.
+///
+/// String literals and function names are skipped. A CEL query or a
+/// selector that doesn't compile has none.
+pub fn variables(query: &str, lang: QueryLang) -> Vec<String> {
+    if selector::is_selector(query) {
+        return selector::to_cel(query)
+            .map(|cel| variables(&cel, QueryLang::Cel))
+            .unwrap_or_default();
+    }
+    if lang == QueryLang::Cel {
+        return cel::Program::compile(query)
+            .map(|program| program.variables().to_vec())
+            .unwrap_or_default();
+    }
+    let tokens = query::tokenize(query);
+    let mut vars: Vec<String> = vec![];
+    for token in query::variable_tokens(&tokens) {
+        if !vars.iter().any(|v| v == token.text) {
+            vars.push(token.text.to_string());
+        }
+    }
+    vars
+}
@@ -0,0 +1,345 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::{bail, err_msg, Fallible};
+use log::info;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Server, ServerTlsConfig};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::acl::AllowedDomains;
+use crate::arrow::{Encoder, IpcMessage};
+use crate::auth::{Auth, Client};
+use crate::csv::{self, Column};
+use crate::filter::Filter;
+use crate::message_tree::MessageTree;
+use crate::message_tree_dumper::MessageTreeDumperBuilder;
+use crate::proto::dump_cat_server::{DumpCat, DumpCatServer};
+use crate::proto::{self, QueryRequest};
+use crate::query::QueryLang;
+
+use self::flight::flight_service_server::{FlightService, FlightServiceServer};
+use self::flight::{FlightData, Ticket};
+
+mod flight {
+    tonic::include_proto!("arrow.flight.protocol");
+}
+
+/// Trees buffered per call before decoding waits for the client.
+const CHANNEL_SIZE: usize = 1024;
+
+/// Rows of the record batches of a Flight ticket without `batch_size`.
+const FLIGHT_BATCH_SIZE: usize = 1024;
+
+type Sender = mpsc::Sender<Result<proto::MessageTree, Status>>;
+
+type FlightSender = mpsc::Sender<Result<FlightData, Status>>;
+
+/// The ticket of a Flight `DoGet`, a JSON object like
+/// `{"path": "cat.dat", "query": "ty == \"URL\"", "columns": ["ts", "name"]}`.
+///
+/// The fields are the ones of a `QueryRequest`, with the columns of the
+/// record batches, like `--columns`, and their number of rows.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlightTicket {
+    path: String,
+    #[serde(default)]
+    query: String,
+    #[serde(default)]
+    limit: u64,
+    #[serde(default)]
+    message_ids: Vec<String>,
+    #[serde(default)]
+    columns: Vec<String>,
+    batch_size: Option<usize>,
+}
+
+/// Serves the `DumpCat` service of `proto/dump_cat.proto` on `addr`, and
+/// the `DoGet` of Arrow Flight, so that e.g. a notebook can read matched
+/// trees with `pyarrow.flight.FlightClient.do_get` straight into a table.
+///
+/// Requests name files relative to `root` and can't read outside of it.
+/// Files are decoded with the settings of `builder`, keeping the trees of
+/// `allowed_domains`. The service is served over TLS with `tls`. With
+/// `auth`, calls from unknown clients are refused and clients only see the
+/// domains they are allowed.
+pub fn serve(
+    addr: SocketAddr,
+    root: &Path,
+    builder: MessageTreeDumperBuilder,
+    allowed_domains: Option<Arc<AllowedDomains>>,
+    tls: Option<ServerTlsConfig>,
+    auth: Option<Auth>,
+    lang: QueryLang,
+) -> Fallible<()> {
+    let service = Service {
+        root: Arc::new(root.canonicalize()?),
+        builder: Arc::new(builder),
+        allowed_domains,
+        auth: auth.map(Arc::new),
+        lang,
+    };
+    info!("serving {} on {}", service.root.display(), addr);
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls)?;
+    }
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(
+        server
+            .add_service(DumpCatServer::new(service.clone()))
+            .add_service(FlightServiceServer::new(service))
+            .serve(addr),
+    )?;
+    Ok(())
+}
+
+#[derive(Clone)]
+struct Service {
+    root: Arc<PathBuf>,
+    builder: Arc<MessageTreeDumperBuilder>,
+    allowed_domains: Option<Arc<AllowedDomains>>,
+    auth: Option<Arc<Auth>>,
+    lang: QueryLang,
+}
+
+#[tonic::async_trait]
+impl DumpCat for Service {
+    type QueryStream = ReceiverStream<Result<proto::MessageTree, Status>>;
+
+    async fn query(
+        &self,
+        request: Request<Streaming<QueryRequest>>,
+    ) -> Result<Response<Self::QueryStream>, Status> {
+        let client = self
+            .authenticate(&request)
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+        let mut requests = request.into_inner();
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match requests.message().await {
+                    Ok(Some(request)) => {
+                        let service = service.clone();
+                        let sender = sender.clone();
+                        let client = client.clone();
+                        tokio::task::spawn_blocking(move || service.run(request, client, sender));
+                    }
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = sender.send(Err(status)).await;
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for Service {
+    type DoGetStream = ReceiverStream<Result<FlightData, Status>>;
+
+    /// Streams the schema of the columns of the ticket, then the matched
+    /// trees as record batches.
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let client = self
+            .authenticate(&request)
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+        let ticket: FlightTicket = serde_json::from_slice(&request.into_inner().ticket)
+            .map_err(|e| Status::invalid_argument(format!("invalid ticket: {}", e)))?;
+        let columns = match ticket.columns.is_empty() {
+            true => csv::DEFAULT_COLUMNS.to_vec(),
+            false => ticket
+                .columns
+                .iter()
+                .map(|c| c.parse::<Column>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+        };
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        let service = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Some(client) = &client {
+                info!(
+                    "{}: flight {:?} {:?}",
+                    client.name, ticket.path, ticket.query
+                );
+            }
+            let encoder = Encoder::new(columns);
+            if let Err(e) = service.stream_batches(&ticket, &encoder, client.as_deref(), &sender) {
+                let _ = sender.blocking_send(Err(Status::invalid_argument(e.to_string())));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+impl Service {
+    /// The client making `request`, `None` without `--auth`.
+    fn authenticate<T>(&self, request: &Request<T>) -> Fallible<Option<Arc<Client>>> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Ok(None),
+        };
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let certificates = request.peer_certs();
+        let certificate = certificates
+            .as_ref()
+            .and_then(|certificates| certificates.first())
+            .map(|certificate| certificate.as_ref());
+        match auth.authenticate(token, certificate) {
+            Some(client) => Ok(Some(client)),
+            None => Err(err_msg("unknown token or certificate")),
+        }
+    }
+
+    /// Decodes the file of `request` for `client`. An error ends the call
+    /// with its message.
+    fn run(&self, request: QueryRequest, client: Option<Arc<Client>>, sender: Sender) {
+        if let Some(client) = &client {
+            info!(
+                "{}: {} {:?} {:?}",
+                client.name, request.request_id, request.path, request.query
+            );
+        }
+        if let Err(e) = self.decode(&request, client.as_deref(), &sender) {
+            let _ = sender.blocking_send(Err(Status::invalid_argument(format!(
+                "{}: {}",
+                request.request_id, e
+            ))));
+        }
+    }
+
+    fn decode(
+        &self,
+        request: &QueryRequest,
+        client: Option<&Client>,
+        sender: &Sender,
+    ) -> Fallible<()> {
+        let matches = Matches {
+            path: &request.path,
+            query: &request.query,
+            limit: request.limit,
+            message_ids: &request.message_ids,
+        };
+        self.for_each_match(&matches, client, |tree| {
+            let tree = proto::MessageTree::new(&request.request_id, tree, &tree.message);
+            // Stops once the client went away.
+            Ok(sender.blocking_send(Ok(tree)).is_ok())
+        })
+    }
+
+    /// Sends the schema of `encoder`, then the trees matching `ticket` in
+    /// record batches.
+    fn stream_batches(
+        &self,
+        ticket: &FlightTicket,
+        encoder: &Encoder,
+        client: Option<&Client>,
+        sender: &FlightSender,
+    ) -> Fallible<()> {
+        let send = |message: IpcMessage| {
+            let data = FlightData {
+                data_header: message.metadata,
+                app_metadata: vec![],
+                data_body: message.body,
+            };
+            sender.blocking_send(Ok(data)).is_ok()
+        };
+        if !send(encoder.schema()) {
+            return Ok(());
+        }
+        let batch_size = ticket.batch_size.unwrap_or(FLIGHT_BATCH_SIZE).max(1);
+        let mut rows = vec![];
+        let matches = Matches {
+            path: &ticket.path,
+            query: &ticket.query,
+            limit: ticket.limit,
+            message_ids: &ticket.message_ids,
+        };
+        let mut connected = true;
+        self.for_each_match(&matches, client, |tree| {
+            rows.push(encoder.row(tree));
+            if rows.len() >= batch_size {
+                connected = send(encoder.batch(&std::mem::take(&mut rows))?);
+            }
+            Ok(connected)
+        })?;
+        if connected && !rows.is_empty() {
+            send(encoder.batch(&rows)?);
+        }
+        Ok(())
+    }
+
+    /// Decodes the file of `matches` for `client` and calls `f` with the
+    /// matched trees, until it returns false.
+    fn for_each_match(
+        &self,
+        matches: &Matches<'_>,
+        client: Option<&Client>,
+        mut f: impl FnMut(&MessageTree) -> Fallible<bool>,
+    ) -> Fallible<()> {
+        let path = self.root.join(matches.path).canonicalize()?;
+        if !path.starts_with(&*self.root) {
+            bail!("{} is outside of the served directory", matches.path);
+        }
+        let query = Some(matches.query).filter(|q| !q.is_empty());
+        let filter = Filter::compile(query, self.lang)?;
+        let ids: HashSet<&str> = matches.message_ids.iter().map(String::as_str).collect();
+
+        let client_domains = client.and_then(|client| client.allowed_domains.as_ref());
+        let allowed_domains = match (&self.allowed_domains, client_domains) {
+            (Some(served), Some(client)) => Some(Arc::new(served.intersection(client))),
+            (served, client) => served.clone().or_else(|| client.cloned()),
+        };
+        let mut builder = (*self.builder).clone();
+        builder.path(path).allowed_domains(allowed_domains);
+        let dumper = builder.build().map_err(err_msg)?;
+        let mut remaining = if matches.limit == 0 {
+            u64::MAX
+        } else {
+            matches.limit
+        };
+        for tree in dumper.read_trees() {
+            if remaining == 0 {
+                break;
+            }
+            if !ids.is_empty() && !ids.contains(tree.message_id.as_str()) {
+                continue;
+            }
+            if !filter.matches(&tree)? {
+                continue;
+            }
+            if !f(&tree)? {
+                break;
+            }
+            remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// The trees asked for by a `QueryRequest` or a Flight ticket.
+struct Matches<'a> {
+    /// Logview file, relative to the root directory.
+    path: &'a str,
+    query: &'a str,
+    /// Maximum number of trees, 0 for no limit.
+    limit: u64,
+    message_ids: &'a [String],
+}
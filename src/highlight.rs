@@ -0,0 +1,160 @@
+use std::env;
+use std::str::FromStr;
+
+use failure::{bail, format_err, Fallible};
+
+use crate::filter::Filter;
+use crate::message_tree::MessageTree;
+use crate::query::QueryLang;
+
+/// The ANSI escape sequence switching back to the default color.
+pub const RESET: &str = "\x1b[0m";
+
+/// Foreground colors of the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+const COLORS: &[(&str, Color)] = &[
+    ("black", Color::Black),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("white", Color::White),
+];
+
+impl Color {
+    /// The ANSI escape sequence switching to the color.
+    pub fn escape(self) -> &'static str {
+        match self {
+            Color::Black => "\x1b[30m",
+            Color::Red => "\x1b[31m",
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+            Color::Blue => "\x1b[34m",
+            Color::Magenta => "\x1b[35m",
+            Color::Cyan => "\x1b[36m",
+            Color::White => "\x1b[37m",
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        COLORS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, color)| *color)
+            .ok_or_else(|| {
+                let names: Vec<_> = COLORS.iter().map(|(name, _)| *name).collect();
+                format_err!("unknown color {}, expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+/// When to color the output, `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only on a terminal, and unless `NO_COLOR` is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color the output, `to_stdout` when it goes to stdout
+    /// rather than to `--output`.
+    pub fn enabled(self, to_stdout: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && to_stdout
+                    && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "auto" => ColorChoice::Auto,
+            "always" => ColorChoice::Always,
+            "never" => ColorChoice::Never,
+            _ => bail!("unknown color choice {}, expected auto, always or never", s),
+        })
+    }
+}
+
+/// A `--highlight` rule, `<query>:<color>`, e.g.
+/// `status_class == "server-error":red`.
+///
+/// The color follows the last colon, so the query may hold colons.
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    pub query: String,
+    pub color: Color,
+}
+
+impl FromStr for Highlight {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let (query, color) = s.rsplit_once(':').ok_or_else(|| {
+            format_err!(
+                "invalid highlight {}, e.g. 'transaction.duration_in_ms > 1000':yellow",
+                s
+            )
+        })?;
+        if query.trim().is_empty() {
+            bail!("highlight {} has no query", s);
+        }
+        Ok(Highlight {
+            query: query.to_string(),
+            color: color.trim().parse()?,
+        })
+    }
+}
+
+/// The compiled `--highlight` rules, giving trees the color of the first
+/// rule they match.
+///
+/// A rule that fails on a tree, e.g. referencing a variable unset for it
+/// like `transaction.duration_in_ms` for an event, doesn't match it.
+pub struct Highlighter {
+    rules: Vec<(Filter, Color)>,
+}
+
+impl Highlighter {
+    pub fn compile(highlights: &[Highlight], lang: QueryLang) -> Fallible<Self> {
+        let rules = highlights
+            .iter()
+            .map(|h| Ok((Filter::compile(Some(&h.query), lang)?, h.color)))
+            .collect::<Fallible<_>>()?;
+        Ok(Highlighter { rules })
+    }
+
+    pub fn color(&self, tree: &MessageTree) -> Option<Color> {
+        self.rules
+            .iter()
+            .find(|(filter, _)| filter.matches(tree).unwrap_or(false))
+            .map(|(_, color)| *color)
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Bits of the hash used to pick a register; the standard error is about
+/// `1.04 / sqrt(2^PRECISION)`, 1.6% here.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// HyperLogLog sketch (Flajolet et al.) estimating the number of distinct
+/// values in fixed memory.
+///
+/// Values are hashed with an unkeyed hasher, so sketches built by
+/// different threads can be merged.
+#[derive(Clone)]
+pub struct HyperLogLog {
+    registers: Box<[u8]>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS].into_boxed_slice(),
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn insert<T: Hash + ?Sized>(&mut self, value: &T) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The guard bit bounds the rank when the remaining bits are all 0.
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (a, b) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *a = (*a).max(*b);
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small cardinalities.
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(values: impl Iterator<Item = u64>) -> HyperLogLog {
+        let mut hll = HyperLogLog::default();
+        for value in values {
+            hll.insert(&value);
+        }
+        hll
+    }
+
+    /// Within 4 standard errors of `expected`.
+    fn assert_close(estimate: u64, expected: u64) {
+        let error = (estimate as f64 - expected as f64).abs() / expected as f64;
+        assert!(error < 0.065, "estimated {} for {}", estimate, expected);
+    }
+
+    #[test]
+    fn estimates() {
+        assert_eq!(HyperLogLog::default().estimate(), 0);
+        assert_close(sketch(0..1000).estimate(), 1000);
+        assert_close(sketch(0..100_000).estimate(), 100_000);
+        // Repeated values count once.
+        assert_close(sketch((0..50_000).chain(0..50_000)).estimate(), 50_000);
+    }
+
+    #[test]
+    fn merge_is_the_sketch_of_the_union() {
+        let mut merged = sketch(0..60_000);
+        merged.merge(&sketch(40_000..100_000));
+        assert_eq!(merged.registers, sketch(0..100_000).registers);
+        assert_close(merged.estimate(), 100_000);
+
+        // Merging again changes nothing.
+        let before = merged.registers.clone();
+        merged.merge(&sketch(0..100_000));
+        assert_eq!(merged.registers, before);
+    }
+}
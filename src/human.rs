@@ -0,0 +1,104 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether `--human` was given, set once at startup.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the functions of this module format for people rather than
+/// scripts.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether `--human` was given.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Thousands separator and decimal mark of the locale of `LC_ALL`,
+/// `LC_NUMERIC` or `LANG`, English ones by default.
+fn separators() -> (&'static str, char) {
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    let language = locale.split(['_', '.', '@']).next().unwrap_or("");
+    match language {
+        "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (".", ','),
+        "fr" | "ru" | "sv" | "pl" | "cs" | "fi" | "nb" | "uk" | "hu" => ("\u{202f}", ','),
+        _ => (",", '.'),
+    }
+}
+
+/// `n`, with thousands separators with `--human`.
+pub fn count(n: u64) -> String {
+    if !enabled() {
+        return n.to_string();
+    }
+    group(&n.to_string(), separators().0)
+}
+
+/// `n` with `decimals` decimals, with thousands separators and the decimal
+/// mark of the locale with `--human`.
+pub fn number(n: f64, decimals: usize) -> String {
+    let plain = format!("{:.*}", decimals, n);
+    if !enabled() {
+        return plain;
+    }
+    let (thousands, mark) = separators();
+    let (sign, digits) = match plain.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", plain.as_str()),
+    };
+    match digits.split_once('.') {
+        Some((int, fract)) => format!("{}{}{}{}", sign, group(int, thousands), mark, fract),
+        None => format!("{}{}", sign, group(digits, thousands)),
+    }
+}
+
+fn group(digits: &str, separator: &str) -> String {
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// `ms` like `340ms`, or in the largest unit it is at least one of with
+/// `--human`, like `1.2s` or `3.5h`.
+pub fn duration_ms(ms: f64) -> String {
+    if !enabled() {
+        return format!("{}ms", ms);
+    }
+    let units = [
+        (86_400_000.0, "d"),
+        (3_600_000.0, "h"),
+        (60_000.0, "m"),
+        (1000.0, "s"),
+    ];
+    for (size, unit) in units {
+        if ms.abs() >= size {
+            return format!("{}{}", number(ms / size, 1), unit);
+        }
+    }
+    format!("{}ms", number(ms, 0))
+}
+
+/// `bytes` like `1234 bytes`, or in decimal units with `--human`, like
+/// `1.1GB`.
+pub fn bytes(bytes: f64) -> String {
+    if !enabled() {
+        return format!("{} bytes", bytes);
+    }
+    let units = [(1e12, "TB"), (1e9, "GB"), (1e6, "MB"), (1e3, "KB")];
+    for (size, unit) in units {
+        if bytes >= size {
+            return format!("{}{}", number(bytes / size, 1), unit);
+        }
+    }
+    format!("{}B", number(bytes, 0))
+}
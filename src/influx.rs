@@ -0,0 +1,157 @@
+use std::io::Write;
+use std::sync::OnceLock;
+
+use failure::Fallible;
+use regex::Regex;
+
+use crate::message_tree::{Message, MessageTree};
+
+// Elements of heartbeat status XML, and their attributes.
+static ELEMENT: OnceLock<Regex> = OnceLock::new();
+static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+
+/// Writes the metrics and heartbeats of `message`, the root of `tree` or
+/// one under it, as InfluxDB line protocol, a point per message tagged with
+/// the domain and host of the tree.
+///
+/// The measurement of a metric is its name and its fields come from its
+/// status, CAT writing a count for `C`, a sum for `S`, a duration for `T`
+/// and a count and a sum for `S,C`. The measurement of a heartbeat is its
+/// type, tagged with its name, and its fields are the numeric attributes of
+/// its status XML, e.g. `memory.free`. Otherwise data like `a=1&b=x` gives
+/// fields `a` and `b`, and a lone number a field `value`. Messages without
+/// fields are left out, as a point needs one.
+pub fn write_points(tree: &MessageTree, message: &Message, out: &mut dyn Write) -> Fallible<()> {
+    let point = match message {
+        Message::Metric(m) => Some((
+            m.name.as_str(),
+            None,
+            metric_fields(&m.status, &m.data),
+            m.timestamp_in_ms,
+        )),
+        Message::Heartbeat(h) => Some((
+            h.ty.as_str(),
+            Some(h.name.as_str()),
+            heartbeat_fields(&h.data),
+            h.timestamp_in_ms,
+        )),
+        Message::Transaction(t) => {
+            for child in &t.children {
+                write_points(tree, child, out)?;
+            }
+            None
+        }
+        _ => None,
+    };
+    let (measurement, name, fields, timestamp_in_ms) = match point {
+        Some(point) if !point.2.is_empty() => point,
+        _ => return Ok(()),
+    };
+    let mut line = escape(measurement, ", ");
+    for (tag, value) in [
+        ("domain", Some(tree.domain.as_str())),
+        ("host", Some(tree.hostname.as_str())),
+        ("name", name),
+    ] {
+        match value {
+            Some(value) if !value.is_empty() => {
+                line.push_str(&format!(",{}={}", tag, escape(value, ", =")))
+            }
+            _ => {}
+        }
+    }
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", escape(key, ", ="), value))
+        .collect();
+    writeln!(
+        out,
+        "{} {} {}",
+        line,
+        fields.join(","),
+        timestamp_in_ms * 1_000_000
+    )?;
+    Ok(())
+}
+
+/// Fields of a metric, as keys and values in line protocol.
+fn metric_fields(status: &str, data: &str) -> Vec<(String, String)> {
+    let values: Vec<_> = data.split(',').map(str::trim).collect();
+    let keys: &[&str] = match (status, values.len()) {
+        ("C", 1) => &["count"],
+        ("S", 1) => &["sum"],
+        ("T", 1) => &["duration"],
+        ("S,C", 2) => &["count", "sum"],
+        _ => return data_fields(data),
+    };
+    let numbers: Option<Vec<f64>> = values.iter().map(|v| number(v)).collect();
+    match numbers {
+        Some(numbers) => keys
+            .iter()
+            .zip(numbers)
+            .map(|(key, n)| (key.to_string(), n.to_string()))
+            .collect(),
+        None => data_fields(data),
+    }
+}
+
+/// Fields of a heartbeat, the numeric attributes of its status XML or else
+/// from its data like a metric's.
+fn heartbeat_fields(data: &str) -> Vec<(String, String)> {
+    if !data.trim_start().starts_with('<') {
+        return data_fields(data);
+    }
+    let element = ELEMENT.get_or_init(|| Regex::new(r"<([\w.-]+)([^<>]*)>").expect("regex"));
+    let attribute =
+        ATTRIBUTE.get_or_init(|| Regex::new(r#"([\w.-]+)\s*=\s*"([^"]*)""#).expect("regex"));
+    let mut fields = vec![];
+    for tag in element.captures_iter(data) {
+        for attr in attribute.captures_iter(&tag[2]) {
+            if let Some(n) = number(&attr[2]) {
+                fields.push((format!("{}.{}", &tag[1], &attr[1]), n.to_string()));
+            }
+        }
+    }
+    fields
+}
+
+/// Fields of data like `a=1&b=x`, or `value` of data which is a number.
+fn data_fields(data: &str) -> Vec<(String, String)> {
+    let data = data.trim();
+    if let Some(n) = number(data) {
+        return vec![("value".to_string(), n.to_string())];
+    }
+    data.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| {
+            let value = match number(value) {
+                Some(n) => n.to_string(),
+                None => format!("\"{}\"", escape(value, "\\\"")),
+            };
+            (key.to_string(), value)
+        })
+        .collect()
+}
+
+/// `s` as a number, unless it is infinite or NaN, which InfluxDB rejects.
+fn number(s: &str) -> Option<f64> {
+    s.trim().parse().ok().filter(|n: &f64| n.is_finite())
+}
+
+/// `s` with the characters of `special` escaped by a backslash, and line
+/// breaks, which line protocol can't hold, replaced by spaces.
+fn escape(s: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' | '\r' => escaped.push(' '),
+            c if special.contains(c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,179 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+
+use failure::Fallible;
+use log::info;
+
+use crate::fields::Field;
+use crate::filter::Filter;
+use crate::message_tree::MessageTree;
+use crate::message_tree_dumper::MessageTreeDumper;
+
+/// Number of partitions both inputs are split into once spilled; each
+/// partition of the left input has to fit in memory.
+const PARTITIONS: u64 = 64;
+
+/// Emits a record for every pair of trees, one from each input, with the
+/// same value of `on`.
+///
+/// The left input is hashed in memory. When it holds more than
+/// `max_in_memory` matched trees, both inputs are partitioned by key into
+/// temporary files and joined one partition at a time (a grace hash join).
+pub struct Join<'a> {
+    pub on: Field,
+    pub filter: &'a Filter,
+    pub json: bool,
+    pub max_in_memory: usize,
+}
+
+/// One side of a partition: `[key, rendered message]` JSON lines.
+struct Partitions {
+    files: Vec<BufWriter<File>>,
+}
+
+impl Partitions {
+    fn new() -> Fallible<Self> {
+        let files = (0..PARTITIONS)
+            .map(|_| Ok(BufWriter::new(tempfile::tempfile()?)))
+            .collect::<Fallible<_>>()?;
+        Ok(Partitions { files })
+    }
+
+    fn write(&mut self, key: &str, record: &str) -> Fallible<()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let file = &mut self.files[(hasher.finish() % PARTITIONS) as usize];
+        serde_json::to_writer(&mut *file, &(key, record))?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Readers of every partition, in the same order for both inputs.
+    fn into_readers(self) -> Fallible<Vec<BufReader<File>>> {
+        self.files
+            .into_iter()
+            .map(|w| {
+                let mut file = w.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(BufReader::new(file))
+            })
+            .collect()
+    }
+}
+
+fn read_partition(reader: BufReader<File>) -> impl Iterator<Item = Fallible<(String, String)>> {
+    reader.lines().map(|line| Ok(serde_json::from_str(&line?)?))
+}
+
+impl<'a> Join<'a> {
+    /// Key and rendered message of `tree`, `None` when it's filtered out or
+    /// has no key.
+    fn record(&self, tree: &MessageTree) -> Fallible<Option<(String, String)>> {
+        if !self.filter.matches(tree)? {
+            return Ok(None);
+        }
+        let key = match self.on.value(tree) {
+            Some(key) if !key.is_empty() => key,
+            _ => return Ok(None),
+        };
+        let record = if self.json {
+            serde_json::to_string(&tree.message)?
+        } else {
+            tree.message.to_string()
+        };
+        Ok(Some((key, record)))
+    }
+
+    fn emit(&self, out: &mut dyn Write, key: &str, left: &str, right: &str) -> Fallible<()> {
+        if self.json {
+            writeln!(
+                out,
+                r#"{{"key":{},"left":{},"right":{}}}"#,
+                serde_json::to_string(key)?,
+                left,
+                right
+            )?;
+        } else {
+            writeln!(out, "{}\t{}\t{}", key, left, right)?;
+        }
+        Ok(())
+    }
+
+    /// Joins the two inputs and returns the number of emitted records.
+    pub fn run(
+        &self,
+        left: MessageTreeDumper,
+        right: MessageTreeDumper,
+        out: &mut dyn Write,
+    ) -> Fallible<u64> {
+        let mut table: HashMap<String, Vec<String>> = HashMap::new();
+        let mut in_memory = 0;
+        let mut spilled: Option<Partitions> = None;
+        for tree in left.read_trees() {
+            let (key, record) = match self.record(&tree)? {
+                Some(r) => r,
+                None => continue,
+            };
+            if let Some(partitions) = &mut spilled {
+                partitions.write(&key, &record)?;
+                continue;
+            }
+            table.entry(key).or_default().push(record);
+            in_memory += 1;
+            if in_memory > self.max_in_memory {
+                info!("join: spilling the left input to disk");
+                let mut partitions = Partitions::new()?;
+                for (key, records) in table.drain() {
+                    for record in records {
+                        partitions.write(&key, &record)?;
+                    }
+                }
+                spilled = Some(partitions);
+            }
+        }
+
+        let mut emitted = 0;
+        let left_partitions = match spilled {
+            None => {
+                for tree in right.read_trees() {
+                    if let Some((key, record)) = self.record(&tree)? {
+                        for left in table.get(&key).into_iter().flatten() {
+                            self.emit(out, &key, left, &record)?;
+                            emitted += 1;
+                        }
+                    }
+                }
+                return Ok(emitted);
+            }
+            Some(partitions) => partitions.into_readers()?,
+        };
+
+        let mut right_partitions = Partitions::new()?;
+        for tree in right.read_trees() {
+            if let Some((key, record)) = self.record(&tree)? {
+                right_partitions.write(&key, &record)?;
+            }
+        }
+        for (left, right) in left_partitions
+            .into_iter()
+            .zip(right_partitions.into_readers()?)
+        {
+            let mut table: HashMap<String, Vec<String>> = HashMap::new();
+            for entry in read_partition(left) {
+                let (key, record) = entry?;
+                table.entry(key).or_default().push(record);
+            }
+            for entry in read_partition(right) {
+                let (key, record) = entry?;
+                for left in table.get(&key).into_iter().flatten() {
+                    self.emit(out, &key, left, &record)?;
+                    emitted += 1;
+                }
+            }
+        }
+        Ok(emitted)
+    }
+}
@@ -0,0 +1,84 @@
+//! Decoding, filtering and reporting on the logview files of CAT, the
+//! library behind the `dump-cat` command.
+//!
+//! Trees are read with `message_tree_dumper::MessageTreeDumper` and written
+//! to a `sink::Sink`, the built-in ones being those of the command line.
+
+pub mod acl;
+pub mod alert;
+pub mod amqp;
+pub mod arrow;
+pub mod auth;
+pub mod auto_tune;
+pub mod avro;
+pub mod backfill;
+pub mod batch_poster;
+pub mod buffers;
+pub mod bundle;
+pub mod catalog;
+pub mod cel;
+pub mod clickhouse;
+pub mod compare_tree;
+pub mod convert;
+pub mod critical_path;
+pub mod csv;
+pub mod elasticsearch;
+pub mod estimate;
+pub mod explain;
+pub mod fetch;
+pub mod fields;
+pub mod filter;
+pub mod grpc;
+pub mod highlight;
+pub mod hll;
+pub mod human;
+pub mod influx;
+pub mod join;
+pub mod logging;
+pub mod logview_writer;
+pub mod mermaid;
+pub mod message_id;
+pub mod message_tree;
+pub mod message_tree_dumper;
+pub mod msgpack;
+pub mod numa;
+pub mod otlp;
+pub mod output;
+pub mod parquet;
+pub mod path_of;
+pub mod payload;
+pub mod pipeline;
+pub mod prometheus;
+pub mod proto;
+pub mod prune;
+pub mod query;
+pub mod rate_limit;
+pub mod remote_call;
+pub mod repair;
+pub mod repl;
+pub mod report;
+pub mod result_cache;
+pub mod rollup;
+pub mod run_manifest;
+pub mod running;
+pub mod sampler;
+pub mod scan;
+pub mod selector;
+pub mod show;
+pub mod sidecar;
+pub mod sink;
+pub mod speedscope;
+pub mod sqlite;
+pub mod stacktrace;
+pub mod status_class;
+pub mod summarize;
+pub mod support_bundle;
+pub mod syslog;
+pub mod table;
+pub mod tee;
+pub mod template;
+pub mod threads;
+pub mod tls;
+pub mod topk;
+pub mod validate;
+pub mod webhook;
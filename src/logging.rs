@@ -0,0 +1,95 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use env_logger::Env;
+use failure::{bail, Fallible};
+use log::kv::{self, Key, Value, VisitSource};
+use serde_json::{json, Map};
+
+/// Format of the diagnostics written to stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LogFormat {
+    /// The one of env_logger, for humans.
+    #[default]
+    Text,
+    /// One JSON object per line, for wrappers parsing them.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format {}, expected text or json", s),
+        }
+    }
+}
+
+/// Logs at the level of `RUST_LOG`, warn by default, in `format`.
+///
+/// A JSON line has the time in ms, the level, the stage, which is the
+/// module logging unless given as a `stage` field, the message and the
+/// fields of the record, like `offset`, `block` or `error_kind`:
+///
+/// ```text
+/// {"ts":1715400000037,"level":"WARN","stage":"output","message":"...","error_kind":"BrokenPipe"}
+/// ```
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::from_env(Env::default().default_filter_or("warn"));
+    if let LogFormat::Json = format {
+        builder.format(|buf, record| {
+            let mut fields = Fields(Map::new());
+            // Fields that fail to convert are left out.
+            let _ = record.key_values().visit(&mut fields);
+            let stage = fields.0.remove("stage").unwrap_or_else(|| {
+                let module = record.module_path().unwrap_or_else(|| record.target());
+                json!(module.rsplit("::").next().unwrap_or(module))
+            });
+            let mut line = Map::new();
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64);
+            line.insert("ts".to_string(), json!(ts));
+            line.insert("level".to_string(), json!(record.level().to_string()));
+            line.insert("stage".to_string(), stage);
+            line.insert("message".to_string(), json!(record.args().to_string()));
+            line.extend(fields.0);
+            writeln!(buf, "{}", serde_json::Value::Object(line))
+        });
+    }
+    builder.init();
+}
+
+/// What went wrong in `e`, e.g. `ConnectionRefused` for an I/O error, else
+/// the type of the error, for the `error_kind` field.
+pub fn error_kind(e: &failure::Error) -> String {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(io) => format!("{:?}", io.kind()),
+        None => {
+            let name = e.as_fail().name().unwrap_or("unknown");
+            name.rsplit("::").next().unwrap_or(name).to_string()
+        }
+    }
+}
+
+struct Fields(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            json!(n)
+        } else if let Some(n) = value.to_i64() {
+            json!(n)
+        } else if let Some(b) = value.to_bool() {
+            json!(b)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::Write;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::Fallible;
+
+use crate::message_tree::MessageTree;
+
+/// Header of every block, skipped by the reader.
+pub const BLOCK_HEADER: &[u8; 16] = b"\xff\x06\x00\x00sNaPpY\x00\x00\x00\x00\x00\x00";
+
+/// Encoded trees per block, before compression.
+const BLOCK_SIZE: usize = 256 * 1024;
+
+/// Length of the magic number the file starts with.
+const MAGIC_LEN: u64 = 4;
+
+/// Encoded trees per snappy chunk of a block.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes trees as a logview file that `MessageTreeDumper` can read.
+pub struct LogviewWriter<W: Write> {
+    out: W,
+    /// Bytes of the file written so far, where the block being filled goes.
+    position: u64,
+    block_size: usize,
+    /// Length-prefixed encoded trees of the block being filled.
+    block: Vec<u8>,
+    tree: Vec<u8>,
+}
+
+impl<W: Write> LogviewWriter<W> {
+    pub fn new(mut out: W) -> Fallible<Self> {
+        out.write_i32::<BigEndian>(-1)?;
+        Ok(Self::append(out, MAGIC_LEN))
+    }
+
+    /// Writes more blocks to a logview file of `len` bytes, `out` being at
+    /// its end.
+    pub fn append(out: W, len: u64) -> Self {
+        LogviewWriter {
+            out,
+            position: len,
+            block_size: BLOCK_SIZE,
+            block: vec![],
+            tree: vec![],
+        }
+    }
+
+    /// Starts a new block once the trees of the current one take
+    /// `block_size` bytes, instead of 256KB.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Adds `tree` to the block being filled and returns where it is: the
+    /// offset of the block in the file and of the tree in the decompressed
+    /// block.
+    pub fn write(&mut self, tree: &MessageTree) -> Fallible<(u64, usize)> {
+        let mut encoded = std::mem::take(&mut self.tree);
+        encoded.clear();
+        tree.encode(&mut encoded);
+        let location = self.write_encoded(&encoded);
+        self.tree = encoded;
+        location
+    }
+
+    /// Like `write`, for a tree already encoded, e.g. copied as is from
+    /// another logview.
+    pub fn write_encoded(&mut self, tree: &[u8]) -> Fallible<(u64, usize)> {
+        let location = (self.position, self.block.len());
+        self.block.write_i32::<BigEndian>(tree.len() as i32)?;
+        self.block.extend_from_slice(tree);
+        if self.block.len() >= self.block_size {
+            self.flush_block()?;
+        }
+        Ok(location)
+    }
+
+    fn flush_block(&mut self) -> Fallible<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let mut block = BLOCK_HEADER.to_vec();
+        let mut encoder = snap::Encoder::new();
+        for chunk in self.block.chunks(CHUNK_SIZE) {
+            let compressed = encoder.compress_vec(chunk)?;
+            block.write_i32::<BigEndian>(compressed.len() as i32)?;
+            block.extend_from_slice(&compressed);
+        }
+        self.out.write_i32::<BigEndian>(block.len() as i32)?;
+        self.out.write_all(&block)?;
+        self.position += 4 + block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and returns the underlying writer.
+    pub fn finish(mut self) -> Fallible<W> {
+        self.flush_block()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::message_tree::{InnerEvent, InnerTransaction, Message, TreeLocation};
+    use crate::message_tree_dumper::{read_block_at, try_read_block, MessageBlockReader};
+
+    fn tree(i: u64) -> MessageTree {
+        let event = Message::Event(Arc::new(InnerEvent {
+            ty: "Error".to_string(),
+            name: format!("java.lang.Exception{}", i),
+            timestamp_in_ms: 1_715_400_000_100 + i,
+            status: "ERROR".to_string(),
+            data: "stack".to_string(),
+            ..Default::default()
+        }));
+        let sql = Message::Transaction(Arc::new(InnerTransaction {
+            ty: "SQL".to_string(),
+            name: "select".to_string(),
+            timestamp_in_ms: 1_715_400_000_010 + i,
+            status: "0".to_string(),
+            duration_in_ms: 12,
+            ..Default::default()
+        }));
+        MessageTree {
+            domain: "shop-web".to_string(),
+            hostname: "host".to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            message_id: format!("shop-web-0a000001-475000-{}", i),
+            message: Message::Transaction(Arc::new(InnerTransaction {
+                ty: "URL".to_string(),
+                name: format!("/api/item/{}", i),
+                timestamp_in_ms: 1_715_400_000_000 + i,
+                status: "0".to_string(),
+                data: "a=1".to_string(),
+                duration_in_ms: 140 + i,
+                children: vec![sql, event],
+                summary: None,
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        // Small blocks, for the trees to span several of them.
+        let mut writer = LogviewWriter::new(vec![]).unwrap().block_size(1024);
+        let written: Vec<_> = (0..50).map(tree).collect();
+        let locations: Vec<_> = written.iter().map(|t| writer.write(t).unwrap()).collect();
+        let file = writer.finish().unwrap();
+
+        let blocks: Vec<_> = MessageBlockReader::new(Cursor::new(file))
+            .unwrap()
+            .into_blocks()
+            .collect();
+        assert!(blocks.len() > 1);
+        let read: Vec<_> = blocks
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, (block_offset, block))| {
+                let at = TreeLocation {
+                    block_offset,
+                    block: index as u32,
+                    ..TreeLocation::default()
+                };
+                read_block_at(&at, block, None)
+            })
+            .collect();
+
+        assert_eq!(read.len(), written.len());
+        for ((read, written), (block_offset, _)) in read.iter().zip(&written).zip(&locations) {
+            assert_eq!(read.location.block_offset, *block_offset);
+            assert_eq!(read.domain, written.domain);
+            assert_eq!(read.hostname, written.hostname);
+            assert_eq!(read.message_id, written.message_id);
+            assert_eq!(read.message.to_string(), written.message.to_string());
+            let (Message::Transaction(read), Message::Transaction(written)) =
+                (&read.message, &written.message)
+            else {
+                panic!("root isn't a transaction");
+            };
+            assert_eq!(read.data, written.data);
+            assert_eq!(read.children.len(), written.children.len());
+            for (read, written) in read.children.iter().zip(&written.children) {
+                assert_eq!(read.to_string(), written.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_block_fails() {
+        let mut writer = LogviewWriter::new(vec![]).unwrap();
+        let mut encoded = vec![];
+        tree(0).encode(&mut encoded);
+        writer.write_encoded(&encoded).unwrap();
+        let file = writer.finish().unwrap();
+        let (_, block) = MessageBlockReader::new(Cursor::new(file))
+            .unwrap()
+            .into_blocks()
+            .next()
+            .unwrap();
+
+        let trees = try_read_block(block.clone()).unwrap();
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].1, encoded);
+
+        let mut corrupt = block;
+        let middle = corrupt.len() / 2;
+        corrupt[middle..].fill(0);
+        assert!(try_read_block(corrupt).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::{bail, Fallible};
+
+use crate::message_tree::{InnerTransaction, Message, MessageTree};
+
+/// Writes the transactions of `tree` as a Mermaid sequence diagram, e.g. for
+/// a wiki page.
+///
+/// Participants are the types of the transactions. Each transaction is a
+/// call from its parent's type to its own, activating it until a return
+/// labeled with its duration, and its status when it failed, so nesting
+/// shows as stacked activations.
+pub fn write_sequence(tree: &MessageTree, out: &mut dyn Write) -> Fallible<()> {
+    let root = match &tree.message {
+        Message::Transaction(t) => t,
+        _ => bail!("the root of {} isn't a transaction", tree.message_id),
+    };
+    let mut diagram = Diagram::default();
+    diagram.participant(&root.ty);
+    diagram.add(root);
+
+    writeln!(out, "sequenceDiagram")?;
+    for (index, ty) in diagram.participants.iter().enumerate() {
+        writeln!(out, "    participant P{} as {}", index, escape(ty))?;
+    }
+    writeln!(
+        out,
+        "    Note over P0: {} {}",
+        escape(&root.name),
+        outcome(root)
+    )?;
+    for line in &diagram.lines {
+        writeln!(out, "    {}", line)?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Diagram {
+    /// Types of the transactions, and their indexes by type.
+    participants: Vec<String>,
+    indexes: HashMap<String, usize>,
+    lines: Vec<String>,
+}
+
+impl Diagram {
+    fn participant(&mut self, ty: &str) -> usize {
+        let next = self.participants.len();
+        let index = *self.indexes.entry(ty.to_string()).or_insert(next);
+        if index == next {
+            self.participants.push(ty.to_string());
+        }
+        index
+    }
+
+    /// Adds the calls of the transactions under `t`, in the order they
+    /// started.
+    fn add(&mut self, t: &InnerTransaction) {
+        let caller = self.participant(&t.ty);
+        let mut children: Vec<_> = t
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Message::Transaction(t) => Some(t),
+                _ => None,
+            })
+            .collect();
+        children.sort_by_key(|child| child.timestamp_in_ms);
+        for child in children {
+            let callee = self.participant(&child.ty);
+            self.lines.push(format!(
+                "P{}->>+P{}: {}",
+                caller,
+                callee,
+                escape(&child.name)
+            ));
+            self.add(child);
+            self.lines
+                .push(format!("P{}-->>-P{}: {}", callee, caller, outcome(child)));
+        }
+    }
+}
+
+fn outcome(t: &InnerTransaction) -> String {
+    if t.status == "0" {
+        format!("{}ms", t.duration_in_ms)
+    } else {
+        format!("{}ms, {}", t.duration_in_ms, escape(&t.status))
+    }
+}
+
+/// `s` on one line, with the characters Mermaid would parse as syntax as
+/// entity codes.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ';' | '#' | ':' | '<' | '>' | '%' => escaped.push_str(&format!("#{};", c as u32)),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
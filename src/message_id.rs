@@ -0,0 +1,47 @@
+use std::net::Ipv4Addr;
+
+use crate::message_tree::Text;
+
+/// The parts of a CAT message id: `{domain}-{hex ip}-{hour}-{index}`.
+///
+/// The domain may itself contain `-`, so the id is split from the right.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParsedMessageId {
+    pub domain: Text,
+    pub ip_hex: Text,
+    /// Hours since the epoch when the id was created.
+    pub hour: u64,
+    /// Sequence number of the id within the hour, per domain and ip.
+    pub index: u64,
+}
+
+impl ParsedMessageId {
+    pub fn parse(id: &str) -> Option<Self> {
+        let mut parts = id.rsplitn(4, '-');
+        let index = parts.next()?.parse().ok()?;
+        let hour = parts.next()?.parse().ok()?;
+        let ip_hex = parts.next()?;
+        let domain = parts.next()?;
+        if ip_hex.len() != 8 || u32::from_str_radix(ip_hex, 16).is_err() || domain.is_empty() {
+            return None;
+        }
+        Some(ParsedMessageId {
+            domain: domain.to_string(),
+            ip_hex: ip_hex.to_string(),
+            hour,
+            index,
+        })
+    }
+
+    /// The producer ip in dotted notation.
+    pub fn ip(&self) -> Text {
+        u32::from_str_radix(&self.ip_hex, 16)
+            .map(|ip| Ipv4Addr::from(ip).to_string())
+            .unwrap_or_else(|_| self.ip_hex.clone())
+    }
+
+    /// Start of the hour the id was created in.
+    pub fn hour_timestamp_in_ms(&self) -> u64 {
+        self.hour * 3_600_000
+    }
+}
@@ -1,9 +1,9 @@
-use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
-use std::io::{Error, Read};
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
 
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
-use failure::Fallible;
+use failure::{bail, format_err, Fallible};
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -18,6 +18,10 @@ pub struct InnerEvent {
     pub name: Text,
     pub timestamp_in_ms: u64,
     pub data: Text,
+    /// Number of identical consecutive events this one stands for, when
+    /// they were collapsed by `Message::collapse_repeats`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeats: Option<u32>,
 }
 
 impl InnerEvent {
@@ -34,6 +38,7 @@ impl InnerEvent {
             timestamp_in_ms: ts,
             status: status.into(),
             data: data.into(),
+            repeats: None,
         }
     }
 }
@@ -49,6 +54,10 @@ pub struct InnerTransaction {
     pub data: Text,
     pub duration_in_ms: u64,
     pub children: Vec<Message>,
+    /// Short form of `data`, which is then left empty, when it was
+    /// summarized by `summarize::Summarizers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Text>,
 }
 
 impl InnerTransaction {
@@ -64,6 +73,39 @@ impl InnerTransaction {
     pub fn add_child(&mut self, message: Message) {
         self.children.push(message);
     }
+
+    /// Pairs of indexes of children whose time ranges overlap, which happens
+    /// when the client logs asynchronous work under this transaction.
+    ///
+    /// Only transactions have a time range. Overlaps up to 1ms are ignored as
+    /// timestamps are truncated to milliseconds.
+    pub fn overlapping_children(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(u64, u64, usize)> = self
+            .children
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                let start = c.timestamp_in_ms();
+                c.duration_in_ms().map(|d| (start, start + d, i))
+            })
+            .collect();
+        ranges.sort_unstable();
+
+        let mut overlapping = vec![];
+        for (n, &(_, end, i)) in ranges.iter().enumerate() {
+            for &(start, _, j) in &ranges[n + 1..] {
+                if start + 1 >= end {
+                    break;
+                }
+                overlapping.push((i.min(j), i.max(j)));
+            }
+        }
+        overlapping
+    }
+
+    pub fn has_concurrent_children(&self) -> bool {
+        !self.overlapping_children().is_empty()
+    }
 }
 
 pub type Transaction = Arc<InnerTransaction>;
@@ -175,6 +217,15 @@ impl Message {
         }
     }
 
+    /// How many consecutive events this event stands for, see
+    /// `collapse_repeats`.
+    pub fn repeats(&self) -> Option<u32> {
+        match self {
+            Message::Event(e) => e.repeats,
+            _ => None,
+        }
+    }
+
     pub fn ty(&self) -> &Text {
         match self {
             Message::Event(e) => &e.ty,
@@ -195,14 +246,24 @@ impl Message {
         }
     }
 
-    pub fn ts(&self) -> i32 {
-        (match self {
-            Message::Event(e) => e.timestamp_in_ms / 1000,
-            Message::Transaction(e) => e.timestamp_in_ms / 1000,
-            Message::Trace(e) => e.timestamp_in_ms / 1000,
-            Message::Heartbeat(e) => e.timestamp_in_ms / 1000,
-            Message::Metric(e) => e.timestamp_in_ms / 1000,
-        }) as i32
+    pub fn data(&self) -> &Text {
+        match self {
+            Message::Event(e) => &e.data,
+            Message::Transaction(e) => &e.data,
+            Message::Trace(e) => &e.data,
+            Message::Heartbeat(e) => &e.data,
+            Message::Metric(e) => &e.data,
+        }
+    }
+
+    pub fn timestamp_in_ms(&self) -> u64 {
+        match self {
+            Message::Event(e) => e.timestamp_in_ms,
+            Message::Transaction(e) => e.timestamp_in_ms,
+            Message::Trace(e) => e.timestamp_in_ms,
+            Message::Heartbeat(e) => e.timestamp_in_ms,
+            Message::Metric(e) => e.timestamp_in_ms,
+        }
     }
 
     pub fn duration_in_ms(&self) -> Option<u64> {
@@ -211,6 +272,44 @@ impl Message {
             _ => None,
         }
     }
+
+    /// This message with the runs of consecutive children events of the
+    /// same type, name and status under every transaction replaced by the
+    /// first event of the run, counting them in `repeats`, e.g. for the
+    /// events of a retry loop.
+    pub fn collapse_repeats(&self) -> Message {
+        let t = match self {
+            Message::Transaction(t) => t,
+            _ => return self.clone(),
+        };
+        let mut children: Vec<Message> = Vec::with_capacity(t.children.len());
+        for child in &t.children {
+            if let (Message::Event(e), Some(Message::Event(last))) = (child, children.last_mut()) {
+                if (&e.ty, &e.name, &e.status) == (&last.ty, &last.name, &last.status) {
+                    let last = Arc::make_mut(last);
+                    last.repeats = Some(last.repeats.unwrap_or(1) + 1);
+                    continue;
+                }
+            }
+            children.push(child.collapse_repeats());
+        }
+        Message::Transaction(Arc::new(InnerTransaction {
+            children,
+            ..InnerTransaction::clone(t)
+        }))
+    }
+
+    /// Whether this message or any transaction under it has overlapping
+    /// children.
+    pub fn has_concurrent_children(&self) -> bool {
+        match self {
+            Message::Transaction(t) => {
+                t.has_concurrent_children()
+                    || t.children.iter().any(Message::has_concurrent_children)
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Display for Message {
@@ -218,27 +317,41 @@ impl Display for Message {
         use self::Message::*;
 
         match self {
-            Event(e) => f
-                .debug_struct("Event")
-                .field("timestamp_in_ms", &e.timestamp_in_ms)
-                .field("ty", &e.ty)
-                .field("name", &e.name)
-                .field("status", &e.status)
-                .field("data", &e.data)
-                .finish(),
-            Transaction(e) => f
-                .debug_struct("Transaction")
-                .field("timestamp_in_ms", &e.timestamp_in_ms)
-                .field("ty", &e.ty)
-                .field("name", &e.name)
-                .field("status", &e.status)
-                .field("duration_in_ms", &e.duration_in_ms)
-                .field("data", &e.data)
+            Event(e) => {
+                let mut s = f.debug_struct("Event");
+                s.field("timestamp_in_ms", &e.timestamp_in_ms)
+                    .field("ty", &e.ty)
+                    .field("name", &e.name)
+                    .field("status", &e.status)
+                    .field("data", &e.data);
+                if let Some(repeats) = e.repeats {
+                    s.field("repeats", &repeats);
+                }
+                s.finish()
+            }
+            Transaction(e) => {
+                let mut s = f.debug_struct("Transaction");
+                s.field("timestamp_in_ms", &e.timestamp_in_ms)
+                    .field("ty", &e.ty)
+                    .field("name", &e.name)
+                    .field("status", &e.status)
+                    .field("duration_in_ms", &e.duration_in_ms);
+                match &e.summary {
+                    Some(summary) => s.field("summary", summary),
+                    None => s.field("data", &e.data),
+                }
                 .field(
                     "children",
-                    &if e.children.is_empty() { "[]" } else { "[...]" },
+                    &if e.children.is_empty() {
+                        "[]"
+                    } else if e.has_concurrent_children() {
+                        "[...] (concurrent)"
+                    } else {
+                        "[...]"
+                    },
                 )
-                .finish(),
+                .finish()
+            }
             Heartbeat(e) => f
                 .debug_struct("Heartbeat")
                 .field("timestamp_in_ms", &e.timestamp_in_ms)
@@ -274,7 +387,27 @@ impl Default for Message {
     }
 }
 
+/// Where a tree is stored in a logview file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TreeLocation {
+    /// Offset of the block holding the tree, from the start of the file.
+    pub block_offset: u64,
+    /// Index of the block holding the tree in the file.
+    pub block: u32,
+    /// Index of the tree in its block.
+    pub index: u32,
+    /// Index of the snappy chunk of the block the tree starts in, and
+    /// offset of the tree, its length, in the decompressed chunk, to decode
+    /// it without decompressing the chunks before.
+    pub chunk: u32,
+    pub chunk_offset: u32,
+    /// The file, `<bundle>#<logview>` for a logview of a bundle, `None`
+    /// when the tree wasn't read from one, e.g. received from a server.
+    pub file: Option<Arc<Path>>,
+}
+
 #[derive(Debug, Default, Clone)]
+#[allow(dead_code)]
 pub struct MessageTree {
     pub domain: Text,
     pub hostname: Text,
@@ -296,6 +429,10 @@ pub struct MessageTree {
     pub heartbeats: Vec<Heartbeat>,
     pub metrics: Vec<Metric>,
     pub traces: Vec<Trace>,
+    pub location: TreeLocation,
+    /// When the tree was decoded, in ms since the epoch, 0 when it wasn't
+    /// decoded from a logview.
+    pub decoded_at_in_ms: u64,
 }
 
 impl MessageTree {
@@ -317,8 +454,20 @@ impl MessageTree {
     }
 
     pub fn decode<T: Read>(buf: &mut T) -> Fallible<MessageTree> {
+        Ok(Self::decode_if(buf, |_| true)?.expect("tree kept"))
+    }
+
+    /// Decodes the header of a tree, then its messages only when `keep`
+    /// accepts the header.
+    pub fn decode_if<T: Read>(
+        buf: &mut T,
+        keep: impl Fn(&MessageTree) -> bool,
+    ) -> Fallible<Option<MessageTree>> {
         let mut tree = MessageTree::default();
         decode_header(&mut tree, buf)?;
+        if !keep(&tree) {
+            return Ok(None);
+        }
         decode_message(&mut tree, &mut None, buf)?;
 
         tree.message = if !tree.transactions.is_empty() {
@@ -332,19 +481,101 @@ impl MessageTree {
         } else if !tree.traces.is_empty() {
             Message::Trace(tree.traces.last().unwrap().clone())
         } else {
-            unreachable!()
+            bail!("tree {} without messages", tree.message_id);
         };
 
-        Ok(tree)
+        Ok(Some(tree))
+    }
+
+    /// Encodes the header and the root message of the tree in the format
+    /// `decode` reads.
+    ///
+    /// Names `decode` rewrote, like the ones of `System` transactions, are
+    /// encoded as rewritten.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(ID.as_bytes());
+        for header in &[
+            &self.domain,
+            &self.hostname,
+            &self.ip_address,
+            &self.thread_group_name,
+            &self.thread_id,
+            &self.thread_name,
+            &self.message_id,
+            &self.parent_message_id,
+            &self.root_message_id,
+            &self.session_token,
+        ] {
+            write_string(buf, header);
+        }
+        encode_message(&self.message, buf);
     }
 }
 
+/// Walks the encoded tree in `buf` without decoding it, checking it's
+/// framed like `decode` expects, and calls `visit` with the tag of every
+/// message and the bytes it takes, without the ones of its children.
+///
+/// Strings are skipped by their length, so text which isn't UTF-8, which
+/// `decode` rejects, goes unnoticed.
+pub fn skim(mut buf: &[u8], mut visit: impl FnMut(u8, usize)) -> Fallible<()> {
+    let buf = &mut buf;
+    if skip(buf, ID.len())? != ID.as_bytes() {
+        bail!("unrecognized tree version");
+    }
+    // domain to session token
+    for _ in 0..10 {
+        skip_string(buf)?;
+    }
+    // Bytes of the start of the transactions not ended yet.
+    let mut open = vec![];
+    let mut messages = 0;
+    while let Some((&tag, rest)) = buf.split_first() {
+        let before = buf.len();
+        *buf = rest;
+        match tag {
+            b't' => {
+                read_varint(buf)?;
+                skip_string(buf)?;
+                skip_string(buf)?;
+                open.push(before - buf.len());
+            }
+            b'T' => {
+                let start = open
+                    .pop()
+                    .ok_or_else(|| format_err!("end of a transaction which didn't start"))?;
+                skip_string(buf)?;
+                skip_string(buf)?;
+                read_varint(buf)?;
+                visit(b't', start + before - buf.len());
+                messages += 1;
+            }
+            b'E' | b'M' | b'H' | b'L' => {
+                read_varint(buf)?;
+                for _ in 0..4 {
+                    skip_string(buf)?;
+                }
+                visit(tag, before - buf.len());
+                messages += 1;
+            }
+            _ => bail!("unsupported message type {:?}", tag as char),
+        }
+    }
+    if !open.is_empty() {
+        bail!("{} transactions not ended", open.len());
+    }
+    if messages == 0 {
+        bail!("tree without messages");
+    }
+    Ok(())
+}
+
 const ID: &str = "NT1";
 
 fn decode_header<T: Read>(tree: &mut MessageTree, buf: &mut T) -> Fallible<()> {
     let version = read_version(buf)?;
     if version != ID {
-        unimplemented!("Unrecognized version");
+        bail!("unrecognized tree version {:?}", version);
     }
     tree.domain = read_string(buf)?;
     tree.hostname = read_string(buf)?;
@@ -385,7 +616,7 @@ fn decode_message<T: Read>(
             b'M' => decode_metric(tree, transaction, buf)?,
             b'H' => decode_heartbeat(tree, transaction, buf)?,
             b'L' => decode_trace(tree, transaction, buf)?,
-            _ => unimplemented!("unsupported type"),
+            _ => bail!("unsupported message type {:?}", ch as char),
         }
     }
 
@@ -547,6 +778,49 @@ fn decode_trace<T: Read>(
     Ok(())
 }
 
+fn encode_message(message: &Message, buf: &mut Vec<u8>) {
+    let (tag, ts, ty, name, status, data) = match message {
+        Message::Transaction(t) => {
+            buf.push(b't');
+            write_varint(buf, t.timestamp_in_ms);
+            write_string(buf, &t.ty);
+            write_string(buf, &t.name);
+            for child in &t.children {
+                encode_message(child, buf);
+            }
+            buf.push(b'T');
+            write_string(buf, &t.status);
+            write_string(buf, &t.data);
+            write_varint(buf, t.duration_in_ms * 1000);
+            return;
+        }
+        Message::Event(e) => (b'E', e.timestamp_in_ms, &e.ty, &e.name, &e.status, &e.data),
+        Message::Metric(m) => (b'M', m.timestamp_in_ms, &m.ty, &m.name, &m.status, &m.data),
+        Message::Heartbeat(h) => (b'H', h.timestamp_in_ms, &h.ty, &h.name, &h.status, &h.data),
+        Message::Trace(t) => (b'L', t.timestamp_in_ms, &t.ty, &t.name, &t.status, &t.data),
+    };
+    buf.push(tag);
+    write_varint(buf, ts);
+    write_string(buf, ty);
+    write_string(buf, name);
+    write_string(buf, status);
+    write_string(buf, data);
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// The inverse of `read_varint`.
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0b1000_0000 {
+        buf.push((n as u8 & 0b0111_1111) | 0b1000_0000);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
 fn read_version<T: Read>(buf: &mut T) -> Fallible<Text> {
     let mut data = vec![0; 3];
     buf.read_exact(&mut data)?;
@@ -575,6 +849,22 @@ fn read_bytes<T: Read>(buf: &mut T) -> Fallible<Vec<u8>> {
     Ok(b)
 }
 
+/// Splits `len` bytes off `buf`.
+fn skip<'a>(buf: &mut &'a [u8], len: usize) -> Fallible<&'a [u8]> {
+    if buf.len() < len {
+        bail!("tree cut short");
+    }
+    let (skipped, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(skipped)
+}
+
+fn skip_string(buf: &mut &[u8]) -> Fallible<()> {
+    let len = read_varint(buf)?;
+    skip(buf, len as usize)?;
+    Ok(())
+}
+
 /// https://developers.google.com/protocol-buffers/docs/encoding#varints
 pub fn read_varint<T: Read>(data: &mut T) -> Fallible<u64> {
     let mut n: u64 = 0;
@@ -582,12 +872,12 @@ pub fn read_varint<T: Read>(data: &mut T) -> Fallible<u64> {
     loop {
         let b = data.read_u8()?;
         if b < 0b1000_0000 {
-            return match u64::try_from(b)?.checked_shl(shift) {
+            return match u64::from(b).checked_shl(shift) {
                 None => Ok(0),
                 Some(b) => Ok(n | b),
             };
         }
-        match (u64::try_from(b)? & 0b0111_1111).checked_shl(shift) {
+        match (u64::from(b) & 0b0111_1111).checked_shl(shift) {
             None => return Ok(0),
             Some(b) => n |= b,
         }
@@ -595,15 +885,25 @@ pub fn read_varint<T: Read>(data: &mut T) -> Fallible<u64> {
     }
 }
 
+/// Reads data prefixed by its length, `None` at the end of `reader`.
+/// Fails on a truncated or negative length.
 pub fn try_read_data<T: Read>(reader: &mut T) -> Result<Option<Vec<u8>>, Error> {
     let mut buf = [0; 4];
-    let size = reader.read(&mut buf)?;
-    if size == 0 {
-        return Ok(None);
-    } else if size != 4 {
-        panic!("read length error")
+    let mut size = 0;
+    while size < buf.len() {
+        match reader.read(&mut buf[size..])? {
+            0 if size == 0 => return Ok(None),
+            0 => return Err(Error::new(ErrorKind::UnexpectedEof, "truncated length")),
+            n => size += n,
+        }
     }
     let length = BigEndian::read_i32(&buf);
+    if length < 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("negative length {}", length),
+        ));
+    }
     let mut buf = vec![0; length as usize];
     reader.read_exact(&mut buf)?;
     Ok(Some(buf))
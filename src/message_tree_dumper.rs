@@ -1,23 +1,170 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Cursor, Error, Read, Write};
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{iter, thread};
 
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
 use bytes::BytesMut;
-use crossbeam::channel::{RecvTimeoutError, SendTimeoutError};
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use crossbeam::utils::Backoff;
 use derive_builder::Builder;
-use failure::Fallible;
+use failure::{bail, format_err, Fallible};
 use log::{debug, info};
 
-use crate::message_tree::{try_read_data, MessageTree};
+use crate::acl::AllowedDomains;
+use crate::buffers::{AdaptiveBuffer, StageBuffers};
+use crate::message_tree::{try_read_data, MessageTree, TreeLocation};
+use crate::numa::{self, Stage};
+use crate::sink::Sink;
 
-fn read_block(block: Vec<u8>) -> Vec<MessageTree> {
+pub fn read_block(block: Vec<u8>) -> Vec<MessageTree> {
+    read_block_at(&TreeLocation::default(), block, None)
+}
+
+/// Decodes the block stored at `at`, its file, index and offset, recording
+/// the location of every tree. Trees of domains not in `allowed_domains`
+/// are skipped right after their header.
+pub fn read_block_at(
+    at: &TreeLocation,
+    block: Vec<u8>,
+    allowed_domains: Option<&AllowedDomains>,
+) -> Vec<MessageTree> {
+    let decoded_at_in_ms = now_in_ms();
     let snappy_reader = SnappyReader::new(block);
     let tree_reader = MessageTreeReader::new(snappy_reader);
-    tree_reader.into_iter().collect()
+    let trees: Vec<_> = tree_reader
+        .into_iter(allowed_domains)
+        .enumerate()
+        .filter_map(|(index, tree)| {
+            let mut tree = tree?;
+            tree.location = TreeLocation {
+                index: index as u32,
+                chunk: tree.location.chunk,
+                chunk_offset: tree.location.chunk_offset,
+                ..at.clone()
+            };
+            tree.decoded_at_in_ms = decoded_at_in_ms;
+            Some(tree)
+        })
+        .collect();
+    debug!(stage = "decode", offset = at.block_offset, trees = trees.len(); "decoded block");
+    trees
+}
+
+fn now_in_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+/// Decodes every tree of `block` along with its encoded bytes, failing on
+/// a corrupt block where `read_block_at` panics, e.g. to rewrite a file
+/// only once all of it was read.
+pub fn try_read_block(block: Vec<u8>) -> Fallible<Vec<(MessageTree, Vec<u8>)>> {
+    let mut reader = SnappyReader::new(block);
+    reader.read_header()?;
+    let mut trees = vec![];
+    while let Some(data) = try_read_data(&mut reader)? {
+        let tree = MessageTree::decode(&mut data.as_slice())?;
+        trees.push((tree, data));
+    }
+    Ok(trees)
+}
+
+/// A block and where it is: its file, index and offset.
+type Block = (TreeLocation, Vec<u8>);
+
+/// Time a decoder without blocks sleeps between looks for more, once it
+/// is done spinning, and a stage with a full buffer between looks for room.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// A decoder waiting this long for room in a buffer which doesn't drain
+/// sends anyway, to find out if the trees are still read.
+const STUCK_WAIT: Duration = Duration::from_secs(1);
+
+/// The blocks read and not yet decoded, shared by the reader and the
+/// decoders.
+///
+/// Every decoder takes blocks from the queue in batches into its own
+/// deque, and steals from the deques of the others when the queue is
+/// empty, so a decoder stuck on a slow block doesn't keep the blocks it
+/// took after it from the idle ones.
+struct BlockQueue {
+    injector: Injector<Block>,
+    stealers: Vec<Stealer<Block>>,
+    /// Blocks in the queue or the deques, up to the buffer size.
+    queued: AtomicUsize,
+    /// Whether the reader is done, and how many decoders still run.
+    read_all: AtomicBool,
+    decoders: AtomicUsize,
+}
+
+impl BlockQueue {
+    fn next_block(&self, local: &Worker<Block>) -> Option<Block> {
+        let block = local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })?;
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        Some(block)
+    }
+
+    /// Whether every block is read and taken by a decoder.
+    fn is_done(&self) -> bool {
+        self.read_all.load(Ordering::Acquire) && self.queued.load(Ordering::Acquire) == 0
+    }
+}
+
+/// Marks the reader done when dropped, also when reading a block panics.
+struct Reading(Arc<BlockQueue>);
+
+impl Drop for Reading {
+    fn drop(&mut self) {
+        self.0.read_all.store(true, Ordering::Release);
+    }
+}
+
+/// Counts a decoder out when dropped, also when decoding a block panics.
+struct Decoding(Arc<BlockQueue>);
+
+impl Drop for Decoding {
+    fn drop(&mut self) {
+        self.0.decoders.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Waits until the trees in `sender` are under the limit of `buffer`, or
+/// don't drain for `STUCK_WAIT`, e.g. as they aren't read anymore.
+fn wait_for_room(sender: &crossbeam::Sender<MessageTree>, buffer: &AdaptiveBuffer) {
+    let mut len = sender.len();
+    if len < buffer.limit() {
+        return;
+    }
+    let waiting = Instant::now();
+    let mut draining = waiting;
+    while len >= buffer.limit() {
+        if draining.elapsed() >= STUCK_WAIT {
+            info!("Decoding too fast.");
+            break;
+        }
+        thread::sleep(IDLE_WAIT);
+        let now = sender.len();
+        if now < len {
+            draining = Instant::now();
+        }
+        len = now;
+    }
+    buffer.producer_waited(waiting.elapsed());
 }
 
 #[derive(Default, Builder, Debug)]
@@ -30,78 +177,149 @@ pub struct MessageTreeDumper {
     block_reader_channel_buffer_size: usize,
     #[builder(default = "10")]
     tree_decoder_channel_buffer_size: usize,
+    /// Buffers between the stages, e.g. to report how they were resized.
+    /// New ones of the buffer sizes by default.
+    #[builder(default)]
+    buffers: Option<Arc<StageBuffers>>,
+    /// Only decode the blocks at these offsets, e.g. from a result cache.
+    #[builder(default)]
+    blocks: Option<Vec<u64>>,
+    /// The file recorded in the locations of the trees, the path by
+    /// default, e.g. `<bundle>#<logview>` for a logview of a bundle.
+    #[builder(default)]
+    source: Option<PathBuf>,
+    /// Drop the trees of other domains before decoding their messages.
+    #[builder(default)]
+    allowed_domains: Option<Arc<AllowedDomains>>,
+    /// Only read this `(start, length)` byte range of the file, e.g. one
+    /// logview of a bundle. Block offsets are relative to `start`.
+    #[builder(default)]
+    range: Option<(u64, u64)>,
+    /// Keep waiting for new blocks at the end of the file, like `tail -f`.
+    #[builder(default)]
+    follow: bool,
 }
 
 impl MessageTreeDumper {
-    #[allow(dead_code)]
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = MessageTree> {
         self.read_trees().into_iter()
     }
 
     pub fn read_trees(self) -> crossbeam::Receiver<MessageTree> {
-        let block_reader = MessageBlockReader::open(&self.path).expect("open message block reader");
-        let (block_sender, block_receiver) =
-            crossbeam::bounded(self.block_reader_channel_buffer_size);
-        let (tree_sender, tree_receiver) =
-            crossbeam::bounded(self.tree_decoder_channel_buffer_size);
+        let start = self.range.map_or(0, |(start, _)| start);
+        let file: Arc<Path> = self.source.as_ref().unwrap_or(&self.path).as_path().into();
+        let at = move |index: u32, block_offset: u64| TreeLocation {
+            block_offset,
+            block: index,
+            file: Some(file.clone()),
+            ..TreeLocation::default()
+        };
+        let blocks: Box<dyn Iterator<Item = Block> + Send> = match self.blocks {
+            Some(offsets) => Box::new(
+                read_blocks_at(&self.path, start, offsets)
+                    .expect("open message block reader")
+                    .map(move |(index, offset, block)| (at(index, offset), block)),
+            ),
+            None => {
+                let block_reader = match self.range {
+                    Some((start, length)) => {
+                        MessageBlockReader::open_range(&self.path, start, length)
+                    }
+                    None if self.follow => MessageBlockReader::follow(&self.path),
+                    None => MessageBlockReader::open(&self.path),
+                };
+                Box::new(
+                    block_reader
+                        .expect("open message block reader")
+                        .into_blocks()
+                        .enumerate()
+                        .map(move |(index, (offset, block))| (at(index as u32, offset), block)),
+                )
+            }
+        };
+        let (block_buffer_size, tree_buffer_size) = (
+            self.block_reader_channel_buffer_size,
+            self.tree_decoder_channel_buffer_size,
+        );
+        let buffers = self
+            .buffers
+            .unwrap_or_else(|| StageBuffers::new(block_buffer_size, tree_buffer_size));
+        let (tree_sender, tree_receiver) = crossbeam::unbounded();
+
+        let threads = self.threads.max(1);
+        let workers: Vec<_> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let queue = Arc::new(BlockQueue {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            queued: AtomicUsize::new(0),
+            read_all: AtomicBool::new(false),
+            decoders: AtomicUsize::new(threads),
+        });
 
+        let reading = Reading(queue.clone());
+        let reader_buffers = buffers.clone();
         thread::Builder::new()
             .name("BlockReaderThread".to_string())
             .spawn(move || {
-                for block in block_reader.into_iter() {
-                    let mut to_send = block;
-                    loop {
-                        let ret = block_sender.send_timeout(to_send, Duration::from_secs(5));
-                        to_send = match ret {
-                            // Send success, continue to send the next one.
-                            Ok(()) => break,
-                            // Send timeout. We retry it.
-                            Err(SendTimeoutError::Timeout(t)) => {
-                                info!("Reading blocks too fast.");
-                                t
-                            }
-                            // Receiver disconnected. Exit current thread.
-                            Err(SendTimeoutError::Disconnected(_)) => return,
-                        };
+                numa::pin(Stage::Decode);
+                let queue = &reading.0;
+                for block in blocks {
+                    let full =
+                        queue.queued.load(Ordering::Acquire) >= reader_buffers.blocks.limit();
+                    let waiting = Instant::now();
+                    while queue.queued.load(Ordering::Acquire) >= reader_buffers.blocks.limit() {
+                        // Every decoder exited. Exit current thread.
+                        if queue.decoders.load(Ordering::Acquire) == 0 {
+                            return;
+                        }
+                        thread::sleep(IDLE_WAIT);
+                    }
+                    if full {
+                        reader_buffers.blocks.producer_waited(waiting.elapsed());
                     }
+                    queue.queued.fetch_add(1, Ordering::AcqRel);
+                    queue.injector.push(block);
                 }
             })
             .expect("spawn error");
 
-        for i in 0..self.threads {
-            let block_receiver = block_receiver.clone();
+        for (i, local) in workers.into_iter().enumerate() {
+            let decoding = Decoding(queue.clone());
             let tree_sender = tree_sender.clone();
+            let allowed_domains = self.allowed_domains.clone();
+            let buffers = buffers.clone();
 
             thread::Builder::new()
                 .name(format!("TreeDecoder{}", i))
                 .spawn(move || {
+                    numa::pin(Stage::Decode);
+                    let queue = &decoding.0;
+                    let backoff = Backoff::new();
+                    let mut idle_since = None;
                     loop {
-                        let block = match block_receiver.recv_timeout(Duration::from_millis(5)) {
-                            Ok(block) => block,
-                            Err(RecvTimeoutError::Timeout) => {
-                                info!("Waiting for new block");
+                        let (at, block) = match queue.next_block(&local) {
+                            Some(block) => block,
+                            None if queue.is_done() => break,
+                            None => {
+                                idle_since.get_or_insert_with(Instant::now);
+                                if backoff.is_completed() {
+                                    thread::sleep(IDLE_WAIT);
+                                } else {
+                                    backoff.snooze();
+                                }
                                 continue;
                             }
-                            Err(RecvTimeoutError::Disconnected) => {
-                                break;
-                            }
                         };
-                        for tree in read_block(block) {
-                            let mut to_send = tree;
-                            loop {
-                                let ret =
-                                    tree_sender.send_timeout(to_send, Duration::from_millis(5));
-                                to_send = match ret {
-                                    // Send success, continue to send the next one.
-                                    Ok(()) => break,
-                                    // Send timeout. We retry it.
-                                    Err(SendTimeoutError::Timeout(t)) => {
-                                        info!("Decoding too fast.");
-                                        t
-                                    }
-                                    // Receiver disconnected. Exit current thread.
-                                    Err(SendTimeoutError::Disconnected(_)) => return,
-                                };
+                        backoff.reset();
+                        if let Some(since) = idle_since.take() {
+                            buffers.blocks.consumer_waited(since.elapsed());
+                        }
+                        for tree in read_block_at(&at, block, allowed_domains.as_deref()) {
+                            wait_for_room(&tree_sender, &buffers.trees);
+                            // Receiver disconnected. Exit current thread.
+                            if tree_sender.send(tree).is_err() {
+                                return;
                             }
                         }
                     }
@@ -111,11 +329,30 @@ impl MessageTreeDumper {
 
         tree_receiver
     }
+
+    /// Writes every tree to each of `sinks`, then flushes them, e.g. for an
+    /// application embedding the dumper with its own sinks.
+    pub fn dump(self, sinks: &mut [Box<dyn Sink>]) -> Fallible<()> {
+        for tree in self.read_trees() {
+            for sink in sinks.iter_mut() {
+                sink.write_tree(&tree)?;
+            }
+        }
+        for sink in sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
 }
 
 struct SnappyReader {
     reader: Cursor<Vec<u8>>,
     buf: BytesMut,
+    /// Where the chunks decompressed so far start in the decompressed block.
+    chunk_starts: Vec<u64>,
+    decompressed: u64,
+    /// Bytes of the decompressed chunks read.
+    consumed: u64,
 }
 
 impl SnappyReader {
@@ -124,7 +361,43 @@ impl SnappyReader {
         SnappyReader {
             reader: Cursor::new(buf),
             buf: BytesMut::new(),
+            chunk_starts: vec![],
+            decompressed: 0,
+            consumed: 0,
+        }
+    }
+
+    /// The index of the chunk holding the byte `position` of the
+    /// decompressed block, and the offset of the byte in the chunk.
+    fn chunk_of(&self, position: u64) -> (u32, u32) {
+        let index = self
+            .chunk_starts
+            .partition_point(|&start| start <= position)
+            .saturating_sub(1);
+        let start = self.chunk_starts.get(index).copied().unwrap_or(0);
+        (index as u32, (position - start) as u32)
+    }
+
+    /// Moves past the first `chunks` chunks after the header by their
+    /// lengths, without decompressing them.
+    fn skip_chunks(&mut self, chunks: u32) -> Fallible<()> {
+        for _ in 0..chunks {
+            let length = self.reader.read_i32::<BigEndian>()?;
+            self.reader.seek(SeekFrom::Current(i64::from(length)))?;
+        }
+        Ok(())
+    }
+
+    /// Moves past `len` decompressed bytes.
+    fn skip(&mut self, len: usize) -> Fallible<()> {
+        while self.buf.len() < len {
+            if self.read_more_chunk()? == 0 {
+                bail!("block ends before offset {}", len);
+            }
         }
+        self.buf.split_to(len);
+        self.consumed += len as u64;
+        Ok(())
     }
 
     pub fn read_header(&mut self) -> Fallible<Vec<u8>> {
@@ -143,6 +416,8 @@ impl SnappyReader {
         let mut decodeder = snap::Decoder::new();
         let message_chunks = decodeder.decompress_vec(&snappy_body)?;
         self.buf.extend_from_slice(&message_chunks);
+        self.chunk_starts.push(self.decompressed);
+        self.decompressed += message_chunks.len() as u64;
         Ok(message_chunks.len())
     }
 }
@@ -166,28 +441,181 @@ impl Read for SnappyReader {
 
         let b = self.buf.split_to(size);
         buf.write_all(&b)?;
+        self.consumed += b.len() as u64;
         Ok(b.len())
     }
 }
 
 pub struct MessageBlockReader {
-    file_reader: BufReader<File>,
+    file_reader: Box<dyn Read + Send>,
+    offset: u64,
 }
 
 impl MessageBlockReader {
     pub fn open(path: impl AsRef<Path>) -> Fallible<Self> {
-        let mut file_reader = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+        Self::new(BufReader::with_capacity(1024 * 1024, File::open(path)?))
+    }
+
+    /// Reads the file as it's being written, waiting for more data at its
+    /// end instead of stopping.
+    pub fn follow(path: impl AsRef<Path>) -> Fallible<Self> {
+        Self::new(BufReader::with_capacity(
+            1024 * 1024,
+            FollowReader {
+                file: File::open(path)?,
+            },
+        ))
+    }
+
+    /// Reads the logview stored in `length` bytes from `start` of the file.
+    pub fn open_range(path: impl AsRef<Path>, start: u64, length: u64) -> Fallible<Self> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        Self::new(BufReader::with_capacity(1024 * 1024, file.take(length)))
+    }
+
+    pub fn new(reader: impl Read + Send + 'static) -> Fallible<Self> {
+        let mut file_reader: Box<dyn Read + Send> = Box::new(reader);
         let magic_number = file_reader.read_i32::<BigEndian>()?;
         assert_eq!(magic_number, -1);
         debug!("magic number: {}", magic_number);
 
-        Ok(MessageBlockReader { file_reader })
+        Ok(MessageBlockReader {
+            file_reader,
+            offset: 4,
+        })
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = Vec<u8>> {
+        self.into_blocks().map(|(_, block)| block)
+    }
+
+    /// Blocks along with their offsets in the file.
+    pub fn into_blocks(self) -> impl Iterator<Item = (u64, Vec<u8>)> {
+        self.try_into_blocks()
+            .map(|block| block.expect("try read data"))
+    }
+
+    /// Blocks along with their offsets in the file, failing on a truncated
+    /// one, after which there are no more.
+    pub fn try_into_blocks(self) -> impl Iterator<Item = Fallible<(u64, Vec<u8>)>> {
         let mut f = self.file_reader;
-        iter::from_fn(move || try_read_data(&mut f).expect("try read data"))
+        let mut offset = self.offset;
+        let mut index = 0;
+        let mut failed = false;
+        iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let block = match try_read_data(&mut f) {
+                Ok(block) => block?,
+                Err(e) => {
+                    failed = true;
+                    return Some(Err(format_err!("block at {}: {}", offset, e)));
+                }
+            };
+            let block_offset = offset;
+            debug!(stage = "read", block = index, offset = block_offset; "read block");
+            offset += 4 + block.len() as u64;
+            index += 1;
+            Some(Ok((block_offset, block)))
+        })
+    }
+}
+
+/// Time between checks for new data at the end of a followed file.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A file whose reads block at its end until more data is appended, so a
+/// block being written is read once complete.
+struct FollowReader {
+    file: File,
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            match self.file.read(buf)? {
+                0 if !buf.is_empty() => thread::sleep(FOLLOW_POLL_INTERVAL),
+                n => return Ok(n),
+            }
+        }
+    }
+}
+
+/// Offsets of the blocks of the logview at `path`, read from their length
+/// prefixes.
+pub fn block_offsets(path: &Path) -> Fallible<Vec<u64>> {
+    let mut file = BufReader::new(File::open(path)?);
+    file.read_i32::<BigEndian>()?;
+    let mut offsets = vec![];
+    let mut offset = 4;
+    loop {
+        let mut length = [0; 4];
+        match file.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(offsets),
+            Err(e) => return Err(e.into()),
+        }
+        let length = i32::from_be_bytes(length);
+        file.seek(SeekFrom::Current(i64::from(length)))?;
+        offsets.push(offset);
+        offset += 4 + length as u64;
+    }
+}
+
+/// Reads the blocks at `offsets`, relative to `start`, of the file at
+/// `path`, along with their indexes, counted by walking the length
+/// prefixes of the blocks before.
+pub fn read_blocks_at(
+    path: &Path,
+    start: u64,
+    offsets: Vec<u64>,
+) -> Fallible<impl Iterator<Item = (u32, u64, Vec<u8>)>> {
+    let mut file = File::open(path)?;
+    let last = offsets.iter().copied().max().unwrap_or(0);
+    let mut indexes = HashMap::new();
+    let (mut index, mut offset) = (0, 4);
+    while offset <= last {
+        indexes.insert(offset, index);
+        file.seek(SeekFrom::Start(start + offset))?;
+        offset += 4 + file.read_i32::<BigEndian>()? as u64;
+        index += 1;
     }
+    Ok(offsets.into_iter().map(move |offset| {
+        file.seek(SeekFrom::Start(start + offset))
+            .expect("seek block");
+        let block = try_read_data(&mut file)
+            .expect("try read data")
+            .expect("block at offset");
+        (indexes[&offset], offset, block)
+    }))
+}
+
+/// Decodes the trees at `locations` of the file at `path`, e.g. from a
+/// result cache. Only the chunks of a block from the one a tree starts in
+/// are decompressed, the ones before are skipped by their lengths.
+pub fn read_trees_at(
+    path: &Path,
+    locations: Vec<TreeLocation>,
+) -> Fallible<impl Iterator<Item = Fallible<MessageTree>>> {
+    let mut file = File::open(path)?;
+    Ok(locations.into_iter().map(move |location| {
+        file.seek(SeekFrom::Start(location.block_offset))?;
+        let block = try_read_data(&mut file)?
+            .ok_or_else(|| format_err!("no block at {}", location.block_offset))?;
+        let mut reader = SnappyReader::new(block);
+        reader.read_header()?;
+        reader.skip_chunks(location.chunk)?;
+        reader.skip(location.chunk_offset as usize)?;
+        let data =
+            try_read_data(&mut reader)?.ok_or_else(|| format_err!("no tree at {:?}", location))?;
+        let mut tree = MessageTree::decode(&mut data.as_slice())?;
+        tree.location = location;
+        tree.decoded_at_in_ms = now_in_ms();
+        Ok(tree)
+    }))
 }
 
 struct MessageTreeReader {
@@ -206,16 +634,28 @@ impl MessageTreeReader {
         reader
     }
 
-    fn into_iter(self) -> impl Iterator<Item = MessageTree> {
+    /// Yields `None` for the trees dropped by `allowed_domains`, so indexes
+    /// still match the positions in the block.
+    fn into_iter(
+        self,
+        allowed_domains: Option<&AllowedDomains>,
+    ) -> impl Iterator<Item = Option<MessageTree>> + '_ {
         let mut snappy_reader = self.snappy_reader;
         iter::from_fn(move || {
+            let position = snappy_reader.consumed;
             let message_buf = try_read_data(&mut snappy_reader).expect("try read data");
             let message_buf = message_buf?;
             debug!("read data from snappy reader: size: {}", message_buf.len());
-            let tree =
-                MessageTree::decode(&mut message_buf.as_slice()).expect("decode message tree");
+            let tree = MessageTree::decode_if(&mut message_buf.as_slice(), |tree| {
+                allowed_domains.is_none_or(|allowed| allowed.allows(&tree.domain))
+            })
+            .expect("decode message tree");
             debug!("decode message tree");
-            Some(tree)
+            Some(tree.map(|mut tree| {
+                (tree.location.chunk, tree.location.chunk_offset) =
+                    snappy_reader.chunk_of(position);
+                tree
+            }))
         })
     }
 }
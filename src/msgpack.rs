@@ -0,0 +1,561 @@
+use std::fmt;
+
+use serde::ser::{self, Serialize};
+
+/// Appends `value` to `buf` as a MessagePack object.
+///
+/// Structs and maps are written as maps keyed by field name, and enum
+/// variants with data as a map of one entry keyed by the variant name, like
+/// `--json`, so the output reads back with any MessagePack decoder without
+/// knowing the structs.
+pub fn to_vec<T: Serialize + ?Sized>(buf: &mut Vec<u8>, value: &T) -> Result<(), Error> {
+    value.serialize(&mut Serializer { buf })
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+struct Serializer<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl Serializer<'_> {
+    fn write_uint(&mut self, n: u64) {
+        if n < 0x80 {
+            self.buf.push(n as u8);
+        } else if n <= u64::from(u8::MAX) {
+            self.buf.extend_from_slice(&[0xcc, n as u8]);
+        } else if n <= u64::from(u16::MAX) {
+            self.buf.push(0xcd);
+            self.buf.extend_from_slice(&(n as u16).to_be_bytes());
+        } else if n <= u64::from(u32::MAX) {
+            self.buf.push(0xce);
+            self.buf.extend_from_slice(&(n as u32).to_be_bytes());
+        } else {
+            self.buf.push(0xcf);
+            self.buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+
+    fn write_int(&mut self, n: i64) {
+        if n >= 0 {
+            self.write_uint(n as u64);
+        } else if n >= -32 {
+            self.buf.push(n as u8);
+        } else if n >= i64::from(i8::MIN) {
+            self.buf.extend_from_slice(&[0xd0, n as u8]);
+        } else if n >= i64::from(i16::MIN) {
+            self.buf.push(0xd1);
+            self.buf.extend_from_slice(&(n as i16).to_be_bytes());
+        } else if n >= i64::from(i32::MIN) {
+            self.buf.push(0xd2);
+            self.buf.extend_from_slice(&(n as i32).to_be_bytes());
+        } else {
+            self.buf.push(0xd3);
+            self.buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+
+    /// Writes the marker and length of a str, bin, array or map: the fixed
+    /// marker `fix` with the length when it is at most `fix_max`, else the
+    /// marker of a 8, 16 or 32 bit length.
+    fn write_header(&mut self, len: usize, fix: Option<(u8, usize)>, markers: [Option<u8>; 3]) {
+        if let Some((fix, _)) = fix.filter(|&(_, max)| len <= max) {
+            self.buf.push(fix | len as u8);
+        } else if let (Some(marker), true) = (markers[0], len <= usize::from(u8::MAX)) {
+            self.buf.extend_from_slice(&[marker, len as u8]);
+        } else if len <= usize::from(u16::MAX) {
+            self.buf.push(markers[1].expect("16 bit length"));
+            self.buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            self.buf.push(markers[2].expect("32 bit length"));
+            self.buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_header(
+            s.len(),
+            Some((0xa0, 31)),
+            [Some(0xd9), Some(0xda), Some(0xdb)],
+        );
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn array_header(len: usize) -> Vec<u8> {
+        let mut header = vec![];
+        Serializer { buf: &mut header }.write_header(
+            len,
+            Some((0x90, 15)),
+            [None, Some(0xdc), Some(0xdd)],
+        );
+        header
+    }
+
+    fn map_header(len: usize) -> Vec<u8> {
+        let mut header = vec![];
+        Serializer { buf: &mut header }.write_header(
+            len,
+            Some((0x80, 15)),
+            [None, Some(0xde), Some(0xdf)],
+        );
+        header
+    }
+
+    /// Starts a map of one entry keyed by `variant`, holding its data.
+    fn start_variant(&mut self, variant: &str) {
+        self.buf.extend_from_slice(&Self::map_header(1));
+        self.write_str(variant);
+    }
+}
+
+/// An array or map being written. When its length isn't known upfront, its
+/// header is inserted before its entries once they are all written.
+struct Compound<'s, 'a> {
+    ser: &'s mut Serializer<'a>,
+    map: bool,
+    len: Option<usize>,
+    start: usize,
+    count: usize,
+}
+
+impl<'s, 'a> Compound<'s, 'a> {
+    fn start(ser: &'s mut Serializer<'a>, map: bool, len: Option<usize>) -> Self {
+        if let Some(len) = len {
+            let header = match map {
+                true => Serializer::map_header(len),
+                false => Serializer::array_header(len),
+            };
+            ser.buf.extend_from_slice(&header);
+        }
+        let start = ser.buf.len();
+        Compound {
+            ser,
+            map,
+            len,
+            start,
+            count: 0,
+        }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.count += 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self.len {
+            Some(len) if len != self.count => Err(Error(format!(
+                "{} entries written, {} announced",
+                self.count, len
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                let header = match self.map {
+                    true => Serializer::map_header(self.count),
+                    false => Serializer::array_header(self.count),
+                };
+                self.ser.buf.splice(self.start..self.start, header);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'s, 'a> ser::Serializer for &'s mut Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'s, 'a>;
+    type SerializeTuple = Compound<'s, 'a>;
+    type SerializeTupleStruct = Compound<'s, 'a>;
+    type SerializeTupleVariant = Compound<'s, 'a>;
+    type SerializeMap = Compound<'s, 'a>;
+    type SerializeStruct = Compound<'s, 'a>;
+    type SerializeStructVariant = Compound<'s, 'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.buf.push(if v { 0xc3 } else { 0xc2 });
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.write_uint(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.buf.push(0xca);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.buf.push(0xcb);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.write_str(v.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.write_str(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write_header(v.len(), None, [Some(0xc4), Some(0xc5), Some(0xc6)]);
+        self.buf.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.buf.push(0xc0);
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.start_variant(variant);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Ok(Compound::start(self, false, len))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Error> {
+        Ok(Compound::start(self, false, Some(len)))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Ok(Compound::start(self, false, Some(len)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        self.start_variant(variant);
+        Ok(Compound::start(self, false, Some(len)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Ok(Compound::start(self, true, len))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Ok(Compound::start(self, true, Some(len)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        self.start_variant(variant);
+        Ok(Compound::start(self, true, Some(len)))
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    /// Counts the entry, the value being written next.
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.element(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.count += 1;
+        self.ser.write_str(key);
+        value.serialize(&mut *self.ser)
+    }
+
+    fn skip_field(&mut self, _key: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rmpv::Value;
+    use serde_json::json;
+
+    use super::*;
+    use crate::message_tree::{InnerEvent, InnerTransaction, Message};
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+        let mut buf = vec![];
+        to_vec(&mut buf, value).unwrap();
+        buf
+    }
+
+    /// `value` as the JSON it was serialized from.
+    fn to_json(value: Value) -> serde_json::Value {
+        match value {
+            Value::Nil => serde_json::Value::Null,
+            Value::Boolean(b) => json!(b),
+            Value::Integer(n) => match n.as_u64() {
+                Some(n) => json!(n),
+                None => json!(n.as_i64().unwrap()),
+            },
+            Value::F32(f) => json!(f),
+            Value::F64(f) => json!(f),
+            Value::String(s) => json!(s.into_str().unwrap()),
+            Value::Array(values) => values.into_iter().map(to_json).collect(),
+            Value::Map(entries) => entries
+                .into_iter()
+                .map(|(k, v)| (k.as_str().unwrap().to_string(), to_json(v)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn decode(buf: &[u8]) -> serde_json::Value {
+        let mut read = buf;
+        let value = rmpv::decode::read_value(&mut read).unwrap();
+        assert!(read.is_empty(), "{} bytes left", read.len());
+        to_json(value)
+    }
+
+    #[test]
+    fn markers() {
+        assert_eq!(encode(&0u8), [0x00]);
+        assert_eq!(encode(&127u64), [0x7f]);
+        assert_eq!(encode(&128u64), [0xcc, 0x80]);
+        assert_eq!(encode(&65_536u64), [0xce, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(encode(&-32i64), [0xe0]);
+        assert_eq!(encode(&-33i64), [0xd0, 0xdf]);
+        assert_eq!(encode(&-129i64), [0xd1, 0xff, 0x7f]);
+        assert_eq!(encode("ab"), [0xa2, b'a', b'b']);
+        assert_eq!(encode(&"x".repeat(32))[..2], [0xd9, 32]);
+        assert_eq!(encode(&"x".repeat(256))[..3], [0xda, 0x01, 0x00]);
+        assert_eq!(encode(&[1u8, 2]), [0x92, 0x01, 0x02]);
+        assert_eq!(encode(&vec![0u8; 16])[..3], [0xdc, 0x00, 0x10]);
+    }
+
+    #[test]
+    fn reads_back_like_json() {
+        let values = [
+            json!(null),
+            json!([true, false, -1, 1.5, u64::MAX, i64::MIN]),
+            json!({"a": {"b": [1, "c"]}, "d": "x".repeat(70_000)}),
+            json!((0..20).collect::<Vec<_>>()),
+        ];
+        for value in &values {
+            assert_eq!(&decode(&encode(value)), value);
+        }
+    }
+
+    #[test]
+    fn messages_read_back_like_json() {
+        let event = Message::Event(Arc::new(InnerEvent {
+            ty: "Error".to_string(),
+            name: "java.lang.Exception".to_string(),
+            timestamp_in_ms: 1_715_400_000_100,
+            status: "ERROR".to_string(),
+            repeats: Some(3),
+            ..Default::default()
+        }));
+        let message = Message::Transaction(Arc::new(InnerTransaction {
+            ty: "URL".to_string(),
+            name: "/api/item/1".to_string(),
+            timestamp_in_ms: 1_715_400_000_000,
+            status: "0".to_string(),
+            duration_in_ms: 140,
+            children: vec![event],
+            ..Default::default()
+        }));
+        assert_eq!(
+            decode(&encode(&message)),
+            serde_json::to_value(&message).unwrap()
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use failure::{format_err, Fallible};
+use log::{info, warn};
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+
+/// Pages of the input looked up to find the node of its page cache.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const SAMPLED_PAGES: usize = 1024;
+
+/// A NUMA node, a socket on most machines, and its CPUs.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// The stages of the pipeline placed on a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Reading and decoding blocks.
+    Decode,
+    /// Filtering trees, reports and output.
+    Filter,
+}
+
+/// Decoding on the node holding the input in its page cache, so blocks
+/// are read from local memory, and filtering on another one, so the two
+/// stages don't compete for the same cores and memory bandwidth.
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub decode: Node,
+    pub filter: Node,
+}
+
+static PLACEMENT: OnceLock<Placement> = OnceLock::new();
+
+/// Makes `pin` pin the threads of the stages to the nodes of `placement`.
+pub fn place(placement: Placement) {
+    info!(
+        "numa: decoding on node {} ({} cpus), filtering on node {} ({} cpus)",
+        placement.decode.id,
+        placement.decode.cpus.len(),
+        placement.filter.id,
+        placement.filter.cpus.len()
+    );
+    let _ = PLACEMENT.set(placement);
+}
+
+/// Restricts the current thread to the CPUs of the node of `stage`, when
+/// the stages were placed with `--numa`.
+pub fn pin(stage: Stage) {
+    let node = match (PLACEMENT.get(), stage) {
+        (None, _) => return,
+        (Some(placement), Stage::Decode) => &placement.decode,
+        (Some(placement), Stage::Filter) => &placement.filter,
+    };
+    if let Err(e) = set_affinity(&node.cpus) {
+        warn!(
+            "numa: can't pin {:?} thread to node {}: {}",
+            stage, node.id, e
+        );
+    }
+}
+
+/// Where to run the stages reading `path`, or `None` on a machine with a
+/// single node.
+pub fn plan(path: &Path) -> Fallible<Option<Placement>> {
+    let nodes = nodes()?;
+    if nodes.len() < 2 {
+        warn!("numa: fewer than two nodes with cpus, running unpinned");
+        return Ok(None);
+    }
+    let cached = page_cache_node(path)?;
+    let decode = match cached.and_then(|id| nodes.iter().position(|node| node.id == id)) {
+        Some(i) => i,
+        None => {
+            info!(
+                "numa: {} isn't in the page cache, decoding on node {}",
+                path.display(),
+                nodes[0].id
+            );
+            0
+        }
+    };
+    Ok(Some(Placement {
+        decode: nodes[decode].clone(),
+        filter: nodes[(decode + 1) % nodes.len()].clone(),
+    }))
+}
+
+/// The nodes with CPUs, by id.
+pub fn nodes() -> Fallible<Vec<Node>> {
+    let mut nodes = vec![];
+    for entry in fs::read_dir(NODE_DIR)? {
+        let entry = entry?;
+        let id = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse().ok())
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let cpus = parse_cpu_list(fs::read_to_string(entry.path().join("cpulist"))?.trim())?;
+        if !cpus.is_empty() {
+            nodes.push(Node { id, cpus });
+        }
+    }
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+/// CPUs of a list like `0-15,32-47`.
+fn parse_cpu_list(list: &str) -> Fallible<Vec<usize>> {
+    let mut cpus = vec![];
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        let bad = || format_err!("bad cpu list {:?}", list);
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (
+                first.parse().map_err(|_| bad())?,
+                last.parse().map_err(|_| bad())?,
+            ),
+            None => {
+                let cpu = range.parse().map_err(|_| bad())?;
+                (cpu, cpu)
+            }
+        };
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+/// The node holding most of the sampled pages of `path` which are in the
+/// page cache, if any are.
+///
+/// Only pages already cached are touched, so looking doesn't read the
+/// file into the cache of the node of the current thread.
+#[cfg(target_os = "linux")]
+pub fn page_cache_node(path: &Path) -> Fallible<Option<usize>> {
+    use std::cmp::Reverse;
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    let file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let pages = len.div_ceil(page_size);
+    let map = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if map == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error().into());
+    }
+    let nodes = (|| -> io::Result<Vec<i32>> {
+        let mut resident = vec![0u8; pages];
+        if unsafe { libc::mincore(map, len, resident.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let samples = pages.min(SAMPLED_PAGES);
+        let mut addresses: Vec<_> = (0..samples)
+            .map(|i| i * pages / samples)
+            .filter(|&page| resident[page] & 1 == 1)
+            .map(|page| unsafe { map.cast::<u8>().add(page * page_size) })
+            .inspect(|&address| {
+                // Maps the cached page, move_pages only knows mapped ones.
+                unsafe { ptr::read_volatile(address) };
+            })
+            .map(|address| address.cast::<libc::c_void>())
+            .collect();
+        let mut status = vec![0i32; addresses.len()];
+        if addresses.is_empty() {
+            return Ok(status);
+        }
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_move_pages,
+                0,
+                addresses.len() as libc::c_ulong,
+                addresses.as_mut_ptr(),
+                ptr::null::<libc::c_int>(),
+                status.as_mut_ptr(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(status)
+    })();
+    unsafe { libc::munmap(map, len) };
+
+    let mut counts = BTreeMap::new();
+    for node in nodes? {
+        // Negative statuses are errors of pages, e.g. not mapped.
+        if node >= 0 {
+            *counts.entry(node as usize).or_insert(0) += 1;
+        }
+    }
+    Ok(counts
+        .into_iter()
+        .max_by_key(|&(node, count)| (count, Reverse(node)))
+        .map(|(node, _)| node))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn page_cache_node(_path: &Path) -> Fallible<Option<usize>> {
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    use std::mem;
+
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &cpu in cpus {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    match unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "thread affinity is only supported on linux",
+    ))
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use failure::Fallible;
+use log::{info, warn};
+use tonic::transport::{Channel, Endpoint};
+use tonic::Code;
+
+use crate::batch_poster::{BatchPoster, Failed, Post, Retries};
+use crate::convert::derived_id;
+use crate::message_tree::{Message, MessageTree};
+use crate::tls::ClientTls;
+
+use self::proto::collector::logs::v1::logs_service_client::LogsServiceClient;
+use self::proto::collector::logs::v1::ExportLogsServiceRequest;
+use self::proto::collector::trace::v1::trace_service_client::TraceServiceClient;
+use self::proto::collector::trace::v1::ExportTraceServiceRequest;
+use self::proto::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use self::proto::logs::v1::{LogRecord, ResourceLogs, ScopeLogs, SeverityNumber};
+use self::proto::resource::v1::Resource;
+use self::proto::trace::v1::{span, status, ResourceSpans, ScopeSpans, Span, Status};
+
+/// The packages of `proto/opentelemetry`, nested like upstream as they
+/// refer to each other by relative paths.
+mod proto {
+    pub mod common {
+        #[allow(clippy::enum_variant_names)]
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.common.v1");
+        }
+    }
+    pub mod resource {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.resource.v1");
+        }
+    }
+    pub mod trace {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.trace.v1");
+        }
+    }
+    pub mod logs {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.logs.v1");
+        }
+    }
+    pub mod collector {
+        pub mod trace {
+            pub mod v1 {
+                tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+            }
+        }
+        pub mod logs {
+            pub mod v1 {
+                tonic::include_proto!("opentelemetry.proto.collector.logs.v1");
+            }
+        }
+    }
+}
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The process a tree was logged by: its domain, hostname and ip.
+type Process = (String, String, String);
+
+/// A tree converted in the thread matching it: the spans of its
+/// transactions, or a log record when its root isn't a transaction.
+enum Converted {
+    Spans(Process, Vec<Span>),
+    Log(Process, LogRecord),
+}
+
+/// Exports matched trees to an OpenTelemetry collector over OTLP/gRPC, from
+/// a background thread.
+///
+/// Every transaction is a span, with the other messages under it as its
+/// events, and trees whose root isn't a transaction, e.g. heartbeats, are
+/// log records. Trace and span ids are derived from the message ids like
+/// `convert --to jaeger` does, the root span of a tree being a child of
+/// the root span of its parent tree. The process of a tree is the resource
+/// of its spans, its domain being the service name.
+///
+/// A batch is exported once it has `batch_size` trees or `interval` after
+/// its first tree, its spans and its logs apart. Failed exports are kept
+/// for later, see `Retries::Queued`, unless the collector refused them for
+/// good.
+pub struct OtlpExporter {
+    poster: BatchPoster<Converted>,
+}
+
+impl OtlpExporter {
+    /// Exports to `endpoint`, e.g. http://collector:4317, over TLS for
+    /// `https://` endpoints.
+    pub fn start(
+        endpoint: &str,
+        batch_size: usize,
+        interval: Duration,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        let mut endpoint = Endpoint::from_shared(endpoint.to_string())?.timeout(TIMEOUT);
+        if endpoint.uri().scheme_str() == Some("https") {
+            endpoint = endpoint.tls_config(tls.grpc_config()?)?;
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let channel = {
+            let _runtime = runtime.enter();
+            endpoint.connect_lazy()
+        };
+        let exporter = Exporter {
+            runtime,
+            traces: TraceServiceClient::new(channel.clone()),
+            logs: LogsServiceClient::new(channel),
+            batch_size: batch_size.max(1),
+        };
+        let poster = BatchPoster::start(
+            "Otlp",
+            exporter,
+            batch_size.max(1) * 2,
+            Some(interval),
+            Retries::Queued,
+        )?;
+        Ok(OtlpExporter { poster })
+    }
+
+    /// Converts `tree`, with its message replaced by `message`, e.g. after
+    /// decoding payloads, to spans or to a log record.
+    pub fn send(&self, tree: &MessageTree, message: &Message) -> Fallible<()> {
+        let process = (
+            tree.domain.to_string(),
+            tree.hostname.to_string(),
+            tree.ip_address.to_string(),
+        );
+        let converted = match message {
+            Message::Transaction(_) => Converted::Spans(process, spans(tree, message)),
+            _ => Converted::Log(process, log_record(tree, message)),
+        };
+        self.poster.send(converted)
+    }
+
+    /// Exports the remaining trees.
+    pub fn finish(self) -> Fallible<()> {
+        self.poster.finish()
+    }
+}
+
+struct Exporter {
+    runtime: tokio::runtime::Runtime,
+    traces: TraceServiceClient<Channel>,
+    logs: LogsServiceClient<Channel>,
+    batch_size: usize,
+}
+
+impl Post for Exporter {
+    type Item = Converted;
+
+    const NAME: &'static str = "otlp";
+
+    fn is_full(&self, batch: &[Converted]) -> bool {
+        batch.len() >= self.batch_size
+    }
+
+    /// Exports the spans, then the logs of `batch`, giving back the trees
+    /// of the first export failing that can be retried.
+    fn post(&mut self, batch: Vec<Converted>) -> Result<(), Failed<Converted>> {
+        let (spans, logs): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .partition(|converted| matches!(converted, Converted::Spans(..)));
+        if !spans.is_empty() {
+            if let Err(e) = self.export(Export::Traces(traces_request(&spans)), spans.len()) {
+                return Err(Failed::new(spans.into_iter().chain(logs).collect(), e));
+            }
+        }
+        if !logs.is_empty() {
+            if let Err(e) = self.export(Export::Logs(logs_request(&logs)), logs.len()) {
+                return Err(Failed::new(logs, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Export {
+    Traces(ExportTraceServiceRequest),
+    Logs(ExportLogsServiceRequest),
+}
+
+impl Exporter {
+    /// Exports the requests of `trees` trees. Those the collector refused
+    /// for good are logged and dropped.
+    fn export(&self, export: Export, trees: usize) -> Fallible<()> {
+        let (mut traces, mut logs) = (self.traces.clone(), self.logs.clone());
+        let result = self.runtime.block_on(async {
+            Ok::<_, tonic::Status>(match export {
+                Export::Traces(request) => traces
+                    .export(request)
+                    .await?
+                    .into_inner()
+                    .partial_success
+                    .filter(|p| p.rejected_spans > 0)
+                    .map(|p| (p.rejected_spans, "spans", p.error_message)),
+                Export::Logs(request) => logs
+                    .export(request)
+                    .await?
+                    .into_inner()
+                    .partial_success
+                    .filter(|p| p.rejected_log_records > 0)
+                    .map(|p| (p.rejected_log_records, "log records", p.error_message)),
+            })
+        });
+        match result {
+            Ok(rejected) => {
+                info!("otlp: exported {} trees", trees);
+                if let Some((count, what, message)) = rejected {
+                    warn!("otlp: collector rejected {} {}: {}", count, what, message);
+                }
+                Ok(())
+            }
+            Err(status) if !retryable(status.code()) => {
+                warn!(
+                    "otlp: collector refused {} trees: {}",
+                    trees,
+                    status.message()
+                );
+                Ok(())
+            }
+            Err(status) => Err(status.into()),
+        }
+    }
+}
+
+/// The spans of `batch`, grouped by process.
+fn traces_request(batch: &[Converted]) -> ExportTraceServiceRequest {
+    let mut spans: BTreeMap<&Process, Vec<Span>> = BTreeMap::new();
+    for converted in batch {
+        if let Converted::Spans(process, tree) = converted {
+            spans
+                .entry(process)
+                .or_default()
+                .extend(tree.iter().cloned());
+        }
+    }
+    let resource_spans = spans
+        .into_iter()
+        .map(|(process, spans)| ResourceSpans {
+            resource: Some(resource(process.clone())),
+            scope_spans: vec![ScopeSpans {
+                scope: Some(scope()),
+                spans,
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        })
+        .collect();
+    ExportTraceServiceRequest { resource_spans }
+}
+
+/// The log records of `batch`, grouped by process.
+fn logs_request(batch: &[Converted]) -> ExportLogsServiceRequest {
+    let mut logs: BTreeMap<&Process, Vec<LogRecord>> = BTreeMap::new();
+    for converted in batch {
+        if let Converted::Log(process, record) = converted {
+            logs.entry(process).or_default().push(record.clone());
+        }
+    }
+    let resource_logs = logs
+        .into_iter()
+        .map(|(process, log_records)| ResourceLogs {
+            resource: Some(resource(process.clone())),
+            scope_logs: vec![ScopeLogs {
+                scope: Some(scope()),
+                log_records,
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        })
+        .collect();
+    ExportLogsServiceRequest { resource_logs }
+}
+
+/// Whether an export failing with `code` may succeed later, per the OTLP
+/// specification.
+fn retryable(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Cancelled
+            | Code::DeadlineExceeded
+            | Code::ResourceExhausted
+            | Code::Aborted
+            | Code::OutOfRange
+            | Code::Unavailable
+            | Code::DataLoss
+    )
+}
+
+fn resource((domain, hostname, ip): Process) -> Resource {
+    Resource {
+        attributes: vec![
+            attribute("service.name", &domain),
+            attribute("host.name", &hostname),
+            attribute("host.ip", &ip),
+        ],
+        dropped_attributes_count: 0,
+    }
+}
+
+fn scope() -> InstrumentationScope {
+    InstrumentationScope {
+        name: "dump-cat".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        attributes: vec![],
+        dropped_attributes_count: 0,
+    }
+}
+
+fn attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// The attributes of a message: its type, its status when it isn't `0`
+/// and its data when there's some.
+fn message_attributes(message: &Message) -> Vec<KeyValue> {
+    let mut attributes = vec![attribute("cat.type", message.ty())];
+    if message.status() != "0" {
+        attributes.push(attribute("cat.status", message.status()));
+    }
+    if !message.data().is_empty() {
+        attributes.push(attribute("cat.data", message.data()));
+    }
+    attributes
+}
+
+fn nanos(ms: u64) -> u64 {
+    ms * 1_000_000
+}
+
+fn trace_id(tree: &MessageTree) -> Vec<u8> {
+    match tree.root_message_id.is_empty() {
+        true => derived_id(&tree.message_id, 16),
+        false => derived_id(&tree.root_message_id, 16),
+    }
+}
+
+/// The spans of the transactions of `tree`, with `message` as its root.
+fn spans(tree: &MessageTree, message: &Message) -> Vec<Span> {
+    let parent = match tree.parent_message_id.is_empty() {
+        true => vec![],
+        false => derived_id(&format!("{}/0", tree.parent_message_id), 8),
+    };
+    let mut spans = vec![];
+    add_span(&mut spans, tree, &trace_id(tree), message, &mut 0, parent);
+    let root = &mut spans[0];
+    root.kind = span::SpanKind::Server as i32;
+    root.attributes.extend([
+        attribute("cat.message_id", &tree.message_id),
+        attribute("thread.name", &tree.thread_name),
+        attribute("thread.id", &tree.thread_id),
+    ]);
+    spans
+}
+
+/// Adds the span of `message`, the `index`th message of `tree`, and of the
+/// transactions under it.
+fn add_span(
+    spans: &mut Vec<Span>,
+    tree: &MessageTree,
+    trace_id: &[u8],
+    message: &Message,
+    index: &mut usize,
+    parent: Vec<u8>,
+) {
+    let span_id = derived_id(&format!("{}/{}", tree.message_id, index), 8);
+    *index += 1;
+    let start = message.timestamp_in_ms();
+    let status = match message.status().as_str() {
+        "0" => Status {
+            message: String::new(),
+            code: status::StatusCode::Ok as i32,
+        },
+        other => Status {
+            message: other.to_string(),
+            code: status::StatusCode::Error as i32,
+        },
+    };
+    let at = spans.len();
+    spans.push(Span {
+        trace_id: trace_id.to_vec(),
+        span_id: span_id.clone(),
+        parent_span_id: parent,
+        name: format!("{}:{}", message.ty(), message.name()),
+        kind: span::SpanKind::Internal as i32,
+        start_time_unix_nano: nanos(start),
+        end_time_unix_nano: nanos(start + message.duration_in_ms().unwrap_or(0)),
+        attributes: message_attributes(message),
+        status: Some(status),
+        ..Default::default()
+    });
+
+    let children = match message {
+        Message::Transaction(t) => &t.children,
+        _ => return,
+    };
+    let mut events = vec![];
+    for child in children {
+        match child {
+            Message::Transaction(_) => {
+                add_span(spans, tree, trace_id, child, index, span_id.clone())
+            }
+            _ => events.push(span::Event {
+                time_unix_nano: nanos(child.timestamp_in_ms()),
+                name: format!("{}:{}", child.ty(), child.name()),
+                attributes: message_attributes(child),
+                dropped_attributes_count: 0,
+            }),
+        }
+    }
+    spans[at].events = events;
+}
+
+/// `tree`, whose root `message` isn't a transaction, as a log record.
+fn log_record(tree: &MessageTree, message: &Message) -> LogRecord {
+    let severity = match message.status().as_str() {
+        "0" => SeverityNumber::Info,
+        _ => SeverityNumber::Error,
+    };
+    let body = match message.data().is_empty() {
+        true => message.name(),
+        false => message.data(),
+    };
+    let mut attributes = message_attributes(message);
+    attributes.extend([
+        attribute("cat.name", message.name()),
+        attribute("cat.message_id", &tree.message_id),
+        attribute("thread.name", &tree.thread_name),
+    ]);
+    LogRecord {
+        time_unix_nano: nanos(message.timestamp_in_ms()),
+        severity_number: severity as i32,
+        severity_text: severity
+            .as_str_name()
+            .trim_start_matches("SEVERITY_NUMBER_")
+            .to_string(),
+        body: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(body.to_string())),
+        }),
+        attributes,
+        trace_id: trace_id(tree),
+        ..Default::default()
+    }
+}
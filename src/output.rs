@@ -0,0 +1,573 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, LineWriter, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use failure::{bail, format_err, Fallible};
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest as _, Sha256};
+
+use crate::message_tree::{Message, MessageTree, Text};
+
+/// Plaintext bytes per AES-GCM chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const AES_GCM_MAGIC: &[u8] = b"DCAESGCM1";
+const NONCE_SIZE: usize = 12;
+/// Bytes the authentication tag adds to the ciphertext of a chunk.
+const TAG_SIZE: usize = 16;
+
+/// Delay before the first reconnection, doubled after every failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// A writer that has to be finalized, e.g. to write the last chunk of an
+/// encrypted stream.
+pub trait OutputWriter: Write + Send {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl OutputWriter for io::Stdout {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write + Send> OutputWriter for BufWriter<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write + Send> OutputWriter for LineWriter<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Where the output goes instead of stdout: `unix://<path>` for a unix
+/// domain socket, otherwise a file or a named pipe.
+#[derive(Debug, Clone)]
+pub enum Destination {
+    Unix(PathBuf),
+    Path(PathBuf),
+}
+
+impl FromStr for Destination {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s.strip_prefix("unix://") {
+            Some(path) => Destination::Unix(PathBuf::from(path)),
+            None => Destination::Path(PathBuf::from(s)),
+        })
+    }
+}
+
+impl Destination {
+    /// Sockets and named pipes are reconnected to when the reader goes
+    /// away, so a consumer can be restarted without losing more than the
+    /// lines written in between.
+    fn open(&self) -> Fallible<Box<dyn OutputWriter>> {
+        Ok(match self {
+            Destination::Unix(path) => {
+                let path = path.clone();
+                Box::new(LineWriter::new(Reconnecting::new(
+                    format!("unix://{}", path.display()),
+                    move || UnixStream::connect(&path),
+                )))
+            }
+            Destination::Path(path) if is_fifo(path) => {
+                let path = path.clone();
+                Box::new(LineWriter::new(Reconnecting::new(
+                    path.display().to_string(),
+                    move || OpenOptions::new().write(true).open(&path),
+                )))
+            }
+            Destination::Path(path) => Box::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+}
+
+fn is_fifo(path: &std::path::Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.file_type().is_fifo())
+        .unwrap_or(false)
+}
+
+/// A connection opened again, with exponential backoff, whenever writing
+/// to it fails. A write interrupted by a failure is retried as a whole on
+/// the new connection.
+///
+/// Writing blocks while no reader is connected. With `--encrypt`, a reader
+/// connecting mid-stream can't decrypt it, so encrypted streams are meant
+/// for a single connection.
+struct Reconnecting<W, F> {
+    name: String,
+    connect: F,
+    conn: Option<W>,
+}
+
+impl<W: Write, F: FnMut() -> io::Result<W>> Reconnecting<W, F> {
+    fn new(name: String, connect: F) -> Self {
+        Reconnecting {
+            name,
+            connect,
+            conn: None,
+        }
+    }
+
+    fn conn(&mut self) -> &mut W {
+        let mut backoff = MIN_BACKOFF;
+        while self.conn.is_none() {
+            match (self.connect)() {
+                Ok(conn) => self.conn = Some(conn),
+                Err(e) => {
+                    warn!(error_kind:? = e.kind(); "{}: {}, retrying in {:?}", self.name, e, backoff);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+        self.conn.as_mut().expect("connected")
+    }
+}
+
+impl<W: Write, F: FnMut() -> io::Result<W>> Write for Reconnecting<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.conn().write(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    warn!(error_kind:? = e.kind(); "{}: {}, reconnecting", self.name, e);
+                    self.conn = None;
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.conn {
+            Some(conn) => conn.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Destination of the trees and reports, shared by the filter threads.
+#[derive(Clone)]
+pub struct Output(Arc<Mutex<Box<dyn OutputWriter>>>);
+
+impl Output {
+    /// `destination`, or stdout, encrypted with `encryption` when set. The
+    /// `digest` of the plaintext is printed to stderr once finished.
+    pub fn open(
+        destination: Option<&Destination>,
+        encryption: Option<&Encryption>,
+        digest: Option<Digest>,
+    ) -> Fallible<Self> {
+        let writer: Box<dyn OutputWriter> = match destination {
+            Some(destination) => destination.open()?,
+            None => Box::new(io::stdout()),
+        };
+        let writer = match encryption {
+            Some(encryption) => encryption.wrap(writer)?,
+            None => writer,
+        };
+        let writer: Box<dyn OutputWriter> = match digest {
+            Some(Digest::Sha256) => Box::new(DigestWriter {
+                inner: writer,
+                line: Sha256::new(),
+                partial: false,
+                lines: vec![],
+            }),
+            None => writer,
+        };
+        Ok(Output(Arc::new(Mutex::new(writer))))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Box<dyn OutputWriter>> {
+        self.0.lock().expect("output poisoned")
+    }
+
+    /// Flushes and finalizes the output. Every clone must have been dropped.
+    pub fn finish(self) -> Fallible<()> {
+        let writer = Arc::try_unwrap(self.0)
+            .map_err(|_| format_err!("output still in use"))?
+            .into_inner()
+            .expect("output poisoned");
+        Ok(writer.finish()?)
+    }
+}
+
+/// Algorithm of `--digest`.
+#[derive(Debug, Clone, Copy)]
+pub enum Digest {
+    Sha256,
+}
+
+impl FromStr for Digest {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "sha256" => Ok(Digest::Sha256),
+            _ => bail!("unsupported digest {}, expected sha256", s),
+        }
+    }
+}
+
+/// Digests the lines written whatever order the filter threads wrote them
+/// in: the SHA-256 of the sorted SHA-256 of every line, so two runs over
+/// the same input and arguments print the same digest.
+struct DigestWriter {
+    inner: Box<dyn OutputWriter>,
+    /// Hasher of the line being written.
+    line: Sha256,
+    /// Whether part of a line was written since the last newline.
+    partial: bool,
+    lines: Vec<[u8; 32]>,
+}
+
+impl DigestWriter {
+    fn end_line(&mut self) {
+        let line = std::mem::replace(&mut self.line, Sha256::new());
+        self.lines.push(line.finalize().into());
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        for piece in buf[..n].split_inclusive(|&b| b == b'\n') {
+            self.line.update(piece);
+            self.partial = !piece.ends_with(b"\n");
+            if !self.partial {
+                self.end_line();
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputWriter for DigestWriter {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if self.partial {
+            self.end_line();
+        }
+        self.lines.sort_unstable();
+        let mut digest = Sha256::new();
+        for line in &self.lines {
+            digest.update(line);
+        }
+        self.inner.finish()?;
+        eprintln!(
+            "sha256:{} over {} lines",
+            hex::encode(digest.finalize()),
+            self.lines.len()
+        );
+        Ok(())
+    }
+}
+
+/// Encryption of the output, `age:<recipient>` or `aes-gcm:<keyfile>`.
+#[derive(Clone)]
+pub enum Encryption {
+    Age(age::x25519::Recipient),
+    /// 256-bit key read from `keyfile`, raw or hex encoded.
+    AesGcm {
+        keyfile: PathBuf,
+        key: Vec<u8>,
+    },
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encryption::Age(recipient) => write!(f, "age:{}", recipient),
+            Encryption::AesGcm { .. } => write!(f, "aes-gcm:<key>"),
+        }
+    }
+}
+
+impl FromStr for Encryption {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s.split_once(':') {
+            Some(("age", recipient)) => Ok(Encryption::Age(
+                recipient.parse().map_err(|e: &str| format_err!("{}", e))?,
+            )),
+            Some(("aes-gcm", keyfile)) => Ok(Encryption::AesGcm {
+                keyfile: PathBuf::from(keyfile),
+                key: read_key(keyfile)?,
+            }),
+            _ => bail!("expected age:<recipient> or aes-gcm:<keyfile>, got {}", s),
+        }
+    }
+}
+
+/// Reads a 256-bit key, either 32 raw bytes or 64 hex digits.
+pub fn read_key(path: &str) -> Fallible<Vec<u8>> {
+    let content = fs::read(path)?;
+    if content.len() == 32 {
+        return Ok(content);
+    }
+    let key = hex::decode(String::from_utf8_lossy(&content).trim())
+        .map_err(|_| format_err!("{}: expected 32 bytes or 64 hex digits", path))?;
+    if key.len() != 32 {
+        bail!("{}: expected a 256-bit key", path);
+    }
+    Ok(key)
+}
+
+impl Encryption {
+    /// The file the key was read from, if any.
+    pub fn keyfile(&self) -> Option<&Path> {
+        match self {
+            Encryption::Age(_) => None,
+            Encryption::AesGcm { keyfile, .. } => Some(keyfile),
+        }
+    }
+
+    fn wrap(&self, writer: Box<dyn OutputWriter>) -> Fallible<Box<dyn OutputWriter>> {
+        Ok(match self {
+            Encryption::Age(recipient) => {
+                let encryptor = age::Encryptor::with_recipients(std::iter::once(
+                    recipient as &dyn age::Recipient,
+                ))?;
+                Box::new(AgeWriter(encryptor.wrap_output(writer)?))
+            }
+            Encryption::AesGcm { key, .. } => {
+                let mut writer = writer;
+                writer.write_all(AES_GCM_MAGIC)?;
+                Box::new(AesGcmWriter {
+                    cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+                    index: 0,
+                    buf: Vec::with_capacity(CHUNK_SIZE),
+                    inner: writer,
+                })
+            }
+        })
+    }
+}
+
+struct AgeWriter(age::stream::StreamWriter<Box<dyn OutputWriter>>);
+
+impl Write for AgeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl OutputWriter for AgeWriter {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.0.finish()?.finish()
+    }
+}
+
+/// AES-256-GCM in chunks, after `AES_GCM_MAGIC`: the length of the
+/// ciphertext as a big endian u32, a random nonce and the ciphertext of at
+/// most `CHUNK_SIZE` bytes, authenticated with the index of the chunk. The
+/// stream ends with an empty chunk, so a truncated, reordered or shortened
+/// stream is detected.
+struct AesGcmWriter {
+    cipher: Aes256Gcm,
+    index: u64,
+    buf: Vec<u8>,
+    inner: Box<dyn OutputWriter>,
+}
+
+impl AesGcmWriter {
+    fn write_chunk(&mut self) -> io::Result<()> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &self.buf,
+                    aad: &self.index.to_be_bytes(),
+                },
+            )
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.index += 1;
+        self.inner.write_u32::<BigEndian>(ciphertext.len() as u32)?;
+        self.inner.write_all(&nonce)?;
+        self.inner.write_all(&ciphertext)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl Write for AesGcmWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputWriter for AesGcmWriter {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.write_chunk()?;
+        }
+        self.write_chunk()?;
+        self.inner.finish()
+    }
+}
+
+/// Decrypts a stream written with `aes-gcm:<keyfile>`.
+pub fn decrypt_aes_gcm(key: &[u8], input: &mut dyn Read, out: &mut dyn Write) -> Fallible<()> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut magic = [0; AES_GCM_MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != AES_GCM_MAGIC {
+        bail!("not an aes-gcm encrypted output");
+    }
+    let mut index = 0u64;
+    loop {
+        let len = input
+            .read_u32::<BigEndian>()
+            .map_err(|_| format_err!("truncated input"))? as usize;
+        // Checked before allocating, the length isn't authenticated.
+        if len > CHUNK_SIZE + TAG_SIZE {
+            bail!("corrupted input, chunk of {} bytes", len);
+        }
+        let mut nonce = [0; NONCE_SIZE];
+        let mut ciphertext = vec![0; len];
+        input
+            .read_exact(&mut nonce)
+            .and_then(|_| input.read_exact(&mut ciphertext))
+            .map_err(|_| format_err!("truncated input"))?;
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &index.to_be_bytes(),
+                },
+            )
+            .map_err(|_| format_err!("wrong key or corrupted input"))?;
+        if plaintext.is_empty() {
+            return Ok(());
+        }
+        out.write_all(&plaintext)?;
+        index += 1;
+    }
+}
+
+/// A tree as sent to the message sinks: its header and `message`, the
+/// message of the tree after e.g. decoding payloads.
+pub fn tree_json(tree: &MessageTree, message: &Message) -> serde_json::Value {
+    json!({
+        "domain": tree.domain,
+        "hostname": tree.hostname,
+        "ip_address": tree.ip_address,
+        "message_id": tree.message_id,
+        "parent_message_id": tree.parent_message_id,
+        "root_message_id": tree.root_message_id,
+        "thread_name": tree.thread_name,
+        "message": message,
+    })
+}
+
+/// Every field of the header of a tree and `message`, the message of the
+/// tree with its children, for `--json-tree` and `--msgpack-tree`.
+#[derive(Serialize)]
+pub struct FullTree<'a> {
+    domain: &'a Text,
+    hostname: &'a Text,
+    ip_address: &'a Text,
+    message_id: &'a Text,
+    parent_message_id: &'a Text,
+    root_message_id: &'a Text,
+    session_token: &'a Text,
+    thread_group_name: &'a Text,
+    thread_id: &'a Text,
+    thread_name: &'a Text,
+    discard: bool,
+    process_loss: bool,
+    hit_sample: bool,
+    message: &'a Message,
+}
+
+impl<'a> FullTree<'a> {
+    pub fn new(tree: &'a MessageTree, message: &'a Message) -> Self {
+        FullTree {
+            domain: &tree.domain,
+            hostname: &tree.hostname,
+            ip_address: &tree.ip_address,
+            message_id: &tree.message_id,
+            parent_message_id: &tree.parent_message_id,
+            root_message_id: &tree.root_message_id,
+            session_token: &tree.session_token,
+            thread_group_name: &tree.thread_group_name,
+            thread_id: &tree.thread_id,
+            thread_name: &tree.thread_name,
+            discard: tree.discard,
+            process_loss: tree.process_loss,
+            hit_sample: tree.hit_sample,
+            message,
+        }
+    }
+}
+
+/// Where a tree was read from, and when, added to the objects of
+/// `--with-provenance` to trace them back to the logview, see
+/// `TreeLocation`.
+#[derive(Serialize)]
+pub struct Provenance<'a> {
+    file: Option<Cow<'a, str>>,
+    block: u32,
+    block_offset: u64,
+    index: u32,
+    decoded_at_in_ms: u64,
+}
+
+impl<'a> Provenance<'a> {
+    pub fn new(tree: &'a MessageTree) -> Self {
+        let location = &tree.location;
+        Provenance {
+            file: location.file.as_deref().map(Path::to_string_lossy),
+            block: location.block,
+            block_offset: location.block_offset,
+            index: location.index,
+            decoded_at_in_ms: tree.decoded_at_in_ms,
+        }
+    }
+}
+
+/// `FullTree` as a JSON object.
+pub fn full_tree_json(tree: &MessageTree, message: &Message) -> serde_json::Value {
+    serde_json::to_value(FullTree::new(tree, message)).expect("tree as json")
+}
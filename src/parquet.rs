@@ -0,0 +1,376 @@
+use std::io::Write;
+
+use failure::Fallible;
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+/// Rows of a row group, the last one holding the rest.
+const ROW_GROUP_SIZE: usize = 1 << 20;
+
+/// Type.
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+
+/// ConvertedType.
+const UTF8: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+
+/// FieldRepetitionType.REQUIRED.
+const REQUIRED: i32 = 0;
+
+/// Encoding.
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+/// CompressionCodec.SNAPPY.
+const SNAPPY: i32 = 1;
+
+/// PageType.DATA_PAGE.
+const DATA_PAGE: i32 = 0;
+
+/// Values of a column, all set.
+pub enum Values {
+    Int64(Vec<i64>),
+    /// In ms since the epoch.
+    TimestampMillis(Vec<i64>),
+    Utf8(Vec<String>),
+}
+
+impl Values {
+    fn len(&self) -> usize {
+        match self {
+            Values::Int64(values) | Values::TimestampMillis(values) => values.len(),
+            Values::Utf8(values) => values.len(),
+        }
+    }
+
+    fn physical_type(&self) -> i32 {
+        match self {
+            Values::Int64(_) | Values::TimestampMillis(_) => INT64,
+            Values::Utf8(_) => BYTE_ARRAY,
+        }
+    }
+
+    fn converted_type(&self) -> Option<i32> {
+        match self {
+            Values::Int64(_) => None,
+            Values::TimestampMillis(_) => Some(TIMESTAMP_MILLIS),
+            Values::Utf8(_) => Some(UTF8),
+        }
+    }
+
+    /// PLAIN encoding of the values of `rows`.
+    fn encode(&self, rows: (usize, usize)) -> Vec<u8> {
+        let mut data = vec![];
+        match self {
+            Values::Int64(values) | Values::TimestampMillis(values) => {
+                for value in &values[rows.0..rows.1] {
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Values::Utf8(values) => {
+                for value in &values[rows.0..rows.1] {
+                    data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    data.extend_from_slice(value.as_bytes());
+                }
+            }
+        }
+        data
+    }
+}
+
+pub struct Column {
+    pub name: &'static str,
+    pub values: Values,
+}
+
+/// Where a column chunk was written and how big it is.
+struct Chunk {
+    offset: u64,
+    rows: usize,
+    uncompressed_size: usize,
+    compressed_size: usize,
+}
+
+/// Writes `columns`, of as many values each, as a Parquet file of flat
+/// required columns, PLAIN encoded in a snappy compressed page per column
+/// chunk.
+pub fn write(columns: &[Column], created_by: &str, out: &mut dyn Write) -> Fallible<()> {
+    let rows = columns.first().map_or(0, |c| c.values.len());
+    assert!(
+        columns.iter().all(|c| c.values.len() == rows),
+        "columns of different lengths"
+    );
+    out.write_all(MAGIC)?;
+    let mut position = MAGIC.len() as u64;
+    let mut row_groups = vec![];
+    let mut start = 0;
+    while start < rows || row_groups.is_empty() {
+        let end = (start + ROW_GROUP_SIZE).min(rows);
+        let mut chunks = vec![];
+        for column in columns {
+            let data = column.values.encode((start, end));
+            let compressed = snap::Encoder::new().compress_vec(&data)?;
+            let mut header = Compact::default();
+            header.i32(1, DATA_PAGE);
+            header.i32(2, data.len() as i32);
+            header.i32(3, compressed.len() as i32);
+            header.begin_struct(Some(5));
+            header.i32(1, (end - start) as i32);
+            header.i32(2, PLAIN);
+            header.i32(3, RLE);
+            header.i32(4, RLE);
+            header.end_struct();
+            header.stop();
+            out.write_all(&header.buf)?;
+            out.write_all(&compressed)?;
+            chunks.push(Chunk {
+                offset: position,
+                rows: end - start,
+                uncompressed_size: header.buf.len() + data.len(),
+                compressed_size: header.buf.len() + compressed.len(),
+            });
+            position += (header.buf.len() + compressed.len()) as u64;
+        }
+        row_groups.push(chunks);
+        start = end;
+    }
+
+    let mut footer = Compact::default();
+    footer.i32(1, 1);
+    footer.list(2, STRUCT, columns.len() + 1);
+    footer.begin_struct(None);
+    footer.binary(4, b"schema");
+    footer.i32(5, columns.len() as i32);
+    footer.end_struct();
+    for column in columns {
+        footer.begin_struct(None);
+        footer.i32(1, column.values.physical_type());
+        footer.i32(3, REQUIRED);
+        footer.binary(4, column.name.as_bytes());
+        if let Some(converted) = column.values.converted_type() {
+            footer.i32(6, converted);
+        }
+        footer.end_struct();
+    }
+    footer.i64(3, rows as i64);
+    footer.list(4, STRUCT, row_groups.len());
+    for chunks in &row_groups {
+        footer.begin_struct(None);
+        footer.list(1, STRUCT, chunks.len());
+        for (column, chunk) in columns.iter().zip(chunks) {
+            footer.begin_struct(None);
+            footer.i64(2, chunk.offset as i64);
+            footer.begin_struct(Some(3));
+            footer.i32(1, column.values.physical_type());
+            footer.list(2, I32, 2);
+            footer.list_i32(PLAIN);
+            footer.list_i32(RLE);
+            footer.list(3, BINARY, 1);
+            footer.list_binary(column.name.as_bytes());
+            footer.i32(4, SNAPPY);
+            footer.i64(5, chunk.rows as i64);
+            footer.i64(6, chunk.uncompressed_size as i64);
+            footer.i64(7, chunk.compressed_size as i64);
+            footer.i64(9, chunk.offset as i64);
+            footer.end_struct();
+            footer.end_struct();
+        }
+        let size: usize = chunks.iter().map(|c| c.uncompressed_size).sum();
+        footer.i64(2, size as i64);
+        footer.i64(3, chunks.first().map_or(0, |c| c.rows) as i64);
+        footer.end_struct();
+    }
+    footer.binary(6, created_by.as_bytes());
+    footer.stop();
+    out.write_all(&footer.buf)?;
+    out.write_all(&(footer.buf.len() as u32).to_le_bytes())?;
+    out.write_all(MAGIC)?;
+    Ok(())
+}
+
+/// Types of the Thrift compact protocol.
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+/// Writes Thrift structs with the compact protocol, the encoding of the
+/// metadata of Parquet files.
+#[derive(Default)]
+struct Compact {
+    buf: Vec<u8>,
+    /// Id of the last field of the struct being written, fields being
+    /// written as deltas from it.
+    last_id: i16,
+    /// Last ids of the enclosing structs.
+    enclosing: Vec<i16>,
+}
+
+impl Compact {
+    fn field(&mut self, id: i16, ty: u8) {
+        let delta = id - self.last_id;
+        if (1..=15).contains(&delta) {
+            self.buf.push((delta as u8) << 4 | ty);
+        } else {
+            self.buf.push(ty);
+            self.varint(zigzag(id as i64));
+        }
+        self.last_id = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.varint(zigzag(value as i64));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        self.varint(zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.list_binary(value);
+    }
+
+    /// Starts a list of `len` elements of type `ty`, written with the
+    /// `list_` methods or as structs.
+    fn list(&mut self, id: i16, ty: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.buf.push((len as u8) << 4 | ty);
+        } else {
+            self.buf.push(0xf0 | ty);
+            self.varint(len as u64);
+        }
+    }
+
+    fn list_i32(&mut self, value: i32) {
+        self.varint(zigzag(value as i64));
+    }
+
+    fn list_binary(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Starts a struct, field `id` of the current one or else an element of
+    /// a list.
+    fn begin_struct(&mut self, id: Option<i16>) {
+        if let Some(id) = id {
+            self.field(id, STRUCT);
+        }
+        self.enclosing.push(self.last_id);
+        self.last_id = 0;
+    }
+
+    fn end_struct(&mut self) {
+        self.stop();
+        self.last_id = self.enclosing.pop().expect("struct to end");
+    }
+
+    fn stop(&mut self) {
+        self.buf.push(0);
+    }
+
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.buf.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom};
+
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use ::parquet::record::Field;
+
+    use super::*;
+
+    #[test]
+    fn compact_protocol() {
+        let mut compact = Compact::default();
+        compact.i32(1, -1);
+        compact.i64(17, 300);
+        compact.begin_struct(Some(18));
+        compact.binary(1, b"ab");
+        compact.end_struct();
+        compact.list(19, I32, 2);
+        compact.list_i32(1);
+        compact.list_i32(2);
+        compact.stop();
+        let expected: &[&[u8]] = &[
+            // Field 1 as a delta, zigzag -1.
+            &[0x15, 0x01],
+            // Field 17 as a full id, zigzag 300 as a varint.
+            &[0x06, 0x22, 0xd8, 0x04],
+            // Nested struct, its ids restarting from 0.
+            &[0x1c, 0x18, 0x02, b'a', b'b', 0x00],
+            // List of 2 i32.
+            &[0x19, 0x25, 0x02, 0x04],
+            &[0x00],
+        ];
+        assert_eq!(compact.buf, expected.concat());
+    }
+
+    #[test]
+    fn read_by_parquet() {
+        let columns = [
+            Column {
+                name: "ts",
+                values: Values::TimestampMillis(vec![1_715_400_000_000, 1_715_400_000_100]),
+            },
+            Column {
+                name: "duration_in_ms",
+                values: Values::Int64(vec![140, -1]),
+            },
+            Column {
+                name: "name",
+                values: Values::Utf8(vec!["/api/item/1".to_string(), "".to_string()]),
+            },
+        ];
+        let mut file = tempfile::tempfile().unwrap();
+        write(&columns, "dump-cat test", &mut file).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+
+        let reader = SerializedFileReader::new(file).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        assert_eq!(metadata.created_by(), Some("dump-cat test"));
+        let rows: Vec<Vec<Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.unwrap()
+                    .get_column_iter()
+                    .map(|(_, field)| field.clone())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    Field::TimestampMillis(1_715_400_000_000),
+                    Field::Long(140),
+                    Field::Str("/api/item/1".to_string()),
+                ],
+                vec![
+                    Field::TimestampMillis(1_715_400_000_100),
+                    Field::Long(-1),
+                    Field::Str("".to_string()),
+                ],
+            ]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+
+use crate::message_tree::{Message, MessageTree, Text};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hop {
+    pub ty: Text,
+    pub name: Text,
+    /// Start of the message relative to the start of the root.
+    pub offset_in_ms: i64,
+    /// `None` for messages other than transactions.
+    pub duration_in_ms: Option<u64>,
+}
+
+/// The chain of messages from the root of a tree to a message of the
+/// requested type.
+#[derive(Debug, Serialize)]
+pub struct MessagePath {
+    pub message_id: Text,
+    pub hops: Vec<Hop>,
+}
+
+impl MessagePath {
+    /// `ty:name @offset duration` of every hop, joined with ` > `.
+    pub fn to_text(&self) -> String {
+        let hops: Vec<String> = self
+            .hops
+            .iter()
+            .map(|hop| match hop.duration_in_ms {
+                Some(duration) => format!(
+                    "{}:{} @{}ms {}ms",
+                    hop.ty, hop.name, hop.offset_in_ms, duration
+                ),
+                None => format!("{}:{} @{}ms", hop.ty, hop.name, hop.offset_in_ms),
+            })
+            .collect();
+        hops.join(" > ")
+    }
+}
+
+/// Paths from the root to every descendant of type `ty`.
+pub fn paths_of(tree: &MessageTree, ty: &str) -> Vec<MessagePath> {
+    let mut found = vec![];
+    let root_start = tree.message.timestamp_in_ms() as i64;
+    walk(tree, &tree.message, ty, root_start, &mut vec![], &mut found);
+    found
+}
+
+fn walk(
+    tree: &MessageTree,
+    message: &Message,
+    ty: &str,
+    root_start: i64,
+    hops: &mut Vec<Hop>,
+    found: &mut Vec<MessagePath>,
+) {
+    hops.push(Hop {
+        ty: message.ty().clone(),
+        name: message.name().clone(),
+        offset_in_ms: message.timestamp_in_ms() as i64 - root_start,
+        duration_in_ms: message.duration_in_ms(),
+    });
+    if hops.len() > 1 && message.ty() == ty {
+        found.push(MessagePath {
+            message_id: tree.message_id.clone(),
+            hops: hops.clone(),
+        });
+    }
+    if let Message::Transaction(t) = message {
+        for child in &t.children {
+            walk(tree, child, ty, root_start, hops, found);
+        }
+    }
+    hops.pop();
+}
@@ -0,0 +1,748 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::Engine;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use failure::{bail, format_err, Fallible};
+use log::debug;
+
+use crate::message_tree::{
+    InnerEvent, InnerHeartbeat, InnerMetric, InnerTrace, InnerTransaction, Message, Text,
+};
+
+/// Decoders for opaque `data` payloads, keyed by message name.
+///
+/// A spec looks like `name=proto:path/to/schema.proto[#Message]` or
+/// `name=thrift:path/to/schema.thrift[#Struct]`. Without `#...` the first
+/// message/struct of the schema is used as the root type.
+#[derive(Default)]
+pub struct PayloadDecoders {
+    decoders: HashMap<Text, Box<dyn PayloadDecoder>>,
+}
+
+pub trait PayloadDecoder: Send + Sync {
+    fn decode(&self, payload: &[u8]) -> Fallible<String>;
+}
+
+impl PayloadDecoders {
+    pub fn from_specs(specs: &[String]) -> Fallible<Self> {
+        let mut decoders = PayloadDecoders::default();
+        for spec in specs {
+            let (name, decoder) = parse_spec(spec)?;
+            decoders.register(name, decoder);
+        }
+        Ok(decoders)
+    }
+
+    pub fn register(&mut self, name: impl Into<Text>, decoder: Box<dyn PayloadDecoder>) {
+        self.decoders.insert(name.into(), decoder);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    /// Returns a copy of `message` with every recognized payload, including
+    /// the ones of nested children, replaced by its pretty-printed form.
+    pub fn rewrite(&self, message: &Message) -> Message {
+        match message {
+            Message::Transaction(t) => {
+                let mut t = InnerTransaction::clone(t);
+                t.data = self.decode_data(&t.name, t.data);
+                t.children = t.children.iter().map(|c| self.rewrite(c)).collect();
+                Message::Transaction(Arc::new(t))
+            }
+            Message::Event(e) => {
+                let mut e = InnerEvent::clone(e);
+                e.data = self.decode_data(&e.name, e.data);
+                Message::Event(Arc::new(e))
+            }
+            Message::Heartbeat(h) => {
+                let mut h = InnerHeartbeat::clone(h);
+                h.data = self.decode_data(&h.name, h.data);
+                Message::Heartbeat(Arc::new(h))
+            }
+            Message::Metric(m) => {
+                let mut m = InnerMetric::clone(m);
+                m.data = self.decode_data(&m.name, m.data);
+                Message::Metric(Arc::new(m))
+            }
+            Message::Trace(t) => {
+                let mut t = InnerTrace::clone(t);
+                t.data = self.decode_data(&t.name, t.data);
+                Message::Trace(Arc::new(t))
+            }
+        }
+    }
+
+    fn decode_data(&self, name: &str, data: Text) -> Text {
+        let decoder = match self.decoders.get(name) {
+            Some(d) => d,
+            None => return data,
+        };
+        let payload = match base64::engine::general_purpose::STANDARD.decode(data.trim()) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!("payload of \"{}\" is not base64: {}", name, e);
+                return data;
+            }
+        };
+        match decoder.decode(&payload) {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("decode payload of \"{}\" error: {}", name, e);
+                data
+            }
+        }
+    }
+}
+
+/// The schema file of a `--payload-decoder` spec.
+pub fn schema_path(spec: &str) -> Option<&Path> {
+    split_spec(spec).ok().map(|(_, _, path, _)| Path::new(path))
+}
+
+/// The name, kind, schema path and root type of a spec.
+fn split_spec(spec: &str) -> Fallible<(&str, &str, &str, Option<&str>)> {
+    let mut parts = spec.splitn(2, '=');
+    let name = parts.next().unwrap_or_default();
+    let decoder = parts.next().ok_or_else(|| {
+        format_err!(
+            "invalid payload decoder \"{}\", expect name=kind:path",
+            spec
+        )
+    })?;
+    let mut parts = decoder.splitn(2, ':');
+    let kind = parts.next().unwrap_or_default();
+    let location = parts.next().ok_or_else(|| {
+        format_err!(
+            "invalid payload decoder \"{}\", expect name=kind:path",
+            spec
+        )
+    })?;
+    let (path, root) = match location.find('#') {
+        Some(i) => (&location[..i], Some(&location[i + 1..])),
+        None => (location, None),
+    };
+    Ok((name, kind, path, root))
+}
+
+fn parse_spec(spec: &str) -> Fallible<(Text, Box<dyn PayloadDecoder>)> {
+    let (name, kind, path, root) = split_spec(spec)?;
+    let decoder: Box<dyn PayloadDecoder> = match kind {
+        "proto" => Box::new(ProtoDecoder::open(path, root)?),
+        "thrift" => Box::new(ThriftDecoder::open(path, root)?),
+        _ => bail!("unsupported payload decoder kind \"{}\"", kind),
+    };
+    Ok((name.to_string(), decoder))
+}
+
+/// Field declaration shared by the proto and thrift schemas.
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    ty: String,
+    repeated: bool,
+}
+
+type Schema = HashMap<String, HashMap<i64, Field>>;
+
+fn read_schema(path: impl AsRef<Path>) -> Fallible<String> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .map_err(|e| format_err!("read schema {}: {}", path.display(), e))?;
+    // Strip comments so they never look like declarations.
+    let mut stripped = String::with_capacity(content.len());
+    for line in content.lines() {
+        let line = match line.find("//") {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        stripped.push_str(line);
+        stripped.push('\n');
+    }
+    Ok(stripped)
+}
+
+/// Splits a schema into `(type name, body)` blocks introduced by `keyword`.
+fn schema_blocks<'a>(content: &'a str, keyword: &str) -> Vec<(&'a str, &'a str)> {
+    let mut blocks = vec![];
+    let mut rest = content;
+    while let Some(i) = rest.find(keyword) {
+        let preceded_by_ident = rest[..i]
+            .chars()
+            .last()
+            .map(|c| c.is_alphanumeric() || c == '_')
+            .unwrap_or(false);
+        rest = &rest[i + keyword.len()..];
+        if preceded_by_ident || !rest.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let open = match rest.find('{') {
+            Some(o) => o,
+            None => break,
+        };
+        let name = rest[..open].trim();
+        let mut depth = 0;
+        let mut close = None;
+        for (j, c) in rest[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(open + j);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let close = match close {
+            Some(c) => c,
+            None => break,
+        };
+        blocks.push((name, &rest[open + 1..close]));
+        // Nested declarations are picked up by the next iterations.
+        rest = &rest[open + 1..];
+    }
+    blocks
+}
+
+fn root_type(blocks: &[(&str, &str)], root: Option<&str>) -> Fallible<String> {
+    match root {
+        Some(r) => Ok(r.to_string()),
+        None => blocks
+            .first()
+            .map(|(name, _)| name.to_string())
+            .ok_or_else(|| format_err!("schema contains no type")),
+    }
+}
+
+/// Nesting level past which payloads stop being decoded, so a crafted
+/// payload cannot exhaust the stack: deeper protobuf messages are printed as
+/// raw bytes, deeper thrift values leave the payload undecoded.
+const MAX_DEPTH: usize = 64;
+
+/// Whether the declaration being read ends with `oneof <name>`.
+fn is_oneof_header(direct: &str) -> bool {
+    let header = direct.rsplit(';').next().unwrap_or_default();
+    matches!(
+        header.split_whitespace().collect::<Vec<_>>().as_slice(),
+        ["oneof", _]
+    )
+}
+
+pub struct ProtoDecoder {
+    schema: Schema,
+    root: String,
+}
+
+impl ProtoDecoder {
+    pub fn open(path: impl AsRef<Path>, root: Option<&str>) -> Fallible<Self> {
+        let content = read_schema(path)?;
+        let blocks = schema_blocks(&content, "message");
+        let mut schema = Schema::new();
+        for (name, body) in &blocks {
+            let mut fields = HashMap::new();
+            // Only direct fields: skip nested message bodies, but keep the
+            // members of a `oneof`, which are fields of this message.
+            let mut depth = 0;
+            let mut in_oneof = false;
+            let mut direct = String::new();
+            for c in body.chars() {
+                match c {
+                    '{' if depth == 0 && !in_oneof && is_oneof_header(&direct) => {
+                        in_oneof = true;
+                        direct.push(';');
+                    }
+                    '}' if depth == 0 && in_oneof => {
+                        in_oneof = false;
+                        direct.push(';');
+                    }
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            direct.push(';');
+                        }
+                    }
+                    _ if depth == 0 => direct.push(c),
+                    _ => {}
+                }
+            }
+            for decl in direct.split(';') {
+                let decl = match decl.find('[') {
+                    Some(i) => &decl[..i],
+                    None => decl,
+                };
+                let mut sides = decl.splitn(2, '=');
+                let lhs: Vec<&str> = sides
+                    .next()
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .collect();
+                let number = match sides.next().and_then(|n| n.trim().parse::<i64>().ok()) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let (repeated, ty, field) = match lhs.as_slice() {
+                    ["repeated", ty, field] => (true, ty, field),
+                    ["optional", ty, field] | ["required", ty, field] | [ty, field] => {
+                        (false, ty, field)
+                    }
+                    _ => continue,
+                };
+                let ty = ty.rsplit('.').next().unwrap_or(ty);
+                fields.insert(
+                    number,
+                    Field {
+                        name: field.to_string(),
+                        ty: ty.to_string(),
+                        repeated,
+                    },
+                );
+            }
+            schema.insert(name.to_string(), fields);
+        }
+        let root = root_type(&blocks, root)?;
+        if !schema.contains_key(&root) {
+            bail!("message \"{}\" not found in schema", root);
+        }
+        Ok(ProtoDecoder { schema, root })
+    }
+
+    fn decode_message(
+        &self,
+        ty: Option<&str>,
+        mut buf: &[u8],
+        depth: usize,
+        out: &mut String,
+    ) -> Fallible<()> {
+        let fields = ty.and_then(|t| self.schema.get(t));
+        let mut first = true;
+        while !buf.is_empty() {
+            let key = read_proto_varint(&mut buf)?;
+            let number = (key >> 3) as i64;
+            let wire_type = key & 0x7;
+            let field = fields.and_then(|f| f.get(&number));
+            let name = field
+                .map(|f| f.name.clone())
+                .unwrap_or_else(|| number.to_string());
+            let ty = field.map(|f| f.ty.as_str());
+
+            if !first {
+                out.push(' ');
+            }
+            first = false;
+
+            match wire_type {
+                0 => {
+                    let v = read_proto_varint(&mut buf)?;
+                    write!(out, "{}: {}", name, proto_varint_to_string(ty, v))?;
+                }
+                1 => {
+                    let v = take(&mut buf, 8)?;
+                    let s = match ty {
+                        Some("double") => LittleEndian::read_f64(v).to_string(),
+                        Some("sfixed64") => LittleEndian::read_i64(v).to_string(),
+                        _ => LittleEndian::read_u64(v).to_string(),
+                    };
+                    write!(out, "{}: {}", name, s)?;
+                }
+                5 => {
+                    let v = take(&mut buf, 4)?;
+                    let s = match ty {
+                        Some("float") => LittleEndian::read_f32(v).to_string(),
+                        Some("sfixed32") => LittleEndian::read_i32(v).to_string(),
+                        _ => LittleEndian::read_u32(v).to_string(),
+                    };
+                    write!(out, "{}: {}", name, s)?;
+                }
+                2 => {
+                    let len = read_proto_varint(&mut buf)? as usize;
+                    let v = take(&mut buf, len)?;
+                    self.decode_length_delimited(&name, field, v, depth, out)?;
+                }
+                _ => bail!("unsupported wire type {}", wire_type),
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_length_delimited(
+        &self,
+        name: &str,
+        field: Option<&Field>,
+        v: &[u8],
+        depth: usize,
+        out: &mut String,
+    ) -> Fallible<()> {
+        let nest = depth < MAX_DEPTH;
+        match field.map(|f| f.ty.as_str()) {
+            Some("string") => write!(out, "{}: {:?}", name, String::from_utf8_lossy(v))?,
+            Some("bytes") => write!(out, "{}: \"{}\"", name, escape_bytes(v))?,
+            Some(ty) if !nest && self.schema.contains_key(ty) => {
+                write!(out, "{}: \"{}\"", name, escape_bytes(v))?
+            }
+            Some(ty) if self.schema.contains_key(ty) => {
+                write!(out, "{} {{ ", name)?;
+                self.decode_message(Some(ty), v, depth + 1, out)?;
+                out.push_str(" }");
+            }
+            Some(ty) if field.map(|f| f.repeated).unwrap_or(false) => {
+                // Packed repeated scalars.
+                let mut packed = v;
+                let mut values = vec![];
+                while !packed.is_empty() {
+                    values.push(proto_varint_to_string(
+                        Some(ty),
+                        read_proto_varint(&mut packed)?,
+                    ));
+                }
+                write!(out, "{}: [{}]", name, values.join(", "))?;
+            }
+            _ => {
+                // Unknown field: guess between a nested message and a string.
+                let mut nested = String::new();
+                if nest
+                    && !v.is_empty()
+                    && self.decode_message(None, v, depth + 1, &mut nested).is_ok()
+                {
+                    write!(out, "{} {{ {} }}", name, nested)?;
+                } else {
+                    match std::str::from_utf8(v) {
+                        Ok(s) => write!(out, "{}: {:?}", name, s)?,
+                        Err(_) => write!(out, "{}: \"{}\"", name, escape_bytes(v))?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PayloadDecoder for ProtoDecoder {
+    fn decode(&self, payload: &[u8]) -> Fallible<String> {
+        let mut out = String::new();
+        self.decode_message(Some(&self.root), payload, 0, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn read_proto_varint(buf: &mut &[u8]) -> Fallible<u64> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let b = *take(buf, 1)?.first().unwrap_or(&0);
+        n |= u64::from(b & 0x7f) << shift;
+        if b < 0x80 {
+            return Ok(n);
+        }
+    }
+    bail!("varint too long")
+}
+
+fn proto_varint_to_string(ty: Option<&str>, v: u64) -> String {
+    match ty {
+        Some("bool") => (v != 0).to_string(),
+        Some("int32") => (v as i32).to_string(),
+        Some("int64") => (v as i64).to_string(),
+        Some("sint32") | Some("sint64") => ((v >> 1) as i64 ^ -((v & 1) as i64)).to_string(),
+        _ => v.to_string(),
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Fallible<&'a [u8]> {
+    if buf.len() < len {
+        bail!("unexpected end of payload");
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn escape_bytes(v: &[u8]) -> String {
+    v.iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect()
+}
+
+/// Decodes payloads encoded with Thrift's binary protocol.
+pub struct ThriftDecoder {
+    schema: Schema,
+    root: String,
+}
+
+const T_STOP: u8 = 0;
+const T_BOOL: u8 = 2;
+const T_BYTE: u8 = 3;
+const T_DOUBLE: u8 = 4;
+const T_I16: u8 = 6;
+const T_I32: u8 = 8;
+const T_I64: u8 = 10;
+const T_STRING: u8 = 11;
+const T_STRUCT: u8 = 12;
+const T_MAP: u8 = 13;
+const T_SET: u8 = 14;
+const T_LIST: u8 = 15;
+
+impl ThriftDecoder {
+    pub fn open(path: impl AsRef<Path>, root: Option<&str>) -> Fallible<Self> {
+        let content = read_schema(path)?;
+        let mut blocks = schema_blocks(&content, "struct");
+        blocks.extend(schema_blocks(&content, "exception"));
+        blocks.extend(schema_blocks(&content, "union"));
+        let mut schema = Schema::new();
+        for (name, body) in &blocks {
+            let mut fields = HashMap::new();
+            for decl in body.split([',', ';', '\n']) {
+                let mut sides = decl.splitn(2, ':');
+                let id = match sides.next().and_then(|i| i.trim().parse::<i64>().ok()) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let rhs = sides.next().unwrap_or_default();
+                let rhs = match rhs.find('=') {
+                    Some(i) => &rhs[..i],
+                    None => rhs,
+                };
+                let words: Vec<&str> = rhs
+                    .split_whitespace()
+                    .filter(|w| *w != "required" && *w != "optional")
+                    .collect();
+                if let [ty, field] = words.as_slice() {
+                    fields.insert(
+                        id,
+                        Field {
+                            name: field.to_string(),
+                            ty: ty.rsplit('.').next().unwrap_or(ty).to_string(),
+                            repeated: false,
+                        },
+                    );
+                }
+            }
+            schema.insert(name.to_string(), fields);
+        }
+        let root = root_type(&blocks, root)?;
+        if !schema.contains_key(&root) {
+            bail!("struct \"{}\" not found in schema", root);
+        }
+        Ok(ThriftDecoder { schema, root })
+    }
+
+    fn decode_struct(
+        &self,
+        ty: Option<&str>,
+        buf: &mut &[u8],
+        depth: usize,
+        out: &mut String,
+    ) -> Fallible<()> {
+        if depth >= MAX_DEPTH {
+            bail!("thrift values nested deeper than {}", MAX_DEPTH);
+        }
+        let fields = ty.and_then(|t| self.schema.get(t));
+        let mut first = true;
+        loop {
+            let field_type = take(buf, 1)?[0];
+            if field_type == T_STOP {
+                return Ok(());
+            }
+            let id = i64::from(BigEndian::read_i16(take(buf, 2)?));
+            let field = fields.and_then(|f| f.get(&id));
+            if !first {
+                out.push(' ');
+            }
+            first = false;
+            match field {
+                Some(f) => write!(out, "{}: ", f.name)?,
+                None => write!(out, "{}: ", id)?,
+            }
+            self.decode_value(field_type, field.map(|f| f.ty.as_str()), buf, depth, out)?;
+        }
+    }
+
+    fn decode_value(
+        &self,
+        value_type: u8,
+        ty: Option<&str>,
+        buf: &mut &[u8],
+        depth: usize,
+        out: &mut String,
+    ) -> Fallible<()> {
+        if depth >= MAX_DEPTH {
+            bail!("thrift values nested deeper than {}", MAX_DEPTH);
+        }
+        match value_type {
+            T_BOOL => write!(out, "{}", take(buf, 1)?[0] != 0)?,
+            T_BYTE => write!(out, "{}", take(buf, 1)?[0] as i8)?,
+            T_DOUBLE => write!(out, "{}", BigEndian::read_f64(take(buf, 8)?))?,
+            T_I16 => write!(out, "{}", BigEndian::read_i16(take(buf, 2)?))?,
+            T_I32 => write!(out, "{}", BigEndian::read_i32(take(buf, 4)?))?,
+            T_I64 => write!(out, "{}", BigEndian::read_i64(take(buf, 8)?))?,
+            T_STRING => {
+                let len = read_thrift_len(buf)?;
+                let v = take(buf, len)?;
+                match (ty, std::str::from_utf8(v)) {
+                    (Some("binary"), _) | (_, Err(_)) => write!(out, "\"{}\"", escape_bytes(v))?,
+                    (_, Ok(s)) => write!(out, "{:?}", s)?,
+                }
+            }
+            T_STRUCT => {
+                out.push_str("{ ");
+                let ty = ty.filter(|t| self.schema.contains_key(*t));
+                self.decode_struct(ty, buf, depth + 1, out)?;
+                out.push_str(" }");
+            }
+            T_MAP => {
+                let key_type = take(buf, 1)?[0];
+                let value_type = take(buf, 1)?[0];
+                let size = read_thrift_len(buf)?;
+                out.push('{');
+                for i in 0..size {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.decode_value(key_type, None, buf, depth + 1, out)?;
+                    out.push_str(": ");
+                    self.decode_value(value_type, None, buf, depth + 1, out)?;
+                }
+                out.push('}');
+            }
+            T_SET | T_LIST => {
+                let elem_type = take(buf, 1)?[0];
+                let size = read_thrift_len(buf)?;
+                out.push('[');
+                for i in 0..size {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.decode_value(elem_type, None, buf, depth + 1, out)?;
+                }
+                out.push(']');
+            }
+            _ => bail!("unsupported thrift type {}", value_type),
+        }
+        Ok(())
+    }
+}
+
+impl PayloadDecoder for ThriftDecoder {
+    fn decode(&self, payload: &[u8]) -> Fallible<String> {
+        let mut out = String::new();
+        let mut buf = payload;
+        self.decode_struct(Some(&self.root), &mut buf, 0, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn read_thrift_len(buf: &mut &[u8]) -> Fallible<usize> {
+    let len = BigEndian::read_i32(take(buf, 4)?);
+    if len < 0 {
+        bail!("negative thrift length {}", len);
+    }
+    Ok(len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write as _;
+
+    fn proto(schema: &str) -> ProtoDecoder {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(schema.as_bytes()).unwrap();
+        ProtoDecoder::open(file.path(), None).unwrap()
+    }
+
+    fn thrift(schema: &str) -> ThriftDecoder {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(schema.as_bytes()).unwrap();
+        ThriftDecoder::open(file.path(), None).unwrap()
+    }
+
+    const NODE: &str = "message Node {
+        Node child = 1;
+        oneof kind {
+            string label = 2;
+            int32 weight = 3;
+        }
+        int64 id = 4;
+    }";
+
+    #[test]
+    fn oneof_members_are_named() {
+        let decoder = proto(NODE);
+        let payload = [0x12, 1, b'x', 0x18, 5, 0x20, 7];
+        assert_eq!(
+            decoder.decode(&payload).unwrap(),
+            r#"label: "x" weight: 5 id: 7"#
+        );
+    }
+
+    #[test]
+    fn deep_nesting_falls_back_to_bytes() {
+        let decoder = proto(NODE);
+        let mut payload = vec![0x20, 1];
+        for _ in 0..10_000 {
+            let mut len = payload.len();
+            let mut outer = vec![0x0a];
+            while len >= 0x80 {
+                outer.push(len as u8 | 0x80);
+                len >>= 7;
+            }
+            outer.push(len as u8);
+            outer.extend(payload);
+            payload = outer;
+        }
+        let decoded = decoder.decode(&payload).unwrap();
+        assert_eq!(decoded.matches("child {").count(), MAX_DEPTH);
+        assert!(decoded.contains("child: \""));
+    }
+
+    const TREE: &str = "struct Tree {
+        1: Tree child,
+        2: list<Tree> children,
+        3: i32 id,
+    }";
+
+    /// A `Tree` nested `levels` deep through `child`, or through single
+    /// element `children` lists when `lists` is set.
+    fn nested_tree(levels: usize, lists: bool) -> Vec<u8> {
+        let mut payload = vec![T_I32, 0, 3, 0, 0, 0, 1, T_STOP];
+        for _ in 0..levels {
+            let mut outer = if lists {
+                vec![T_LIST, 0, 2, T_STRUCT, 0, 0, 0, 1]
+            } else {
+                vec![T_STRUCT, 0, 1]
+            };
+            outer.extend(payload);
+            outer.push(T_STOP);
+            payload = outer;
+        }
+        payload
+    }
+
+    #[test]
+    fn thrift_nesting() {
+        let decoder = thrift(TREE);
+        assert_eq!(
+            decoder.decode(&nested_tree(2, false)).unwrap(),
+            "child: { child: { id: 1 } }"
+        );
+        assert_eq!(
+            decoder.decode(&nested_tree(1, true)).unwrap(),
+            "children: [{ 3: 1 }]"
+        );
+        assert!(decoder.decode(&nested_tree(10, false)).is_ok());
+        assert!(decoder.decode(&nested_tree(10_000, false)).is_err());
+        assert!(decoder.decode(&nested_tree(10_000, true)).is_err());
+    }
+}
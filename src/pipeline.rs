@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use failure::{err_msg, Fallible};
+
+use crate::message_tree::MessageTree;
+use crate::message_tree_dumper::MessageTreeDumperBuilder;
+use crate::sink::Sink;
+
+/// A step between decoding and the sink, e.g. enriching or deduplicating
+/// trees. Returning `None` drops the tree.
+pub trait Stage {
+    fn process(&mut self, tree: MessageTree) -> Fallible<Option<MessageTree>>;
+}
+
+impl<F: FnMut(MessageTree) -> Fallible<Option<MessageTree>>> Stage for F {
+    fn process(&mut self, tree: MessageTree) -> Fallible<Option<MessageTree>> {
+        self(tree)
+    }
+}
+
+/// Trees of a logview decoded by the threads of `MessageTreeDumper`, then
+/// passed through stages in order and written to a sink:
+///
+/// ```text
+/// Pipeline::source("logview.dat")
+///     .decode(4)
+///     .filter(move |tree| filter.matches(tree))
+///     .stage(enrich)
+///     .sink(JsonSink { .. })?;
+/// ```
+///
+/// The stages and the sink run on the calling thread, in the order the
+/// trees are decoded.
+pub struct Pipeline {
+    dumper: MessageTreeDumperBuilder,
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn source(path: impl Into<PathBuf>) -> Self {
+        let mut dumper = MessageTreeDumperBuilder::default();
+        dumper.path(path.into());
+        Pipeline {
+            dumper,
+            stages: vec![],
+        }
+    }
+
+    /// Decodes blocks on `threads` threads, one by default.
+    pub fn decode(mut self, threads: usize) -> Self {
+        self.dumper.threads(threads.max(1));
+        self
+    }
+
+    /// Drops the trees `f` doesn't match, e.g. with `Filter::matches`.
+    pub fn filter(self, mut f: impl FnMut(&MessageTree) -> Fallible<bool> + 'static) -> Self {
+        self.stage(move |tree: MessageTree| Ok(if f(&tree)? { Some(tree) } else { None }))
+    }
+
+    pub fn stage(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Runs the pipeline until the input is drained, then flushes `sink`.
+    pub fn sink(mut self, mut sink: impl Sink) -> Fallible<()> {
+        let dumper = self.dumper.build().map_err(err_msg)?;
+        'trees: for mut tree in dumper.read_trees() {
+            for stage in &mut self.stages {
+                tree = match stage.process(tree)? {
+                    Some(tree) => tree,
+                    None => continue 'trees,
+                };
+            }
+            sink.write_tree(&tree)?;
+        }
+        sink.flush()
+    }
+}
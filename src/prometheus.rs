@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use failure::Fallible;
+use log::{info, warn};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::logging;
+use crate::message_tree::{Message, MessageTree, Text};
+
+/// Longest a scraper may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request line and headers read.
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Series of a message: its type, name and status.
+type Labels = (Text, Text, Text);
+
+#[derive(Default)]
+struct Histogram {
+    /// One more than the bounds, the last one counting the durations above
+    /// all of them. Not cumulative, unlike the `le` buckets rendered.
+    buckets: Vec<u64>,
+    count: u64,
+    sum_in_ms: u64,
+}
+
+#[derive(Default)]
+struct Series {
+    trees: u64,
+    transactions: BTreeMap<Labels, Histogram>,
+    events: BTreeMap<Labels, u64>,
+}
+
+/// Counters and latency histograms of the matched trees, by type, name and
+/// status, served for Prometheus to scrape on `/metrics` while following a
+/// file.
+///
+/// `cat_trees_total` counts the matched trees, `cat_events_total` their
+/// events and `cat_transaction_duration_seconds` the durations of their
+/// transactions, at any depth. Values only grow for the life of the
+/// process, as Prometheus expects.
+pub struct Metrics {
+    /// Upper bounds of the buckets in ms, ascending.
+    buckets: Vec<f64>,
+    series: Mutex<Series>,
+}
+
+impl Metrics {
+    /// Serves the metrics on `addr` from a background thread, for as long
+    /// as the process runs, over TLS with `tls`.
+    pub fn serve(
+        addr: SocketAddr,
+        mut buckets: Vec<f64>,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> Fallible<Arc<Self>> {
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        let metrics = Arc::new(Metrics {
+            buckets,
+            series: Mutex::default(),
+        });
+        let listener = TcpListener::bind(addr)?;
+        info!(
+            "serving metrics on {}://{}/metrics",
+            if tls.is_some() { "https" } else { "http" },
+            listener.local_addr()?
+        );
+        let served = metrics.clone();
+        thread::Builder::new()
+            .name("Metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.map_err(Into::into).and_then(|stream| {
+                        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                        match &tls {
+                            Some(tls) => {
+                                let conn = ServerConnection::new(tls.clone())?;
+                                let mut stream = StreamOwned::new(conn, stream);
+                                served.respond(&mut stream)?;
+                                stream.conn.send_close_notify();
+                                stream.flush()?;
+                                Ok(())
+                            }
+                            None => served.respond(stream),
+                        }
+                    });
+                    if let Err(e) = result {
+                        warn!(error_kind = logging::error_kind(&e); "metrics: {}", e);
+                    }
+                }
+            })?;
+        Ok(metrics)
+    }
+
+    pub fn observe(&self, tree: &MessageTree) {
+        let mut series = self.series.lock().expect("metrics poisoned");
+        series.trees += 1;
+        self.observe_message(&mut series, &tree.message);
+    }
+
+    fn observe_message(&self, series: &mut Series, message: &Message) {
+        match message {
+            Message::Transaction(t) => {
+                let labels = (t.ty.clone(), t.name.clone(), t.status.clone());
+                let histogram = series.transactions.entry(labels).or_default();
+                if histogram.buckets.is_empty() {
+                    histogram.buckets = vec![0; self.buckets.len() + 1];
+                }
+                let duration = t.duration_in_ms;
+                histogram.count += 1;
+                histogram.sum_in_ms += duration;
+                histogram.buckets[self
+                    .buckets
+                    .partition_point(|&bound| bound < duration as f64)] += 1;
+                for child in &t.children {
+                    self.observe_message(series, child);
+                }
+            }
+            Message::Event(e) => {
+                let labels = (e.ty.clone(), e.name.clone(), e.status.clone());
+                *series.events.entry(labels).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    fn render(&self) -> String {
+        let series = self.series.lock().expect("metrics poisoned");
+        let mut text = String::new();
+        // Writing to a String can't fail.
+        let _ = writeln!(text, "# HELP cat_trees_total Trees matched.");
+        let _ = writeln!(text, "# TYPE cat_trees_total counter");
+        let _ = writeln!(text, "cat_trees_total {}", series.trees);
+
+        let _ = writeln!(text, "# HELP cat_events_total Events of the trees matched.");
+        let _ = writeln!(text, "# TYPE cat_events_total counter");
+        for (labels, count) in &series.events {
+            let _ = writeln!(
+                text,
+                "cat_events_total{{{}}} {}",
+                labels_text(labels),
+                count
+            );
+        }
+
+        let name = "cat_transaction_duration_seconds";
+        let _ = writeln!(
+            text,
+            "# HELP {} Durations of the transactions of the trees matched.",
+            name
+        );
+        let _ = writeln!(text, "# TYPE {} histogram", name);
+        for (labels, histogram) in &series.transactions {
+            let labels = labels_text(labels);
+            let mut cumulative = 0;
+            let bounds = self.buckets.iter().map(|ms| (ms / 1000.0).to_string());
+            for (bound, count) in bounds
+                .chain(Some("+Inf".to_string()))
+                .zip(&histogram.buckets)
+            {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, bound, cumulative
+                );
+            }
+            let sum = histogram.sum_in_ms as f64 / 1000.0;
+            let _ = writeln!(text, "{}_sum{{{}}} {}", name, labels, sum);
+            let _ = writeln!(text, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+        text
+    }
+
+    /// Answers the request of `stream`, with the metrics for `GET /metrics`.
+    fn respond(&self, mut stream: impl Read + Write) -> Fallible<()> {
+        let mut request_line = String::new();
+        {
+            let mut reader = BufReader::new((&mut stream).take(MAX_REQUEST_BYTES));
+            reader.read_line(&mut request_line)?;
+            // Headers are ignored, but read for the client not to see a reset.
+            let mut header = String::new();
+            while reader.read_line(&mut header)? > 2 {
+                header.clear();
+            }
+        }
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next();
+        let path = parts
+            .next()
+            .map(|target| target.split('?').next().unwrap_or_default());
+        let (status, body) = match (method, path) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), _) => ("404 Not Found", "not found, see /metrics\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "only GET is supported\n".to_string(),
+            ),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            CONTENT_TYPE,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+}
+
+fn labels_text((ty, name, status): &Labels) -> String {
+    format!(
+        "ty=\"{}\",name=\"{}\",status=\"{}\"",
+        escape(ty),
+        escape(name),
+        escape(status)
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,179 @@
+use std::io::Write;
+
+use failure::Fallible;
+use prost::Message as _;
+
+use crate::message_tree;
+
+use self::message::Kind;
+
+tonic::include_proto!("dump_cat");
+
+/// Schema of the messages of `--proto` and of `grpc-serve`, for consumers
+/// to generate their classes from, printed by `dump-cat proto-schema`.
+pub const SCHEMA: &str = include_str!("../proto/dump_cat.proto");
+
+impl MessageTree {
+    /// `tree` with its message replaced by `message`, e.g. after decoding
+    /// payloads.
+    pub fn new(
+        request_id: &str,
+        tree: &message_tree::MessageTree,
+        message: &message_tree::Message,
+    ) -> Self {
+        MessageTree {
+            request_id: request_id.to_string(),
+            domain: tree.domain.clone(),
+            hostname: tree.hostname.clone(),
+            ip_address: tree.ip_address.clone(),
+            message_id: tree.message_id.clone(),
+            parent_message_id: tree.parent_message_id.clone(),
+            root_message_id: tree.root_message_id.clone(),
+            session_token: tree.session_token.clone(),
+            thread_group_name: tree.thread_group_name.clone(),
+            thread_id: tree.thread_id.clone(),
+            thread_name: tree.thread_name.clone(),
+            message: Some(message.into()),
+        }
+    }
+}
+
+impl From<&message_tree::Message> for Message {
+    fn from(message: &message_tree::Message) -> Self {
+        let (kind, data, children) = match message {
+            message_tree::Message::Event(e) => (Kind::Event, &e.data, vec![]),
+            message_tree::Message::Transaction(t) => (
+                Kind::Transaction,
+                &t.data,
+                t.children.iter().map(Into::into).collect(),
+            ),
+            message_tree::Message::Heartbeat(h) => (Kind::Heartbeat, &h.data, vec![]),
+            message_tree::Message::Metric(m) => (Kind::Metric, &m.data, vec![]),
+            message_tree::Message::Trace(t) => (Kind::Trace, &t.data, vec![]),
+        };
+        Message {
+            kind: kind as i32,
+            status: message.status().clone(),
+            ty: message.ty().clone(),
+            name: message.name().clone(),
+            timestamp_in_ms: message.timestamp_in_ms(),
+            data: data.clone(),
+            duration_in_ms: message.duration_in_ms(),
+            children,
+        }
+    }
+}
+
+/// Writes `tree`, with its message replaced by `message`, as a
+/// `dump_cat.MessageTree` prefixed by its length as a varint, the framing
+/// of `parseDelimitedFrom` in Java and `ParseDelimitedFrom` in C++.
+pub fn write_delimited(
+    tree: &message_tree::MessageTree,
+    message: &message_tree::Message,
+    buf: &mut Vec<u8>,
+    out: &mut dyn Write,
+) -> Fallible<()> {
+    buf.clear();
+    MessageTree::new("", tree, message).encode_length_delimited(buf)?;
+    out.write_all(buf)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::message_tree::{InnerEvent, InnerTransaction};
+
+    fn event(name: &str, timestamp_in_ms: u64) -> message_tree::Message {
+        message_tree::Message::Event(Arc::new(InnerEvent {
+            name: name.to_string(),
+            timestamp_in_ms,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn delimited_bytes() {
+        let tree = message_tree::MessageTree {
+            domain: "d".to_string(),
+            ..Default::default()
+        };
+        let (mut buf, mut out) = (vec![], vec![]);
+        write_delimited(&tree, &event("x", 1), &mut buf, &mut out).unwrap();
+        assert_eq!(
+            out,
+            [
+                0x0a, // Length of the tree.
+                0x12, 0x01, b'd', // domain = 2
+                0x62, 0x05, // message = 12
+                0x22, 0x01, b'x', // name = 4
+                0x28, 0x01, // timestamp_in_ms = 5
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = message_tree::Message::Transaction(Arc::new(InnerTransaction {
+            ty: "URL".to_string(),
+            name: "/api/item/1".to_string(),
+            timestamp_in_ms: 1_715_400_000_000,
+            status: "0".to_string(),
+            data: "a=1".to_string(),
+            duration_in_ms: 140,
+            children: vec![event("first", 1_715_400_000_010), event("second", 0)],
+            ..Default::default()
+        }));
+        let tree = message_tree::MessageTree {
+            domain: "shop-web".to_string(),
+            message_id: "shop-web-0a000001-475000-1".to_string(),
+            thread_name: "main".to_string(),
+            ..Default::default()
+        };
+        let (mut buf, mut out) = (vec![], vec![]);
+        write_delimited(&tree, &message, &mut buf, &mut out).unwrap();
+        write_delimited(&tree, &event("alone", 2), &mut buf, &mut out).unwrap();
+
+        let mut read = &out[..];
+        let first = MessageTree::decode_length_delimited(&mut read).unwrap();
+        assert_eq!(first.domain, "shop-web");
+        assert_eq!(first.message_id, "shop-web-0a000001-475000-1");
+        assert_eq!(first.thread_name, "main");
+        let decoded = first.message.unwrap();
+        assert_eq!(decoded.kind(), Kind::Transaction);
+        assert_eq!(
+            (
+                decoded.ty.as_str(),
+                decoded.name.as_str(),
+                decoded.data.as_str()
+            ),
+            ("URL", "/api/item/1", "a=1")
+        );
+        assert_eq!(decoded.duration_in_ms, Some(140));
+        let children: Vec<_> = decoded
+            .children
+            .iter()
+            .map(|c| {
+                (
+                    c.kind(),
+                    c.name.as_str(),
+                    c.timestamp_in_ms,
+                    c.duration_in_ms,
+                )
+            })
+            .collect();
+        assert_eq!(
+            children,
+            [
+                (Kind::Event, "first", 1_715_400_000_010, None),
+                (Kind::Event, "second", 0, None),
+            ]
+        );
+
+        let second = MessageTree::decode_length_delimited(&mut read).unwrap();
+        assert_eq!(second.message.unwrap().name, "alone");
+        assert!(read.is_empty());
+    }
+}
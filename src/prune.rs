@@ -0,0 +1,169 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{format_err, Fallible};
+use log::info;
+
+use crate::human;
+use crate::logview_writer::LogviewWriter;
+use crate::message_tree::MessageTree;
+use crate::message_tree_dumper::{try_read_block, MessageBlockReader};
+use crate::sidecar;
+
+/// How long trees are kept, by age of their root message.
+pub struct Retention {
+    pub keep: Duration,
+    /// Longer retention of the trees with an error.
+    pub keep_errors: Duration,
+}
+
+impl Retention {
+    fn keeps(&self, tree: &MessageTree, now_in_ms: u64) -> bool {
+        let age = Duration::from_millis(now_in_ms.saturating_sub(tree.message.timestamp_in_ms()));
+        age <= self.keep || (age <= self.keep_errors && is_error(tree))
+    }
+}
+
+/// Whether the root of `tree` failed or an `Error` event was logged in it.
+fn is_error(tree: &MessageTree) -> bool {
+    tree.message.status() != "0" || tree.events.iter().any(|e| e.ty == "Error")
+}
+
+/// Trees kept and dropped from one file, and its size before and after.
+#[derive(Debug, Default)]
+pub struct Pruned {
+    pub kept: u64,
+    pub dropped: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl Pruned {
+    /// The size after is only known once the file is rewritten.
+    pub fn summary(&self, dry_run: bool) -> String {
+        let counts = format!(
+            "kept {}\tdropped {}",
+            human::count(self.kept),
+            human::count(self.dropped)
+        );
+        let before = human::bytes(self.bytes_before as f64);
+        match dry_run {
+            true => format!("{}\t{}", counts, before),
+            false => {
+                let after = human::bytes(self.bytes_after as f64);
+                match human::enabled() {
+                    true => format!("{}\t{} -> {}", counts, before, after),
+                    // Keeps the unit once, as in `1234 -> 567 bytes`.
+                    false => format!("{}\t{} -> {}", counts, self.bytes_before, after),
+                }
+            }
+        }
+    }
+}
+
+/// The logview files under `dir`, recognized by their magic number, so
+/// sidecars, bundles and other files are left alone.
+pub fn logviews(dir: &Path) -> Fallible<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(logviews(&path)?);
+            continue;
+        }
+        let mut magic = [0; 4];
+        if File::open(&path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .is_ok()
+            && magic == [0xff; 4]
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Rewrites the logview at `path` without the trees `retention` drops,
+/// removing it when none is left. Its sidecar, stale once the file changes,
+/// is removed too. With `dry_run`, only counts the trees.
+///
+/// The kept trees are copied as they are encoded in the file. The file is
+/// left untouched unless every block of it could be read.
+pub fn prune(path: &Path, retention: &Retention, dry_run: bool) -> Fallible<Pruned> {
+    let now_in_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let mut pruned = Pruned {
+        bytes_before: fs::metadata(path)?.len(),
+        ..Pruned::default()
+    };
+
+    let tmp = path.with_extension("prune.tmp");
+    let mut writer = match dry_run {
+        true => None,
+        false => Some(LogviewWriter::new(BufWriter::new(File::create(&tmp)?))?),
+    };
+    let copied = copy_kept(path, retention, now_in_ms, &mut pruned, writer.as_mut())
+        .and_then(|()| writer.map(LogviewWriter::finish).transpose());
+    match copied {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(pruned),
+        Err(e) => {
+            if !dry_run {
+                fs::remove_file(&tmp)?;
+            }
+            return Err(format_err!("prune {}: {}", path.display(), e));
+        }
+    }
+
+    if pruned.dropped == 0 {
+        fs::remove_file(&tmp)?;
+        pruned.bytes_after = pruned.bytes_before;
+        return Ok(pruned);
+    }
+    if pruned.kept == 0 {
+        fs::remove_file(&tmp)?;
+        fs::remove_file(path)?;
+    } else {
+        pruned.bytes_after = fs::metadata(&tmp)?.len();
+        fs::rename(&tmp, path)?;
+    }
+    let sidecar = sidecar::sidecar_path(path);
+    if sidecar.exists() {
+        fs::remove_file(&sidecar)?;
+    }
+    info!(
+        "pruned {}: kept {} trees, dropped {}",
+        path.display(),
+        pruned.kept,
+        pruned.dropped
+    );
+    Ok(pruned)
+}
+
+/// Counts the trees of the logview at `path` `retention` keeps and drops,
+/// copying the kept ones to `writer`.
+fn copy_kept(
+    path: &Path,
+    retention: &Retention,
+    now_in_ms: u64,
+    pruned: &mut Pruned,
+    mut writer: Option<&mut LogviewWriter<BufWriter<File>>>,
+) -> Fallible<()> {
+    for block in MessageBlockReader::open(path)?.try_into_blocks() {
+        let (offset, block) = block?;
+        let trees = try_read_block(block).map_err(|e| format_err!("block at {}: {}", offset, e))?;
+        for (tree, encoded) in trees {
+            if !retention.keeps(&tree, now_in_ms) {
+                pruned.dropped += 1;
+                continue;
+            }
+            pruned.kept += 1;
+            if let Some(writer) = &mut writer {
+                writer.write_encoded(&encoded)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,596 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use evalexpr::{build_operator_tree, Node};
+use failure::{bail, Error, Fallible};
+
+use crate::filter;
+use crate::running;
+
+/// Functions of evalexpr.
+const FUNCTIONS: &[&str] = &["min", "max"];
+
+/// Functions on timestamps in milliseconds, with their number of
+/// arguments, in both languages.
+pub const TIMESTAMP_FUNCTIONS: &[(&str, usize)] = &[
+    // The hour of the timestamp, 0 to 23, in the local time zone.
+    ("hour_of_day", 1),
+    // The start of the bucket of `n` minutes holding the timestamp.
+    ("minute_bucket", 2),
+    // Seconds elapsed since the timestamp. Duration literals are in
+    // milliseconds, so compare it with a number, e.g. `> 600`.
+    ("age_seconds", 1),
+];
+
+/// Calls the function `name` of `TIMESTAMP_FUNCTIONS`.
+pub fn call_timestamp_function(name: &str, args: &[i64]) -> Fallible<i64> {
+    Ok(match (name, args) {
+        ("hour_of_day", &[ts]) => {
+            let spec = time::Timespec::new(ts.div_euclid(1000), 0);
+            i64::from(time::at(spec).tm_hour)
+        }
+        ("minute_bucket", &[ts, minutes]) => {
+            if minutes <= 0 {
+                bail!(
+                    "minute_bucket needs a positive number of minutes, got {}",
+                    minutes
+                );
+            }
+            ts - ts.rem_euclid(minutes * 60 * 1000)
+        }
+        ("age_seconds", &[ts]) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            (now.as_millis() as i64 - ts).div_euclid(1000)
+        }
+        _ => bail!("no overload of {} for {} arguments", name, args.len()),
+    })
+}
+
+/// The language queries are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum QueryLang {
+    #[default]
+    Evalexpr,
+    /// A subset of the Common Expression Language, see [`crate::cel`].
+    Cel,
+}
+
+impl FromStr for QueryLang {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "evalexpr" => QueryLang::Evalexpr,
+            "cel" => QueryLang::Cel,
+            _ => bail!("unknown query language {}, expected evalexpr or cel", s),
+        })
+    }
+}
+
+/// A mistake in a query, with where it is.
+pub struct Diagnostic {
+    query: String,
+    span: Range<usize>,
+    message: String,
+    help: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let column = self.query[..self.span.start].chars().count();
+        let width = self.query[self.span.clone()].chars().count().max(1);
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "  {}", self.query)?;
+        write!(f, "  {}{}", " ".repeat(column), "^".repeat(width))?;
+        if let Some(help) = &self.help {
+            write!(f, "\n  help: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+// Errors returned from main are printed with Debug.
+impl fmt::Debug for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Identifier,
+    Number,
+    /// An RFC 3339 timestamp, e.g. `2024-05-01T12:00:00+08:00`.
+    Timestamp,
+    String,
+    /// A string missing its closing quote.
+    UnclosedString,
+    Operator,
+    LeftParen,
+    RightParen,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub kind: Kind,
+    pub text: &'a str,
+    pub span: Range<usize>,
+}
+
+/// Operators of two characters, matched before single characters.
+const OPERATORS: &[&str] = &["==", "!=", ">=", "<=", "&&", "||"];
+
+/// Splits `query` like evalexpr does, keeping the position of each token.
+pub fn tokenize(query: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = |kind, end: usize| Token {
+            kind,
+            text: &query[start..end],
+            span: start..end,
+        };
+        if c.is_whitespace() {
+            continue;
+        } else if let Some((len, _)) = timestamp_ms(&query[start..]) {
+            while chars.peek().is_some_and(|&(i, _)| i < start + len) {
+                chars.next();
+            }
+            tokens.push(token(Kind::Timestamp, start + len));
+        } else if c == '"' {
+            let mut escaped = false;
+            let mut end = None;
+            for (i, c) in chars.by_ref() {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => {
+                        end = Some(i + 1);
+                        break;
+                    }
+                    _ => escaped = false,
+                }
+            }
+            tokens.push(match end {
+                Some(end) => token(Kind::String, end),
+                None => token(Kind::UnclosedString, query.len()),
+            });
+        } else if c.is_alphabetic() || c == '_' || c.is_ascii_digit() {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' || c == '.' {
+                    end = i + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let kind = if c.is_ascii_digit() {
+                Kind::Number
+            } else {
+                Kind::Identifier
+            };
+            tokens.push(token(kind, end));
+        } else if c == '(' {
+            tokens.push(token(Kind::LeftParen, start + 1));
+        } else if c == ')' {
+            tokens.push(token(Kind::RightParen, start + 1));
+        } else {
+            let two = OPERATORS.iter().any(|op| query[start..].starts_with(op));
+            if two {
+                chars.next();
+            }
+            let end = start + if two { 2 } else { c.len_utf8() };
+            tokens.push(token(Kind::Operator, end));
+        }
+    }
+    tokens
+}
+
+/// Milliseconds of a duration literal like `500ms`, `2s`, `5m`, `1h` or
+/// `1d`.
+pub fn duration_ms(text: &str) -> Option<i64> {
+    let split = text.find(|c: char| !c.is_ascii_digit())?;
+    let (value, unit) = text.split_at(split);
+    let unit = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 3600 * 1000,
+        "d" => 24 * 3600 * 1000,
+        _ => return None,
+    };
+    value.parse::<i64>().ok()?.checked_mul(unit)
+}
+
+/// Length and epoch milliseconds of the RFC 3339 timestamp `s` starts
+/// with, e.g. `2024-05-01T12:00:00+08:00` or `2024-05-01T04:00:00.250Z`.
+/// Timestamps without an offset are in UTC.
+pub fn timestamp_ms(s: &str) -> Option<(usize, i64)> {
+    let b = s.as_bytes();
+    let number = |range: Range<usize>| -> Option<i64> {
+        let digits = b.get(range)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        Some(digits.iter().fold(0, |n, d| n * 10 + i64::from(d - b'0')))
+    };
+    let at = |i: usize, c: u8| b.get(i) == Some(&c);
+    if !(at(4, b'-') && at(7, b'-') && at(10, b'T') && at(13, b':') && at(16, b':')) {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if second > 60 {
+        return None;
+    }
+
+    let mut len = 19;
+    let mut ms = 0;
+    if at(len, b'.') {
+        let digits = b[len + 1..]
+            .iter()
+            .take_while(|d| d.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return None;
+        }
+        // Digits past milliseconds are dropped.
+        let kept = digits.min(3);
+        ms = number(len + 1..len + 1 + kept)? * 10_i64.pow(3 - kept as u32);
+        len += 1 + digits;
+    }
+    let offset_minutes = match b.get(len) {
+        Some(b'Z') | Some(b'z') => {
+            len += 1;
+            0
+        }
+        Some(&sign @ (b'+' | b'-')) if at(len + 3, b':') => {
+            let minutes = number(len + 1..len + 3)? * 60 + number(len + 4..len + 6)?;
+            len += 6;
+            if sign == b'-' {
+                -minutes
+            } else {
+                minutes
+            }
+        }
+        _ => 0,
+    };
+    // Days since the epoch of a proleptic Gregorian date, after Howard
+    // Hinnant's days_from_civil.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+    Some((len, seconds * 1000 + ms))
+}
+
+/// Functions of evalexpr queries.
+fn functions() -> impl Iterator<Item = &'static str> + Clone {
+    FUNCTIONS
+        .iter()
+        .copied()
+        .chain(TIMESTAMP_FUNCTIONS.iter().map(|(name, _)| *name))
+        .chain(running::FUNCTIONS.iter().map(|(name, _)| *name))
+}
+
+/// Whether the identifier at `i` of `tokens` is called as a function.
+fn is_call(tokens: &[Token], i: usize) -> bool {
+    tokens.get(i + 1).map(|t| t.kind) == Some(Kind::LeftParen)
+}
+
+/// Identifiers of `tokens` used as variables, with their tokens.
+pub fn variable_tokens<'a, 'b>(tokens: &'b [Token<'a>]) -> impl Iterator<Item = &'b Token<'a>> {
+    tokens.iter().enumerate().filter_map(move |(i, token)| {
+        let keyword = token.text == "true" || token.text == "false";
+        if token.kind == Kind::Identifier && !keyword && !is_call(tokens, i) {
+            Some(token)
+        } else {
+            None
+        }
+    })
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous + (ca != cb) as usize;
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to `name`, if close enough to be a typo.
+pub(crate) fn closest<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|candidate| (levenshtein(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Checks `query` and compiles it with evalexpr.
+///
+/// Mistakes evalexpr would only report when evaluating, or without saying
+/// where, are reported as a [`Diagnostic`] pointing at the offending part.
+pub fn compile(query: &str) -> Fallible<Node> {
+    let tokens = tokenize(query);
+    let diagnostic = |span: Range<usize>, message: String, help: Option<String>| Diagnostic {
+        query: query.to_string(),
+        span,
+        message,
+        help,
+    };
+
+    let mut open = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        let error = match (token.kind, token.text) {
+            (Kind::UnclosedString, _) => Some(("unclosed string".to_string(), None)),
+            (Kind::LeftParen, _) => {
+                open.push(token.span.clone());
+                None
+            }
+            (Kind::RightParen, _) => match open.pop() {
+                Some(_) => None,
+                None => Some(("unmatched `)`".to_string(), None)),
+            },
+            (Kind::Operator, "'") => Some((
+                "strings are double quoted".to_string(),
+                Some("write \"...\"".to_string()),
+            )),
+            (Kind::Operator, "!=") => Some((
+                "`!=` isn't evaluated correctly".to_string(),
+                Some("write !(a == b)".to_string()),
+            )),
+            (Kind::Operator, "=") => Some((
+                "assignments aren't supported".to_string(),
+                Some("compare with ==".to_string()),
+            )),
+            (Kind::Identifier, "and") | (Kind::Identifier, "or") => Some((
+                format!("`{}` isn't an operator", token.text),
+                Some(format!(
+                    "write {}",
+                    if token.text == "and" { "&&" } else { "||" }
+                )),
+            )),
+            (Kind::Identifier, name) if is_call(&tokens, i) && !functions().any(|f| f == name) => {
+                let help = match closest(name, functions()) {
+                    Some(function) => format!("did you mean `{}`?", function),
+                    None => format!(
+                        "functions are {}",
+                        functions().collect::<Vec<_>>().join(", ")
+                    ),
+                };
+                Some((format!("unknown function `{}`", name), Some(help)))
+            }
+            _ => None,
+        };
+        if let Some((message, help)) = error {
+            return Err(diagnostic(token.span.clone(), message, help).into());
+        }
+    }
+    if let Some(span) = open.pop() {
+        return Err(diagnostic(span, "unclosed `(`".to_string(), None).into());
+    }
+    if let Some(token) = dangling_operator(&tokens) {
+        let message = format!("`{}` is missing an operand", token.text);
+        return Err(diagnostic(token.span.clone(), message, None).into());
+    }
+    if let Some(token) = missing_operator(&tokens) {
+        let message = format!("missing operator before `{}`", token.text);
+        return Err(diagnostic(token.span.clone(), message, None).into());
+    }
+
+    for token in variable_tokens(&tokens) {
+        let known = filter::VARIABLES.iter().map(|(name, _)| *name);
+        if known.clone().any(|name| name == token.text) {
+            continue;
+        }
+        let help = match closest(token.text, known) {
+            Some(variable) => format!("did you mean `{}`?", variable),
+            None => format!("for the string, write \"{}\"", token.text),
+        };
+        return Err(diagnostic(
+            token.span.clone(),
+            format!("unknown variable `{}`", token.text),
+            Some(help),
+        )
+        .into());
+    }
+
+    let rewritten = number_running_calls(&rewrite_literals(query, &tokens)?);
+    build_operator_tree(&rewritten)
+        .map_err(|e| diagnostic(0..query.len(), e.to_string(), None).into())
+}
+
+/// `query` with its durations and timestamps replaced by milliseconds, as
+/// they are compared.
+fn rewrite_literals(query: &str, tokens: &[Token]) -> Result<String, Diagnostic> {
+    let mut rewritten = String::with_capacity(query.len());
+    let mut copied = 0;
+    for token in tokens {
+        let ms = match token.kind {
+            Kind::Timestamp => timestamp_ms(token.text).map(|(_, ms)| ms),
+            Kind::Number if token.text.parse::<f64>().is_err() => match duration_ms(token.text) {
+                Some(ms) => Some(ms),
+                None => {
+                    return Err(Diagnostic {
+                        query: query.to_string(),
+                        span: token.span.clone(),
+                        message: format!("invalid number `{}`", token.text),
+                        help: Some("durations are in ms, s, m, h or d, e.g. 500ms".to_string()),
+                    })
+                }
+            },
+            _ => None,
+        };
+        if let Some(ms) = ms {
+            rewritten.push_str(&query[copied..token.span.start]);
+            rewritten.push_str(&ms.to_string());
+            copied = token.span.end;
+        }
+    }
+    rewritten.push_str(&query[copied..]);
+    Ok(rewritten)
+}
+
+/// `query` with the number of each call of `running::FUNCTIONS`, in the
+/// order of the query, passed as its first argument: the aggregates are
+/// kept per call site.
+fn number_running_calls(query: &str) -> String {
+    let tokens = tokenize(query);
+    let mut rewritten = String::with_capacity(query.len());
+    let mut copied = 0;
+    let mut site = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let running = token.kind == Kind::Identifier
+            && is_call(&tokens, i)
+            && running::FUNCTIONS.iter().any(|(f, _)| *f == token.text);
+        if running {
+            let paren = &tokens[i + 1];
+            rewritten.push_str(&query[copied..paren.span.end]);
+            rewritten.push_str(&site.to_string());
+            rewritten.push_str(", ");
+            copied = paren.span.end;
+            site += 1;
+        }
+    }
+    rewritten.push_str(&query[copied..]);
+    rewritten
+}
+
+/// The first operator missing an operand.
+fn dangling_operator<'a, 'b>(tokens: &'b [Token<'a>]) -> Option<&'b Token<'a>> {
+    let is_operand_end =
+        |t: Option<&Token>| t.is_some_and(|t| !matches!(t.kind, Kind::Operator | Kind::LeftParen));
+    let is_operand_start = |t: Option<&Token>| {
+        t.is_some_and(|t| {
+            t.kind != Kind::RightParen
+                && !(t.kind == Kind::Operator && t.text != "!" && t.text != "-")
+        })
+    };
+    tokens.iter().enumerate().find_map(|(i, token)| {
+        let unary = token.text == "!" || token.text == "-";
+        let before = i.checked_sub(1).and_then(|i| tokens.get(i));
+        let missing = token.kind == Kind::Operator
+            && (!is_operand_start(tokens.get(i + 1)) || (!unary && !is_operand_end(before)));
+        if missing {
+            Some(token)
+        } else {
+            None
+        }
+    })
+}
+
+/// The first operand following another one without an operator between
+/// them, which evalexpr would take for a function call.
+fn missing_operator<'a, 'b>(tokens: &'b [Token<'a>]) -> Option<&'b Token<'a>> {
+    tokens.windows(2).enumerate().find_map(|(i, pair)| {
+        let ends_operand = match pair[0].kind {
+            Kind::Identifier => !is_call(tokens, i),
+            Kind::Number | Kind::Timestamp | Kind::String | Kind::RightParen => true,
+            _ => false,
+        };
+        let starts_operand = matches!(
+            pair[1].kind,
+            Kind::Identifier | Kind::Number | Kind::Timestamp | Kind::String | Kind::LeftParen
+        );
+        if ends_operand && starts_operand {
+            Some(&pair[1])
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(query: &str) -> Result<String, Diagnostic> {
+        rewrite_literals(query, &tokenize(query))
+    }
+
+    #[test]
+    fn rewrites_durations() {
+        assert_eq!(
+            rewrite("transaction.duration_in_ms > 2s && transaction.duration_in_ms < 1m").unwrap(),
+            "transaction.duration_in_ms > 2000 && transaction.duration_in_ms < 60000"
+        );
+        assert_eq!(
+            rewrite("duration_in_ms>=500ms").unwrap(),
+            "duration_in_ms>=500"
+        );
+        assert_eq!(rewrite("x == 1d").unwrap(), "x == 86400000");
+    }
+
+    #[test]
+    fn rewrites_timestamps() {
+        assert_eq!(
+            rewrite("timestamp_in_ms >= 2024-05-11T04:00:00Z").unwrap(),
+            "timestamp_in_ms >= 1715400000000"
+        );
+        assert_eq!(
+            rewrite("timestamp_in_ms < 2024-05-11T12:00:00.250+08:00").unwrap(),
+            "timestamp_in_ms < 1715400000250"
+        );
+    }
+
+    #[test]
+    fn leaves_numbers_and_strings() {
+        let query = r#"status == "2s" && duration_in_ms > 1.5 && x == 10"#;
+        assert_eq!(rewrite(query).unwrap(), query);
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        let error = rewrite("duration_in_ms > 2parsecs").unwrap_err();
+        assert_eq!(error.span, 17..25);
+        assert_eq!(error.message, "invalid number `2parsecs`");
+    }
+
+    #[test]
+    fn numbers_running_calls() {
+        assert_eq!(
+            number_running_calls("running_count(name) <= 3 && !seen_before( type)"),
+            "running_count(0, name) <= 3 && !seen_before(1,  type)"
+        );
+        assert_eq!(
+            number_running_calls("running_count(\"1\") + running_count(1) > 2"),
+            "running_count(0, \"1\") + running_count(1, 1) > 2"
+        );
+        assert_eq!(number_running_calls("seen_before == 1"), "seen_before == 1");
+    }
+
+    #[test]
+    fn compiles_rewritten_queries() {
+        compile("timestamp_in_ms >= 2024-05-11T04:00:00Z && status == \"0\"").unwrap();
+        let error = compile("status != \"0\"").unwrap_err().to_string();
+        assert!(
+            error.starts_with("`!=` isn't evaluated correctly"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::{bail, format_err, Fallible};
+
+/// A number of trees per second, minute or hour, e.g. `5000/s`. A bare
+/// number is per second.
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    count: u32,
+    per: Duration,
+}
+
+impl FromStr for Rate {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let (count, unit) = s.split_once('/').unwrap_or((s, "s"));
+        let count: u32 = count
+            .parse()
+            .map_err(|_| format_err!("invalid rate {}, e.g. 5000/s", s))?;
+        if count == 0 {
+            bail!("invalid rate {}, must be positive", s);
+        }
+        let per = match unit {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => bail!("invalid rate unit in {}, expected s, m or h", s),
+        };
+        Ok(Rate { count, per })
+    }
+}
+
+/// A token bucket shared by the filter threads, limiting the trees sent
+/// downstream to `rate` with bursts of up to one period's worth.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: Rate,
+    /// Available tokens and when they were counted.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Rate) -> Self {
+        RateLimiter {
+            rate,
+            bucket: Mutex::new((f64::from(rate.count), Instant::now())),
+        }
+    }
+
+    fn tokens_per_sec(&self) -> f64 {
+        f64::from(self.rate.count) / self.rate.per.as_secs_f64()
+    }
+
+    /// Waits for a token.
+    pub fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("rate limiter poisoned");
+            let (tokens, counted_at) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens
+                + now.duration_since(*counted_at).as_secs_f64() * self.tokens_per_sec())
+            .min(f64::from(self.rate.count));
+            *counted_at = now;
+            // Taken right away, in debt until refilled, so that waiting
+            // threads are served in turn.
+            *tokens -= 1.0;
+            if *tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-*tokens / self.tokens_per_sec())
+        };
+        thread::sleep(wait);
+    }
+}
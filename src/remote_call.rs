@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::message_tree::{Message, MessageTree, Text};
+
+pub const REMOTE_CALL_TYPE: &str = "RemoteCall";
+
+/// Root messages of every tree of the input, keyed by message id, so that a
+/// `RemoteCall` event can be resolved to the downstream tree it points to.
+#[derive(Default)]
+pub struct RemoteCallIndex {
+    messages: HashMap<Text, Message>,
+}
+
+impl RemoteCallIndex {
+    pub fn build(trees: impl IntoIterator<Item = MessageTree>) -> Self {
+        let mut index = RemoteCallIndex::default();
+        for tree in trees {
+            index.messages.insert(tree.message_id, tree.message);
+        }
+        debug!("indexed {} trees for remote calls", index.messages.len());
+        index
+    }
+
+    /// The child message ids of all `RemoteCall` events of `tree`, with the
+    /// downstream root message when it is part of the input.
+    pub fn resolve<'a>(&'a self, tree: &'a MessageTree) -> Vec<(&'a Text, Option<&'a Message>)> {
+        tree.events
+            .iter()
+            .filter(|e| e.ty == REMOTE_CALL_TYPE && !e.data.is_empty())
+            .map(|e| (&e.data, self.messages.get(&e.data)))
+            .collect()
+    }
+}
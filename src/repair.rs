@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use failure::{bail, err_msg, Fallible};
+
+use crate::human;
+use crate::logview_writer::BLOCK_HEADER;
+use crate::message_tree::MessageTree;
+
+/// The part of the block header blocks are found by after a corrupt one:
+/// the stream identifier of snappy.
+const MAGIC: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+
+/// Bytes read at once when looking for the next block.
+const SCAN_SIZE: usize = 1024 * 1024;
+
+/// What `repair` kept and dropped.
+#[derive(Default)]
+pub struct Repaired {
+    pub blocks: u64,
+    pub trees: u64,
+    pub dropped_blocks: u64,
+    pub dropped_bytes: u64,
+    /// Bytes of the last block cut short, if any.
+    pub truncated_tail: u64,
+}
+
+/// Copies the blocks of the logview at `path` which decode to `out`, as
+/// they are, and prints why the others are dropped.
+///
+/// The block after a corrupt one is found by its length when the length
+/// frames the next block, else by scanning for the header of the next
+/// block. A last block going past the end of the file, e.g. of a file
+/// copied while being written, is truncated.
+pub fn repair(path: &Path, out: &Path, report: &mut dyn Write) -> Fallible<Repaired> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if file.read_i32::<BigEndian>()? != -1 {
+        bail!("{} isn't a logview", path.display());
+    }
+    let mut writer = BufWriter::new(File::create(out)?);
+    writer.write_i32::<BigEndian>(-1)?;
+
+    let mut repaired = Repaired::default();
+    let mut offset = 4;
+    while offset < len {
+        if len - offset < 4 {
+            repaired.truncated_tail = len - offset;
+            writeln!(report, "{}: length cut short, truncated", offset)?;
+            break;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let length = file.read_i32::<BigEndian>()?;
+        let end = offset + 4 + length.max(0) as u64;
+        if length < BLOCK_HEADER.len() as i32 {
+            let next = resync(&mut file, offset + 1, len)?;
+            drop_range(&mut repaired, report, offset, next, "bad block length")?;
+            offset = next;
+            continue;
+        }
+        if end > len {
+            let mut header = vec![];
+            (&mut file)
+                .take(MAGIC.len() as u64)
+                .read_to_end(&mut header)?;
+            match resync(&mut file, offset + 1, len)? {
+                next if next == len && MAGIC.starts_with(&header) => {
+                    repaired.truncated_tail = len - offset;
+                    writeln!(
+                        report,
+                        "{}: last block cut short, truncated {}",
+                        offset,
+                        human::bytes((len - offset) as f64)
+                    )?;
+                    break;
+                }
+                next => {
+                    drop_range(&mut repaired, report, offset, next, "block past the end")?;
+                    offset = next;
+                    continue;
+                }
+            }
+        }
+        let mut block = vec![0; length as usize];
+        file.read_exact(&mut block)?;
+        match check_block(&block) {
+            Ok(trees) => {
+                writer.write_i32::<BigEndian>(length)?;
+                writer.write_all(&block)?;
+                repaired.blocks += 1;
+                repaired.trees += trees;
+                offset = end;
+            }
+            Err(e) => {
+                let next = match frames_next_block(&mut file, end, len)? {
+                    true => end,
+                    false => resync(&mut file, offset + 1, len)?,
+                };
+                drop_range(&mut repaired, report, offset, next, &e.to_string())?;
+                offset = next;
+            }
+        }
+    }
+    writer.flush()?;
+
+    writeln!(
+        report,
+        "kept {} blocks of {} trees, dropped {} blocks of {}{}",
+        human::count(repaired.blocks),
+        human::count(repaired.trees),
+        human::count(repaired.dropped_blocks),
+        human::bytes(repaired.dropped_bytes as f64),
+        match repaired.truncated_tail {
+            0 => String::new(),
+            tail => format!(", truncated a tail of {}", human::bytes(tail as f64)),
+        }
+    )?;
+    Ok(repaired)
+}
+
+fn drop_range(
+    repaired: &mut Repaired,
+    report: &mut dyn Write,
+    from: u64,
+    to: u64,
+    reason: &str,
+) -> Fallible<()> {
+    repaired.dropped_blocks += 1;
+    repaired.dropped_bytes += to - from;
+    writeln!(
+        report,
+        "{}: dropped {}: {}",
+        from,
+        human::bytes((to - from) as f64),
+        reason
+    )?;
+    Ok(())
+}
+
+/// Decodes every tree of `block`, returning how many.
+fn check_block(block: &[u8]) -> Fallible<u64> {
+    let data = decompress_block(block)?;
+    let mut trees = &data[..];
+    let mut count = 0;
+    while !trees.is_empty() {
+        let mut tree = next_framed(&mut trees).ok_or_else(|| err_msg("bad tree length"))?;
+        MessageTree::decode(&mut tree)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The length-prefixed encoded trees of `block`, checking its header and
+/// the framing of its snappy chunks.
+pub fn decompress_block(block: &[u8]) -> Fallible<Vec<u8>> {
+    if block.len() < BLOCK_HEADER.len() || !block.starts_with(MAGIC) {
+        bail!("bad block header");
+    }
+    let mut chunks = &block[BLOCK_HEADER.len()..];
+    let mut data = vec![];
+    while !chunks.is_empty() {
+        let chunk = next_framed(&mut chunks).ok_or_else(|| err_msg("bad chunk length"))?;
+        data.extend_from_slice(&snap::Decoder::new().decompress_vec(chunk)?);
+    }
+    Ok(data)
+}
+
+/// Splits the bytes prefixed by their length off `buf`.
+pub fn next_framed<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if buf.len() < 4 {
+        return None;
+    }
+    let length = BigEndian::read_i32(buf);
+    let rest = &buf[4..];
+    if length < 0 || length as usize > rest.len() {
+        return None;
+    }
+    let (framed, rest) = rest.split_at(length as usize);
+    *buf = rest;
+    Some(framed)
+}
+
+/// Whether `offset` is the end of the file or the start of a block.
+fn frames_next_block(file: &mut File, offset: u64, len: u64) -> Fallible<bool> {
+    if offset == len {
+        return Ok(true);
+    }
+    if len - offset < 4 + MAGIC.len() as u64 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::Start(offset + 4))?;
+    let mut header = vec![0; MAGIC.len()];
+    file.read_exact(&mut header)?;
+    Ok(header == MAGIC)
+}
+
+/// Offset of the first block starting at or after `from`, found by its
+/// header, or the end of the file.
+fn resync(file: &mut File, from: u64, len: u64) -> Fallible<u64> {
+    let mut offset = from;
+    let mut buf = vec![0; SCAN_SIZE + 4 + MAGIC.len()];
+    while offset + 4 + MAGIC.len() as u64 <= len {
+        file.seek(SeekFrom::Start(offset))?;
+        let read = (len - offset).min(buf.len() as u64) as usize;
+        file.read_exact(&mut buf[..read])?;
+        let window = &buf[..read];
+        if let Some(i) = window[4..].windows(MAGIC.len()).position(|w| w == MAGIC) {
+            return Ok(offset + i as u64);
+        }
+        // The next window overlaps this one by a header, less one byte.
+        offset += (read - 4 - MAGIC.len() + 1) as u64;
+    }
+    Ok(len)
+}
@@ -0,0 +1,197 @@
+use std::io::Write;
+use std::time::Instant;
+
+use failure::Fallible;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::filter::{self, Filter};
+use crate::human;
+use crate::message_tree::MessageTree;
+use crate::query::{self, QueryLang};
+use crate::running;
+use crate::show;
+
+const PROMPT: &str = "query> ";
+
+/// Matches printed per query unless changed with `:limit`.
+const DEFAULT_LIMIT: usize = 10;
+
+const COMMANDS: &[(&str, &str)] = &[
+    (":help", "print the commands and variables"),
+    (":limit", "<n> matches printed per query"),
+    (":json", "toggle printing matches as JSON"),
+    (":show", "<message id> print a tree with its children"),
+    (":quit", "leave, like Ctrl-D"),
+];
+
+/// An interactive prompt running queries against `trees`, kept in memory so
+/// every query answers without decoding the file again.
+///
+/// Each line is a query in `lang` or a selector, like `--query`, or one of
+/// `COMMANDS`. The first matches are printed with how many trees matched;
+/// errors are printed and the prompt goes on. Tab completes the variables,
+/// functions and commands.
+pub fn run(trees: Vec<MessageTree>, lang: QueryLang, out: &mut dyn Write) -> Fallible<()> {
+    let mut editor = Editor::<Completion, DefaultHistory>::new()?;
+    editor.set_helper(Some(Completion));
+    let mut session = Session {
+        trees,
+        lang,
+        limit: DEFAULT_LIMIT,
+        json: false,
+    };
+    writeln!(
+        out,
+        "{} trees loaded, :help for the commands",
+        human::count(session.trees.len() as u64)
+    )?;
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+        match session.eval(line, out) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => writeln!(out, "error: {}", e)?,
+        }
+        out.flush()?;
+    }
+}
+
+struct Session {
+    trees: Vec<MessageTree>,
+    lang: QueryLang,
+    limit: usize,
+    json: bool,
+}
+
+impl Session {
+    /// Runs the query or command of `line`, and returns whether to go on.
+    fn eval(&mut self, line: &str, out: &mut dyn Write) -> Fallible<bool> {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            ":quit" | ":q" => return Ok(false),
+            ":help" => help(out)?,
+            ":limit" => {
+                self.limit = arg.trim().parse()?;
+                writeln!(out, "printing {} matches", self.limit)?;
+            }
+            ":json" => {
+                self.json = !self.json;
+                writeln!(out, "json {}", if self.json { "on" } else { "off" })?;
+            }
+            ":show" => match self.trees.iter().find(|t| t.message_id == arg.trim()) {
+                Some(tree) => show::print_tree(&tree.message, out)?,
+                None => writeln!(out, "message {} not found", arg.trim())?,
+            },
+            _ if command.starts_with(':') => {
+                writeln!(out, "unknown command {}, :help for the commands", command)?
+            }
+            _ => self.query(line, out)?,
+        }
+        Ok(true)
+    }
+
+    fn query(&self, query: &str, out: &mut dyn Write) -> Fallible<()> {
+        let started = Instant::now();
+        let filter = Filter::compile(Some(query), self.lang)?;
+        let mut matched = 0u64;
+        for tree in &self.trees {
+            if !filter.matches(tree)? {
+                continue;
+            }
+            if matched < self.limit as u64 {
+                if self.json {
+                    writeln!(out, "{}", serde_json::to_string(&tree.message)?)?;
+                } else {
+                    writeln!(out, "{}", tree.message)?;
+                }
+            }
+            matched += 1;
+        }
+        writeln!(
+            out,
+            "{} of {} trees matched in {}",
+            human::count(matched),
+            human::count(self.trees.len() as u64),
+            human::duration_ms(started.elapsed().as_millis() as f64)
+        )?;
+        Ok(())
+    }
+}
+
+fn help(out: &mut dyn Write) -> Fallible<()> {
+    writeln!(out, "commands:")?;
+    for (command, description) in COMMANDS {
+        writeln!(out, "  {:<8} {}", command, description)?;
+    }
+    writeln!(out, "variables:")?;
+    for (variable, description) in filter::VARIABLES {
+        writeln!(out, "  {:<28} {}", variable, description)?;
+    }
+    Ok(())
+}
+
+/// Completes the word before the cursor with the names of `COMMANDS` at
+/// the start of the line, or else of the variables and functions of
+/// queries.
+struct Completion;
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == ':'))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let names: Vec<&str> = if start == 0 && word.starts_with(':') {
+            COMMANDS.iter().map(|(name, _)| *name).collect()
+        } else {
+            filter::VARIABLES
+                .iter()
+                .map(|(name, _)| *name)
+                .chain(running::FUNCTIONS.iter().map(|(name, _)| *name))
+                .chain(query::TIMESTAMP_FUNCTIONS.iter().map(|(name, _)| *name))
+                .collect()
+        };
+        let candidates = names
+            .into_iter()
+            .filter(|name| name.starts_with(word))
+            .map(|name| Pair {
+                display: name.to_string(),
+                replacement: name.to_string(),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
@@ -0,0 +1,60 @@
+use std::any::Any;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::message_tree::MessageTree;
+
+pub mod call_graph;
+pub mod call_matrix;
+pub mod clock_skew;
+pub mod concurrency;
+pub mod critical_path;
+pub mod distinct;
+pub mod duplicates;
+pub mod errors;
+pub mod first_last;
+pub mod flamegraph;
+pub mod gaps;
+pub mod group_by;
+pub mod otlp_metrics;
+pub mod size;
+pub mod sla;
+pub mod top;
+pub mod window;
+
+/// An aggregation over matched trees, printed once the input is drained.
+///
+/// Every filter thread owns its own instance; the instances are merged
+/// before rendering, so `observe` never needs to synchronize.
+pub trait Report: Send {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()>;
+
+    /// Folds `other`, which is always of the same concrete type, into `self`.
+    fn merge(&mut self, other: Box<dyn Report>);
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()>;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+/// Downcasts the argument of `Report::merge`.
+pub fn downcast<T: Report + 'static>(report: Box<dyn Report>) -> T {
+    *report
+        .into_any()
+        .downcast::<T>()
+        .expect("merge reports of different types")
+}
+
+/// Formats `ms` since the epoch like `2024-05-11T04:00:00.037Z`.
+pub fn format_timestamp(ms: u64) -> Fallible<String> {
+    let tm = time::at_utc(time::Timespec::new(
+        (ms / 1000) as i64,
+        ((ms % 1000) * 1_000_000) as i32,
+    ));
+    Ok(format!(
+        "{}.{:03}Z",
+        tm.strftime("%Y-%m-%dT%H:%M:%S")?,
+        ms % 1000
+    ))
+}
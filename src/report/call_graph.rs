@@ -0,0 +1,106 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::message_id::ParsedMessageId;
+use crate::message_tree::{Message, MessageTree, Text};
+use crate::remote_call::REMOTE_CALL_TYPE;
+use crate::report::call_matrix::CALL_TYPES;
+use crate::report::{downcast, Report};
+
+/// The calls between domains as a Graphviz DOT digraph, for `dot -Tsvg`,
+/// each edge labeled with its number of calls and p99 in ms.
+///
+/// The caller is the domain of the tree. The callee is the name of a
+/// `*.app` event of the call transaction, like `PigeonCall.app`, or else
+/// the domain of the message id of a `RemoteCall` event of it.
+#[derive(Default)]
+pub struct CallGraphReport {
+    edges: HashMap<(Text, Text), Vec<u64>>,
+}
+
+impl CallGraphReport {
+    fn observe_message(&mut self, caller: &Text, message: &Message) {
+        let t = match message {
+            Message::Transaction(t) => t,
+            _ => return,
+        };
+        if CALL_TYPES.contains(&t.ty.as_str()) {
+            self.edges
+                .entry((caller.clone(), callee(&t.children)))
+                .or_default()
+                .push(t.duration_in_ms);
+        }
+        for child in &t.children {
+            self.observe_message(caller, child);
+        }
+    }
+}
+
+/// The domain called by a call transaction with `children`.
+fn callee(children: &[Message]) -> Text {
+    let events = || {
+        children.iter().filter_map(|c| match c {
+            Message::Event(e) => Some(e),
+            _ => None,
+        })
+    };
+    if let Some(app) = events().find(|e| e.ty.ends_with(".app") && !e.name.is_empty()) {
+        return app.name.clone();
+    }
+    events()
+        .filter(|e| e.ty == REMOTE_CALL_TYPE)
+        .find_map(|e| ParsedMessageId::parse(&e.data))
+        .map(|id| id.domain)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// `s` as a double-quoted DOT id.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Report for CallGraphReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.observe_message(&tree.domain, &tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: CallGraphReport = downcast(other);
+        for (key, durations) in other.edges {
+            self.edges.entry(key).or_default().extend(durations);
+        }
+    }
+
+    /// Edges sorted by caller and callee, so the output is stable.
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort_by(|a, b| a.0.cmp(b.0));
+        writeln!(out, "digraph calls {{")?;
+        writeln!(out, "  rankdir=LR;")?;
+        writeln!(out, "  node [shape=box];")?;
+        for ((caller, callee), durations) in edges {
+            let mut durations = durations.clone();
+            durations.sort_unstable();
+            let calls = durations.len();
+            let p99 = durations[(calls * 99).div_ceil(100).max(1) - 1];
+            writeln!(
+                out,
+                "  {} -> {} [label=\"{} calls\\np99 {}ms\"];",
+                quote(caller),
+                quote(callee),
+                calls,
+                p99
+            )?;
+        }
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
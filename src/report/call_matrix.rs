@@ -0,0 +1,136 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_id::ParsedMessageId;
+use crate::message_tree::{Message, MessageTree, Text};
+use crate::remote_call::REMOTE_CALL_TYPE;
+use crate::report::{downcast, Report};
+use crate::status_class;
+
+/// Types of the transactions timing a call to another host.
+pub const CALL_TYPES: &[&str] = &["PigeonCall", "RemoteCall", "Call"];
+
+#[derive(Default)]
+struct Path {
+    errors: u64,
+    durations: Vec<u64>,
+}
+
+/// Latency and errors of the calls between each pair of hosts, to find a
+/// bad network path or node.
+///
+/// The source of a call is the ip address of its tree. The target is the
+/// name of a `*.server` event of the call, like `PigeonCall.server`, or
+/// else the producer ip of the message id of a `RemoteCall` event of it.
+#[derive(Default)]
+pub struct CallMatrixReport {
+    paths: HashMap<(Text, Text), Path>,
+}
+
+impl CallMatrixReport {
+    fn observe_message(&mut self, source: &Text, message: &Message) {
+        let t = match message {
+            Message::Transaction(t) => t,
+            _ => return,
+        };
+        if CALL_TYPES.contains(&t.ty.as_str()) {
+            let path = self
+                .paths
+                .entry((source.clone(), target(&t.children)))
+                .or_default();
+            if !status_class::is_success(&t.status) {
+                path.errors += 1;
+            }
+            path.durations.push(t.duration_in_ms);
+        }
+        for child in &t.children {
+            self.observe_message(source, child);
+        }
+    }
+}
+
+/// The host called by a call transaction with `children`.
+fn target(children: &[Message]) -> Text {
+    let events = || {
+        children.iter().filter_map(|c| match c {
+            Message::Event(e) => Some(e),
+            _ => None,
+        })
+    };
+    if let Some(server) = events().find(|e| e.ty.ends_with(".server")) {
+        // Drop the port.
+        return match server.name.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+            _ => server.name.clone(),
+        };
+    }
+    events()
+        .filter(|e| e.ty == REMOTE_CALL_TYPE)
+        .find_map(|e| ParsedMessageId::parse(&e.data))
+        .map(|id| id.ip())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+impl Report for CallMatrixReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.observe_message(&tree.ip_address, &tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: CallMatrixReport = downcast(other);
+        for (key, theirs) in other.paths {
+            let ours = self.paths.entry(key).or_default();
+            ours.errors += theirs.errors;
+            ours.durations.extend(theirs.durations);
+        }
+    }
+
+    /// Paths from the highest error rate down, then from the highest p99.
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut paths: Vec<_> = self
+            .paths
+            .iter()
+            .map(|(key, path)| {
+                let mut durations = path.durations.clone();
+                durations.sort_unstable();
+                let calls = durations.len();
+                let p99 = durations[(calls * 99).div_ceil(100).max(1) - 1];
+                let mean = durations.iter().sum::<u64>() as f64 / calls as f64;
+                let error_rate = path.errors as f64 / calls as f64;
+                (key, calls, path.errors, error_rate, mean, p99)
+            })
+            .collect();
+        paths.sort_by(|a, b| {
+            b.3.total_cmp(&a.3)
+                .then(b.5.cmp(&a.5))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        writeln!(
+            out,
+            "source\ttarget\tcalls\terrors\terror%\tmean_ms\tp99_ms"
+        )?;
+        for ((source, target), calls, errors, error_rate, mean, p99) in paths {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                source,
+                target,
+                human::count(calls as u64),
+                human::count(errors),
+                human::number(error_rate * 100.0, 1),
+                human::number(mean, 1),
+                human::count(p99)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
@@ -0,0 +1,113 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_id::ParsedMessageId;
+use crate::message_tree::{MessageTree, Text};
+use crate::report::{downcast, Report};
+
+const HOUR_IN_MS: i64 = 3_600_000;
+
+#[derive(Default)]
+struct HostSkew {
+    trees: u64,
+    skewed: u64,
+    sum_in_ms: i64,
+    /// Skew with the largest magnitude, negative when behind.
+    max_in_ms: i64,
+    sample_message_id: Text,
+}
+
+/// Compares the hour encoded in the message id of each tree with the
+/// timestamp of its root message, grouped by hostname.
+///
+/// Both come from the producer, but the id hour is taken when the id is
+/// allocated, so a root message outside of that hour means its timestamp
+/// can't be trusted for latency analysis.
+pub struct ClockSkewReport {
+    threshold_in_ms: i64,
+    hosts: HashMap<Text, HostSkew>,
+}
+
+impl ClockSkewReport {
+    pub fn new(threshold_in_ms: u64) -> Self {
+        ClockSkewReport {
+            threshold_in_ms: threshold_in_ms as i64,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+/// Distance of `ts` to the hour starting at `hour_start`, 0 when inside.
+fn skew(hour_start: i64, ts: i64) -> i64 {
+    if ts < hour_start {
+        ts - hour_start
+    } else if ts >= hour_start + HOUR_IN_MS {
+        ts - (hour_start + HOUR_IN_MS)
+    } else {
+        0
+    }
+}
+
+impl Report for ClockSkewReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let id = match ParsedMessageId::parse(&tree.message_id) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let skew = skew(
+            id.hour_timestamp_in_ms() as i64,
+            tree.message.timestamp_in_ms() as i64,
+        );
+        let host = self.hosts.entry(tree.hostname.clone()).or_default();
+        host.trees += 1;
+        host.sum_in_ms += skew;
+        if skew.abs() > self.threshold_in_ms {
+            host.skewed += 1;
+        }
+        if skew.abs() > host.max_in_ms.abs() {
+            host.max_in_ms = skew;
+            host.sample_message_id = tree.message_id.clone();
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: ClockSkewReport = downcast(other);
+        for (hostname, other) in other.hosts {
+            let host = self.hosts.entry(hostname).or_default();
+            host.trees += other.trees;
+            host.skewed += other.skewed;
+            host.sum_in_ms += other.sum_in_ms;
+            if other.max_in_ms.abs() > host.max_in_ms.abs() {
+                host.max_in_ms = other.max_in_ms;
+                host.sample_message_id = other.sample_message_id;
+            }
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut hosts: Vec<_> = self.hosts.iter().filter(|(_, h)| h.skewed > 0).collect();
+        hosts.sort_by_key(|(_, h)| std::cmp::Reverse(h.max_in_ms.abs()));
+        for (hostname, host) in hosts {
+            writeln!(
+                out,
+                "{}: {}/{} trees skewed, max {}, mean {}, sample {}",
+                hostname,
+                human::count(host.skewed),
+                human::count(host.trees),
+                human::duration_ms(host.max_in_ms as f64),
+                human::duration_ms((host.sum_in_ms / host.trees as i64) as f64),
+                host.sample_message_id
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
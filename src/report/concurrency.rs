@@ -0,0 +1,130 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_tree::{Message, MessageTree, Text};
+use crate::report::{downcast, format_timestamp, Report};
+
+/// How many root transactions of each name were in flight at once, from a
+/// sweep over their `[timestamp, timestamp + duration)` intervals.
+///
+/// Every second from the first start to the last end of a name gets the
+/// peak concurrency reached during it. The report gives the median, p99
+/// and maximum of those peaks, and the mean concurrency over the whole
+/// span, i.e. the total duration of the transactions divided by the span.
+#[derive(Default)]
+pub struct ConcurrencyReport {
+    intervals: HashMap<Text, Vec<(u64, u64)>>,
+}
+
+struct Concurrency {
+    transactions: usize,
+    mean: f64,
+    p50: i64,
+    p99: i64,
+    peak: i64,
+    peak_at: u64,
+}
+
+impl Concurrency {
+    fn of(intervals: &[(u64, u64)]) -> Self {
+        // Ends sort before starts at the same millisecond, as intervals are
+        // half-open.
+        let mut events: Vec<(u64, i64)> = intervals
+            .iter()
+            .flat_map(|&(start, end)| vec![(start, 1), (end, -1)])
+            .collect();
+        events.sort_unstable();
+        let first = events.first().map_or(0, |e| e.0);
+        let last = events.last().map_or(0, |e| e.0);
+        let span_ms = (last - first).max(1);
+
+        let seconds = (last / 1000 - first / 1000 + 1) as usize;
+        let mut peaks = vec![0_i64; seconds];
+        let (mut current, mut peak, mut peak_at, mut previous) = (0, 0, first, first);
+        for (timestamp, delta) in events {
+            // Transactions in flight across the starts of seconds without
+            // events of their own.
+            if current > 0 {
+                for second in previous / 1000 + 1..=timestamp / 1000 {
+                    let slot = &mut peaks[(second - first / 1000) as usize];
+                    *slot = (*slot).max(current);
+                }
+            }
+            current += delta;
+            let slot = &mut peaks[(timestamp / 1000 - first / 1000) as usize];
+            *slot = (*slot).max(current);
+            if current > peak {
+                peak = current;
+                peak_at = timestamp;
+            }
+            previous = timestamp;
+        }
+        peaks.sort_unstable();
+        let percentile = |p: usize| peaks[((seconds * p).div_ceil(100)).max(1) - 1];
+
+        let busy_ms: u64 = intervals.iter().map(|(start, end)| end - start).sum();
+        Concurrency {
+            transactions: intervals.len(),
+            mean: busy_ms as f64 / span_ms as f64,
+            p50: percentile(50),
+            p99: percentile(99),
+            peak,
+            peak_at,
+        }
+    }
+}
+
+impl Report for ConcurrencyReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        if let Message::Transaction(t) = &tree.message {
+            // A transaction shorter than the resolution of timestamps is
+            // still in flight for a moment.
+            let end = t.timestamp_in_ms + t.duration_in_ms.max(1);
+            self.intervals
+                .entry(t.name.clone())
+                .or_default()
+                .push((t.timestamp_in_ms, end));
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: ConcurrencyReport = downcast(other);
+        for (name, intervals) in other.intervals {
+            self.intervals.entry(name).or_default().extend(intervals);
+        }
+    }
+
+    /// Names from the highest peak down.
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut names: Vec<_> = self
+            .intervals
+            .iter()
+            .map(|(name, intervals)| (name, Concurrency::of(intervals)))
+            .collect();
+        names.sort_by(|a, b| b.1.peak.cmp(&a.1.peak).then_with(|| a.0.cmp(b.0)));
+        writeln!(out, "name\ttransactions\tmean\tp50/s\tp99/s\tpeak\tpeak_at")?;
+        for (name, c) in names {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                human::count(c.transactions as u64),
+                human::number(c.mean, 2),
+                human::count(c.p50 as u64),
+                human::count(c.p99 as u64),
+                human::count(c.peak as u64),
+                format_timestamp(c.peak_at)?
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
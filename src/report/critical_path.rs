@@ -0,0 +1,117 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::critical_path::critical_path;
+use crate::human;
+use crate::message_tree::{MessageTree, Text};
+use crate::report::{downcast, Report};
+
+/// Critical paths printed per root transaction.
+const PATHS_PER_ROOT: usize = 5;
+
+#[derive(Default)]
+struct PathStats {
+    count: u64,
+    /// Sum of the root durations.
+    total_in_ms: u64,
+    /// Sum of the self time of the last step, the time the path blames.
+    blamed_in_ms: u64,
+    sample_message_id: Text,
+}
+
+/// Groups trees by root transaction and by the chain of `ty:name` of their
+/// critical path, ranked by the time blamed on the end of the chain.
+#[derive(Default)]
+pub struct CriticalPathReport {
+    /// Root `ty:name` to critical path to stats.
+    roots: HashMap<Text, HashMap<Text, PathStats>>,
+}
+
+impl Report for CriticalPathReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let path = critical_path(&tree.message);
+        let (root, last) = match (path.first(), path.last()) {
+            (Some(root), Some(last)) => (root, last),
+            _ => return Ok(()),
+        };
+        let chain: Vec<String> = path[1..]
+            .iter()
+            .map(|step| format!("{}:{}", step.ty, step.name))
+            .collect();
+        let stats = self
+            .roots
+            .entry(format!("{}:{}", root.ty, root.name))
+            .or_default()
+            .entry(chain.join(" > "))
+            .or_insert_with(|| PathStats {
+                sample_message_id: tree.message_id.clone(),
+                ..PathStats::default()
+            });
+        stats.count += 1;
+        stats.total_in_ms += root.duration_in_ms;
+        stats.blamed_in_ms += last.self_time_in_ms;
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: CriticalPathReport = downcast(other);
+        for (root, paths) in other.roots {
+            let mine = self.roots.entry(root).or_default();
+            for (chain, other) in paths {
+                let stats = mine.entry(chain).or_insert_with(|| PathStats {
+                    sample_message_id: other.sample_message_id.clone(),
+                    ..PathStats::default()
+                });
+                stats.count += other.count;
+                stats.total_in_ms += other.total_in_ms;
+                stats.blamed_in_ms += other.blamed_in_ms;
+            }
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut roots: Vec<_> = self
+            .roots
+            .iter()
+            .map(|(root, paths)| {
+                (
+                    root,
+                    paths,
+                    paths.values().map(|s| s.total_in_ms).sum::<u64>(),
+                )
+            })
+            .collect();
+        roots.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)));
+        for (root, paths, total_in_ms) in roots {
+            let count: u64 = paths.values().map(|s| s.count).sum();
+            writeln!(
+                out,
+                "{}: {} trees, mean {}",
+                root,
+                human::count(count),
+                human::duration_ms((total_in_ms / count.max(1)) as f64)
+            )?;
+            let mut paths: Vec<_> = paths.iter().collect();
+            paths.sort_by(|a, b| b.1.blamed_in_ms.cmp(&a.1.blamed_in_ms).then(a.0.cmp(b.0)));
+            for (chain, stats) in paths.into_iter().take(PATHS_PER_ROOT) {
+                let chain = if chain.is_empty() { "(self)" } else { chain };
+                writeln!(
+                    out,
+                    "    {:>6} trees  self_time_in_ms mean {:>6}  {}  sample {}",
+                    human::count(stats.count),
+                    human::count(stats.blamed_in_ms / stats.count),
+                    chain,
+                    stats.sample_message_id
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
@@ -0,0 +1,62 @@
+use std::any::Any;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::fields::Field;
+use crate::human;
+use crate::message_tree::MessageTree;
+use crate::report::{downcast, Report};
+use crate::topk::{SpaceSaving, DEFAULT_CAPACITY};
+
+/// The distinct values of a field of the matched trees, most frequent
+/// first. Past `topk::DEFAULT_CAPACITY` values the rarest are dropped and
+/// the counts are bounds.
+pub struct DistinctReport {
+    field: Field,
+    counts: SpaceSaving<String>,
+}
+
+impl DistinctReport {
+    pub fn new(field: Field) -> Self {
+        DistinctReport {
+            field,
+            counts: SpaceSaving::default(),
+        }
+    }
+}
+
+impl Report for DistinctReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        if let Some(value) = self.field.value(tree) {
+            self.counts.offer(value);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: DistinctReport = downcast(other);
+        self.counts.merge(other.counts);
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        for ranked in self.counts.top(DEFAULT_CAPACITY) {
+            if ranked.error > 0 {
+                writeln!(
+                    out,
+                    "{:>8} (±{})  {}",
+                    human::count(ranked.count),
+                    human::count(ranked.error),
+                    ranked.key
+                )?;
+            } else {
+                writeln!(out, "{:>8}  {}", human::count(ranked.count), ranked.key)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
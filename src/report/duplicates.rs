@@ -0,0 +1,183 @@
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_tree::{InnerTransaction, Message, MessageTree, Text};
+use crate::report::{downcast, Report};
+
+/// Domain, type and name of a transaction.
+type Key = (Text, Text, Text);
+
+#[derive(Default)]
+struct NameDuplicates {
+    transactions: u64,
+    /// Transactions duplicating an earlier sibling.
+    repeated: u64,
+    /// Transactions duplicating their parent.
+    nested: u64,
+    /// Trees with duplicates of the name.
+    trees: u64,
+    sample_message_id: Text,
+}
+
+/// Finds transactions recorded twice by the client, e.g. by two agents or
+/// interceptors instrumenting the same call, which inflates the counts and
+/// latencies computed from the trees.
+///
+/// A transaction is a duplicate when a sibling before it, or its parent,
+/// has the same type and name and starts and ends within `tolerance_in_ms`
+/// of it. Transactions are looked at at any depth, and the report counts
+/// the duplicates of each domain, type and name.
+pub struct DuplicatesReport {
+    tolerance_in_ms: u64,
+    names: HashMap<Key, NameDuplicates>,
+}
+
+impl DuplicatesReport {
+    pub fn new(tolerance_in_ms: u64) -> Self {
+        DuplicatesReport {
+            tolerance_in_ms,
+            names: HashMap::new(),
+        }
+    }
+
+    fn near(&self, a: &InnerTransaction, b: &InnerTransaction) -> bool {
+        let end = |t: &InnerTransaction| t.timestamp_in_ms + t.duration_in_ms;
+        a.ty == b.ty
+            && a.name == b.name
+            && a.timestamp_in_ms.abs_diff(b.timestamp_in_ms) <= self.tolerance_in_ms
+            && end(a).abs_diff(end(b)) <= self.tolerance_in_ms
+    }
+
+    /// Counts `t`, a transaction of `tree` under `parent`, and the
+    /// transactions under it, adding the names with duplicates to
+    /// `duplicated`.
+    fn observe_transaction(
+        &mut self,
+        tree: &MessageTree,
+        t: &InnerTransaction,
+        parent: Option<&InnerTransaction>,
+        duplicated: &mut HashSet<Key>,
+    ) {
+        let key = (tree.domain.clone(), t.ty.clone(), t.name.clone());
+        let nested = parent.is_some_and(|parent| self.near(parent, t));
+        let name = self.names.entry(key.clone()).or_default();
+        name.transactions += 1;
+        if nested {
+            name.nested += 1;
+            duplicated.insert(key);
+        }
+
+        let mut children: Vec<_> = t
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Message::Transaction(child) => Some(&**child),
+                _ => None,
+            })
+            .collect();
+        for child in &children {
+            self.observe_transaction(tree, child, Some(t), duplicated);
+        }
+        // Siblings by type, name and start, so that the ones a transaction
+        // may duplicate are right before it.
+        children.sort_by(|a, b| {
+            (&a.ty, &a.name, a.timestamp_in_ms).cmp(&(&b.ty, &b.name, b.timestamp_in_ms))
+        });
+        for (i, child) in children.iter().enumerate() {
+            let repeated = children[..i]
+                .iter()
+                .rev()
+                .take_while(|sibling| {
+                    sibling.ty == child.ty
+                        && sibling.name == child.name
+                        && child.timestamp_in_ms - sibling.timestamp_in_ms <= self.tolerance_in_ms
+                })
+                .any(|sibling| self.near(sibling, child));
+            if repeated {
+                let key = (tree.domain.clone(), child.ty.clone(), child.name.clone());
+                self.names.entry(key.clone()).or_default().repeated += 1;
+                duplicated.insert(key);
+            }
+        }
+    }
+}
+
+impl Report for DuplicatesReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let t = match &tree.message {
+            Message::Transaction(t) => t,
+            _ => return Ok(()),
+        };
+        let mut duplicated = HashSet::new();
+        self.observe_transaction(tree, t, None, &mut duplicated);
+        for key in duplicated {
+            let name = self
+                .names
+                .get_mut(&key)
+                .expect("observed names are counted");
+            name.trees += 1;
+            if name.sample_message_id.is_empty() {
+                name.sample_message_id = tree.message_id.clone();
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: DuplicatesReport = downcast(other);
+        for (key, other) in other.names {
+            let name = self.names.entry(key).or_default();
+            name.transactions += other.transactions;
+            name.repeated += other.repeated;
+            name.nested += other.nested;
+            name.trees += other.trees;
+            if name.sample_message_id.is_empty() {
+                name.sample_message_id = other.sample_message_id;
+            }
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut names: Vec<_> = self
+            .names
+            .iter()
+            .filter(|(_, n)| n.repeated + n.nested > 0)
+            .collect();
+        names.sort_by(|(a_key, a), (b_key, b)| {
+            (b.repeated + b.nested)
+                .cmp(&(a.repeated + a.nested))
+                .then_with(|| a_key.cmp(b_key))
+        });
+        writeln!(
+            out,
+            "domain\tty\tname\ttransactions\trepeated\tnested\tduplicated\ttrees\tsample"
+        )?;
+        for ((domain, ty, name), n) in names {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}%\t{}\t{}",
+                domain,
+                ty,
+                name,
+                human::count(n.transactions),
+                human::count(n.repeated),
+                human::count(n.nested),
+                human::number(
+                    (n.repeated + n.nested) as f64 * 100.0 / n.transactions as f64,
+                    2
+                ),
+                human::count(n.trees),
+                n.sample_message_id
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
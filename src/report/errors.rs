@@ -0,0 +1,65 @@
+use std::any::Any;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_tree::{MessageTree, Text};
+use crate::report::{downcast, Report};
+use crate::stacktrace::{self, StackTrace};
+use crate::topk::SpaceSaving;
+
+struct Problem {
+    stack: StackTrace,
+    sample_message_id: Text,
+}
+
+/// Groups the stack traces of error events by fingerprint.
+///
+/// Only the most frequent fingerprints are kept, so rare problems may be
+/// missing from the report of a very large file.
+#[derive(Default)]
+pub struct ErrorsReport {
+    problems: SpaceSaving<u64, Problem>,
+}
+
+impl Report for ErrorsReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        for stack in stacktrace::find_all(tree) {
+            self.problems
+                .offer_with(stack.fingerprint(), 1, || Problem {
+                    stack,
+                    sample_message_id: tree.message_id.clone(),
+                });
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: ErrorsReport = downcast(other);
+        self.problems.merge(other.problems);
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        for ranked in self.problems.top(usize::MAX) {
+            let problem = ranked.value;
+            writeln!(
+                out,
+                "{:>8}  {:016x}  {}: {}",
+                human::count(ranked.count),
+                ranked.key,
+                problem.stack.exception_class,
+                problem.stack.message
+            )?;
+            for frame in problem.stack.top_frames() {
+                writeln!(out, "            at {}", frame)?;
+            }
+            writeln!(out, "            sample: {}", problem.sample_message_id)?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
@@ -0,0 +1,119 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::fields::Field;
+use crate::human;
+use crate::message_tree::{Message, MessageTree};
+use crate::report::{downcast, format_timestamp, Report};
+use crate::status_class;
+
+struct Occurrences {
+    count: u64,
+    first: u64,
+    last: u64,
+}
+
+/// When each group of transactions and events first and last appears,
+/// e.g. to tell when an event introduced by a deployment started firing.
+///
+/// Every transaction and event of the trees is looked at, not only roots.
+/// `ty`, `name`, `status` and `status_class` are the ones of the message, other fields the
+/// ones of its tree.
+pub struct FirstLastReport {
+    group_by: Vec<Field>,
+    groups: HashMap<Vec<String>, Occurrences>,
+}
+
+impl FirstLastReport {
+    pub fn new(group_by: Vec<Field>) -> Self {
+        FirstLastReport {
+            group_by,
+            groups: HashMap::new(),
+        }
+    }
+
+    fn observe_message(&mut self, tree: &MessageTree, message: &Message) {
+        let children = match message {
+            Message::Transaction(t) => Some(&t.children),
+            Message::Event(_) => None,
+            _ => return,
+        };
+        let key = self
+            .group_by
+            .iter()
+            .map(|field| match field {
+                Field::Ty => message.ty().clone(),
+                Field::Name => message.name().clone(),
+                Field::Status => message.status().clone(),
+                Field::StatusClass => status_class::of(message.status()).to_string(),
+                _ => field.value(tree).unwrap_or_default(),
+            })
+            .collect();
+        let timestamp = message.timestamp_in_ms();
+        let occurrences = self.groups.entry(key).or_insert(Occurrences {
+            count: 0,
+            first: timestamp,
+            last: timestamp,
+        });
+        occurrences.count += 1;
+        occurrences.first = occurrences.first.min(timestamp);
+        occurrences.last = occurrences.last.max(timestamp);
+
+        for child in children.into_iter().flatten() {
+            self.observe_message(tree, child);
+        }
+    }
+}
+
+impl Report for FirstLastReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.observe_message(tree, &tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: FirstLastReport = downcast(other);
+        for (key, theirs) in other.groups {
+            match self.groups.get_mut(&key) {
+                Some(ours) => {
+                    ours.count += theirs.count;
+                    ours.first = ours.first.min(theirs.first);
+                    ours.last = ours.last.max(theirs.last);
+                }
+                None => {
+                    self.groups.insert(key, theirs);
+                }
+            }
+        }
+    }
+
+    /// Groups in order of first appearance.
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut groups: Vec<_> = self.groups.iter().collect();
+        groups.sort_by(|a, b| (a.1.first, a.0).cmp(&(b.1.first, b.0)));
+        for field in &self.group_by {
+            write!(out, "{}\t", field)?;
+        }
+        writeln!(out, "count\tfirst\tlast")?;
+        for (key, occurrences) in groups {
+            for value in key {
+                write!(out, "{}\t", value)?;
+            }
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                human::count(occurrences.count),
+                format_timestamp(occurrences.first)?,
+                format_timestamp(occurrences.last)?
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
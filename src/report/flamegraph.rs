@@ -0,0 +1,79 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::critical_path::self_time_in_ms;
+use crate::message_tree::{Message, MessageTree};
+use crate::report::{downcast, Report};
+
+/// The transactions of all trees as folded stacks, `Root;Child;GrandChild
+/// weight` lines for `inferno-flamegraph` or `flamegraph.pl`.
+///
+/// Frames are the `ty:name` of the transactions from the root, and the
+/// weight of a stack is the self time of its last transaction, in
+/// microseconds, summed over the trees, so the width of a frame in the
+/// graph is the time spent in it and under it. Self time is floored at 0,
+/// so the children of a transaction running concurrently may make it wider
+/// than it took.
+#[derive(Default)]
+pub struct FlamegraphReport {
+    stacks: HashMap<String, u64>,
+}
+
+impl FlamegraphReport {
+    fn add(&mut self, stack: &mut String, message: &Message) {
+        let t = match message {
+            Message::Transaction(t) => t,
+            _ => return,
+        };
+        let len = stack.len();
+        if len > 0 {
+            stack.push(';');
+        }
+        // `;` separates the frames and the last space the weight.
+        stack.extend(format!("{}:{}", t.ty, t.name).chars().map(|c| {
+            if c == ';' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        }));
+        let self_time_in_us = self_time_in_ms(t) * 1000;
+        if self_time_in_us > 0 {
+            *self.stacks.entry(stack.clone()).or_insert(0) += self_time_in_us;
+        }
+        for child in &t.children {
+            self.add(stack, child);
+        }
+        stack.truncate(len);
+    }
+}
+
+impl Report for FlamegraphReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.add(&mut String::new(), &tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: FlamegraphReport = downcast(other);
+        for (stack, weight) in other.stacks {
+            *self.stacks.entry(stack).or_insert(0) += weight;
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, weight) in stacks {
+            writeln!(out, "{} {}", stack, weight)?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
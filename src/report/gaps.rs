@@ -0,0 +1,146 @@
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_id::ParsedMessageId;
+use crate::message_tree::{MessageTree, Text};
+use crate::report::{downcast, Report};
+
+/// Index of the first message id of every producer in an hour.
+const FIRST_INDEX: u64 = 0;
+
+/// `(domain, hour, [(first missing, last missing)])` of one sequence.
+type MissingRanges<'a> = (&'a Text, u64, Vec<(u64, u64)>);
+
+/// Finds holes in the message id sequences of every producer, which are
+/// messages dropped between the client and the collector. A sequence is
+/// expected from `FIRST_INDEX` up to the last index observed.
+#[derive(Default)]
+pub struct GapsReport {
+    /// `(ip, domain, hour)` to the observed indexes.
+    sequences: HashMap<(Text, Text, u64), Vec<u64>>,
+    hostnames: HashMap<Text, Text>,
+}
+
+impl Report for GapsReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let id = match ParsedMessageId::parse(&tree.message_id) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let ip = id.ip();
+        if !self.hostnames.contains_key(&ip) {
+            self.hostnames.insert(ip.clone(), tree.hostname.clone());
+        }
+        self.sequences
+            .entry((ip, id.domain, id.hour))
+            .or_default()
+            .push(id.index);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: GapsReport = downcast(other);
+        for (key, indexes) in other.sequences {
+            self.sequences.entry(key).or_default().extend(indexes);
+        }
+        for (ip, hostname) in other.hostnames {
+            self.hostnames.entry(ip).or_insert(hostname);
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut hosts: BTreeMap<&Text, Vec<MissingRanges>> = BTreeMap::new();
+        for ((ip, domain, hour), indexes) in &self.sequences {
+            let mut indexes = indexes.clone();
+            indexes.sort_unstable();
+            indexes.dedup();
+            let mut ranges = vec![];
+            if indexes[0] > FIRST_INDEX {
+                ranges.push((FIRST_INDEX, indexes[0] - 1));
+            }
+            ranges.extend(
+                indexes
+                    .windows(2)
+                    .filter(|w| w[1] > w[0] + 1)
+                    .map(|w| (w[0] + 1, w[1] - 1)),
+            );
+            if !ranges.is_empty() {
+                hosts.entry(ip).or_default().push((domain, *hour, ranges));
+            }
+        }
+
+        for (ip, mut sequences) in hosts {
+            sequences.sort();
+            let missing: u64 = sequences
+                .iter()
+                .flat_map(|(_, _, ranges)| ranges)
+                .map(|(from, to)| to - from + 1)
+                .sum();
+            let hostname = self.hostnames.get(ip).map(String::as_str).unwrap_or("");
+            writeln!(
+                out,
+                "{} ({}): {} missing",
+                ip,
+                hostname,
+                human::count(missing)
+            )?;
+            for (domain, hour, ranges) in sequences {
+                let ranges: Vec<String> = ranges
+                    .iter()
+                    .map(|(from, to)| {
+                        if from == to {
+                            from.to_string()
+                        } else {
+                            format!("{}-{}", from, to)
+                        }
+                    })
+                    .collect();
+                writeln!(out, "    {} hour {}: {}", domain, hour, ranges.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(ids: &[&str]) -> String {
+        let mut report = GapsReport::default();
+        for id in ids {
+            let tree = MessageTree {
+                message_id: id.to_string(),
+                ..MessageTree::default()
+            };
+            report.observe(&tree).unwrap();
+        }
+        let mut out = vec![];
+        report.render(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn missing_ranges() {
+        assert_eq!(
+            render(&["app-0a000001-480000-0", "app-0a000001-480000-3"]),
+            "10.0.0.1 (): 2 missing\n    app hour 480000: 1-2\n"
+        );
+        assert_eq!(
+            render(&["app-0a000001-480000-2", "app-0a000001-480000-3"]),
+            "10.0.0.1 (): 2 missing\n    app hour 480000: 0-1\n"
+        );
+        assert_eq!(
+            render(&["app-0a000001-480000-1", "app-0a000001-480000-0"]),
+            ""
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use failure::{bail, format_err, Error, Fallible};
+
+use crate::fields::Field;
+use crate::hll::HyperLogLog;
+use crate::human;
+use crate::message_tree::MessageTree;
+use crate::report::{downcast, Report};
+
+/// An aggregation computed per group, e.g. `count` or `avg(transaction.duration_in_ms)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Agg {
+    Count,
+    /// Approximated with HyperLogLog.
+    CountDistinct(Field),
+    Sum(Field),
+    Avg(Field),
+    Min(Field),
+    Max(Field),
+}
+
+impl FromStr for Agg {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "count" {
+            return Ok(Agg::Count);
+        }
+        let (func, field) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| {
+                format_err!(
+                    "invalid aggregation {:?}, expected e.g. count or sum(field)",
+                    s
+                )
+            })?;
+        let field: Field = field.parse()?;
+        Ok(match func.trim() {
+            "count_distinct" => Agg::CountDistinct(field),
+            "sum" => Agg::Sum(field),
+            "avg" => Agg::Avg(field),
+            "min" => Agg::Min(field),
+            "max" => Agg::Max(field),
+            other => bail!("unknown aggregation function {:?}", other),
+        })
+    }
+}
+
+impl Agg {
+    /// The aggregated field, `None` for `count`.
+    pub fn field(&self) -> Option<Field> {
+        match *self {
+            Agg::Count => None,
+            Agg::CountDistinct(field)
+            | Agg::Sum(field)
+            | Agg::Avg(field)
+            | Agg::Min(field)
+            | Agg::Max(field) => Some(field),
+        }
+    }
+}
+
+impl fmt::Display for Agg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Agg::Count => write!(f, "count"),
+            Agg::CountDistinct(field) => write!(f, "count_distinct({})", field),
+            Agg::Sum(field) => write!(f, "sum({})", field),
+            Agg::Avg(field) => write!(f, "avg({})", field),
+            Agg::Min(field) => write!(f, "min({})", field),
+            Agg::Max(field) => write!(f, "max({})", field),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum State {
+    Count(u64),
+    Distinct(HyperLogLog),
+    Sum { sum: f64, count: u64 },
+    Min(Option<f64>),
+    Max(Option<f64>),
+}
+
+impl State {
+    fn new(agg: Agg) -> Self {
+        match agg {
+            Agg::Count => State::Count(0),
+            Agg::CountDistinct(_) => State::Distinct(HyperLogLog::default()),
+            Agg::Sum(_) | Agg::Avg(_) => State::Sum { sum: 0.0, count: 0 },
+            Agg::Min(_) => State::Min(None),
+            Agg::Max(_) => State::Max(None),
+        }
+    }
+
+    fn observe(&mut self, agg: Agg, tree: &MessageTree) {
+        match (self, agg) {
+            (State::Count(count), _) => *count += 1,
+            (State::Distinct(hll), Agg::CountDistinct(field)) => {
+                if let Some(value) = field.value(tree) {
+                    hll.insert(&value);
+                }
+            }
+            (State::Sum { sum, count }, Agg::Sum(field))
+            | (State::Sum { sum, count }, Agg::Avg(field)) => {
+                if let Some(n) = field.number(tree) {
+                    *sum += n;
+                    *count += 1;
+                }
+            }
+            (State::Min(min), Agg::Min(field)) => {
+                if let Some(n) = field.number(tree) {
+                    *min = Some(min.map_or(n, |m| m.min(n)));
+                }
+            }
+            (State::Max(max), Agg::Max(field)) => {
+                if let Some(n) = field.number(tree) {
+                    *max = Some(max.map_or(n, |m| m.max(n)));
+                }
+            }
+            _ => unreachable!("state doesn't match its aggregation"),
+        }
+    }
+
+    fn merge(&mut self, other: State) {
+        match (self, other) {
+            (State::Count(a), State::Count(b)) => *a += b,
+            (State::Distinct(a), State::Distinct(b)) => a.merge(&b),
+            (State::Sum { sum, count }, State::Sum { sum: s, count: c }) => {
+                *sum += s;
+                *count += c;
+            }
+            (State::Min(a), State::Min(b)) => *a = min_max(*a, b, f64::min),
+            (State::Max(a), State::Max(b)) => *a = min_max(*a, b, f64::max),
+            _ => unreachable!("merge states of different aggregations"),
+        }
+    }
+
+    fn value(&self, agg: Agg) -> Option<f64> {
+        match self {
+            State::Count(count) => Some(*count as f64),
+            State::Distinct(hll) => Some(hll.estimate() as f64),
+            State::Sum { sum, count } => match agg {
+                Agg::Avg(_) if *count == 0 => None,
+                Agg::Avg(_) => Some(sum / *count as f64),
+                _ => Some(*sum),
+            },
+            State::Min(v) | State::Max(v) => *v,
+        }
+    }
+}
+
+fn min_max(a: Option<f64>, b: Option<f64>, f: fn(f64, f64) -> f64) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(f(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn format_number(n: Option<f64>, agg: Agg) -> String {
+    let duration = match agg {
+        Agg::Sum(field) | Agg::Avg(field) | Agg::Min(field) | Agg::Max(field) => {
+            matches!(field, Field::DurationInMs | Field::SelfDurationInMs)
+        }
+        Agg::Count | Agg::CountDistinct(_) => false,
+    };
+    match n {
+        None => "-".to_string(),
+        Some(n) if duration && human::enabled() => human::duration_ms(n),
+        Some(n) if n.fract() == 0.0 => human::number(n, 0),
+        Some(n) => human::number(n, 3),
+    }
+}
+
+/// Groups matched trees by the values of some fields and aggregates each
+/// group, printed as tab separated columns ordered by the first aggregation.
+#[derive(Clone)]
+pub struct GroupByReport {
+    keys: Vec<Field>,
+    aggs: Vec<Agg>,
+    groups: HashMap<Vec<String>, Vec<State>>,
+}
+
+impl GroupByReport {
+    /// Counts the trees of each group when `aggs` is empty.
+    pub fn new(keys: Vec<Field>, mut aggs: Vec<Agg>) -> Self {
+        if aggs.is_empty() {
+            aggs.push(Agg::Count);
+        }
+        GroupByReport {
+            keys,
+            aggs,
+            groups: HashMap::new(),
+        }
+    }
+}
+
+impl Report for GroupByReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let key = self
+            .keys
+            .iter()
+            .map(|field| field.value(tree).unwrap_or_default())
+            .collect();
+        let aggs = &self.aggs;
+        let states = self
+            .groups
+            .entry(key)
+            .or_insert_with(|| aggs.iter().map(|&agg| State::new(agg)).collect());
+        for (state, &agg) in states.iter_mut().zip(aggs) {
+            state.observe(agg, tree);
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: GroupByReport = downcast(other);
+        for (key, states) in other.groups {
+            match self.groups.get_mut(&key) {
+                Some(mine) => {
+                    for (state, other) in mine.iter_mut().zip(states) {
+                        state.merge(other);
+                    }
+                }
+                None => {
+                    self.groups.insert(key, states);
+                }
+            }
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let header: Vec<String> = self
+            .keys
+            .iter()
+            .map(ToString::to_string)
+            .chain(self.aggs.iter().map(ToString::to_string))
+            .collect();
+        writeln!(out, "{}", header.join("\t"))?;
+
+        let mut rows: Vec<(&Vec<String>, Vec<Option<f64>>)> = self
+            .groups
+            .iter()
+            .map(|(key, states)| {
+                let values = states
+                    .iter()
+                    .zip(&self.aggs)
+                    .map(|(state, &agg)| state.value(agg))
+                    .collect();
+                (key, values)
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            let first = |values: &[Option<f64>]| values[0].unwrap_or(f64::NEG_INFINITY);
+            first(&b.1)
+                .partial_cmp(&first(&a.1))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(b.0))
+        });
+        for (key, values) in rows {
+            let columns: Vec<String> = key
+                .iter()
+                .cloned()
+                .chain(
+                    values
+                        .into_iter()
+                        .zip(&self.aggs)
+                        .map(|(value, &agg)| format_number(value, agg)),
+                )
+                .collect();
+            writeln!(out, "{}", columns.join("\t"))?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
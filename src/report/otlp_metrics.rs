@@ -0,0 +1,242 @@
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use failure::{bail, Fallible};
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::logging;
+use crate::message_tree::{Message, MessageTree, Text};
+use crate::report::{downcast, Report};
+use crate::tls::ClientTls;
+
+/// Bucket bounds used when `--otlp-buckets` isn't given, in ms.
+pub const DEFAULT_BUCKETS: &str = "5,10,25,50,100,250,500,1000,2500,5000,10000";
+
+/// Data points per request, which collectors commonly limit the size of.
+const POINTS_PER_REQUEST: usize = 1000;
+
+/// Attempts of a request before giving up the export.
+const ATTEMPTS: u32 = 3;
+const BACKOFF: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// AGGREGATION_TEMPORALITY_DELTA, each data point covering one interval.
+const DELTA: u32 = 1;
+
+/// Where and how `OtlpMetricsReport` pushes its histograms.
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP metrics endpoint, e.g. http://collector:4318/v1/metrics.
+    pub endpoint: String,
+    pub interval: Duration,
+    /// Upper bounds of the buckets in ms, ascending.
+    pub buckets: Vec<f64>,
+    pub tls: ClientTls,
+}
+
+#[derive(Clone)]
+struct Histogram {
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+    /// One more than the bounds, the last one counting the durations above
+    /// all of them.
+    buckets: Vec<u64>,
+}
+
+impl Histogram {
+    fn new(bounds: usize) -> Self {
+        Histogram {
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+            buckets: vec![0; bounds + 1],
+        }
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        for (a, b) in self.buckets.iter_mut().zip(&other.buckets) {
+            *a += b;
+        }
+    }
+}
+
+/// Series of a histogram: the domain of the trees, the type and name of
+/// the transactions and the start of their interval in ms.
+type Key = (Text, Text, Text, u64);
+
+/// Aggregates the durations of the transactions of matched trees, at any
+/// depth, into explicit-bucket histograms per domain, type and name and per
+/// interval of their start, then pushes them to an OTLP/HTTP endpoint as
+/// `cat.transaction.duration`, e.g. to backfill a metrics store with
+/// historical latency.
+///
+/// The domain is the `service.name` of the resource of the data points,
+/// the type and name are their `cat.type` and `cat.name` attributes.
+pub struct OtlpMetricsReport {
+    config: OtlpConfig,
+    histograms: HashMap<Key, Histogram>,
+}
+
+impl OtlpMetricsReport {
+    pub fn new(config: OtlpConfig) -> Self {
+        OtlpMetricsReport {
+            config,
+            histograms: HashMap::new(),
+        }
+    }
+
+    fn observe_message(&mut self, domain: &Text, message: &Message) {
+        if let Message::Transaction(t) = message {
+            let interval = (self.config.interval.as_millis() as u64).max(1);
+            let start = t.timestamp_in_ms - t.timestamp_in_ms % interval;
+            let key = (domain.clone(), t.ty.clone(), t.name.clone(), start);
+            let bounds = &self.config.buckets;
+            let histogram = self
+                .histograms
+                .entry(key)
+                .or_insert_with(|| Histogram::new(bounds.len()));
+            let duration = t.duration_in_ms;
+            histogram.count += 1;
+            histogram.sum += duration;
+            histogram.min = histogram.min.min(duration);
+            histogram.max = histogram.max.max(duration);
+            let bucket = bounds.partition_point(|&bound| bound < duration as f64);
+            histogram.buckets[bucket] += 1;
+            for child in &t.children {
+                self.observe_message(domain, child);
+            }
+        }
+    }
+
+    /// The body of an export request of `points`, grouped by domain.
+    fn request(&self, points: &[(&Key, &Histogram)]) -> Value {
+        let interval = self.config.interval.as_millis() as u64;
+        let mut by_domain: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+        for ((domain, ty, name, start), histogram) in points {
+            let nanos = |ms: u64| (u128::from(ms) * 1_000_000).to_string();
+            by_domain.entry(domain).or_default().push(json!({
+                "attributes": [
+                    {"key": "cat.type", "value": {"stringValue": ty.to_string()}},
+                    {"key": "cat.name", "value": {"stringValue": name.to_string()}},
+                ],
+                "startTimeUnixNano": nanos(*start),
+                "timeUnixNano": nanos(start + interval),
+                "count": histogram.count.to_string(),
+                "sum": histogram.sum as f64,
+                "min": histogram.min as f64,
+                "max": histogram.max as f64,
+                "bucketCounts": histogram
+                    .buckets
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                "explicitBounds": self.config.buckets,
+            }));
+        }
+        let resource_metrics: Vec<_> = by_domain
+            .into_iter()
+            .map(|(domain, data_points)| {
+                json!({
+                    "resource": {
+                        "attributes": [
+                            {"key": "service.name", "value": {"stringValue": domain}},
+                        ],
+                    },
+                    "scopeMetrics": [{
+                        "scope": {"name": "dump-cat", "version": env!("CARGO_PKG_VERSION")},
+                        "metrics": [{
+                            "name": "cat.transaction.duration",
+                            "unit": "ms",
+                            "histogram": {
+                                "aggregationTemporality": DELTA,
+                                "dataPoints": data_points,
+                            },
+                        }],
+                    }],
+                })
+            })
+            .collect();
+        json!({ "resourceMetrics": resource_metrics })
+    }
+
+    fn post(&self, agent: &ureq::Agent, body: &Value) -> Fallible<()> {
+        let mut backoff = BACKOFF;
+        for attempt in 1..=ATTEMPTS {
+            let result = agent
+                .post(&self.config.endpoint)
+                .header("Content-Type", "application/json")
+                .send(serde_json::to_vec(body)?);
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < ATTEMPTS => {
+                    let e = e.into();
+                    warn!(
+                        error_kind = logging::error_kind(&e);
+                        "otlp: {}, retrying in {:?}", e, backoff
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(e) => bail!("otlp: giving up after {} attempts: {}", ATTEMPTS, e),
+            }
+        }
+        unreachable!("the last attempt returns")
+    }
+}
+
+impl Report for OtlpMetricsReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.observe_message(&tree.domain, &tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: OtlpMetricsReport = downcast(other);
+        for (key, histogram) in other.histograms {
+            match self.histograms.get_mut(&key) {
+                Some(mine) => mine.merge(&histogram),
+                None => {
+                    self.histograms.insert(key, histogram);
+                }
+            }
+        }
+    }
+
+    /// Pushes the histograms, then prints how many were.
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        let mut points: Vec<_> = self.histograms.iter().collect();
+        points.sort_by(|a, b| a.0.cmp(b.0));
+        let agent = self.config.tls.http_agent(Some(TIMEOUT))?;
+        for chunk in points.chunks(POINTS_PER_REQUEST) {
+            self.post(&agent, &self.request(chunk))?;
+        }
+        let series = points
+            .iter()
+            .map(|((domain, ty, name, _), _)| (domain, ty, name))
+            .collect::<HashSet<_>>()
+            .len();
+        writeln!(
+            out,
+            "otlp: pushed {} data points of {} histograms to {}",
+            points.len(),
+            series,
+            self.config.endpoint
+        )?;
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
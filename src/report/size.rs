@@ -0,0 +1,154 @@
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_tree::{Message, MessageTree, Text, TreeLocation};
+use crate::report::{downcast, Report};
+
+/// A tree ranked by its encoded size.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct TreeSize {
+    bytes: u64,
+    location: TreeLocation,
+    message_id: Text,
+}
+
+/// The data of the messages of one type and name.
+#[derive(Default)]
+struct DataBytes {
+    messages: u64,
+    bytes: u64,
+    /// The message with the most data, and where its tree is.
+    largest: u64,
+    largest_message_id: Text,
+    largest_location: TreeLocation,
+}
+
+/// The largest matched trees, by encoded size, and the types and names of
+/// messages, at any depth, with the most bytes of data, to find the
+/// instrumentation bloating the hourly files.
+///
+/// Trees are listed with their message id, e.g. for `show`, and the offset
+/// of their block and their index in it.
+pub struct SizeReport {
+    k: usize,
+    /// The `k` largest trees, smallest first.
+    largest: BinaryHeap<Reverse<TreeSize>>,
+    data: HashMap<(Text, Text), DataBytes>,
+    buf: Vec<u8>,
+}
+
+impl SizeReport {
+    pub fn new(k: usize) -> Self {
+        SizeReport {
+            k,
+            largest: BinaryHeap::new(),
+            data: HashMap::new(),
+            buf: vec![],
+        }
+    }
+
+    fn offer(&mut self, tree: TreeSize) {
+        if self.largest.len() < self.k {
+            self.largest.push(Reverse(tree));
+        } else if let Some(mut smallest) = self.largest.peek_mut() {
+            if tree > smallest.0 {
+                smallest.0 = tree;
+            }
+        }
+    }
+
+    fn observe_message(&mut self, tree: &MessageTree, message: &Message) {
+        let bytes = message.data().len() as u64;
+        let data = self
+            .data
+            .entry((message.ty().clone(), message.name().clone()))
+            .or_default();
+        data.messages += 1;
+        data.bytes += bytes;
+        if bytes > data.largest || data.messages == 1 {
+            data.largest = bytes;
+            data.largest_message_id = tree.message_id.clone();
+            data.largest_location = tree.location.clone();
+        }
+        if let Message::Transaction(t) = message {
+            for child in &t.children {
+                self.observe_message(tree, child);
+            }
+        }
+    }
+}
+
+impl Report for SizeReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.buf.clear();
+        tree.encode(&mut self.buf);
+        self.offer(TreeSize {
+            bytes: self.buf.len() as u64,
+            location: tree.location.clone(),
+            message_id: tree.message_id.clone(),
+        });
+        self.observe_message(tree, &tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: SizeReport = downcast(other);
+        for Reverse(tree) in other.largest {
+            self.offer(tree);
+        }
+        for (key, theirs) in other.data {
+            let mine = self.data.entry(key).or_default();
+            if theirs.largest > mine.largest || mine.messages == 0 {
+                mine.largest = theirs.largest;
+                mine.largest_message_id = theirs.largest_message_id;
+                mine.largest_location = theirs.largest_location;
+            }
+            mine.messages += theirs.messages;
+            mine.bytes += theirs.bytes;
+        }
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        writeln!(out, "largest trees:")?;
+        let mut largest: Vec<_> = self.largest.iter().map(|Reverse(tree)| tree).collect();
+        largest.sort_by(|a, b| b.cmp(a));
+        for tree in largest {
+            writeln!(
+                out,
+                "{:>12}  {}  block {} #{}",
+                human::bytes(tree.bytes as f64),
+                tree.message_id,
+                tree.location.block_offset,
+                tree.location.index
+            )?;
+        }
+
+        writeln!(out, "most data:")?;
+        let mut data: Vec<_> = self.data.iter().filter(|(_, d)| d.bytes > 0).collect();
+        data.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(b.0)));
+        for ((ty, name), d) in data.into_iter().take(self.k) {
+            writeln!(
+                out,
+                "{:>12}  {}  {}  in {} messages, largest {} in {} block {} #{}",
+                human::bytes(d.bytes as f64),
+                ty,
+                name,
+                human::count(d.messages),
+                human::bytes(d.largest as f64),
+                d.largest_message_id,
+                d.largest_location.block_offset,
+                d.largest_location.index
+            )?;
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
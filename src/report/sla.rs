@@ -0,0 +1,256 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::{bail, Fallible};
+use serde::Deserialize;
+
+use crate::human;
+use crate::message_tree::{Message, MessageTree};
+use crate::report::{downcast, format_timestamp, Report};
+use crate::status_class;
+
+const DEFAULT_LATENCY_TARGET: f64 = 0.99;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RulesFile {
+    List(Vec<SlaRule>),
+    Map { rules: Vec<SlaRule> },
+}
+
+/// Targets of the transactions called `name`, of type `ty` if set.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlaRule {
+    pub name: String,
+    pub ty: Option<String>,
+    /// Transactions should take at most this long, `latency_target` of
+    /// them at least.
+    pub latency_ms: Option<u64>,
+    #[serde(default = "default_latency_target")]
+    pub latency_target: f64,
+    /// Share of the transactions that should succeed, see `status_class`.
+    pub availability_target: Option<f64>,
+}
+
+fn default_latency_target() -> f64 {
+    DEFAULT_LATENCY_TARGET
+}
+
+/// The rules of `dump-cat sla`, loaded from `--rules`:
+///
+/// ```yaml
+/// rules:
+///   - name: /api/checkout
+///     ty: URL
+///     latency_ms: 300
+///     latency_target: 0.99
+///     availability_target: 0.999
+///   - name: order.insert
+///     availability_target: 0.9999
+/// ```
+///
+/// A rule needs `latency_ms`, `availability_target` or both.
+/// `latency_target` is 0.99 unless set.
+#[derive(Debug)]
+pub struct SlaRules {
+    rules: Vec<SlaRule>,
+    /// Indexes of the rules of each transaction name.
+    by_name: HashMap<String, Vec<usize>>,
+    /// The file the rules were loaded from.
+    path: PathBuf,
+}
+
+impl SlaRules {
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let file: RulesFile = serde_yaml::from_reader(File::open(path)?)?;
+        let rules = match file {
+            RulesFile::List(rules) | RulesFile::Map { rules } => rules,
+        };
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, rule) in rules.iter().enumerate() {
+            if rule.latency_ms.is_none() && rule.availability_target.is_none() {
+                bail!(
+                    "rule {}: needs latency_ms or availability_target",
+                    rule.name
+                );
+            }
+            for target in Some(rule.latency_target)
+                .filter(|_| rule.latency_ms.is_some())
+                .into_iter()
+                .chain(rule.availability_target)
+            {
+                if !(target > 0.0 && target < 1.0) {
+                    bail!(
+                        "rule {}: target {} is not between 0 and 1 excluded",
+                        rule.name,
+                        target
+                    );
+                }
+            }
+            by_name.entry(rule.name.clone()).or_default().push(i);
+        }
+        Ok(SlaRules {
+            rules,
+            by_name,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    transactions: u64,
+    /// Slower than the `latency_ms` of the rule.
+    slow: u64,
+    failed: u64,
+}
+
+/// How the transactions of each rule of `SlaRules` compare to its latency
+/// and availability targets over the time range of the trees.
+///
+/// Transactions are looked at at any depth. For each target the report
+/// shows the share of good transactions, and how much of the error budget,
+/// the bad transactions the target allows, was burned: over 100% means the
+/// target was missed.
+pub struct SlaReport {
+    rules: Arc<SlaRules>,
+    counts: Vec<Counts>,
+    /// Earliest and latest root timestamps of the trees.
+    range: Option<(u64, u64)>,
+}
+
+impl SlaReport {
+    pub fn new(rules: Arc<SlaRules>) -> Self {
+        SlaReport {
+            counts: vec![Counts::default(); rules.rules.len()],
+            rules,
+            range: None,
+        }
+    }
+
+    fn observe_message(&mut self, message: &Message) {
+        let t = match message {
+            Message::Transaction(t) => t,
+            _ => return,
+        };
+        for &i in self.rules.by_name.get(&t.name).into_iter().flatten() {
+            let rule = &self.rules.rules[i];
+            if rule.ty.as_ref().is_some_and(|ty| *ty != t.ty) {
+                continue;
+            }
+            let counts = &mut self.counts[i];
+            counts.transactions += 1;
+            if rule.latency_ms.is_some_and(|ms| t.duration_in_ms > ms) {
+                counts.slow += 1;
+            }
+            if !status_class::is_success(&t.status) {
+                counts.failed += 1;
+            }
+        }
+        for child in &t.children {
+            self.observe_message(child);
+        }
+    }
+}
+
+impl Report for SlaReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let timestamp = tree.message.timestamp_in_ms();
+        self.range = Some(match self.range {
+            Some((first, last)) => (first.min(timestamp), last.max(timestamp)),
+            None => (timestamp, timestamp),
+        });
+        self.observe_message(&tree.message);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: SlaReport = downcast(other);
+        for (ours, theirs) in self.counts.iter_mut().zip(other.counts) {
+            ours.transactions += theirs.transactions;
+            ours.slow += theirs.slow;
+            ours.failed += theirs.failed;
+        }
+        self.range = match (self.range, other.range) {
+            (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        if let Some((first, last)) = self.range {
+            writeln!(
+                out,
+                "from {} to {} ({})",
+                format_timestamp(first)?,
+                format_timestamp(last)?,
+                human::duration_ms((last - first) as f64)
+            )?;
+        }
+        writeln!(
+            out,
+            "ty\tname\tobjective\ttarget\tactual\ttransactions\tbad\tbudget burned\tstatus"
+        )?;
+        for (rule, counts) in self.rules.rules.iter().zip(&self.counts) {
+            let objectives = rule
+                .latency_ms
+                .map(|ms| {
+                    (
+                        format!("latency <= {}ms", ms),
+                        rule.latency_target,
+                        counts.slow,
+                    )
+                })
+                .into_iter()
+                .chain(
+                    rule.availability_target
+                        .map(|target| ("availability".to_string(), target, counts.failed)),
+                );
+            for (objective, target, bad) in objectives {
+                write!(
+                    out,
+                    "{}\t{}\t{}\t{}\t",
+                    rule.ty.as_deref().unwrap_or("*"),
+                    rule.name,
+                    objective,
+                    percent(target)
+                )?;
+                if counts.transactions == 0 {
+                    writeln!(out, "-\t0\t0\t-\tno data")?;
+                    continue;
+                }
+                let total = counts.transactions as f64;
+                let actual = 1.0 - bad as f64 / total;
+                // The bad transactions allowed by the target.
+                let budget = total * (1.0 - target);
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}",
+                    percent(actual),
+                    human::count(counts.transactions),
+                    human::count(bad),
+                    percent(bad as f64 / budget),
+                    if actual >= target { "met" } else { "missed" }
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+fn percent(ratio: f64) -> String {
+    format!("{}%", human::number(ratio * 100.0, 2))
+}
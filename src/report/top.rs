@@ -0,0 +1,61 @@
+use std::any::Any;
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_tree::{MessageTree, Text};
+use crate::report::{downcast, Report};
+use crate::topk::{SpaceSaving, DEFAULT_CAPACITY};
+
+/// The most frequent `ty` and `name` pairs of the matched trees.
+pub struct TopReport {
+    k: usize,
+    counts: SpaceSaving<(Text, Text)>,
+}
+
+impl TopReport {
+    pub fn new(k: usize) -> Self {
+        TopReport {
+            k,
+            // Tracking fewer keys than reported would cut the report short.
+            counts: SpaceSaving::new(k.max(DEFAULT_CAPACITY)),
+        }
+    }
+}
+
+impl Report for TopReport {
+    fn observe(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.counts
+            .offer((tree.message.ty().clone(), tree.message.name().clone()));
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Box<dyn Report>) {
+        let other: TopReport = downcast(other);
+        self.counts.merge(other.counts);
+    }
+
+    fn render(&self, out: &mut dyn Write) -> Fallible<()> {
+        for ranked in self.counts.top(self.k) {
+            let (ty, name) = ranked.key;
+            if ranked.error > 0 {
+                writeln!(
+                    out,
+                    "{:>8} (±{})  {}  {}",
+                    human::count(ranked.count),
+                    human::count(ranked.error),
+                    ty,
+                    name
+                )?;
+            } else {
+                writeln!(out, "{:>8}  {}  {}", human::count(ranked.count), ty, name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossbeam::{RecvTimeoutError, Sender};
+use failure::Fallible;
+use log::warn;
+
+use crate::logging;
+use crate::message_tree::MessageTree;
+use crate::output::Output;
+use crate::report::Report;
+
+/// A report over the trees matched during the last `window`, rendered every
+/// `every` while the input is read, e.g. while following a file.
+///
+/// Trees are counted in panes of `every`; a window is the merge of its
+/// last panes. The window is tumbling when `every` equals `window` and
+/// sliding when it's shorter. Windows follow the wall clock, not the
+/// timestamps of the trees.
+pub struct Windowed<R> {
+    empty: R,
+    window: Duration,
+    panes_per_window: usize,
+    panes: Mutex<VecDeque<R>>,
+}
+
+impl<R: Report + Clone + Sync + 'static> Windowed<R> {
+    pub fn new(empty: R, window: Duration, every: Duration) -> Self {
+        let every = every.max(Duration::from_millis(1));
+        let panes_per_window = (window.as_nanos().div_ceil(every.as_nanos()) as usize).max(1);
+        Windowed {
+            panes: Mutex::new(VecDeque::from(vec![empty.clone()])),
+            empty,
+            window,
+            panes_per_window,
+        }
+    }
+
+    pub fn observe(&self, tree: &MessageTree) -> Fallible<()> {
+        let mut panes = self.panes.lock().expect("windows poisoned");
+        panes.back_mut().expect("current pane").observe(tree)
+    }
+
+    /// Renders the current window and starts a new pane.
+    fn emit(&self, out: &mut dyn std::io::Write) -> Fallible<()> {
+        let mut merged = self.empty.clone();
+        {
+            let mut panes = self.panes.lock().expect("windows poisoned");
+            for pane in panes.iter() {
+                merged.merge(Box::new(pane.clone()));
+            }
+            panes.push_back(self.empty.clone());
+            while panes.len() > self.panes_per_window {
+                panes.pop_front();
+            }
+        }
+        let now = time::now_utc();
+        writeln!(
+            out,
+            "# {} window {:?}",
+            now.strftime("%Y-%m-%dT%H:%M:%SZ")?,
+            self.window
+        )?;
+        merged.render(out)
+    }
+
+    /// Renders the window to `output` every `every`, and once more when
+    /// the returned emitter is finished.
+    pub fn start(self: Arc<Self>, every: Duration, output: Output) -> Fallible<Emitter> {
+        let (stop, stopped) = crossbeam::bounded::<()>(0);
+        let handle = thread::Builder::new()
+            .name("WindowEmitter".to_string())
+            .spawn(move || loop {
+                let last = !matches!(stopped.recv_timeout(every), Err(RecvTimeoutError::Timeout));
+                if let Err(e) = self.emit(&mut *output.lock()) {
+                    warn!(error_kind = logging::error_kind(&e); "failed to emit window: {}", e);
+                }
+                if last {
+                    return;
+                }
+            })?;
+        Ok(Emitter { stop, handle })
+    }
+}
+
+pub struct Emitter {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Emitter {
+    /// Emits the last window and waits for it to be written.
+    pub fn finish(self) {
+        drop(self.stop);
+        self.handle.join().expect("window emitter");
+    }
+}
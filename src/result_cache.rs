@@ -0,0 +1,148 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use failure::{bail, Fallible};
+use log::info;
+
+use crate::message_tree::TreeLocation;
+use crate::query::QueryLang;
+
+const MAGIC: &[u8] = b"DCRC3";
+
+/// Entries written before the chunks, then the blocks, of the trees were
+/// recorded, read as misses and overwritten.
+const OLD_MAGICS: &[&[u8]] = &[b"DCRC1", b"DCRC2"];
+
+/// Bytes hashed at the start and at the end of the input file.
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// `$XDG_CACHE_HOME/dump-cat`, or `~/.cache/dump-cat`.
+pub fn default_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("dump-cat")
+}
+
+/// Locations of the trees of a file matched by a query, so a later run
+/// with the same file and query only decodes the trees, from the snappy chunks
+/// they start in.
+///
+/// Files are identified by their path, size, modification time and the
+/// content of their first and last bytes rather than a hash of the whole
+/// file, which would cost as much as the scan the cache avoids.
+pub struct ResultCache {
+    path: PathBuf,
+    /// The file of the locations.
+    input: Arc<Path>,
+}
+
+impl ResultCache {
+    /// The cache entry in `dir` of `query`, in `lang`, and `ids` run against
+    /// `input`.
+    pub fn new(
+        dir: &Path,
+        input: &Path,
+        query: Option<&str>,
+        lang: QueryLang,
+        ids: Option<&[String]>,
+    ) -> Fallible<Self> {
+        let mut hasher = DefaultHasher::new();
+        fingerprint(input, &mut hasher)?;
+        query.map(str::trim).hash(&mut hasher);
+        lang.hash(&mut hasher);
+        ids.hash(&mut hasher);
+        Ok(ResultCache {
+            path: dir.join(format!("{:016x}", hasher.finish())),
+            input: input.into(),
+        })
+    }
+
+    pub fn load(&self) -> Fallible<Option<Vec<TreeLocation>>> {
+        let mut reader = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if OLD_MAGICS.contains(&&magic[..]) {
+            info!("result cache {} is outdated", self.path.display());
+            return Ok(None);
+        }
+        if magic != MAGIC {
+            bail!("invalid result cache {}", self.path.display());
+        }
+        let len = reader.read_u64::<BigEndian>()?;
+        let mut locations = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            locations.push(TreeLocation {
+                block_offset: reader.read_u64::<BigEndian>()?,
+                block: reader.read_u32::<BigEndian>()?,
+                index: reader.read_u32::<BigEndian>()?,
+                chunk: reader.read_u32::<BigEndian>()?,
+                chunk_offset: reader.read_u32::<BigEndian>()?,
+                file: Some(self.input.clone()),
+            });
+        }
+        info!(
+            "result cache hit: {} ({} trees)",
+            self.path.display(),
+            locations.len()
+        );
+        Ok(Some(locations))
+    }
+
+    pub fn store(&self, locations: &[TreeLocation]) -> Fallible<()> {
+        let dir = self.path.parent().expect("cache entry in a directory");
+        fs::create_dir_all(dir)?;
+        // Written aside and renamed so concurrent runs never read a partial entry.
+        let tmp = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(MAGIC)?;
+        writer.write_u64::<BigEndian>(locations.len() as u64)?;
+        for location in locations {
+            writer.write_u64::<BigEndian>(location.block_offset)?;
+            writer.write_u32::<BigEndian>(location.block)?;
+            writer.write_u32::<BigEndian>(location.index)?;
+            writer.write_u32::<BigEndian>(location.chunk)?;
+            writer.write_u32::<BigEndian>(location.chunk_offset)?;
+        }
+        writer.flush()?;
+        fs::rename(&tmp, &self.path)?;
+        info!(
+            "result cache stored: {} ({} trees)",
+            self.path.display(),
+            locations.len()
+        );
+        Ok(())
+    }
+}
+
+fn fingerprint(input: &Path, hasher: &mut impl Hasher) -> Fallible<()> {
+    let mut file = File::open(input)?;
+    let metadata = file.metadata()?;
+    fs::canonicalize(input)?.hash(hasher);
+    metadata.len().hash(hasher);
+    metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .hash(hasher);
+
+    let mut sample = vec![];
+    (&mut file).take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    file.seek(SeekFrom::Start(metadata.len().saturating_sub(SAMPLE_SIZE)))?;
+    file.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    sample.hash(hasher);
+    Ok(())
+}
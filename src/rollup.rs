@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use failure::{bail, Fallible};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::message_tree::{Message, Text};
+use crate::message_tree_dumper::MessageTreeDumper;
+use crate::parquet::{self, Column, Values};
+use crate::prune;
+use crate::sidecar::source_version;
+use crate::status_class;
+
+/// Bucket start in ms since the epoch, domain, type and name of a row.
+type Key = (u64, Text, Text, Text);
+
+/// Stats of the transactions of a name in a bucket.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Stats {
+    count: u64,
+    /// Not a success, see `status_class`.
+    failures: u64,
+    duration_sum_ms: u64,
+    duration_min_ms: u64,
+    duration_max_ms: u64,
+}
+
+impl Stats {
+    fn new(duration: u64) -> Self {
+        Stats {
+            count: 0,
+            failures: 0,
+            duration_sum_ms: 0,
+            duration_min_ms: duration,
+            duration_max_ms: duration,
+        }
+    }
+
+    fn merge(&mut self, other: &Stats) {
+        self.count += other.count;
+        self.failures += other.failures;
+        self.duration_sum_ms += other.duration_sum_ms;
+        self.duration_min_ms = self.duration_min_ms.min(other.duration_min_ms);
+        self.duration_max_ms = self.duration_max_ms.max(other.duration_max_ms);
+    }
+}
+
+/// The rows of one file, with the version of the file they come from.
+#[derive(Serialize, Deserialize)]
+struct FileRollup {
+    size: u64,
+    modified: u128,
+    rows: Vec<(Key, Stats)>,
+}
+
+/// The rows of every file rolled up so far, saved after each file so an
+/// interrupted rollup resumes where it stopped, and a later one only reads
+/// the files that are new or changed.
+#[derive(Default, Serialize, Deserialize)]
+struct Checkpoint {
+    granularity_ms: u64,
+    /// By path relative to the directory.
+    files: BTreeMap<PathBuf, FileRollup>,
+}
+
+impl Checkpoint {
+    fn load(path: &Path, granularity_ms: u64) -> Fallible<Self> {
+        let checkpoint: Checkpoint = match File::open(path) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(_) => {
+                return Ok(Checkpoint {
+                    granularity_ms,
+                    files: BTreeMap::new(),
+                })
+            }
+        };
+        if checkpoint.granularity_ms != granularity_ms {
+            bail!(
+                "{} was made with a granularity of {}ms, remove it to roll up by {}ms",
+                path.display(),
+                checkpoint.granularity_ms,
+                granularity_ms
+            );
+        }
+        Ok(checkpoint)
+    }
+
+    /// Replaces the checkpoint at `path` whole, so a crash leaves either
+    /// the old or the new one.
+    fn save(&self, path: &Path) -> Fallible<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut out, self)?;
+        out.flush()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub files_read: usize,
+    pub files_unchanged: usize,
+    pub rows: usize,
+}
+
+/// Path of the checkpoint of a rollup written to `out`, when not given.
+pub fn default_checkpoint(out: &Path) -> PathBuf {
+    let mut path = out.as_os_str().to_owned();
+    path.push(".checkpoint");
+    PathBuf::from(path)
+}
+
+/// Aggregates the transactions of the logviews under `dir`, at any depth,
+/// by bucket of `granularity_ms`, domain, type and name, into a Parquet
+/// file at `out` for trend analysis.
+///
+/// Each row holds the count, failures and duration sum, min and max of
+/// its transactions, the bucket being the UTC time they start in. The rows
+/// of every file are kept in the checkpoint at `checkpoint`, so only files
+/// new or changed since are read again, and files removed from `dir`, e.g.
+/// by retention, stay in the rollup. `dumper_for` decodes the logviews.
+pub fn rollup(
+    dir: &Path,
+    granularity_ms: u64,
+    out: &Path,
+    checkpoint_path: &Path,
+    dumper_for: impl Fn(PathBuf) -> Fallible<MessageTreeDumper>,
+) -> Fallible<Summary> {
+    if granularity_ms == 0 {
+        bail!("the granularity of a rollup can't be 0");
+    }
+    let mut checkpoint = Checkpoint::load(checkpoint_path, granularity_ms)?;
+    let mut summary = Summary::default();
+    for path in prune::logviews(dir)? {
+        let relative = path.strip_prefix(dir)?.to_path_buf();
+        let (size, modified) = source_version(&path)?;
+        if let Some(file) = checkpoint.files.get(&relative) {
+            if (file.size, file.modified) == (size, modified) {
+                summary.files_unchanged += 1;
+                continue;
+            }
+        }
+        info!("rolling up {}", path.display());
+        let mut rows = HashMap::new();
+        for tree in dumper_for(path)?.read_trees() {
+            observe(&tree.domain, &tree.message, granularity_ms, &mut rows);
+        }
+        checkpoint.files.insert(
+            relative,
+            FileRollup {
+                size,
+                modified,
+                rows: rows.into_iter().collect(),
+            },
+        );
+        checkpoint.save(checkpoint_path)?;
+        summary.files_read += 1;
+    }
+
+    let mut rows: BTreeMap<&Key, Stats> = BTreeMap::new();
+    for file in checkpoint.files.values() {
+        for (key, stats) in &file.rows {
+            match rows.get_mut(key) {
+                Some(row) => row.merge(stats),
+                None => {
+                    rows.insert(key, *stats);
+                }
+            }
+        }
+    }
+    summary.rows = rows.len();
+    let column = |f: fn(&Key, &Stats) -> i64| rows.iter().map(|(k, s)| f(k, s)).collect();
+    let text = |f: fn(&Key) -> &Text| rows.keys().map(|k| f(k).clone()).collect();
+    let columns = [
+        Column {
+            name: "bucket",
+            values: Values::TimestampMillis(column(|k, _| k.0 as i64)),
+        },
+        Column {
+            name: "domain",
+            values: Values::Utf8(text(|k| &k.1)),
+        },
+        Column {
+            name: "ty",
+            values: Values::Utf8(text(|k| &k.2)),
+        },
+        Column {
+            name: "name",
+            values: Values::Utf8(text(|k| &k.3)),
+        },
+        Column {
+            name: "count",
+            values: Values::Int64(column(|_, s| s.count as i64)),
+        },
+        Column {
+            name: "failures",
+            values: Values::Int64(column(|_, s| s.failures as i64)),
+        },
+        Column {
+            name: "duration_sum_ms",
+            values: Values::Int64(column(|_, s| s.duration_sum_ms as i64)),
+        },
+        Column {
+            name: "duration_min_ms",
+            values: Values::Int64(column(|_, s| s.duration_min_ms as i64)),
+        },
+        Column {
+            name: "duration_max_ms",
+            values: Values::Int64(column(|_, s| s.duration_max_ms as i64)),
+        },
+    ];
+    let mut writer = BufWriter::new(File::create(out)?);
+    parquet::write(
+        &columns,
+        concat!("dump-cat version ", env!("CARGO_PKG_VERSION")),
+        &mut writer,
+    )?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+fn observe(domain: &Text, message: &Message, granularity_ms: u64, rows: &mut HashMap<Key, Stats>) {
+    let t = match message {
+        Message::Transaction(t) => t,
+        _ => return,
+    };
+    let bucket = t.timestamp_in_ms - t.timestamp_in_ms % granularity_ms;
+    let stats = rows
+        .entry((bucket, domain.clone(), t.ty.clone(), t.name.clone()))
+        .or_insert_with(|| Stats::new(t.duration_in_ms));
+    stats.merge(&Stats {
+        count: 1,
+        failures: !status_class::is_success(&t.status) as u64,
+        duration_sum_ms: t.duration_in_ms,
+        duration_min_ms: t.duration_in_ms,
+        duration_max_ms: t.duration_in_ms,
+    });
+    for child in &t.children {
+        observe(domain, child, granularity_ms, rows);
+    }
+}
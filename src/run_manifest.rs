@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::{bail, format_err, Fallible};
+use serde::{Deserialize, Serialize};
+
+use crate::{bundle, prune};
+
+/// Flag whose value is the manifest itself, left out of the recorded
+/// arguments.
+const SAVE_MANIFEST: &str = "--save-manifest";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputFile {
+    /// As given on the command line, relative to `cwd` of the manifest.
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// What a run was made of, to repeat it with `dump-cat run` and prove it
+/// read the same files.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunManifest {
+    /// Version of dump-cat that made the run.
+    pub version: String,
+    pub created_at_in_ms: u64,
+    /// Directory the run was started in.
+    pub cwd: PathBuf,
+    /// Command line arguments, without the program name and
+    /// `--save-manifest`.
+    pub args: Vec<String>,
+    pub query: Option<String>,
+    pub inputs: Vec<InputFile>,
+    /// Directories the run read, whose logviews are in `inputs`.
+    #[serde(default)]
+    pub directories: Vec<PathBuf>,
+}
+
+impl InputFile {
+    fn new(path: &Path) -> Fallible<Self> {
+        Ok(InputFile {
+            path: path.to_path_buf(),
+            size: fs::metadata(path)?.len(),
+            sha256: bundle::sha256(path)?,
+        })
+    }
+}
+
+impl RunManifest {
+    /// Records the run of `args`, which read `inputs`. Directories are
+    /// recorded with each logview under them.
+    pub fn capture(
+        args: impl IntoIterator<Item = String>,
+        query: Option<String>,
+        inputs: &[&Path],
+    ) -> Fallible<Self> {
+        let mut recorded = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == SAVE_MANIFEST {
+                args.next();
+            } else if !arg.starts_with(&format!("{}=", SAVE_MANIFEST)) {
+                recorded.push(arg);
+            }
+        }
+        let mut files = vec![];
+        let mut directories = vec![];
+        for path in inputs {
+            if path.is_dir() {
+                for logview in prune::logviews(path)? {
+                    files.push(InputFile::new(&logview)?);
+                }
+                directories.push(path.to_path_buf());
+            } else if path.is_file() {
+                // Named pipes and the like can't be checked later.
+                files.push(InputFile::new(path)?);
+            }
+        }
+        Ok(RunManifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at_in_ms: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+            cwd: std::env::current_dir()?,
+            args: recorded,
+            query,
+            inputs: files,
+            directories,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Fallible<()> {
+        serde_yaml::to_writer(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Fallible<Self> {
+        serde_yaml::from_reader(File::open(path)?)
+            .map_err(|e| format_err!("invalid manifest {}: {}", path.display(), e))
+    }
+
+    /// Moves to the directory of the run and checks its inputs are the
+    /// files it read, and that no logview was added to its directories.
+    pub fn restore(&self) -> Fallible<()> {
+        std::env::set_current_dir(&self.cwd)
+            .map_err(|e| format_err!("can't enter {}: {}", self.cwd.display(), e))?;
+        for input in &self.inputs {
+            if !input.path.is_file() {
+                bail!("input {} is missing", input.path.display());
+            }
+            let changed = fs::metadata(&input.path)?.len() != input.size
+                || bundle::sha256(&input.path)? != input.sha256;
+            if changed {
+                bail!(
+                    "input {} changed since the manifest was saved",
+                    input.path.display()
+                );
+            }
+        }
+        for dir in &self.directories {
+            if !dir.is_dir() {
+                bail!("input {} is missing", dir.display());
+            }
+            for logview in prune::logviews(dir)? {
+                if !self.inputs.iter().any(|input| input.path == logview) {
+                    bail!(
+                        "input {} was added since the manifest was saved",
+                        logview.display()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::cel::Value;
+
+/// Functions over the trees matched so far in the run, with their number
+/// of arguments:
+///
+/// - `running_count(x)`: how many matched trees passed the value of `x`,
+///   counting the tree evaluated, e.g. `running_count(name) <= 3` keeps
+///   the first 3 trees of each name.
+/// - `seen_before(x)`: whether a matched tree passed the value of `x`, e.g.
+///   `!(status == "0") && !seen_before(name)` keeps the first error of each
+///   name.
+///
+/// Values are recorded once the tree matches, so the trees a query skips
+/// don't count whatever order its operands are evaluated in. They are
+/// counted per call, so `running_count(name) <= 3 && !seen_before(type)`
+/// doesn't mix names and types, and per type, so `1` and `"1"` differ.
+pub const FUNCTIONS: &[(&str, usize)] = &[("running_count", 1), ("seen_before", 1)];
+
+/// A value passed to `FUNCTIONS`: the call, numbered in the order of the
+/// query, and the value as a literal, with strings quoted.
+pub type Key = (i64, String);
+
+/// The values passed to `FUNCTIONS` by the trees a query matched, shared by
+/// the filter threads.
+#[derive(Default)]
+pub struct Aggregates {
+    counts: Mutex<HashMap<Key, u64>>,
+    /// Held while a tree is evaluated and recorded, so two threads don't
+    /// both take their tree for the first with a value.
+    evaluation: Mutex<()>,
+}
+
+impl Aggregates {
+    pub fn lock(&self) -> MutexGuard<'_, ()> {
+        self.evaluation.lock().expect("aggregates poisoned")
+    }
+
+    fn count(&self, key: &Key) -> u64 {
+        let counts = self.counts.lock().expect("aggregates poisoned");
+        counts.get(key).copied().unwrap_or(0)
+    }
+
+    /// Calls the function `name` of `FUNCTIONS` on the value of `key`, which
+    /// is recorded into `passed`.
+    pub fn call(&self, name: &str, key: Key, passed: &mut HashSet<Key>) -> Value {
+        let count = self.count(&key);
+        passed.insert(key);
+        match name {
+            "running_count" => Value::Int(count as i64 + 1),
+            _ => Value::Bool(count > 0),
+        }
+    }
+
+    /// Records the values passed by a tree that matched.
+    pub fn record(&self, passed: HashSet<Key>) {
+        let mut counts = self.counts.lock().expect("aggregates poisoned");
+        for key in passed {
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+}
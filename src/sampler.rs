@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use byteorder::{BigEndian, ByteOrder};
+use failure::{bail, format_err, Fallible};
+use sha2::{Digest, Sha256};
+
+use crate::message_tree::{MessageTree, Text};
+
+/// Key of the rate of the domains not listed.
+const DEFAULT_KEY: &str = "default";
+
+/// What the keep or drop decision of a tree is hashed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleKey {
+    #[default]
+    MessageId,
+    /// The root message id of the tree, or its own message id when it's a
+    /// root, so the trees of a distributed trace are kept or dropped
+    /// together.
+    RootMessageId,
+}
+
+impl FromStr for SampleKey {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "message_id" => SampleKey::MessageId,
+            "root_message_id" => SampleKey::RootMessageId,
+            _ => bail!(
+                "unknown sample key {}, expected message_id or root_message_id",
+                s
+            ),
+        })
+    }
+}
+
+/// Rates at which the trees of each domain are kept, e.g.
+/// `noisy-service=0.01,default=1.0`. Domains not listed, without a
+/// `default`, are all kept.
+///
+/// Whether a tree is kept depends on the SHA-256 of its key only, so the
+/// same trees are kept by every filter thread, every run and every build,
+/// and a tree kept at a rate is kept at any higher one. Keyed by root message id, a trace is
+/// then kept whole across the domains with the same rate, and in the
+/// domains with higher rates than the one it's kept in.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    rates: HashMap<Text, f64>,
+    default: f64,
+    key: SampleKey,
+}
+
+impl FromStr for Sampler {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let mut sampler = Sampler {
+            rates: HashMap::new(),
+            default: 1.0,
+            key: SampleKey::default(),
+        };
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (domain, rate) = pair
+                .split_once('=')
+                .ok_or_else(|| format_err!("invalid rate {}, e.g. noisy-service=0.01", pair))?;
+            let rate: f64 = rate
+                .trim()
+                .parse()
+                .map_err(|_| format_err!("invalid rate {}, e.g. noisy-service=0.01", pair))?;
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("invalid rate {}, must be between 0 and 1", pair);
+            }
+            match domain.trim() {
+                DEFAULT_KEY => sampler.default = rate,
+                domain => {
+                    sampler.rates.insert(domain.to_string(), rate);
+                }
+            }
+        }
+        Ok(sampler)
+    }
+}
+
+impl Sampler {
+    pub fn with_key(self, key: SampleKey) -> Self {
+        Sampler { key, ..self }
+    }
+
+    pub fn rate(&self, domain: &str) -> f64 {
+        self.rates.get(domain).copied().unwrap_or(self.default)
+    }
+
+    pub fn keeps(&self, tree: &MessageTree) -> bool {
+        let rate = self.rate(&tree.domain);
+        if rate >= 1.0 {
+            return true;
+        }
+        let key = match self.key {
+            SampleKey::RootMessageId if !tree.root_message_id.is_empty() => &tree.root_message_id,
+            _ => &tree.message_id,
+        };
+        (hash(key) as f64) < rate * u64::MAX as f64
+    }
+}
+
+/// The first 8 bytes of the SHA-256 of `key`, as a big endian u64.
+fn hash(key: &str) -> u64 {
+    BigEndian::read_u64(&Sha256::digest(key.as_bytes())[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_sha256() {
+        // SHA-256 of "abc" from FIPS 180-2.
+        assert_eq!(hash("abc"), 0xba78_16bf_8f01_cfea);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Instant;
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use failure::{bail, err_msg, Fallible};
+
+use crate::human;
+use crate::message_tree::skim;
+use crate::repair::{decompress_block, next_framed};
+
+/// Tags of the messages and what they're counted as, in the order they're
+/// printed.
+const KINDS: [(u8, &str); 5] = [
+    (b't', "transactions"),
+    (b'E', "events"),
+    (b'M', "metrics"),
+    (b'H', "heartbeats"),
+    (b'L', "traces"),
+];
+
+/// What `scan` counted, and what it couldn't read.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub blocks: u64,
+    pub trees: u64,
+    /// Bytes of the blocks as stored, and of their trees decompressed.
+    pub compressed_bytes: u64,
+    pub decompressed_bytes: u64,
+    /// Count and bytes of the messages of each of `KINDS`, the bytes of a
+    /// transaction not counting its children.
+    pub messages: [(u64, u64); KINDS.len()],
+    pub bad_blocks: u64,
+    pub bad_trees: u64,
+    /// Where the bad blocks and trees are and why, by offset.
+    pub errors: Vec<(u64, String)>,
+}
+
+impl Scan {
+    fn add(&mut self, other: Scan) {
+        self.blocks += other.blocks;
+        self.trees += other.trees;
+        self.compressed_bytes += other.compressed_bytes;
+        self.decompressed_bytes += other.decompressed_bytes;
+        for (total, (count, bytes)) in self.messages.iter_mut().zip(other.messages) {
+            total.0 += count;
+            total.1 += bytes;
+        }
+        self.bad_blocks += other.bad_blocks;
+        self.bad_trees += other.bad_trees;
+        self.errors.extend(other.errors);
+    }
+
+    fn block(&mut self, offset: u64, block: &[u8]) {
+        self.blocks += 1;
+        self.compressed_bytes += 4 + block.len() as u64;
+        let data = match decompress_block(block) {
+            Ok(data) => data,
+            Err(e) => {
+                self.bad_blocks += 1;
+                self.errors.push((offset, e.to_string()));
+                return;
+            }
+        };
+        self.decompressed_bytes += data.len() as u64;
+        let mut trees = &data[..];
+        let mut index = 0;
+        while !trees.is_empty() {
+            let mut messages = [(0, 0); KINDS.len()];
+            let skimmed = match next_framed(&mut trees) {
+                Some(tree) => skim(tree, |tag, bytes| {
+                    let kind = KINDS.iter().position(|&(t, _)| t == tag).expect("kind");
+                    messages[kind].0 += 1;
+                    messages[kind].1 += bytes as u64;
+                }),
+                None => {
+                    trees = &[];
+                    Err(err_msg("bad tree length"))
+                }
+            };
+            match skimmed {
+                Ok(()) => {
+                    self.trees += 1;
+                    for (total, (count, bytes)) in self.messages.iter_mut().zip(messages) {
+                        total.0 += count;
+                        total.1 += bytes;
+                    }
+                }
+                Err(e) => {
+                    self.bad_trees += 1;
+                    self.errors
+                        .push((offset, format!("tree #{}: {}", index, e)));
+                }
+            }
+            index += 1;
+        }
+    }
+}
+
+/// Walks the blocks and trees of the logview at `path` on `threads`
+/// threads, checking they are framed right, and prints how many of each
+/// there are, of each kind of message, and how fast they were read.
+///
+/// No tree is decoded, so it's as fast as reading the file gets, e.g. to
+/// check a file or measure the disk and decompression before picking
+/// thread counts. A block whose length is cut short or negative ends the
+/// scan, as the blocks after it can't be found without `repair`.
+pub fn scan(path: &Path, threads: usize, out: &mut dyn Write) -> Fallible<Scan> {
+    let started = Instant::now();
+    let mut file = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+    if file.read_i32::<BigEndian>()? != -1 {
+        bail!("{} isn't a logview", path.display());
+    }
+
+    let threads = threads.max(1);
+    let (sender, receiver) = crossbeam::bounded::<(u64, Vec<u8>)>(threads * 2);
+    let scanners: Vec<_> = (0..threads)
+        .map(|i| {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("Scanner{}", i))
+                .spawn(move || {
+                    let mut scan = Scan::default();
+                    for (offset, block) in receiver {
+                        scan.block(offset, &block);
+                    }
+                    scan
+                })
+                .expect("spawn error")
+        })
+        .collect();
+
+    let mut stopped = None;
+    let mut offset = 4;
+    loop {
+        let mut length = vec![];
+        (&mut file).take(4).read_to_end(&mut length)?;
+        match length.len() {
+            0 => break,
+            4 => {}
+            _ => {
+                stopped = Some((offset, "length cut short".to_string()));
+                break;
+            }
+        }
+        let length = BigEndian::read_i32(&length);
+        if length < 0 {
+            stopped = Some((offset, format!("bad block length {}", length)));
+            break;
+        }
+        let mut block = Vec::with_capacity(length as usize);
+        (&mut file).take(length as u64).read_to_end(&mut block)?;
+        if block.len() < length as usize {
+            stopped = Some((offset, "last block cut short".to_string()));
+            break;
+        }
+        sender.send((offset, block))?;
+        offset += 4 + length as u64;
+    }
+    drop(sender);
+
+    let mut scan = Scan::default();
+    for scanner in scanners {
+        scan.add(scanner.join().expect("scanner panicked"));
+    }
+    if let Some((offset, reason)) = stopped {
+        scan.bad_blocks += 1;
+        scan.errors.push((offset, format!("{}, stopped", reason)));
+    }
+    scan.errors.sort();
+    let elapsed = started.elapsed().as_secs_f64();
+
+    writeln!(
+        out,
+        "blocks: {} of {}",
+        human::count(scan.blocks),
+        human::bytes(scan.compressed_bytes as f64)
+    )?;
+    writeln!(
+        out,
+        "trees: {} of {} decompressed",
+        human::count(scan.trees),
+        human::bytes(scan.decompressed_bytes as f64)
+    )?;
+    for ((_, kind), (count, bytes)) in KINDS.iter().zip(scan.messages) {
+        writeln!(
+            out,
+            "{}: {} of {}",
+            kind,
+            human::count(count),
+            human::bytes(bytes as f64)
+        )?;
+    }
+    for (offset, reason) in &scan.errors {
+        writeln!(out, "{}: {}", offset, reason)?;
+    }
+    writeln!(
+        out,
+        "bad blocks: {}, bad trees: {}",
+        human::count(scan.bad_blocks),
+        human::count(scan.bad_trees)
+    )?;
+    writeln!(
+        out,
+        "took {}: {}/s read, {}/s decompressed, {} trees/s",
+        human::duration_ms((elapsed * 1000.0).round()),
+        human::bytes((scan.compressed_bytes as f64 / elapsed).round()),
+        human::bytes((scan.decompressed_bytes as f64 / elapsed).round()),
+        human::number(scan.trees as f64 / elapsed, 0)
+    )?;
+    Ok(scan)
+}
@@ -0,0 +1,126 @@
+use failure::{bail, Fallible};
+
+use crate::cel;
+use crate::filter;
+use crate::query;
+
+/// Variables a selector can match on, the ones holding strings.
+const LABELS: &[&str] = &[
+    "status",
+    "ty",
+    "name",
+    "exception_class",
+    "event.status",
+    "event.ty",
+    "event.name",
+    "event.data",
+    "heartbeat.status",
+    "heartbeat.ty",
+    "heartbeat.name",
+    "heartbeat.data",
+    "metric.status",
+    "metric.ty",
+    "metric.name",
+    "metric.data",
+];
+
+/// Whether `query` is a selector rather than an expression.
+pub fn is_selector(query: &str) -> bool {
+    query.trim_start().starts_with('{')
+}
+
+/// Translates a selector like `{ty="URL", name=~"/api/.*", status!="0"}`
+/// to the CEL expression matching the same trees.
+///
+/// As in PromQL, `=` and `!=` compare a label to a string, `=~` and `!~`
+/// match it against a regex covering the whole value, and the matchers
+/// must all hold. `{}` matches every tree.
+pub fn to_cel(selector: &str) -> Fallible<String> {
+    let inner = match selector
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+    {
+        Some(inner) => inner,
+        None => bail!("selector {} isn't enclosed in {{ and }}", selector),
+    };
+    let mut conditions = vec![];
+    let mut rest = inner.trim_start();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+        let (label, after) = rest.split_at(end);
+        if !LABELS.contains(&label) {
+            if filter::VARIABLES.iter().any(|(name, _)| *name == label) {
+                bail!("`{}` isn't a string label, compare it in a query", label);
+            }
+            match query::closest(label, LABELS.iter().copied()) {
+                Some(known) if !label.is_empty() => {
+                    bail!("unknown label `{}`, did you mean `{}`?", label, known)
+                }
+                _ => bail!("expected a label in {} instead of {:?}", selector, rest),
+            }
+        }
+        let after = after.trim_start();
+        let op = ["=~", "!~", "!=", "="]
+            .iter()
+            .find(|op| after.starts_with(**op))
+            .copied();
+        let op = match op {
+            Some(op) => op,
+            None => bail!("expected =, !=, =~ or !~ after label `{}`", label),
+        };
+        let (value, after) = string(after[op.len()..].trim_start())?;
+        // Regexes cover whole values.
+        let regex = || cel::quote(&format!("^(?:{})$", value));
+        // As in PromQL, a label that isn't set, like `metric.name` of an
+        // event, is empty.
+        let label = if label.contains('.') {
+            format!("(has({0}) ? {0} : \"\")", label)
+        } else {
+            label.to_string()
+        };
+        conditions.push(match op {
+            "=" => format!("{} == {}", label, cel::quote(&value)),
+            "!=" => format!("{} != {}", label, cel::quote(&value)),
+            "=~" => format!("{}.matches({})", label, regex()),
+            _ => format!("!{}.matches({})", label, regex()),
+        });
+
+        rest = after.trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after.trim_start(),
+            None if rest.is_empty() => {}
+            None => bail!("expected , between matchers instead of {:?}", rest),
+        }
+    }
+    if conditions.is_empty() {
+        return Ok("true".to_string());
+    }
+    Ok(conditions.join(" && "))
+}
+
+/// The value of the quoted string `s` starts with, and what follows it.
+fn string(s: &str) -> Fallible<(String, &str)> {
+    let quote = match s.chars().next() {
+        Some(quote @ ('"' | '\'' | '`')) => quote,
+        _ => bail!("expected a quoted value instead of {:?}", s),
+    };
+    let mut value = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            // Backquoted strings are raw.
+            '\\' if quote != '`' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c if c == quote => return Ok((value, &s[i + 1..])),
+            c => value.push(c),
+        }
+    }
+    bail!("unclosed string {}", s)
+}
@@ -0,0 +1,199 @@
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::highlight::{Color, RESET};
+use crate::human;
+use crate::message_tree::Message;
+
+/// Width of the `ty:name` column of the timeline.
+const LABEL_WIDTH: usize = 40;
+
+/// The `ty:name` of `message`, followed by `×N` for a run of `N` collapsed
+/// events.
+fn label(message: &Message, depth: usize, concurrent: bool) -> String {
+    let mut label = format!(
+        "{}{}{}:{}",
+        "  ".repeat(depth),
+        if concurrent { "‖ " } else { "" },
+        message.ty(),
+        message.name()
+    );
+    if let Some(repeats) = message.repeats() {
+        label.push_str(&format!(" ×{}", repeats));
+    }
+    label
+}
+
+/// Indexes of the children of `message` overlapping a sibling.
+fn concurrent_children(message: &Message) -> Vec<usize> {
+    match message {
+        Message::Transaction(t) => t
+            .overlapping_children()
+            .into_iter()
+            .flat_map(|(a, b)| vec![a, b])
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Prints `message` and its descendants, one per line, indented by depth.
+/// Children running concurrently with a sibling are marked with `‖`.
+pub fn print_tree(message: &Message, out: &mut dyn Write) -> Fallible<()> {
+    print_node(message, 0, false, out)
+}
+
+fn print_node(
+    message: &Message,
+    depth: usize,
+    concurrent: bool,
+    out: &mut dyn Write,
+) -> Fallible<()> {
+    let label = label(message, depth, concurrent);
+    match message.duration_in_ms() {
+        Some(duration) => writeln!(out, "{} [{}] {}ms", label, message.status(), duration)?,
+        None => writeln!(out, "{} [{}]", label, message.status())?,
+    }
+    if let Message::Transaction(t) = message {
+        let concurrent = concurrent_children(message);
+        for (i, child) in t.children.iter().enumerate() {
+            print_node(child, depth + 1, concurrent.contains(&i), out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Width of the longest duration bar of `print_pretty`.
+const BAR_WIDTH: usize = 20;
+
+/// Prints `message` and its descendants like `print_tree`, with their
+/// durations as bars scaled to the duration of `message`, and with
+/// `colors` the messages with a status other than `0` in red.
+pub fn print_pretty(message: &Message, colors: bool, out: &mut dyn Write) -> Fallible<()> {
+    let span = message.duration_in_ms().unwrap_or(0).max(1);
+    print_pretty_node(message, span, 0, false, colors, out)
+}
+
+fn print_pretty_node(
+    message: &Message,
+    span: u64,
+    depth: usize,
+    concurrent: bool,
+    colors: bool,
+    out: &mut dyn Write,
+) -> Fallible<()> {
+    let mut label = label(message, depth, concurrent);
+    if label.chars().count() > LABEL_WIDTH {
+        label = label.chars().take(LABEL_WIDTH - 1).collect::<String>() + "…";
+    }
+    let (start, end) = match (colors, message.status().as_str()) {
+        (false, _) | (true, "0") => ("", ""),
+        (true, _) => (Color::Red.escape(), RESET),
+    };
+    write!(
+        out,
+        "{}{:<width$} {:>8}{}",
+        start,
+        label,
+        message.status(),
+        end,
+        width = LABEL_WIDTH
+    )?;
+    match message.duration_in_ms() {
+        Some(duration) => {
+            // Rounded up, so that any duration shows.
+            let bar = (duration * BAR_WIDTH as u64).div_ceil(span) as usize;
+            let (start, end) = match colors {
+                true => (Color::Cyan.escape(), RESET),
+                false => ("", ""),
+            };
+            writeln!(
+                out,
+                " {:>8} {}{}{}",
+                human::duration_ms(duration as f64),
+                start,
+                "█".repeat(bar.min(BAR_WIDTH)),
+                end
+            )?;
+        }
+        None => writeln!(out)?,
+    }
+    if let Message::Transaction(t) = message {
+        let concurrent = concurrent_children(message);
+        for (i, child) in t.children.iter().enumerate() {
+            print_pretty_node(child, span, depth + 1, concurrent.contains(&i), colors, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders `message` and its descendants as a Gantt chart `width` columns
+/// wide, scaled to the duration of `message`.
+///
+/// Transactions are drawn as bars, other messages as a `•` at their
+/// timestamp. Children running concurrently with a sibling are marked with
+/// `‖`.
+pub fn print_timeline(message: &Message, width: usize, out: &mut dyn Write) -> Fallible<()> {
+    let start = message.timestamp_in_ms();
+    let span = message.duration_in_ms().unwrap_or(0).max(1);
+    let timeline = Timeline { start, span, width };
+    timeline.print(message, 0, false, out)
+}
+
+struct Timeline {
+    start: u64,
+    span: u64,
+    width: usize,
+}
+
+impl Timeline {
+    fn column(&self, offset_in_ms: u64) -> usize {
+        ((offset_in_ms * self.width as u64) / self.span).min(self.width as u64) as usize
+    }
+
+    fn print(
+        &self,
+        message: &Message,
+        depth: usize,
+        concurrent: bool,
+        out: &mut dyn Write,
+    ) -> Fallible<()> {
+        let offset = message.timestamp_in_ms().saturating_sub(self.start);
+        let begin = self.column(offset).min(self.width.saturating_sub(1));
+        let mut bar = vec![' '; self.width];
+        let timing = match message.duration_in_ms() {
+            Some(duration) => {
+                let end = self.column(offset + duration).max(begin + 1);
+                for c in &mut bar[begin..end.min(self.width)] {
+                    *c = '█';
+                }
+                format!("+{}ms {}ms", offset, duration)
+            }
+            None => {
+                bar[begin] = '•';
+                format!("+{}ms", offset)
+            }
+        };
+
+        let mut label = label(message, depth, concurrent);
+        if label.chars().count() > LABEL_WIDTH {
+            label = label.chars().take(LABEL_WIDTH - 1).collect::<String>() + "…";
+        }
+        writeln!(
+            out,
+            "{:<width$} |{}| {}",
+            label,
+            bar.into_iter().collect::<String>(),
+            timing,
+            width = LABEL_WIDTH
+        )?;
+
+        if let Message::Transaction(t) = message {
+            let concurrent = concurrent_children(message);
+            for (i, child) in t.children.iter().enumerate() {
+                self.print(child, depth + 1, concurrent.contains(&i), out)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use failure::{bail, Fallible};
+
+use crate::message_tree::{InnerEvent, InnerTransaction, Message, MessageTree, Text};
+use crate::message_tree_dumper::MessageTreeDumper;
+
+const MAGIC: &[u8] = b"DCCOLS1";
+
+/// Marks messages without a duration in the duration column.
+const NO_DURATION: u64 = u64::MAX;
+
+/// Query variables and fields that can be answered from the sidecar alone.
+pub const COLUMNS: &[&str] = &[
+    "timestamp_in_ms",
+    "ty",
+    "name",
+    "status",
+    "status_class",
+    "transaction.duration_in_ms",
+];
+
+/// Path of the sidecar of the logview at `path`.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".cols");
+    PathBuf::from(name)
+}
+
+/// Size and modification time of `path`, to tell whether a sidecar is stale.
+pub fn source_version(path: &Path) -> Fallible<(u64, u128)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    Ok((metadata.len(), modified))
+}
+
+/// Strings of a column stored once, rows refer to them by index.
+#[derive(Default)]
+struct Dictionary {
+    values: Vec<Text>,
+    codes: HashMap<Text, u32>,
+}
+
+impl Dictionary {
+    fn code(&mut self, value: &Text) -> u32 {
+        if let Some(&code) = self.codes.get(value) {
+            return code;
+        }
+        let code = self.values.len() as u32;
+        self.values.push(value.clone());
+        self.codes.insert(value.clone(), code);
+        code
+    }
+}
+
+/// One row per message of a logview: the columns most aggregations need,
+/// without the data blobs or the tree headers.
+#[derive(Default)]
+pub struct Sidecar {
+    /// Index of the tree of the message, in decoding order.
+    tree: Vec<u32>,
+    /// 0 for the root message of a tree.
+    depth: Vec<u16>,
+    timestamp_in_ms: Vec<u64>,
+    duration_in_ms: Vec<u64>,
+    ty: Vec<u32>,
+    name: Vec<u32>,
+    status: Vec<u32>,
+    dictionary: Vec<Text>,
+}
+
+impl Sidecar {
+    /// Decodes the whole logview and writes its sidecar next to it.
+    pub fn build(path: &Path, dumper: MessageTreeDumper) -> Fallible<PathBuf> {
+        let version = source_version(path)?;
+        let mut sidecar = Sidecar::default();
+        let mut dictionary = Dictionary::default();
+        for (i, tree) in dumper.into_iter().enumerate() {
+            sidecar.push(i as u32, 0, &tree.message, &mut dictionary);
+        }
+        sidecar.dictionary = dictionary.values;
+
+        let out = sidecar_path(path);
+        let tmp = out.with_extension("cols.tmp");
+        sidecar.write(version, &mut BufWriter::new(File::create(&tmp)?))?;
+        fs::rename(&tmp, &out)?;
+        Ok(out)
+    }
+
+    fn push(&mut self, tree: u32, depth: u16, message: &Message, dictionary: &mut Dictionary) {
+        self.tree.push(tree);
+        self.depth.push(depth);
+        self.timestamp_in_ms.push(message.timestamp_in_ms());
+        self.duration_in_ms
+            .push(message.duration_in_ms().unwrap_or(NO_DURATION));
+        self.ty.push(dictionary.code(message.ty()));
+        self.name.push(dictionary.code(message.name()));
+        self.status.push(dictionary.code(message.status()));
+        if let Message::Transaction(t) = message {
+            for child in &t.children {
+                self.push(tree, depth + 1, child, dictionary);
+            }
+        }
+    }
+
+    fn write(&self, version: (u64, u128), out: &mut impl Write) -> Fallible<()> {
+        out.write_all(MAGIC)?;
+        out.write_u64::<BigEndian>(version.0)?;
+        out.write_u128::<BigEndian>(version.1)?;
+        out.write_u32::<BigEndian>(self.dictionary.len() as u32)?;
+        for value in &self.dictionary {
+            out.write_u32::<BigEndian>(value.len() as u32)?;
+            out.write_all(value.as_bytes())?;
+        }
+        out.write_u64::<BigEndian>(self.tree.len() as u64)?;
+        for &v in &self.tree {
+            out.write_u32::<BigEndian>(v)?;
+        }
+        for &v in &self.depth {
+            out.write_u16::<BigEndian>(v)?;
+        }
+        for column in &[&self.timestamp_in_ms, &self.duration_in_ms] {
+            for &v in column.iter() {
+                out.write_u64::<BigEndian>(v)?;
+            }
+        }
+        for column in &[&self.ty, &self.name, &self.status] {
+            for &v in column.iter() {
+                out.write_u32::<BigEndian>(v)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Whether the sidecar of `path` exists and was built from its current
+    /// content.
+    pub fn is_fresh(path: &Path) -> Fallible<bool> {
+        let mut reader = match File::open(sidecar_path(path)) {
+            Ok(file) => BufReader::new(file),
+            Err(_) => return Ok(false),
+        };
+        Ok(read_version(&mut reader)? == source_version(path)?)
+    }
+
+    /// Marks the sidecar of `path` as built from its current content.
+    pub fn restamp(path: &Path) -> Fallible<()> {
+        let (len, modified) = source_version(path)?;
+        let mut file = OpenOptions::new().write(true).open(sidecar_path(path))?;
+        file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        file.write_u64::<BigEndian>(len)?;
+        file.write_u128::<BigEndian>(modified)?;
+        Ok(())
+    }
+
+    /// The sidecar of `path`, `None` when missing or older than `path`.
+    pub fn load_fresh(path: &Path) -> Fallible<Option<Sidecar>> {
+        let file = match File::open(sidecar_path(path)) {
+            Ok(file) => file,
+            Err(_) => return Ok(None),
+        };
+        let mut reader = BufReader::new(file);
+        if read_version(&mut reader)? != source_version(path)? {
+            return Ok(None);
+        }
+
+        let mut sidecar = Sidecar::default();
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let mut value = vec![0; reader.read_u32::<BigEndian>()? as usize];
+            reader.read_exact(&mut value)?;
+            sidecar.dictionary.push(String::from_utf8(value)?);
+        }
+        let rows = reader.read_u64::<BigEndian>()? as usize;
+        sidecar.tree = read_column(&mut reader, rows, |r| r.read_u32::<BigEndian>())?;
+        sidecar.depth = read_column(&mut reader, rows, |r| r.read_u16::<BigEndian>())?;
+        sidecar.timestamp_in_ms = read_column(&mut reader, rows, |r| r.read_u64::<BigEndian>())?;
+        sidecar.duration_in_ms = read_column(&mut reader, rows, |r| r.read_u64::<BigEndian>())?;
+        sidecar.ty = read_column(&mut reader, rows, |r| r.read_u32::<BigEndian>())?;
+        sidecar.name = read_column(&mut reader, rows, |r| r.read_u32::<BigEndian>())?;
+        sidecar.status = read_column(&mut reader, rows, |r| r.read_u32::<BigEndian>())?;
+        Ok(Some(sidecar))
+    }
+
+    /// A tree per root message holding only the sidecar columns, enough to
+    /// evaluate queries and aggregations on [`COLUMNS`].
+    pub fn root_trees(&self) -> impl Iterator<Item = MessageTree> + '_ {
+        (0..self.tree.len())
+            .filter(move |&row| self.depth[row] == 0)
+            .map(move |row| {
+                let text = |code: u32| self.dictionary[code as usize].clone();
+                let (ty, name, status) = (
+                    text(self.ty[row]),
+                    text(self.name[row]),
+                    text(self.status[row]),
+                );
+                let timestamp_in_ms = self.timestamp_in_ms[row];
+                let message = match self.duration_in_ms[row] {
+                    NO_DURATION => Message::Event(Arc::new(InnerEvent {
+                        status,
+                        ty,
+                        name,
+                        timestamp_in_ms,
+                        data: Text::new(),
+                        repeats: None,
+                    })),
+                    duration_in_ms => Message::Transaction(Arc::new(InnerTransaction {
+                        status,
+                        ty,
+                        name,
+                        timestamp_in_ms,
+                        data: Text::new(),
+                        duration_in_ms,
+                        children: vec![],
+                        summary: None,
+                    })),
+                };
+                MessageTree {
+                    message,
+                    ..MessageTree::default()
+                }
+            })
+    }
+}
+
+fn read_version(reader: &mut impl Read) -> Fallible<(u64, u128)> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        bail!("invalid sidecar");
+    }
+    Ok((
+        reader.read_u64::<BigEndian>()?,
+        reader.read_u128::<BigEndian>()?,
+    ))
+}
+
+fn read_column<R: Read, T>(
+    reader: &mut R,
+    rows: usize,
+    read: impl Fn(&mut R) -> std::io::Result<T>,
+) -> Fallible<Vec<T>> {
+    let mut column = Vec::with_capacity(rows);
+    for _ in 0..rows {
+        column.push(read(reader)?);
+    }
+    Ok(column)
+}
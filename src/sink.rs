@@ -0,0 +1,346 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use failure::Fallible;
+use serde_json::json;
+
+use crate::amqp::AmqpPublisher;
+use crate::clickhouse::ClickHouse;
+use crate::csv::Csv;
+use crate::elasticsearch::Elasticsearch;
+use crate::highlight::{self, Color};
+use crate::influx;
+use crate::message_tree::{Message, MessageTree, Text};
+use crate::msgpack;
+use crate::otlp::OtlpExporter;
+use crate::output::{self, FullTree, Output, Provenance};
+use crate::proto;
+use crate::remote_call::RemoteCallIndex;
+use crate::show;
+use crate::syslog::Syslog;
+use crate::table::Table;
+use crate::template::Template;
+use crate::webhook::Webhook;
+
+/// Where matched trees go, printed or sent downstream.
+///
+/// Every filter thread owns its own sink, so `write_tree` never needs to
+/// synchronize; the built-in sinks share their connection or output.
+pub trait Sink: Send {
+    /// Writes `tree`, whose message may have been rewritten, e.g. with its
+    /// payloads decoded.
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()>;
+
+    /// Writes `tree`, matched by a `--highlight` rule of `color`. Sinks
+    /// without colors write it like any other.
+    fn write_highlighted(&mut self, tree: &MessageTree, _color: Color) -> Fallible<()> {
+        self.write_tree(tree)
+    }
+
+    /// Writes what is buffered. More trees may be written afterwards.
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        (**self).write_tree(tree)
+    }
+
+    fn write_highlighted(&mut self, tree: &MessageTree, color: Color) -> Fallible<()> {
+        (**self).write_highlighted(tree, color)
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        (**self).flush()
+    }
+}
+
+/// Prints the message of every tree, or the line of `template` when set,
+/// followed by its remote calls when `remote_calls` is set.
+pub struct TextSink {
+    pub out: Output,
+    pub remote_calls: Option<Arc<RemoteCallIndex>>,
+    pub template: Option<Arc<Template>>,
+}
+
+impl TextSink {
+    /// Writes `tree` between `start` and `end`, e.g. color escapes.
+    fn write_between(&mut self, tree: &MessageTree, start: &str, end: &str) -> Fallible<()> {
+        let line = match &self.template {
+            Some(template) => template.render(tree)?,
+            None => tree.message.to_string(),
+        };
+        let mut out = self.out.lock();
+        writeln!(out, "{}{}{}", start, line, end)?;
+        let resolved = self.remote_calls.as_ref().map(|index| index.resolve(tree));
+        for (id, message) in resolved.unwrap_or_default() {
+            match message {
+                Some(m) => writeln!(out, "{}    -> RemoteCall {}: {}{}", start, id, m, end)?,
+                None => writeln!(out, "{}    -> RemoteCall {}: not found{}", start, id, end)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for TextSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.write_between(tree, "", "")
+    }
+
+    fn write_highlighted(&mut self, tree: &MessageTree, color: Color) -> Fallible<()> {
+        self.write_between(tree, color.escape(), highlight::RESET)
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// Prints trees as indented trees of their messages, with `colors` in
+/// ANSI colors, separated by blank lines.
+pub struct PrettySink {
+    pub out: Output,
+    pub colors: bool,
+}
+
+impl Sink for PrettySink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let mut out = self.out.lock();
+        show::print_pretty(&tree.message, self.colors, &mut *out)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// Prints one JSON object per tree: its message, or with `full_tree` the
+/// header of the tree too. The remote calls are added to the object when
+/// `remote_calls` is set, and the provenance of the tree with `provenance`.
+pub struct JsonSink {
+    pub out: Output,
+    pub remote_calls: Option<Arc<RemoteCallIndex>>,
+    pub full_tree: bool,
+    pub provenance: bool,
+}
+
+impl Sink for JsonSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        let resolved = self.remote_calls.as_ref().map(|index| index.resolve(tree));
+        let provenance = self.provenance.then(|| Provenance::new(tree));
+        let line = match (self.full_tree, resolved, provenance) {
+            (true, None, None) => output::full_tree_json(tree, &tree.message).to_string(),
+            // Keeps the fields in the order of the message.
+            (false, None, None) => serde_json::to_string(&tree.message)?,
+            (full_tree, resolved, provenance) => {
+                with_extras(tree, full_tree, resolved, provenance).to_string()
+            }
+        };
+        writeln!(self.out.lock(), "{}", line)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// The object of `tree`, its message or with `full_tree` the header of the
+/// tree too, with its remote calls and provenance when given.
+fn with_extras(
+    tree: &MessageTree,
+    full_tree: bool,
+    resolved: Option<Vec<(&Text, Option<&Message>)>>,
+    provenance: Option<Provenance>,
+) -> serde_json::Value {
+    let mut object = match full_tree {
+        true => output::full_tree_json(tree, &tree.message),
+        false => json!({ "message": tree.message }),
+    };
+    if let Some(resolved) = resolved {
+        object["remote_calls"] = remote_calls_json(resolved);
+    }
+    if let Some(provenance) = provenance {
+        object["provenance"] = json!(provenance);
+    }
+    object
+}
+
+/// The remote calls of a tree, with a null message for the ones not in the
+/// input.
+fn remote_calls_json(resolved: Vec<(&Text, Option<&Message>)>) -> serde_json::Value {
+    resolved
+        .into_iter()
+        .map(|(id, message)| json!({"message_id": id, "message": message}))
+        .collect()
+}
+
+/// Writes one MessagePack object per tree, with the same fields as
+/// `JsonSink`, but cheaper to encode and smaller.
+pub struct MsgpackSink {
+    out: Output,
+    remote_calls: Option<Arc<RemoteCallIndex>>,
+    full_tree: bool,
+    provenance: bool,
+    buf: Vec<u8>,
+}
+
+impl MsgpackSink {
+    pub fn new(
+        out: Output,
+        remote_calls: Option<Arc<RemoteCallIndex>>,
+        full_tree: bool,
+        provenance: bool,
+    ) -> Self {
+        MsgpackSink {
+            out,
+            remote_calls,
+            full_tree,
+            provenance,
+            buf: vec![],
+        }
+    }
+}
+
+impl Sink for MsgpackSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.buf.clear();
+        let resolved = self.remote_calls.as_ref().map(|index| index.resolve(tree));
+        let provenance = self.provenance.then(|| Provenance::new(tree));
+        match (self.full_tree, resolved, provenance) {
+            (true, None, None) => {
+                msgpack::to_vec(&mut self.buf, &FullTree::new(tree, &tree.message))?
+            }
+            (false, None, None) => msgpack::to_vec(&mut self.buf, &tree.message)?,
+            // Rare enough to build the JSON object first.
+            (full_tree, resolved, provenance) => msgpack::to_vec(
+                &mut self.buf,
+                &with_extras(tree, full_tree, resolved, provenance),
+            )?,
+        }
+        self.out.lock().write_all(&self.buf)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// Prints every tree as a CSV row, see `Csv`.
+pub struct CsvSink {
+    pub out: Output,
+    pub csv: Arc<Csv>,
+}
+
+impl Sink for CsvSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.csv.write(tree, &tree.message, &mut *self.out.lock())
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// Prints every tree as a row of an aligned table, see `Table`.
+pub struct TableSink {
+    pub out: Output,
+    pub table: Arc<Table>,
+}
+
+impl Sink for TableSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.table.write(tree, "", "", &mut *self.out.lock())
+    }
+
+    fn write_highlighted(&mut self, tree: &MessageTree, color: Color) -> Fallible<()> {
+        let mut out = self.out.lock();
+        self.table
+            .write(tree, color.escape(), highlight::RESET, &mut *out)
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// Prints the metrics and heartbeats of every tree as InfluxDB line
+/// protocol, see `influx::write_points`.
+pub struct InfluxSink {
+    pub out: Output,
+}
+
+impl Sink for InfluxSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        influx::write_points(tree, &tree.message, &mut *self.out.lock())
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+/// Writes every tree as a length-delimited protobuf message, see
+/// `proto::write_delimited`.
+pub struct ProtoSink {
+    out: Output,
+    buf: Vec<u8>,
+}
+
+impl ProtoSink {
+    pub fn new(out: Output) -> Self {
+        ProtoSink { out, buf: vec![] }
+    }
+}
+
+impl Sink for ProtoSink {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        proto::write_delimited(tree, &tree.message, &mut self.buf, &mut *self.out.lock())
+    }
+
+    fn flush(&mut self) -> Fallible<()> {
+        Ok(self.out.lock().flush()?)
+    }
+}
+
+impl Sink for Arc<Syslog> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.send(tree, &tree.message)
+    }
+}
+
+impl Sink for Arc<AmqpPublisher> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.publish(tree, &tree.message)
+    }
+}
+
+impl Sink for Arc<Webhook> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.send(tree, &tree.message)
+    }
+}
+
+impl Sink for Arc<OtlpExporter> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.send(tree, &tree.message)
+    }
+}
+
+impl Sink for Arc<ClickHouse> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.send(tree, &tree.message)
+    }
+}
+
+impl Sink for Arc<Elasticsearch> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.send(tree, &tree.message)
+    }
+}
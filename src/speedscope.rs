@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use failure::{bail, Fallible};
+use serde_json::{json, Value};
+
+use crate::message_tree::{InnerTransaction, Message, MessageTree};
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+/// Writes the transactions of `tree` as an evented profile for
+/// https://www.speedscope.app, in milliseconds from the start of the root.
+///
+/// A frame is opened and closed per transaction, its frame being its
+/// `ty:name`. Events must nest, so a child is cut to end with its parent,
+/// and a child running concurrently with the sibling before it is cut to
+/// start once that sibling ends, or left out when it ends before.
+pub fn write_profile(tree: &MessageTree, out: &mut dyn Write) -> Fallible<()> {
+    let root = match &tree.message {
+        Message::Transaction(t) => t,
+        _ => bail!("the root of {} isn't a transaction", tree.message_id),
+    };
+    let mut profile = Profile {
+        start_in_ms: root.timestamp_in_ms,
+        frames: vec![],
+        indexes: HashMap::new(),
+        events: vec![],
+    };
+    profile.add(root, root.timestamp_in_ms, end_in_ms(root));
+
+    let name = format!("{}:{} {}", root.ty, root.name, tree.message_id);
+    let frames: Vec<_> = profile
+        .frames
+        .iter()
+        .map(|frame| json!({ "name": frame }))
+        .collect();
+    let document = json!({
+        "$schema": SCHEMA,
+        "name": name,
+        "exporter": format!("dump-cat {}", env!("CARGO_PKG_VERSION")),
+        "activeProfileIndex": 0,
+        "shared": { "frames": frames },
+        "profiles": [{
+            "type": "evented",
+            "name": name,
+            "unit": "milliseconds",
+            "startValue": 0,
+            "endValue": root.duration_in_ms,
+            "events": profile.events,
+        }],
+    });
+    writeln!(out, "{}", document)?;
+    Ok(())
+}
+
+struct Profile {
+    start_in_ms: u64,
+    /// Names of the frames, and their indexes by name.
+    frames: Vec<String>,
+    indexes: HashMap<String, usize>,
+    events: Vec<Value>,
+}
+
+impl Profile {
+    /// Adds the events of `t`, running from `start` to `end`, and of the
+    /// transactions under it.
+    fn add(&mut self, t: &InnerTransaction, start: u64, end: u64) {
+        let name = format!("{}:{}", t.ty, t.name);
+        let next = self.frames.len();
+        let frame = *self.indexes.entry(name.clone()).or_insert(next);
+        if frame == next {
+            self.frames.push(name);
+        }
+        self.events.push(json!({
+            "type": "O", "frame": frame, "at": start - self.start_in_ms,
+        }));
+
+        let mut children: Vec<_> = t
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Message::Transaction(t) => Some(t),
+                _ => None,
+            })
+            .collect();
+        children.sort_by_key(|child| child.timestamp_in_ms);
+        let mut cursor = start;
+        for child in children {
+            let child_start = child.timestamp_in_ms.clamp(cursor, end);
+            let child_end = end_in_ms(child).min(end);
+            if child_end < child_start {
+                continue;
+            }
+            self.add(child, child_start, child_end);
+            cursor = child_end;
+        }
+
+        self.events.push(json!({
+            "type": "C", "frame": frame, "at": end - self.start_in_ms,
+        }));
+    }
+}
+
+fn end_in_ms(t: &InnerTransaction) -> u64 {
+    t.timestamp_in_ms + t.duration_in_ms
+}
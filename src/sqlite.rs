@@ -0,0 +1,151 @@
+use std::path::Path;
+
+use failure::Fallible;
+use rusqlite::{params, Connection, Transaction};
+
+use crate::filter::Filter;
+use crate::message_tree::{Message, MessageTree};
+
+/// Trees inserted per transaction.
+const BATCH_SIZE: u64 = 10_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS trees (
+    id INTEGER PRIMARY KEY,
+    message_id TEXT NOT NULL,
+    parent_message_id TEXT NOT NULL,
+    root_message_id TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    hostname TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    thread_group_name TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    thread_name TEXT NOT NULL,
+    root INTEGER REFERENCES messages (id)
+);
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    tree INTEGER NOT NULL REFERENCES trees (id),
+    kind TEXT NOT NULL,
+    ty TEXT NOT NULL,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL,
+    timestamp_in_ms INTEGER NOT NULL,
+    duration_in_ms INTEGER,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS children (
+    parent INTEGER NOT NULL REFERENCES messages (id),
+    child INTEGER NOT NULL REFERENCES messages (id),
+    position INTEGER NOT NULL,
+    PRIMARY KEY (parent, position)
+);
+";
+
+/// Created once the rows are inserted, as inserting into indexed tables is
+/// slower.
+const INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS trees_message_id ON trees (message_id);
+CREATE INDEX IF NOT EXISTS trees_root_message_id ON trees (root_message_id);
+CREATE INDEX IF NOT EXISTS trees_domain ON trees (domain);
+CREATE INDEX IF NOT EXISTS messages_tree ON messages (tree);
+CREATE INDEX IF NOT EXISTS messages_ty_name ON messages (ty, name);
+CREATE INDEX IF NOT EXISTS children_child ON children (child);
+";
+
+/// Inserts the trees matching `filter` into the SQLite database at `path`,
+/// created if needed, returning how many.
+///
+/// A tree is a row of `trees` pointing at the row of its root message in
+/// `messages`, which holds the messages of every kind, and `children` links
+/// each transaction to its children in order. Exports to the same database
+/// add to it, so several logviews can be queried together.
+pub fn export(
+    trees: impl IntoIterator<Item = MessageTree>,
+    filter: &Filter,
+    path: &Path,
+) -> Fallible<u64> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    let mut exported = 0;
+    let mut batch = connection.transaction()?;
+    for tree in trees {
+        if !filter.matches(&tree)? {
+            continue;
+        }
+        insert_tree(&batch, &tree)?;
+        exported += 1;
+        if exported % BATCH_SIZE == 0 {
+            batch.commit()?;
+            batch = connection.transaction()?;
+        }
+    }
+    batch.commit()?;
+    connection.execute_batch(INDEXES)?;
+    Ok(exported)
+}
+
+fn insert_tree(batch: &Transaction, tree: &MessageTree) -> Fallible<()> {
+    batch
+        .prepare_cached(
+            "INSERT INTO trees (message_id, parent_message_id, root_message_id, domain, \
+             hostname, ip_address, thread_group_name, thread_id, thread_name) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?
+        .execute(params![
+            tree.message_id,
+            tree.parent_message_id,
+            tree.root_message_id,
+            tree.domain,
+            tree.hostname,
+            tree.ip_address,
+            tree.thread_group_name,
+            tree.thread_id,
+            tree.thread_name,
+        ])?;
+    let id = batch.last_insert_rowid();
+    let root = insert_message(batch, id, &tree.message)?;
+    batch
+        .prepare_cached("UPDATE trees SET root = ?1 WHERE id = ?2")?
+        .execute(params![root, id])?;
+    Ok(())
+}
+
+/// Inserts `message` of the tree `tree` and the messages under it,
+/// returning its id.
+fn insert_message(batch: &Transaction, tree: i64, message: &Message) -> Fallible<i64> {
+    let kind = match message {
+        Message::Transaction(_) => "transaction",
+        Message::Event(_) => "event",
+        Message::Heartbeat(_) => "heartbeat",
+        Message::Metric(_) => "metric",
+        Message::Trace(_) => "trace",
+    };
+    batch
+        .prepare_cached(
+            "INSERT INTO messages (tree, kind, ty, name, status, timestamp_in_ms, \
+             duration_in_ms, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?
+        .execute(params![
+            tree,
+            kind,
+            message.ty(),
+            message.name(),
+            message.status(),
+            message.timestamp_in_ms() as i64,
+            message.duration_in_ms().map(|d| d as i64),
+            message.data(),
+        ])?;
+    let id = batch.last_insert_rowid();
+    if let Message::Transaction(t) = message {
+        for (position, child) in t.children.iter().enumerate() {
+            let child = insert_message(batch, tree, child)?;
+            batch
+                .prepare_cached(
+                    "INSERT INTO children (parent, child, position) VALUES (?1, ?2, ?3)",
+                )?
+                .execute(params![id, child, position as i64])?;
+        }
+    }
+    Ok(id)
+}
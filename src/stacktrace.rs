@@ -0,0 +1,97 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::message_tree::{MessageTree, Text};
+
+/// Number of frames kept for fingerprinting and display.
+pub const TOP_FRAMES: usize = 5;
+
+/// A Java stack trace embedded in the `data` of an error event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackTrace {
+    pub exception_class: Text,
+    pub message: Text,
+    pub frames: Vec<Text>,
+}
+
+impl StackTrace {
+    /// Parses text like:
+    ///
+    /// ```text
+    /// java.lang.IllegalStateException: boom
+    ///     at com.foo.Service.run(Service.java:10)
+    ///     at com.foo.Controller.handle(Controller.java:22)
+    /// Caused by: ...
+    /// ```
+    ///
+    /// Only the outermost exception is kept. Returns `None` when `data` does
+    /// not look like a stack trace.
+    pub fn parse(data: &str) -> Option<StackTrace> {
+        let mut lines = data.lines().map(str::trim).skip_while(|l| l.is_empty());
+        let first = lines.next()?;
+        let (exception_class, message) = match first.find(':') {
+            Some(i) => (&first[..i], first[i + 1..].trim()),
+            None => (first, ""),
+        };
+        if !is_class_name(exception_class) {
+            return None;
+        }
+
+        let mut frames = vec![];
+        for line in lines {
+            if line.starts_with("Caused by:") {
+                break;
+            }
+            if let Some(frame) = line.strip_prefix("at ") {
+                frames.push(frame.trim().to_string());
+            }
+        }
+        if frames.is_empty() {
+            return None;
+        }
+
+        Some(StackTrace {
+            exception_class: exception_class.to_string(),
+            message: message.to_string(),
+            frames,
+        })
+    }
+
+    pub fn top_frames(&self) -> &[Text] {
+        &self.frames[..self.frames.len().min(TOP_FRAMES)]
+    }
+
+    /// Hash of the exception class and the top frames without line numbers,
+    /// so the same failure from different builds groups together.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.exception_class.hash(&mut hasher);
+        for frame in self.top_frames() {
+            let method = match frame.find('(') {
+                Some(i) => &frame[..i],
+                None => frame,
+            };
+            method.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+fn is_class_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.contains('.')
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '.' || c == '_' || c == '$')
+}
+
+/// All stack traces found in the events of a tree, in decoding order.
+pub fn find_all(tree: &MessageTree) -> impl Iterator<Item = StackTrace> + '_ {
+    tree.events
+        .iter()
+        .filter_map(|e| StackTrace::parse(&e.data))
+}
+
+/// The first stack trace of a tree, if any.
+pub fn find(tree: &MessageTree) -> Option<StackTrace> {
+    find_all(tree).next()
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use failure::{bail, format_err, Fallible};
+use regex::Regex;
+use serde::Deserialize;
+
+pub const SUCCESS: &str = "success";
+pub const CLIENT_ERROR: &str = "client-error";
+pub const SERVER_ERROR: &str = "server-error";
+pub const TIMEOUT: &str = "timeout";
+
+const CLASSES: &[&str] = &[SUCCESS, CLIENT_ERROR, SERVER_ERROR, TIMEOUT];
+
+static STATUS_MAP: OnceLock<StatusMap> = OnceLock::new();
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusMapFile {
+    classes: serde_yaml::Mapping,
+    default: Option<String>,
+}
+
+/// Maps the raw statuses of messages to one of `CLASSES`, loaded from
+/// `--status-map`, since frameworks don't agree on what a status other
+/// than `0` means.
+///
+/// The file maps each class to patterns, regexes matching the whole
+/// status, tried in the order of the file. Statuses matching none of them
+/// are in the `default` class, `server-error` unless set:
+///
+/// ```yaml
+/// classes:
+///   success: ["0", "200", OK]
+///   timeout: [".*Timeout.*"]
+///   client-error: ["4\\d\\d", "BizException"]
+/// default: server-error
+/// ```
+pub struct StatusMap {
+    rules: Vec<(&'static str, Regex)>,
+    default: &'static str,
+}
+
+impl Default for StatusMap {
+    /// CAT's convention: `0` is a success, anything else an error, a
+    /// timeout when the status says so.
+    fn default() -> Self {
+        StatusMap {
+            rules: vec![
+                (SUCCESS, Regex::new("^0$").expect("regex")),
+                (TIMEOUT, Regex::new("(?i)time ?out").expect("regex")),
+            ],
+            default: SERVER_ERROR,
+        }
+    }
+}
+
+impl StatusMap {
+    pub fn load(path: &Path) -> Fallible<Self> {
+        let file: StatusMapFile = serde_yaml::from_reader(File::open(path)?)?;
+        let mut rules = vec![];
+        for (class, patterns) in file.classes {
+            let class = class
+                .as_str()
+                .ok_or_else(|| format_err!("invalid class {:?}", class))
+                .and_then(parse_class)?;
+            let patterns: Vec<String> = serde_yaml::from_value(patterns)
+                .map_err(|e| format_err!("patterns of {}: {}", class, e))?;
+            for pattern in patterns {
+                let regex = Regex::new(&format!("^(?:{})$", pattern))
+                    .map_err(|e| format_err!("pattern {:?} of {}: {}", pattern, class, e))?;
+                rules.push((class, regex));
+            }
+        }
+        let default = match &file.default {
+            Some(class) => parse_class(class)?,
+            None => SERVER_ERROR,
+        };
+        Ok(StatusMap { rules, default })
+    }
+
+    pub fn class(&self, status: &str) -> &'static str {
+        self.rules
+            .iter()
+            .find(|(_, regex)| regex.is_match(status))
+            .map_or(self.default, |(class, _)| class)
+    }
+}
+
+fn parse_class(class: &str) -> Fallible<&'static str> {
+    match CLASSES.iter().find(|c| **c == class) {
+        Some(c) => Ok(c),
+        None => bail!(
+            "unknown status class {}, expected one of {}",
+            class,
+            CLASSES.join(", ")
+        ),
+    }
+}
+
+/// Makes `of` classify statuses with `map` for the rest of the process.
+pub fn install(map: StatusMap) {
+    let _ = STATUS_MAP.set(map);
+}
+
+/// The class of `status`, with the map of `--status-map` if any.
+pub fn of(status: &str) -> &'static str {
+    STATUS_MAP.get_or_init(StatusMap::default).class(status)
+}
+
+pub fn is_success(status: &str) -> bool {
+    of(status) == SUCCESS
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use failure::{bail, format_err, Fallible};
+
+use crate::message_tree::{InnerTransaction, Message, Text};
+
+/// Longest summary, in characters, longer ones are cut with `...`.
+const MAX_SUMMARY_LEN: usize = 120;
+
+const HTTP_METHODS: &[&str] = &[
+    "GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "TRACE", "CONNECT",
+];
+
+/// Summarizers of the `data` of transactions, keyed by transaction type,
+/// so the output shows a short `summary` instead of e.g. a whole SQL
+/// statement with its parameters.
+///
+/// A spec looks like `SQL=sql`, `URL=http` or `Cache.redis=cache-key`, the
+/// summarizer being one of [`builtin`]. Others can be added with
+/// `register`.
+#[derive(Default)]
+pub struct Summarizers {
+    summarizers: HashMap<Text, Box<dyn Summarizer>>,
+}
+
+pub trait Summarizer: Send + Sync {
+    /// The summary of `t`, or `None` to leave its data as it is.
+    fn summarize(&self, t: &InnerTransaction) -> Option<Text>;
+}
+
+/// The built-in summarizer called `name`: `sql`, `http` or `cache-key`.
+pub fn builtin(name: &str) -> Option<Box<dyn Summarizer>> {
+    Some(match name {
+        "sql" => Box::new(SqlSummarizer),
+        "http" => Box::new(HttpSummarizer),
+        "cache-key" => Box::new(CacheKeySummarizer),
+        _ => return None,
+    })
+}
+
+impl Summarizers {
+    pub fn from_specs(specs: &[String]) -> Fallible<Self> {
+        let mut summarizers = Summarizers::default();
+        for spec in specs {
+            let (ty, name) = spec
+                .split_once('=')
+                .ok_or_else(|| format_err!("invalid summarizer \"{}\", expect type=kind", spec))?;
+            let summarizer = match builtin(name) {
+                Some(summarizer) => summarizer,
+                None => bail!(
+                    "unknown summarizer \"{}\", expected sql, http or cache-key",
+                    name
+                ),
+            };
+            summarizers.register(ty, summarizer);
+        }
+        Ok(summarizers)
+    }
+
+    pub fn register(&mut self, ty: impl Into<Text>, summarizer: Box<dyn Summarizer>) {
+        self.summarizers.insert(ty.into(), summarizer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.summarizers.is_empty()
+    }
+
+    /// Returns a copy of `message` with the data of every transaction of a
+    /// summarized type, including nested ones, replaced by its summary.
+    pub fn rewrite(&self, message: &Message) -> Message {
+        let t = match message {
+            Message::Transaction(t) => t,
+            _ => return message.clone(),
+        };
+        let mut t = InnerTransaction::clone(t);
+        if let Some(summary) = self
+            .summarizers
+            .get(&t.ty)
+            .and_then(|summarizer| summarizer.summarize(&t))
+        {
+            t.summary = Some(shorten(summary));
+            t.data.clear();
+        }
+        t.children = t.children.iter().map(|c| self.rewrite(c)).collect();
+        Message::Transaction(Arc::new(t))
+    }
+}
+
+fn shorten(mut summary: Text) -> Text {
+    if let Some((cut, _)) = summary.char_indices().nth(MAX_SUMMARY_LEN) {
+        summary.truncate(cut);
+        summary.push_str("...");
+    }
+    summary
+}
+
+/// The statement of the data, or else the name, of a SQL transaction on one
+/// line, with its literals replaced by `?` and lists of them by one.
+pub struct SqlSummarizer;
+
+impl Summarizer for SqlSummarizer {
+    fn summarize(&self, t: &InnerTransaction) -> Option<Text> {
+        let sql = if t.data.trim().is_empty() {
+            &t.name
+        } else {
+            &t.data
+        };
+        let mut summary = String::with_capacity(sql.len());
+        let mut chars = sql.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' | '"' => {
+                    // A doubled quote escapes itself.
+                    while let Some(q) = chars.next() {
+                        if q == c && chars.next_if_eq(&c).is_none() {
+                            break;
+                        }
+                    }
+                    summary.push('?');
+                }
+                c if c.is_ascii_digit() && !ends_identifier(&summary) => {
+                    while chars
+                        .next_if(|d| d.is_ascii_alphanumeric() || *d == '.')
+                        .is_some()
+                    {}
+                    summary.push('?');
+                }
+                c if c.is_whitespace() => {
+                    if !summary.is_empty() && !summary.ends_with(' ') {
+                        summary.push(' ');
+                    }
+                }
+                c => summary.push(c),
+            }
+        }
+        while summary.contains("?, ?") || summary.contains("?,?") {
+            summary = summary.replace("?, ?", "?").replace("?,?", "?");
+        }
+        let summary = summary.trim();
+        (!summary.is_empty()).then(|| summary.to_string())
+    }
+}
+
+fn ends_identifier(s: &str) -> bool {
+    s.chars()
+        .last()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+/// The method and path of an HTTP transaction, and the names of its query
+/// parameters without their values.
+///
+/// The method is the first word of the data, or the name of a `URL.Method`
+/// event like `HTTP/GET`; the path the name of the transaction, or the
+/// second word of the data.
+pub struct HttpSummarizer;
+
+impl Summarizer for HttpSummarizer {
+    fn summarize(&self, t: &InnerTransaction) -> Option<Text> {
+        let mut words = t.data.split_whitespace();
+        let first = words.next().unwrap_or_default();
+        let (method, target) = if HTTP_METHODS.contains(&first.to_ascii_uppercase().as_str()) {
+            (Some(first.to_ascii_uppercase()), words.next())
+        } else {
+            let method = t.children.iter().find_map(|c| match c {
+                Message::Event(e) if e.ty.ends_with(".Method") => Some(
+                    e.name
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or_default()
+                        .to_ascii_uppercase(),
+                ),
+                _ => None,
+            });
+            (method, None)
+        };
+        let target = target.unwrap_or(&t.name);
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        // Without a query in the path, data like `a=1&b=2` is the query.
+        let query = match query {
+            "" if t.data.contains('=') && !t.data.contains(char::is_whitespace) => &t.data,
+            query => query,
+        };
+        let mut summary = match method {
+            Some(method) => format!("{} {}", method, path),
+            None => path.to_string(),
+        };
+        let names: Vec<_> = query
+            .split('&')
+            .filter_map(|pair| pair.split('=').next())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if !names.is_empty() {
+            summary.push_str(" ?");
+            summary.push_str(&names.join(","));
+        }
+        Some(summary)
+    }
+}
+
+/// The operation of a cache transaction and the shape of its key, the key
+/// being the data or else the name, with the parts of it which look like
+/// ids, numbers, uuids or long hex strings, replaced by `*`.
+pub struct CacheKeySummarizer;
+
+impl Summarizer for CacheKeySummarizer {
+    fn summarize(&self, t: &InnerTransaction) -> Option<Text> {
+        let key = match t.data.trim() {
+            "" => &t.name,
+            data => data,
+        };
+        let mut shape = String::with_capacity(key.len());
+        let mut part = String::new();
+        for c in key.chars().chain(std::iter::once(':')) {
+            if matches!(c, ':' | '/' | '.' | '_' | '|' | '#' | ',' | ' ') {
+                shape.push_str(if looks_like_id(&part) { "*" } else { &part });
+                shape.push(c);
+                part.clear();
+            } else {
+                part.push(c);
+            }
+        }
+        shape.pop();
+        if key == t.name {
+            Some(shape)
+        } else {
+            Some(format!("{} {}", t.name, shape))
+        }
+    }
+}
+
+fn looks_like_id(part: &str) -> bool {
+    let hex = part.chars().filter(|&c| c != '-').collect::<String>();
+    !part.is_empty()
+        && (part.chars().all(|c| c.is_ascii_digit())
+            || (hex.len() >= 8 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            || (part.chars().any(|c| c.is_ascii_digit())
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && part.len() >= 16))
+}
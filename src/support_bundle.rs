@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::{bail, Fallible};
+use serde::Serialize;
+
+use crate::catalog::{Catalog, Entry, Route};
+use crate::fields::Field;
+use crate::logview_writer::LogviewWriter;
+use crate::message_tree_dumper::MessageTreeDumper;
+use crate::report::errors::ErrorsReport;
+use crate::report::group_by::{Agg, GroupByReport};
+use crate::report::Report;
+
+const HOUR_IN_MS: u64 = 3_600_000;
+
+const MANIFEST: &str = "manifest.json";
+const CATALOG: &str = "catalog.json";
+const STATS: &str = "stats.tsv";
+const PROBLEMS: &str = "problems.txt";
+const SLICES: &str = "slices/";
+
+#[derive(Debug, Serialize)]
+pub struct Slice {
+    /// Name of the slice in the bundle.
+    pub name: String,
+    /// Logview of the directory the trees come from.
+    pub source: PathBuf,
+    pub trees: u64,
+}
+
+/// Describes the content of a support bundle.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub domain: String,
+    pub hour: String,
+    pub since_in_ms: u64,
+    pub until_in_ms: u64,
+    pub created_at_in_ms: u64,
+    pub version: String,
+    pub trees: u64,
+    pub slices: Vec<Slice>,
+}
+
+/// Parses `--hour` like `2024051114`, the hour from 14:00 UTC on the 11th
+/// of May 2024, into its start in ms since the epoch.
+pub fn parse_hour(s: &str) -> Fallible<u64> {
+    if s.len() != 10 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("invalid hour {}, expected yyyymmddhh like 2024051114", s);
+    }
+    let tm = time::strptime(
+        &format!("{}-{}-{} {}:00", &s[..4], &s[4..6], &s[6..8], &s[8..]),
+        "%Y-%m-%d %H:%M",
+    )?;
+    let spec = tm.to_timespec();
+    if spec.sec < 0 {
+        bail!("invalid hour {}, before 1970", s);
+    }
+    Ok(spec.sec as u64 * 1000)
+}
+
+/// Writes the trees of `domain` whose root starts in the hour of `hour`
+/// found in the catalog of `dir` into one zstd compressed tar at `out`,
+/// to attach to a support ticket.
+///
+/// The bundle holds the slices of the logviews with those trees, which
+/// dump-cat reads like any logview, the stats of the trees by type, name
+/// and status, the problems report of their stack traces, the catalog
+/// entries of the logviews read and a manifest. `dumper_for` decodes the
+/// logviews.
+pub fn export(
+    dir: &Path,
+    domain: &str,
+    hour: &str,
+    out: &Path,
+    dumper_for: impl Fn(PathBuf) -> Fallible<MessageTreeDumper>,
+) -> Fallible<Manifest> {
+    let since_in_ms = parse_hour(hour)?;
+    let route = Route {
+        domains: vec![domain.into()],
+        since_in_ms: Some(since_in_ms),
+        until_in_ms: Some(since_in_ms + HOUR_IN_MS),
+    };
+    let catalog = Catalog::load(dir)?;
+    let selected: HashSet<PathBuf> = catalog.select(dir, &route).into_iter().collect();
+    let entries: Vec<&Entry> = catalog
+        .files
+        .iter()
+        .filter(|entry| selected.contains(&dir.join(&entry.path)))
+        .collect();
+
+    let mut stats = GroupByReport::new(
+        vec![Field::Ty, Field::Name, Field::Status],
+        vec![
+            Agg::Count,
+            Agg::Avg(Field::DurationInMs),
+            Agg::Max(Field::DurationInMs),
+        ],
+    );
+    let mut problems = ErrorsReport::default();
+    // Slices are written aside first, their sizes being needed in the tar
+    // headers.
+    let tmp = tempfile::tempdir()?;
+    let mut slices = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        let path = tmp.path().join(i.to_string());
+        let mut writer = LogviewWriter::new(BufWriter::new(File::create(&path)?))?;
+        let mut trees = 0;
+        for tree in dumper_for(dir.join(&entry.path))?.into_iter() {
+            if !route.matches(&tree) {
+                continue;
+            }
+            writer.write(&tree)?;
+            stats.observe(&tree)?;
+            problems.observe(&tree)?;
+            trees += 1;
+        }
+        writer.finish()?.flush()?;
+        if trees > 0 {
+            let slice = Slice {
+                name: format!("{}{}", SLICES, entry.path.display()),
+                source: entry.path.clone(),
+                trees,
+            };
+            slices.push((path, slice));
+        }
+    }
+    if slices.is_empty() {
+        bail!(
+            "no trees of {} in hour {} in the catalog of {}",
+            domain,
+            hour,
+            dir.display()
+        );
+    }
+
+    let mut manifest = Manifest {
+        domain: domain.to_string(),
+        hour: hour.to_string(),
+        since_in_ms,
+        until_in_ms: since_in_ms + HOUR_IN_MS,
+        created_at_in_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        trees: slices.iter().map(|(_, slice)| slice.trees).sum(),
+        slices: vec![],
+    };
+
+    let encoder = zstd::Encoder::new(File::create(out)?, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+    for (path, slice) in slices {
+        builder.append_path_with_name(&path, &slice.name)?;
+        manifest.slices.push(slice);
+    }
+    let mut text = vec![];
+    stats.render(&mut text)?;
+    append(&mut builder, STATS, &text)?;
+    text.clear();
+    problems.render(&mut text)?;
+    append(&mut builder, PROBLEMS, &text)?;
+    append(&mut builder, CATALOG, &serde_json::to_vec_pretty(&entries)?)?;
+    append(
+        &mut builder,
+        MANIFEST,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    builder.into_inner()?.finish()?;
+    Ok(manifest)
+}
+
+fn append<W: Write>(builder: &mut tar::Builder<W>, name: &str, data: &[u8]) -> Fallible<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use failure::{bail, format_err, Fallible};
+
+use crate::message_tree::{Message, MessageTree};
+
+const DEFAULT_PORT: u16 = 514;
+
+/// Facility of the messages, local0.
+const FACILITY: u8 = 16;
+const SEVERITY_ERROR: u8 = 3;
+const SEVERITY_INFO: u8 = 6;
+
+/// SD-ID of the structured data, under the example enterprise number of
+/// RFC 5424.
+const SD_ID: &str = "cat@32473";
+
+/// Datagrams are cut to this size, which common receivers accept.
+const MAX_DATAGRAM_SIZE: usize = 8192;
+
+/// Sends matched trees as RFC 5424 messages over UDP, one datagram per tree.
+///
+/// The header of the tree goes to the structured data and the message, as
+/// JSON, to the MSG part. Trees whose root status isn't `0` are sent with
+/// the error severity.
+pub struct Syslog {
+    url: String,
+    socket: UdpSocket,
+}
+
+impl fmt::Debug for Syslog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Syslog({})", self.url)
+    }
+}
+
+impl Syslog {
+    /// Connects to `udp://host[:port]`.
+    pub fn connect(url: &str) -> Fallible<Self> {
+        let addr = match url.strip_prefix("udp://") {
+            Some(addr) if addr.contains(':') => addr.to_string(),
+            Some(addr) => format!("{}:{}", addr, DEFAULT_PORT),
+            None => bail!("unsupported syslog url {}, expected udp://host:port", url),
+        };
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format_err!("can't resolve {}", addr))?;
+        let socket = UdpSocket::bind(if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(target)?;
+        Ok(Syslog {
+            url: url.to_string(),
+            socket,
+        })
+    }
+
+    /// Sends `tree`, with its message replaced by `message`, e.g. after
+    /// decoding payloads.
+    pub fn send(&self, tree: &MessageTree, message: &Message) -> Fallible<()> {
+        let mut datagram = format_message(tree, message)?;
+        if datagram.len() > MAX_DATAGRAM_SIZE {
+            let mut end = MAX_DATAGRAM_SIZE;
+            while !datagram.is_char_boundary(end) {
+                end -= 1;
+            }
+            datagram.truncate(end);
+        }
+        self.socket.send(datagram.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn format_message(tree: &MessageTree, message: &Message) -> Fallible<String> {
+    let severity = if message.status() == "0" {
+        SEVERITY_INFO
+    } else {
+        SEVERITY_ERROR
+    };
+    let mut params = vec![
+        ("message_id", tree.message_id.clone()),
+        ("root_message_id", tree.root_message_id.clone()),
+        ("parent_message_id", tree.parent_message_id.clone()),
+        ("ip_address", tree.ip_address.clone()),
+        ("thread_name", tree.thread_name.clone()),
+        ("ty", message.ty().clone()),
+        ("name", message.name().clone()),
+        ("status", message.status().clone()),
+    ];
+    if let Some(duration) = message.duration_in_ms() {
+        params.push(("duration_in_ms", duration.to_string()));
+    }
+    let structured_data: String = params
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_param(&value)))
+        .collect();
+
+    Ok(format!(
+        "<{}>1 {} {} {} - tree [{}{}] {}",
+        FACILITY * 8 + severity,
+        timestamp(message.timestamp_in_ms())?,
+        header_field(&tree.hostname, 255),
+        header_field(&tree.domain, 48),
+        SD_ID,
+        structured_data,
+        serde_json::to_string(message)?
+    ))
+}
+
+/// RFC 3339 timestamp in UTC, with milliseconds.
+fn timestamp(timestamp_in_ms: u64) -> Fallible<String> {
+    let tm = time::at_utc(time::Timespec::new(
+        (timestamp_in_ms / 1000) as i64,
+        ((timestamp_in_ms % 1000) * 1_000_000) as i32,
+    ));
+    Ok(format!(
+        "{}.{:03}Z",
+        tm.strftime("%Y-%m-%dT%H:%M:%S")?,
+        timestamp_in_ms % 1000
+    ))
+}
+
+/// A header field: printable ASCII without spaces, at most `max_len` long,
+/// or `-` when empty.
+fn header_field(value: &str, max_len: usize) -> String {
+    if value.is_empty() {
+        return "-".to_string();
+    }
+    value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect()
+}
+
+/// Escapes `"`, `\` and `]` in a structured data parameter value.
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,152 @@
+use std::env;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use failure::Fallible;
+use unicode_width::UnicodeWidthChar;
+
+use crate::human;
+use crate::message_tree::MessageTree;
+use crate::report::format_timestamp;
+
+/// Width of the table when stdout isn't a terminal and `COLUMNS` isn't set.
+const DEFAULT_WIDTH: usize = 120;
+
+/// Width of `format_timestamp`.
+const TS_WIDTH: usize = 24;
+const DURATION_WIDTH: usize = 10;
+const SEPARATOR: &str = "  ";
+
+/// Prints trees as the rows of an aligned table of the timestamp, type,
+/// name, status and duration of their root message, after a header row
+/// printed with the first of them.
+///
+/// The columns are sized once to fit the terminal, trees being printed as
+/// they come, and longer values are truncated with `…`.
+pub struct Table {
+    ty_width: usize,
+    name_width: usize,
+    status_width: usize,
+    header_written: AtomicBool,
+}
+
+impl Table {
+    /// A table fitting in `width` columns, the name taking most of what the
+    /// timestamp and duration leave.
+    pub fn new(width: usize) -> Self {
+        let rest = width.saturating_sub(TS_WIDTH + DURATION_WIDTH + 4 * SEPARATOR.len());
+        let ty_width = (rest / 5).max(6);
+        let status_width = (rest / 5).max(6);
+        Table {
+            ty_width,
+            name_width: rest.saturating_sub(ty_width + status_width).max(10),
+            status_width,
+            header_written: AtomicBool::new(false),
+        }
+    }
+
+    /// Writes the row of `tree` between `start` and `end`, e.g. color
+    /// escapes.
+    ///
+    /// `out` must be held for the header to be written once.
+    pub fn write(
+        &self,
+        tree: &MessageTree,
+        start: &str,
+        end: &str,
+        out: &mut dyn Write,
+    ) -> Fallible<()> {
+        if !self.header_written.swap(true, Ordering::Relaxed) {
+            self.write_row(["ts", "type", "name", "status", "duration"], out)?;
+            writeln!(out)?;
+        }
+        let message = &tree.message;
+        let duration = message
+            .duration_in_ms()
+            .map(|d| human::duration_ms(d as f64))
+            .unwrap_or_default();
+        write!(out, "{}", start)?;
+        self.write_row(
+            [
+                &format_timestamp(message.timestamp_in_ms())?,
+                message.ty(),
+                message.name(),
+                message.status(),
+                &duration,
+            ],
+            out,
+        )?;
+        writeln!(out, "{}", end)?;
+        Ok(())
+    }
+
+    fn write_row(
+        &self,
+        [ts, ty, name, status, duration]: [&str; 5],
+        out: &mut dyn Write,
+    ) -> Fallible<()> {
+        write!(
+            out,
+            "{}{}{}{}{}{}{}{}{:>w$}",
+            pad(ts, TS_WIDTH),
+            SEPARATOR,
+            pad(ty, self.ty_width),
+            SEPARATOR,
+            pad(name, self.name_width),
+            SEPARATOR,
+            pad(status, self.status_width),
+            SEPARATOR,
+            truncate(duration, DURATION_WIDTH),
+            w = DURATION_WIDTH
+        )?;
+        Ok(())
+    }
+}
+
+/// Width of the terminal of stdout, else of `COLUMNS`, else a default.
+pub fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        return size.ws_col as usize;
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// `s` cut to `width` terminal columns, ending with `…` when cut.
+fn truncate(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    let mut taken = 0;
+    let mut cut = String::new();
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        // Leaving a column for the ellipsis.
+        if taken + w + 1 > width {
+            break;
+        }
+        taken += w;
+        cut.push(c);
+    }
+    if width > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+fn display_width(s: &str) -> usize {
+    s.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// `s` truncated or padded with spaces to `width` terminal columns.
+fn pad(s: &str, width: usize) -> String {
+    let mut s = truncate(s, width);
+    let taken = display_width(&s);
+    s.extend(std::iter::repeat_n(' ', width.saturating_sub(taken)));
+    s
+}
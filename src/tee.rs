@@ -0,0 +1,197 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crossbeam::{Receiver, Sender, TrySendError};
+use failure::{bail, format_err, Fallible};
+use log::{info, warn};
+
+use crate::highlight::Color;
+use crate::human;
+use crate::message_tree::MessageTree;
+use crate::sink::Sink;
+
+/// What a branch of `Tee` does with a tree when its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Waits for room, slowing the other branches and the input down to the
+    /// pace of the slowest sink.
+    #[default]
+    Block,
+    /// Drops the oldest tree of the buffer, so the other branches keep
+    /// their pace.
+    DropOldest,
+}
+
+impl FromStr for Overflow {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "block" => Overflow::Block,
+            "drop-oldest" => Overflow::DropOldest,
+            _ => bail!(
+                "unknown overflow policy {}, expected block or drop-oldest",
+                s
+            ),
+        })
+    }
+}
+
+type Item = (MessageTree, Option<Color>);
+
+struct Branch {
+    name: &'static str,
+    sender: Sender<Item>,
+    /// For dropping the oldest trees.
+    receiver: Receiver<Item>,
+    dropped: AtomicU64,
+    /// Set once the sink failed, its trees being dropped from then on.
+    failed: Arc<AtomicBool>,
+    handle: JoinHandle<Fallible<u64>>,
+}
+
+/// Writes every tree to several sinks, each from its own thread through a
+/// buffer of its own, so a slow sink only holds the others back with
+/// `Overflow::Block`, and a failed one not at all.
+///
+/// The trees a sink dropped or couldn't write are counted and reported by
+/// `finish`.
+pub struct Tee {
+    branches: Vec<Branch>,
+    overflow: Overflow,
+}
+
+impl Tee {
+    /// Starts a thread per sink of `sinks`, named for the logs, each
+    /// buffering up to `buffer` trees.
+    pub fn start(
+        sinks: Vec<(&'static str, Box<dyn Sink>)>,
+        buffer: usize,
+        overflow: Overflow,
+    ) -> Fallible<Self> {
+        let mut branches = vec![];
+        for (name, sink) in sinks {
+            let (sender, receiver) = crossbeam::bounded(buffer.max(1));
+            let failed = Arc::new(AtomicBool::new(false));
+            let handle = {
+                let receiver = receiver.clone();
+                let failed = failed.clone();
+                thread::Builder::new()
+                    .name(format!("Tee-{}", name))
+                    .spawn(move || run(sink, receiver, &failed))?
+            };
+            branches.push(Branch {
+                name,
+                sender,
+                receiver,
+                dropped: AtomicU64::new(0),
+                failed,
+                handle,
+            });
+        }
+        Ok(Tee { branches, overflow })
+    }
+
+    fn send(&self, tree: &MessageTree, color: Option<Color>) -> Fallible<()> {
+        for branch in &self.branches {
+            if branch.failed.load(Ordering::Relaxed) {
+                branch.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            let mut item = (tree.clone(), color);
+            match self.overflow {
+                Overflow::Block => branch.sender.send(item)?,
+                Overflow::DropOldest => loop {
+                    match branch.sender.try_send(item) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(back)) => {
+                            if branch.receiver.try_recv().is_ok() {
+                                branch.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                            item = back;
+                        }
+                        Err(TrySendError::Disconnected(_)) => bail!("tee {} closed", branch.name),
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for every sink to write its buffered trees and reports what
+    /// each wrote and dropped, failing if a sink failed.
+    pub fn finish(self) -> Fallible<()> {
+        let mut failure = None;
+        for branch in self.branches {
+            drop(branch.sender);
+            drop(branch.receiver);
+            let written = branch.handle.join().expect("tee thread");
+            let dropped = branch.dropped.load(Ordering::Relaxed);
+            match written {
+                Ok(written) => info!(
+                    "tee {}: {} trees written, {} dropped",
+                    branch.name,
+                    human::count(written),
+                    human::count(dropped)
+                ),
+                Err(e) => {
+                    failure.get_or_insert(format_err!("tee {}: {}", branch.name, e));
+                }
+            }
+            if dropped > 0 {
+                warn!(
+                    "tee {} dropped {} trees; raise --tee-buffer or use --tee-overflow block to keep them",
+                    branch.name,
+                    human::count(dropped)
+                );
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes the trees of `receiver` to `sink` until the tee finishes,
+/// flushing whenever the buffer runs empty so followed trees show up
+/// promptly. Returns the number of trees written.
+///
+/// Once the sink fails, `failed` is set and the rest is drained unwritten.
+fn run(mut sink: Box<dyn Sink>, receiver: Receiver<Item>, failed: &AtomicBool) -> Fallible<u64> {
+    let mut written = 0;
+    let mut result = Ok(());
+    for (tree, color) in receiver.iter() {
+        if result.is_err() {
+            continue;
+        }
+        result = match color {
+            Some(color) => sink.write_highlighted(&tree, color),
+            None => sink.write_tree(&tree),
+        };
+        if result.is_ok() {
+            written += 1;
+            if receiver.is_empty() {
+                result = sink.flush();
+            }
+        }
+        if result.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+    }
+    result?;
+    sink.flush()?;
+    Ok(written)
+}
+
+impl Sink for Arc<Tee> {
+    fn write_tree(&mut self, tree: &MessageTree) -> Fallible<()> {
+        self.send(tree, None)
+    }
+
+    fn write_highlighted(&mut self, tree: &MessageTree, color: Color) -> Fallible<()> {
+        self.send(tree, Some(color))
+    }
+}
@@ -0,0 +1,143 @@
+use std::str::FromStr;
+
+use failure::{bail, Fallible};
+
+use crate::critical_path;
+use crate::csv::Column;
+use crate::fields::Field;
+use crate::message_tree::{Message, MessageTree};
+use crate::report::format_timestamp;
+use crate::status_class;
+
+/// The line of `--format`, with `{placeholders}` replaced by the values of
+/// each tree, e.g. `{ts} {domain} {ty}/{name} {duration_in_ms}ms`.
+///
+/// A placeholder is a column of `--columns`, of the root message, or of a
+/// message nested under it when prefixed by `children.<index>.`, e.g.
+/// `{children.0.name}` or `{children.-1.children.0.duration_in_ms}`, a
+/// negative index counting from the last child. Fields of the header of
+/// the tree, like `domain`, are the same at any depth. Missing values are
+/// printed empty. `{{` and `}}` print braces.
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Placeholder { path: Vec<isize>, column: Column },
+}
+
+impl FromStr for Template {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..i]);
+            let brace = &rest[i..i + 1];
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                literal.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                bail!("unmatched }} in format {}, write }}}} for a brace", s);
+            }
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => bail!("unclosed {{ in format {}, write {{{{ for a brace", s),
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(parse_placeholder(&rest[..end])?);
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template(segments))
+    }
+}
+
+fn parse_placeholder(s: &str) -> Fallible<Segment> {
+    let mut path = vec![];
+    let mut rest = s.trim();
+    while let Some(after) = rest.strip_prefix("children.") {
+        let (index, after) = match after.split_once('.') {
+            Some(split) => split,
+            None => bail!("{{{}}} names no column after the child index", s),
+        };
+        match index.parse() {
+            Ok(index) => path.push(index),
+            Err(_) => bail!("invalid child index {} in {{{}}}", index, s),
+        }
+        rest = after;
+    }
+    Ok(Segment::Placeholder {
+        path,
+        column: rest.parse()?,
+    })
+}
+
+impl Template {
+    pub fn render(&self, tree: &MessageTree) -> Fallible<String> {
+        let mut line = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Literal(s) => line.push_str(s),
+                Segment::Placeholder { path, column } => {
+                    if let Some(message) = descend(&tree.message, path) {
+                        line.push_str(&value(tree, message, *column)?.unwrap_or_default());
+                    }
+                }
+            }
+        }
+        Ok(line)
+    }
+}
+
+/// The message at `path` under `message`, `None` when missing.
+fn descend<'a>(message: &'a Message, path: &[isize]) -> Option<&'a Message> {
+    let mut message = message;
+    for &index in path {
+        let children = match message {
+            Message::Transaction(t) => &t.children,
+            _ => return None,
+        };
+        let index = match index {
+            i if i < 0 => children.len().checked_sub(i.unsigned_abs())?,
+            i => i as usize,
+        };
+        message = children.get(index)?;
+    }
+    Some(message)
+}
+
+/// The value of `column` for `message`, a message of `tree`.
+fn value(tree: &MessageTree, message: &Message, column: Column) -> Fallible<Option<String>> {
+    Ok(match column {
+        Column::Ts => Some(format_timestamp(message.timestamp_in_ms())?),
+        Column::DurationInMs | Column::Field(Field::DurationInMs) => {
+            message.duration_in_ms().map(|d| d.to_string())
+        }
+        Column::Data => Some(message.data().clone()),
+        Column::Field(Field::Status) => Some(message.status().clone()),
+        Column::Field(Field::StatusClass) => Some(status_class::of(message.status()).to_string()),
+        Column::Field(Field::Ty) => Some(message.ty().clone()),
+        Column::Field(Field::Name) => Some(message.name().clone()),
+        Column::Field(Field::TimestampInMs) => Some(message.timestamp_in_ms().to_string()),
+        Column::Field(Field::SelfDurationInMs) => match message {
+            Message::Transaction(t) => Some(critical_path::self_time_in_ms(t).to_string()),
+            _ => None,
+        },
+        Column::Field(Field::HasConcurrentChildren) => {
+            Some(message.has_concurrent_children().to_string())
+        }
+        Column::Field(field) => field.value(tree),
+    })
+}
@@ -0,0 +1,79 @@
+use std::io::Write;
+
+use failure::Fallible;
+
+use crate::human;
+use crate::message_tree::MessageTree;
+use crate::report::format_timestamp;
+
+/// Whether `tree` was logged by `thread` of `host`, matching either the
+/// hostname or the ip address, and either the thread name or its id.
+pub fn is_of(tree: &MessageTree, host: &str, thread: &str) -> bool {
+    (tree.hostname == host || tree.ip_address == host)
+        && (tree.thread_name == thread || tree.thread_id == thread)
+}
+
+/// Prints the trees of one thread in the order they started, one per line,
+/// with the idle gaps of at least `min_gap_ms` and the overlaps between
+/// them.
+///
+/// A thread serves one request at a time, so long gaps between requests
+/// hint at a starved thread pool, and overlaps at work logged on the wrong
+/// thread or clock jumps.
+pub fn print_timeline(
+    mut trees: Vec<MessageTree>,
+    min_gap_ms: u64,
+    out: &mut dyn Write,
+) -> Fallible<()> {
+    trees.sort_by_key(|tree| tree.message.timestamp_in_ms());
+    let (mut busy, mut idle, mut longest_gap, mut overlaps) = (0, 0, 0, 0);
+    let mut previous_end = None;
+    for tree in &trees {
+        let message = &tree.message;
+        let start = message.timestamp_in_ms();
+        let duration = message.duration_in_ms().unwrap_or(0);
+        match previous_end {
+            Some(end) if start >= end => {
+                let gap = start - end;
+                idle += gap;
+                longest_gap = longest_gap.max(gap);
+                if gap >= min_gap_ms && gap > 0 {
+                    writeln!(out, "{:>24}  idle {}", "", human::duration_ms(gap as f64))?;
+                }
+            }
+            Some(end) => {
+                overlaps += 1;
+                writeln!(
+                    out,
+                    "{:>24}  overlap {}",
+                    "",
+                    human::duration_ms((end - start) as f64)
+                )?;
+            }
+            None => {}
+        }
+        writeln!(
+            out,
+            "{}  {}:{} [{}] {}  {}",
+            format_timestamp(start)?,
+            message.ty(),
+            message.name(),
+            message.status(),
+            human::duration_ms(duration as f64),
+            tree.message_id
+        )?;
+        busy += duration;
+        let end = start + duration;
+        previous_end = Some(previous_end.map_or(end, |previous: u64| previous.max(end)));
+    }
+    writeln!(
+        out,
+        "{} trees, busy {}, idle {}, longest idle gap {}, {} overlaps",
+        human::count(trees.len() as u64),
+        human::duration_ms(busy as f64),
+        human::duration_ms(idle as f64),
+        human::duration_ms(longest_gap as f64),
+        human::count(overlaps)
+    )?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::{bail, format_err, Fallible};
+use lapin::tcp::OwnedTLSConfig;
+use ureq::tls::{ClientCert, PemItem, RootCerts, TlsConfig};
+
+fn read(path: &Path) -> Fallible<Vec<u8>> {
+    fs::read(path).map_err(|e| format_err!("read {}: {}", path.display(), e))
+}
+
+/// TLS settings of the connections made to other services: a CA to trust
+/// instead of the system roots, and a client certificate for the ones
+/// requiring mutual authentication. Files are PEM encoded.
+#[derive(Debug, Clone, Default)]
+pub struct ClientTls {
+    pub ca: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl ClientTls {
+    pub fn check(&self) -> Fallible<()> {
+        if self.cert.is_some() != self.key.is_some() {
+            bail!("--tls-client-cert and --tls-client-key go together");
+        }
+        Ok(())
+    }
+
+    /// An agent for HTTPS requests, e.g. to webhooks or the CAT server.
+    pub fn http_agent(&self, timeout: Option<Duration>) -> Fallible<ureq::Agent> {
+        self.check()?;
+        let mut tls = TlsConfig::builder();
+        if let Some(ca) = &self.ca {
+            let mut certs = vec![];
+            for item in ureq::tls::parse_pem(&read(ca)?) {
+                if let PemItem::Certificate(cert) = item? {
+                    certs.push(cert);
+                }
+            }
+            if certs.is_empty() {
+                bail!("no certificate in {}", ca.display());
+            }
+            tls = tls.root_certs(RootCerts::Specific(Arc::new(certs)));
+        }
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            let mut chain = vec![];
+            for item in ureq::tls::parse_pem(&read(cert)?) {
+                if let PemItem::Certificate(cert) = item? {
+                    chain.push(cert);
+                }
+            }
+            let key = ureq::tls::PrivateKey::from_pem(&read(key)?)?;
+            tls = tls.client_cert(Some(ClientCert::new_with_certs(&chain, key)));
+        }
+        Ok(ureq::Agent::config_builder()
+            .timeout_global(timeout)
+            .tls_config(tls.build())
+            .build()
+            .into())
+    }
+
+    /// Settings of `amqps://` connections. The client only authenticates
+    /// with a PKCS#12 identity there, so client certificates aren't
+    /// supported.
+    pub fn amqp_config(&self) -> Fallible<OwnedTLSConfig> {
+        self.check()?;
+        if self.cert.is_some() {
+            bail!("client certificates aren't supported for AMQP");
+        }
+        let cert_chain = match &self.ca {
+            Some(ca) => Some(String::from_utf8(read(ca)?)?),
+            None => None,
+        };
+        Ok(OwnedTLSConfig {
+            identity: None,
+            cert_chain,
+        })
+    }
+
+    /// Settings of the connections to the SMTP server `domain`.
+    pub fn smtp_parameters(
+        &self,
+        domain: &str,
+    ) -> Fallible<lettre::transport::smtp::client::TlsParameters> {
+        use lettre::transport::smtp::client::{Certificate, Identity, TlsParameters};
+
+        self.check()?;
+        let mut parameters = TlsParameters::builder(domain.to_string());
+        if let Some(ca) = &self.ca {
+            parameters = parameters.add_root_certificate(Certificate::from_pem(&read(ca)?)?);
+        }
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            parameters = parameters.identify_with(Identity::from_pem(&read(cert)?, &read(key)?)?);
+        }
+        Ok(parameters.build_rustls()?)
+    }
+
+    /// Settings of the gRPC connections over `https://`, e.g. to an OTLP
+    /// collector.
+    pub fn grpc_config(&self) -> Fallible<tonic::transport::ClientTlsConfig> {
+        use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+        self.check()?;
+        let mut config = match &self.ca {
+            Some(ca) => ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read(ca)?)),
+            None => ClientTlsConfig::new().with_native_roots(),
+        };
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            config = config.identity(Identity::from_pem(read(cert)?, read(key)?));
+        }
+        Ok(config)
+    }
+}
+
+/// TLS settings of `grpc-serve`: the certificate of the server and the CA
+/// the certificates of clients are signed by, if any. Clients without a
+/// certificate are only let in with `client_auth_optional`.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+    client_auth_optional: bool,
+) -> Fallible<tonic::transport::ServerTlsConfig> {
+    use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+    let mut config = ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?));
+    if let Some(ca) = client_ca {
+        config = config
+            .client_ca_root(Certificate::from_pem(read(ca)?))
+            .client_auth_optional(client_auth_optional);
+    }
+    Ok(config)
+}
+
+/// TLS settings of the HTTP endpoint of `--metrics-listen`, read from the
+/// same files as `server_config`. Scrapers must present a certificate
+/// signed by `client_ca` when set.
+pub fn https_server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Fallible<Arc<rustls::ServerConfig>> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::RootCertStore;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certs = CertificateDer::pem_slice_iter(&read(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format_err!("read {}: {}", cert.display(), e))?;
+    let key = PrivateKeyDer::from_pem_slice(&read(key)?)
+        .map_err(|e| format_err!("read {}: {}", key.display(), e))?;
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for root in CertificateDer::pem_slice_iter(&read(ca)?) {
+                roots.add(root.map_err(|e| format_err!("read {}: {}", ca.display(), e))?)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(builder.with_single_cert(certs, key)?))
+}
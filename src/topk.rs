@@ -0,0 +1,213 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+
+/// Number of counters kept by default, independent of the input size.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct Counter<V> {
+    count: u64,
+    /// Upper bound of the overestimation of `count`.
+    error: u64,
+    /// Sample attached to the key, e.g. a stack trace or a message id.
+    value: V,
+}
+
+/// Streaming top-k with the space-saving algorithm (Metwally et al.).
+///
+/// At most `capacity` keys are tracked. A new key replaces the one with the
+/// smallest count and inherits that count as its error, so every key whose
+/// true frequency exceeds `total / capacity` is guaranteed to be kept.
+#[derive(Debug, Clone)]
+pub struct SpaceSaving<K: Hash + Eq + Ord + Clone, V = ()> {
+    capacity: usize,
+    counters: HashMap<K, Counter<V>>,
+    /// Keys in rank order: most frequent first, ties by key.
+    by_count: BTreeSet<(Reverse<u64>, K)>,
+}
+
+/// One ranked key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranked<'a, K, V> {
+    pub key: &'a K,
+    pub count: u64,
+    pub error: u64,
+    pub value: &'a V,
+}
+
+impl<K: Hash + Eq + Ord + Clone, V> Default for SpaceSaving<K, V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl<K: Hash + Eq + Ord + Clone, V> SpaceSaving<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        SpaceSaving {
+            capacity: capacity.max(1),
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    /// Counts `key` `n` times. `value` is only evaluated for keys not yet
+    /// tracked.
+    pub fn offer_with(&mut self, key: K, n: u64, value: impl FnOnce() -> V) {
+        if let Some(counter) = self.counters.get_mut(&key) {
+            self.by_count.remove(&(Reverse(counter.count), key.clone()));
+            counter.count += n;
+            self.by_count.insert((Reverse(counter.count), key));
+            return;
+        }
+
+        let mut error = 0;
+        if self.counters.len() >= self.capacity {
+            let (Reverse(min), evicted) = self.by_count.pop_last().expect("full but empty");
+            self.counters.remove(&evicted);
+            error = min;
+        }
+        self.by_count.insert((Reverse(error + n), key.clone()));
+        self.counters.insert(
+            key,
+            Counter {
+                count: error + n,
+                error,
+                value: value(),
+            },
+        );
+    }
+
+    /// Smallest tracked count when full, which bounds the count of any key
+    /// that is not tracked.
+    fn floor(&self) -> u64 {
+        if self.counters.len() >= self.capacity {
+            self.by_count.last().map(|(Reverse(c), _)| *c).unwrap_or(0)
+        } else {
+            0
+        }
+    }
+
+    /// Merges two summaries (Agarwal et al., "Mergeable summaries").
+    ///
+    /// A key tracked by one summary only may have been counted by the other
+    /// up to its floor, which is added to both its count and error.
+    pub fn merge(&mut self, other: SpaceSaving<K, V>) {
+        let self_floor = self.floor();
+        let other_floor = other.floor();
+
+        let mut other_counters = other.counters;
+        for (key, counter) in self.counters.iter_mut() {
+            match other_counters.remove(key) {
+                Some(other_counter) => {
+                    counter.count += other_counter.count;
+                    counter.error += other_counter.error;
+                }
+                None => {
+                    counter.count += other_floor;
+                    counter.error += other_floor;
+                }
+            }
+        }
+        for (key, other_counter) in other_counters {
+            self.counters.insert(
+                key,
+                Counter {
+                    count: other_counter.count + self_floor,
+                    error: other_counter.error + self_floor,
+                    value: other_counter.value,
+                },
+            );
+        }
+
+        self.by_count = self
+            .counters
+            .iter()
+            .map(|(k, c)| (Reverse(c.count), k.clone()))
+            .collect();
+        while self.counters.len() > self.capacity {
+            let (_, evicted) = self.by_count.pop_last().expect("over capacity");
+            self.counters.remove(&evicted);
+        }
+    }
+
+    /// The `k` most frequent keys, most frequent first.
+    pub fn top(&self, k: usize) -> Vec<Ranked<'_, K, V>> {
+        self.by_count
+            .iter()
+            .take(k)
+            .map(|(_, key)| {
+                let counter = &self.counters[key];
+                Ranked {
+                    key,
+                    count: counter.count,
+                    error: counter.error,
+                    value: &counter.value,
+                }
+            })
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Ord + Clone> SpaceSaving<K, ()> {
+    pub fn offer(&mut self, key: K) {
+        self.offer_with(key, 1, || ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts `keys` in a summary of `capacity` counters, and exactly.
+    fn summarize(keys: &[u32], capacity: usize) -> (SpaceSaving<u32>, HashMap<u32, u64>) {
+        let mut summary = SpaceSaving::new(capacity);
+        let mut exact = HashMap::new();
+        for &key in keys {
+            summary.offer(key);
+            *exact.entry(key).or_insert(0) += 1;
+        }
+        (summary, exact)
+    }
+
+    #[test]
+    fn merge_full_summaries() {
+        // Frequent keys first, so the summaries fill up with counters of no
+        // error before evicting.
+        let a: Vec<u32> = (0..4).flat_map(|k| vec![k; 50]).chain(10..60).collect();
+        let b: Vec<u32> = (2..6).flat_map(|k| vec![k; 40]).chain(100..160).collect();
+        let (mut merged, mut exact) = summarize(&a, 8);
+        let (other, other_exact) = summarize(&b, 8);
+        assert!(merged.floor() > 0 && other.floor() > 0);
+        for (key, n) in other_exact {
+            *exact.entry(key).or_insert(0) += n;
+        }
+
+        merged.merge(other);
+
+        let top = merged.top(8);
+        assert_eq!(top.len(), 8);
+        for ranked in &top {
+            let truth = exact[ranked.key];
+            assert!(ranked.error <= ranked.count);
+            assert!(ranked.count - ranked.error <= truth, "{:?}", ranked);
+            assert!(truth <= ranked.count, "{:?}", ranked);
+        }
+        let keys: Vec<_> = top.iter().take(4).map(|ranked| *ranked.key).collect();
+        assert_eq!(keys, vec![2, 3, 0, 1]);
+        assert_eq!((top[0].count, top[0].error), (90, 0));
+    }
+
+    #[test]
+    fn merge_keeps_exact_counts_below_capacity() {
+        let (mut merged, _) = summarize(&[1, 1, 2], 4);
+        let (other, _) = summarize(&[1, 3, 3, 3], 4);
+        merged.merge(other);
+        let top: Vec<_> = merged
+            .top(3)
+            .into_iter()
+            .map(|ranked| (*ranked.key, ranked.count, ranked.error))
+            .collect();
+        assert_eq!(top, vec![(1, 3, 0), (3, 3, 0), (2, 1, 0)]);
+    }
+}
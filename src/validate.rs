@@ -0,0 +1,87 @@
+use serde::Serialize;
+
+use crate::message_tree::{Message, MessageTree, Text};
+
+/// Durations are truncated to milliseconds when decoded, so differences up
+/// to this much are rounding, not broken instrumentation.
+const TOLERANCE_IN_MS: i64 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Inconsistency {
+    pub message_id: Text,
+    /// `ty:name` of the transactions from the root to the offending one.
+    pub path: Text,
+    pub kind: &'static str,
+    /// Size of the inconsistency, in milliseconds.
+    pub delta_in_ms: i64,
+    pub recorded_duration_in_ms: u64,
+    pub computed_duration_in_ms: u64,
+}
+
+/// Recomputes the duration of every transaction of `tree` from its
+/// children and reports the ones that don't add up.
+pub fn validate(tree: &MessageTree) -> Vec<Inconsistency> {
+    let mut found = vec![];
+    if let Message::Transaction(_) = &tree.message {
+        check(tree, &tree.message, &mut vec![], &mut found);
+    }
+    found
+}
+
+fn check(
+    tree: &MessageTree,
+    message: &Message,
+    path: &mut Vec<Text>,
+    found: &mut Vec<Inconsistency>,
+) {
+    let t = match message {
+        Message::Transaction(t) => t,
+        _ => return,
+    };
+    path.push(format!("{}:{}", t.ty, t.name));
+
+    let start = t.timestamp_in_ms as i64;
+    let end = start + t.duration_in_ms as i64;
+    let mut computed_end = start;
+    let mut previous_end: Option<i64> = None;
+    let mut issues = vec![];
+
+    for child in &t.children {
+        let child_start = child.timestamp_in_ms() as i64;
+        let child_end = child_start + child.duration_in_ms().unwrap_or(0) as i64;
+        computed_end = computed_end.max(child_end);
+
+        if start - child_start > TOLERANCE_IN_MS {
+            issues.push(("child_starts_before_parent", start - child_start));
+        }
+        if let Some(previous_end) = previous_end {
+            if previous_end - child_start > TOLERANCE_IN_MS {
+                issues.push(("negative_gap", previous_end - child_start));
+            }
+        }
+        if child.duration_in_ms().is_some() {
+            previous_end = Some(child_end);
+        }
+    }
+
+    if computed_end - end > TOLERANCE_IN_MS {
+        issues.push(("children_exceed_parent", computed_end - end));
+    }
+
+    let joined = path.join(" > ");
+    for (kind, delta_in_ms) in issues {
+        found.push(Inconsistency {
+            message_id: tree.message_id.clone(),
+            path: joined.clone(),
+            kind,
+            delta_in_ms,
+            recorded_duration_in_ms: t.duration_in_ms,
+            computed_duration_in_ms: (computed_end - start) as u64,
+        });
+    }
+
+    for child in &t.children {
+        check(tree, child, path, found);
+    }
+    path.pop();
+}
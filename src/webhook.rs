@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use failure::Fallible;
+use log::info;
+
+use crate::batch_poster::{BatchPoster, Failed, Post, Retries};
+use crate::message_tree::{Message, MessageTree};
+use crate::output;
+use crate::tls::ClientTls;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Posts matched trees to a URL as JSON arrays, from a background thread.
+///
+/// A batch is posted once it has `batch_size` trees or `interval` after its
+/// first tree. Failed batches are retried with exponential backoff while
+/// new trees keep being batched, see `Retries::Queued`.
+pub struct Webhook {
+    poster: BatchPoster<serde_json::Value>,
+}
+
+impl Webhook {
+    pub fn start(
+        url: &str,
+        batch_size: usize,
+        interval: Duration,
+        tls: &ClientTls,
+    ) -> Fallible<Self> {
+        let post = JsonPost {
+            url: url.to_string(),
+            agent: tls.http_agent(Some(TIMEOUT))?,
+            batch_size: batch_size.max(1),
+        };
+        let poster = BatchPoster::start(
+            "Webhook",
+            post,
+            batch_size.max(1) * 2,
+            Some(interval),
+            Retries::Queued,
+        )?;
+        Ok(Webhook { poster })
+    }
+
+    /// Queues `tree`, with its message replaced by `message`, e.g. after
+    /// decoding payloads.
+    pub fn send(&self, tree: &MessageTree, message: &Message) -> Fallible<()> {
+        self.poster.send(output::tree_json(tree, message))
+    }
+
+    /// Posts the remaining trees.
+    pub fn finish(self) -> Fallible<()> {
+        self.poster.finish()
+    }
+}
+
+struct JsonPost {
+    url: String,
+    agent: ureq::Agent,
+    batch_size: usize,
+}
+
+impl Post for JsonPost {
+    type Item = serde_json::Value;
+
+    const NAME: &'static str = "webhook";
+
+    fn is_full(&self, batch: &[serde_json::Value]) -> bool {
+        batch.len() >= self.batch_size
+    }
+
+    fn post(&mut self, batch: Vec<serde_json::Value>) -> Result<(), Failed<serde_json::Value>> {
+        let body = match serde_json::to_vec(&batch) {
+            Ok(body) => body,
+            Err(e) => return Err(Failed::new(batch, e)),
+        };
+        let result = self
+            .agent
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .send(body);
+        match result {
+            Ok(_) => {
+                info!("webhook: posted {} trees", batch.len());
+                Ok(())
+            }
+            Err(e) => Err(Failed::new(batch, e)),
+        }
+    }
+}